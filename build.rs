@@ -0,0 +1,136 @@
+//Compiles every shader to SPIR-V when the embedded-shaders feature is enabled, see render::utils::embedded_shaders
+#[cfg(feature = "embedded-shaders")]
+mod embedded_shaders {
+    use std::{env, error::Error, fmt::Write, fs, path::Path};
+
+    use shaderc_build::{CompileOptions, Compiler, ResolvedInclude, ShaderKind, SpirvVersion};
+
+    //Shaders which depend on some of these defines are embedded once for every combination of them, the defines of a
+    //variant are in the order of this list
+    const VARIANT_DEFINES: &[&str] = &["PRIMITIVE_SHADING_RATE", "ALPHA_TEST"];
+
+    fn shader_kind(file_name: &str) -> Option<ShaderKind> {
+        match file_name.strip_suffix(".glsl")?.rsplit('.').next()? {
+            "comp" => Some(ShaderKind::Compute),
+            "task" => Some(ShaderKind::Task),
+            "mesh" => Some(ShaderKind::Mesh),
+            "frag" => Some(ShaderKind::Fragment),
+            _ => None,
+        }
+    }
+
+    //Only used to find the defines a shader depends on, so the includes are resolved naively
+    fn read_with_includes(path: &Path) -> Result<String, Box<dyn Error>> {
+        let mut source = String::new();
+        for line in fs::read_to_string(path)?.lines() {
+            match line.trim().strip_prefix("#include") {
+                Some(include) => {
+                    source += &read_with_includes(
+                        &Path::new("shaders").join(include.trim().trim_matches('"')),
+                    )?
+                }
+                None => {
+                    source += line;
+                    source.push('\n');
+                }
+            }
+        }
+        Ok(source)
+    }
+
+    pub fn compile() -> Result<(), Box<dyn Error>> {
+        println!("cargo:rerun-if-changed=shaders");
+
+        let out_dir = Path::new(&env::var("OUT_DIR")?).join("shaders");
+        fs::create_dir_all(&out_dir)?;
+
+        let compiler = Compiler::new().ok_or("Failed to create compiler")?;
+
+        let mut file_names = fs::read_dir("shaders")?
+            .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        file_names.sort();
+
+        let mut embedded_shaders =
+            String::from("pub static EMBEDDED_SHADERS: &[(&str, &[u8])] = &[\n");
+        for file_name in file_names {
+            let Some(kind) = shader_kind(&file_name) else {
+                continue
+            };
+
+            let path = Path::new("shaders").join(&file_name);
+            let source = fs::read_to_string(&path)?;
+            let resolved_source = read_with_includes(&path)?;
+
+            let defines: Vec<_> = VARIANT_DEFINES
+                .iter()
+                .copied()
+                .filter(|define| resolved_source.contains(define))
+                .collect();
+            for variant in 0..1 << defines.len() {
+                let variant_defines: Vec<_> = defines
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| variant & (1 << i) != 0)
+                    .map(|(_, define)| *define)
+                    .collect();
+
+                let mut compile_options =
+                    CompileOptions::new().ok_or("Failed to create compile options")?;
+                compile_options.set_include_callback(|requested_source, _, _, _| {
+                    let path = Path::new("shaders").join(requested_source);
+
+                    Ok(ResolvedInclude {
+                        resolved_name: path.to_str().unwrap().to_owned(),
+                        content: fs::read_to_string(path).map_err(|error| error.to_string())?,
+                    })
+                });
+                compile_options.set_target_spirv(SpirvVersion::V1_6);
+                for define in &variant_defines {
+                    compile_options.add_macro_definition(define, None);
+                }
+
+                let artifact = compiler.compile_into_spirv(
+                    &source,
+                    kind,
+                    path.to_str().unwrap(),
+                    "main",
+                    Some(&compile_options),
+                )?;
+
+                //Has to match the key built by embedded_shaders::find
+                let key = variant_defines
+                    .iter()
+                    .fold(format!("shaders/{file_name}"), |key, define| {
+                        format!("{key}#{define}")
+                    });
+                let spirv_path = out_dir.join(
+                    variant_defines
+                        .iter()
+                        .fold(file_name.clone(), |name, define| format!("{name}.{define}"))
+                        + ".spv",
+                );
+                fs::write(&spirv_path, artifact.as_binary_u8())?;
+
+                writeln!(
+                    embedded_shaders,
+                    "    ({key:?}, include_bytes!({:?})),",
+                    spirv_path.to_str().unwrap()
+                )?;
+            }
+        }
+        embedded_shaders += "];\n";
+
+        fs::write(
+            Path::new(&env::var("OUT_DIR")?).join("embedded_shaders.rs"),
+            embedded_shaders,
+        )?;
+
+        Ok(())
+    }
+}
+
+fn main() {
+    #[cfg(feature = "embedded-shaders")]
+    embedded_shaders::compile().unwrap();
+}
@@ -0,0 +1,23 @@
+//Needs GL_EXT_nonuniform_qualifier, types.glsl, draw_constants.glsl and geometry_resources.glsl. Only included with the
+//ALPHA_TEST define
+
+//Has to match MAX_MASK_TEXTURES in src/render/mask_textures.rs
+#define MAX_MASK_TEXTURES 64
+
+layout(set = 1, binding = 0) uniform sampler2D mask_textures[MAX_MASK_TEXTURES];
+
+//Discards the fragments which are cut out of the material, the meshlets of one draw can have different materials
+void alpha_test(uint material_idx, vec2 tex_coords) {
+    const Material material = meshes[instances[draw_instance_idx].mesh_idx].materials.value[material_idx];
+    if((material.flags & MATERIAL_ALPHA_TEST) == 0) {
+        return;
+    }
+
+    float alpha = material.base_color.a;
+    if(material.mask_texture_idx != NO_MASK_TEXTURE) {
+        alpha *= texture(mask_textures[nonuniformEXT(material.mask_texture_idx)], tex_coords).r;
+    }
+    if(alpha < material.alpha_cutoff) {
+        discard;
+    }
+}
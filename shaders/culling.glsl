@@ -0,0 +1,18 @@
+bool is_aabb_visible(AABB aabb, mat4 world_matrix, vec4 frustum_planes[6]) {
+    const vec3 aabb_min = vec3(aabb.min_x, aabb.min_y, aabb.min_z);
+    const vec3 aabb_max = vec3(aabb.max_x, aabb.max_y, aabb.max_z);
+
+    const vec3 center = (world_matrix * vec4(0.5 * (aabb_min + aabb_max), 1.0)).xyz;
+    const vec3 half_size = 0.5 * (aabb_max - aabb_min);
+    const vec3 extents = abs(world_matrix[0].xyz) * half_size.x + abs(world_matrix[1].xyz) * half_size.y
+        + abs(world_matrix[2].xyz) * half_size.z;
+
+    for(uint i = 0; i < 6; i++) {
+        const vec4 plane = frustum_planes[i];
+        if(dot(plane.xyz, center) + plane.w < -dot(abs(plane.xyz), extents)) {
+            return false;
+        }
+    }
+
+    return true;
+}
\ No newline at end of file
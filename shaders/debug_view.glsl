@@ -0,0 +1,11 @@
+//Has to match DebugView in src/render/passes/geometry.rs
+#define DEBUG_VIEW_MESHLET_ID 0
+#define DEBUG_VIEW_LOD_LEVEL 1
+#define DEBUG_VIEW_NORMALS 2
+#define DEBUG_VIEW_TEX_COORDS 3
+#define DEBUG_VIEW_DEPTH 4
+#define DEBUG_VIEW_MESHLET_BORDERS 5
+#define DEBUG_VIEW_MATERIALS 6
+
+//Every debug view is its own pipeline permutation, see GeometryPermutation
+layout(constant_id = 3) const uint DEBUG_VIEW = DEBUG_VIEW_MESHLET_ID;
@@ -0,0 +1,25 @@
+#version 460
+
+#ifdef ALPHA_TEST
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_buffer_reference_uvec2 : require
+#extension GL_EXT_mesh_shader : require
+#extension GL_EXT_nonuniform_qualifier : require
+
+#include "types.glsl"
+
+layout(location = 0) in vec2 tex_coords;
+layout(location = 10) perprimitiveEXT flat in uint material_idx;
+
+#include "draw_constants.glsl"
+#include "geometry_resources.glsl"
+#include "alpha_test.glsl"
+#endif
+
+//Only the depth is written, the color is shaded by the geometry pass. Alpha tested materials discard the same fragments
+//as in the geometry pass, otherwise their cut out parts would hide what is behind them
+void main() {
+#ifdef ALPHA_TEST
+    alpha_test(material_idx, tex_coords);
+#endif
+}
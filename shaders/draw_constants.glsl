@@ -0,0 +1,25 @@
+//Has to match DrawConstants in src/render/passes/geometry.rs, written once per frame
+struct DrawConstants {
+    uvec2 culling_stats_address;
+    uvec2 globals_address;
+    uvec2 meshes_address;
+    uvec2 instances_address;
+    uvec2 instance_levels_address;
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 8) readonly buffer DrawConstantsRef {
+    DrawConstants value;
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 4) readonly buffer InstanceLevelsRef {
+    uint value[];
+};
+
+//Has to match DrawPushConstants in src/render/passes/geometry.rs, the draws only differ in their instance
+layout(push_constant) uniform PushConstants {
+    DrawConstantsRef draw_constants_ref;
+    uint draw_instance_idx;
+};
+
+#define draw_constants draw_constants_ref.value
+#define draw_level_idx (InstanceLevelsRef(draw_constants.instance_levels_address).value[draw_instance_idx])
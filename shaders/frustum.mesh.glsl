@@ -0,0 +1,42 @@
+#version 460
+
+#extension GL_EXT_mesh_shader : require
+
+layout(local_size_x = 1) in;
+layout(max_vertices = 8, max_primitives = 12, lines) out;
+
+#include "types.glsl"
+
+layout(set = 0, binding = 0) uniform GlobalsBuffer {
+    Globals globals;
+};
+
+layout(push_constant) uniform PushConstants {
+    mat4 inverse_view_projection_matrix;
+    float far_depth;
+} push_constants;
+
+void main() {
+    SetMeshOutputsEXT(8, 12);
+
+    //Unproject the corners of the clip space volume, depth is reversed and goes from 1 at the near plane to far_depth
+    for(uint i = 0; i < 8; i++) {
+        const vec4 corner = vec4((i & 1) != 0 ? 1.0 : -1.0, (i & 2) != 0 ? 1.0 : -1.0, (i & 4) != 0 ? 1.0 : push_constants.far_depth, 1.0);
+        const vec4 position = push_constants.inverse_view_projection_matrix * corner;
+
+        gl_MeshVerticesEXT[i].gl_Position = globals.view_projection_matrix * vec4(position.xyz / position.w, 1.0);
+    }
+
+    gl_PrimitiveLineIndicesEXT[0] = uvec2(0, 1);
+    gl_PrimitiveLineIndicesEXT[1] = uvec2(2, 3);
+    gl_PrimitiveLineIndicesEXT[2] = uvec2(0, 2);
+    gl_PrimitiveLineIndicesEXT[3] = uvec2(1, 3);
+    gl_PrimitiveLineIndicesEXT[4] = uvec2(4, 5);
+    gl_PrimitiveLineIndicesEXT[5] = uvec2(6, 7);
+    gl_PrimitiveLineIndicesEXT[6] = uvec2(4, 6);
+    gl_PrimitiveLineIndicesEXT[7] = uvec2(5, 7);
+    gl_PrimitiveLineIndicesEXT[8] = uvec2(0, 4);
+    gl_PrimitiveLineIndicesEXT[9] = uvec2(1, 5);
+    gl_PrimitiveLineIndicesEXT[10] = uvec2(2, 6);
+    gl_PrimitiveLineIndicesEXT[11] = uvec2(3, 7);
+}
@@ -1,11 +1,116 @@
 #version 460
 
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_buffer_reference_uvec2 : require
+#extension GL_EXT_mesh_shader : require
+#ifdef ALPHA_TEST
+#extension GL_EXT_nonuniform_qualifier : require
+#endif
+
+#include "types.glsl"
+#include "utils.glsl"
+
 layout(location = 0) in vec2 tex_coords;
 layout(location = 1) in vec3 normal;
 layout(location = 2) in vec3 color;
+layout(location = 3) in vec4 clip_position;
+layout(location = 4) in vec4 prev_clip_position;
+//Only written by the mesh shader for the meshlet border view
+layout(location = 5) in vec3 position;
+layout(location = 6) perprimitiveEXT in mat3 triangle_positions;
+layout(location = 9) perprimitiveEXT flat in uint border_edges;
+layout(location = 10) perprimitiveEXT flat in uint material_idx;
 
 layout(location = 0) out vec4 out_color;
+//Only stored if the pipeline has a velocity attachment, which it has with TAA
+layout(location = 1) out vec2 out_velocity;
+//Only stored if the pipeline has an ID attachment, which it has on frames which pick or outline the selected instance
+layout(location = 2) out uint out_instance_idx;
+
+//Specialized to NEAR_PLANE and FAR_PLANE of render_ctx.rs, FAR_PLANE is infinite with an infinite far plane
+layout(constant_id = 1) const float NEAR_PLANE = 0.1;
+layout(constant_id = 2) const float FAR_PLANE = 1000.0;
+//Shades the default and material views with the clustered lights
+layout(constant_id = 7) const bool LIGHTING = false;
+
+#include "debug_view.glsl"
+
+#include "draw_constants.glsl"
+#include "geometry_resources.glsl"
+#include "lights.glsl"
+#ifdef ALPHA_TEST
+#include "alpha_test.glsl"
+#endif
+
+const vec3 SUN_DIRECTION = vec3(0.32, 0.9, 0.3);
+
+//Depth is reversed, 1 at the near plane and 0 at the far plane
+float linearize_depth(float depth) {
+    if(isinf(FAR_PLANE)) {
+        return NEAR_PLANE / depth;
+    }
+    return NEAR_PLANE * FAR_PLANE / (NEAR_PLANE + depth * (FAR_PLANE - NEAR_PLANE));
+}
+
+//Light of the clustered lights which reaches the surface, in view space like the lights
+vec3 clustered_light(vec3 albedo) {
+    if(!LIGHTING) {
+        return vec3(0.0);
+    }
+
+    const mat3 normal_matrix = mat3(globals.view_matrix) * mat3(instances[draw_instance_idx].world_matrix);
+    const uint cluster_idx = cluster_index(clip_position.xy / clip_position.w, clip_position.w, NEAR_PLANE);
+    return shade_lights(albedo, light_view_position(clip_position.xy / clip_position.w, clip_position.w),
+        normalize(normal_matrix * normal), cluster_idx);
+}
+
+//Distance in pixels to the closest edge of the triangle which is also an edge of its meshlet
+float border_distance() {
+    float distance = 1e30;
+    for(uint i = 0; i < 3; i++) {
+        if((border_edges & (1u << i)) != 0) {
+            const vec3 start = triangle_positions[i];
+            const vec3 direction = normalize(triangle_positions[(i + 1) % 3] - start);
+            distance = min(distance, length(cross(position - start, direction)));
+        }
+    }
+    return distance / max(fwidth(distance), 1e-6);
+}
 
 void main() {
-    out_color = vec4(color, 1.0);
-}
\ No newline at end of file
+#ifdef ALPHA_TEST
+    alpha_test(material_idx, tex_coords);
+#endif
+
+    switch(DEBUG_VIEW) {
+        case DEBUG_VIEW_LOD_LEVEL:
+            out_color = vec4(murmur_hash_11_color(draw_level_idx), 1.0);
+            break;
+        case DEBUG_VIEW_NORMALS:
+            out_color = vec4(normalize(normal) * 0.5 + 0.5, 1.0);
+            break;
+        case DEBUG_VIEW_TEX_COORDS:
+            out_color = vec4(fract(tex_coords), 0.0, 1.0);
+            break;
+        case DEBUG_VIEW_DEPTH:
+            out_color = vec4(vec3(1.0 - clamp(linearize_depth(gl_FragCoord.z) / 100.0, 0.0, 1.0)), 1.0);
+            break;
+        case DEBUG_VIEW_MESHLET_BORDERS:
+            out_color = vec4(mix(vec3(0.0), color, smoothstep(0.5, 1.5, border_distance())), 1.0);
+            break;
+        case DEBUG_VIEW_MATERIALS: {
+            //Lit in the space of the mesh, the normals aren't transformed by the instance
+            const Material material = meshes[instances[draw_instance_idx].mesh_idx].materials.value[material_idx];
+            const float diffuse = max(dot(normalize(normal), normalize(SUN_DIRECTION)), 0.0);
+            out_color = vec4(material.base_color.rgb * (0.35 + 0.65 * diffuse) + clustered_light(material.base_color.rgb), 1.0);
+            break;
+        }
+        default:
+            out_color = vec4(color + clustered_light(color), 1.0);
+            break;
+    }
+
+    //How far the surface moved since the last frame in texture coordinates
+    out_velocity = (clip_position.xy / clip_position.w - prev_clip_position.xy / prev_clip_position.w) * 0.5;
+    out_instance_idx = draw_instance_idx;
+}
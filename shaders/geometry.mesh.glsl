@@ -2,76 +2,229 @@
 
 #extension GL_EXT_buffer_reference : require
 #extension GL_EXT_buffer_reference2 : require
+#extension GL_EXT_buffer_reference_uvec2 : require
 #extension GL_EXT_mesh_shader : require
+#extension GL_EXT_multiview : require
+#ifdef PRIMITIVE_SHADING_RATE
+#extension GL_EXT_fragment_shading_rate : require
+#endif
 
-layout(local_size_x = LOCAL_SIZE_X) in;
-layout(max_vertices = 64, max_primitives = 124, triangles) out;
+#include "meshlet_limits.glsl"
+
+layout(local_size_x_id = 0) in;
+layout(max_vertices = MESHLET_MAX_VERTICES, max_primitives = MESHLET_MAX_TRIANGLES, triangles) out;
+
+layout(constant_id = 5) const bool CULLING_STATS = true;
+//Shades the coarser levels of detail at a coarser rate, only has an effect if the pipeline uses the primitive rate
+layout(constant_id = 6) const bool VARIABLE_SHADING_RATE = false;
+//Culls back faces, triangles without area and triangles which miss every pixel center before they are rasterized
+layout(constant_id = 8) const bool TRIANGLE_CULLING = false;
 
 #include "types.glsl"
+#include "vertex_format.glsl"
 #include "utils.glsl"
+#include "debug_view.glsl"
 
 layout(location = 0) out vec2[] out_tex_coords;
 layout(location = 1) out vec3[] out_normals;
 layout(location = 2) out vec3[] out_colors;
-
-layout(set = 0, binding = 0) uniform GlobalsBuffer {
-    Globals globals;
-};
-
-layout(set = 1, binding = 0) readonly buffer MeshBuffersBuffer {
-    Mesh meshes[];
-};
-
-layout(push_constant) uniform PushConstants {
-    float translation_x, translation_y, translation_z, scale;
-	vec4 rotation;
-    uint mesh_idx;
-    uint level_idx;
-} push_constants;
+layout(location = 3) out vec4[] out_clip_positions;
+layout(location = 4) out vec4[] out_prev_clip_positions;
+//The meshlet border view finds the edges which aren't shared with another triangle of the meshlet, the fragment shader
+//darkens the pixels close to them
+layout(location = 5) out vec3[] out_positions;
+layout(location = 6) perprimitiveEXT out mat3[] out_triangle_positions;
+layout(location = 9) perprimitiveEXT flat out uint[] out_border_edges;
+layout(location = 10) perprimitiveEXT flat out uint[] out_material_indices;
+
+//The depth pre-pass has to compute the exact same positions as the geometry pass
+out gl_MeshPerVertexEXT {
+    invariant vec4 gl_Position;
+} gl_MeshVerticesEXT[];
+
+#include "draw_constants.glsl"
+#include "geometry_resources.glsl"
+
+//Without task shaders every meshlet of the level is drawn by a workgroup of its own, none of them is culled
+#ifndef NO_TASK_SHADER
+taskPayloadSharedEXT MeshletPayload payload;
+#endif
+
+//Only written with triangle culling, the triangles are tested against the vertices of other invocations
+shared vec3 world_positions[MESHLET_MAX_VERTICES];
+//In pixels, the w of the clip position is kept to find the vertices behind the camera
+shared vec3 screen_positions[MESHLET_MAX_VERTICES];
+shared uint num_triangles_culled;
 
 uint get_index(MeshletDataRef meshlet_data, uint index_offset, uint index) {
     const uint byte_offset = ((index & 3)) << 3;
     return (meshlet_data[index_offset + (index >> 2)].value & (0xFF << byte_offset)) >> byte_offset;
 }
 
-vec4 calculate_pos(mat4 view_projection_matrix, vec3 position, vec3 translation, float scale, vec4 rotation) {
-	vec3 translated_pos = scale * position + translation;
-	vec3 target_pos = translated_pos + 2.0 * cross(rotation.xyz, cross(rotation.xyz, translated_pos) + rotation.w * translated_pos);
-	return view_projection_matrix * vec4(target_pos, 1.0);
+uvec3 get_triangle(MeshletDataRef meshlet_data, uint index_offset, uint triangle_idx) {
+    return uvec3(get_index(meshlet_data, index_offset, 3 * triangle_idx),
+        get_index(meshlet_data, index_offset, 3 * triangle_idx + 1),
+        get_index(meshlet_data, index_offset, 3 * triangle_idx + 2));
+}
+
+bool has_edge(uvec3 triangle, uint start, uint end) {
+    return any(equal(triangle, uvec3(start))) && any(equal(triangle, uvec3(end)));
+}
+
+//Alpha tested materials are seen from both sides, so only their empty and tiny triangles are culled
+bool is_triangle_culled(uvec3 triangle, bool two_sided) {
+    const vec3 a = screen_positions[triangle.x];
+    const vec3 b = screen_positions[triangle.y];
+    const vec3 c = screen_positions[triangle.z];
+
+    //Triangles reaching behind the camera can't be projected, they are left to the clipping of the rasterizer
+    if(a.z <= 0.0 || b.z <= 0.0 || c.z <= 0.0) {
+        return false;
+    }
+
+    const vec2 ab = b.xy - a.xy;
+    const vec2 ac = c.xy - a.xy;
+    if(ab.x * ac.y - ab.y * ac.x == 0.0) {
+        return true;
+    }
+
+    //Tested in world space, so it doesn't depend on the winding the projection produces. Front faces wind
+    //counter-clockwise around their normal like in OBJ and glTF files, the builtin meshes follow them
+    if(!two_sided) {
+        const vec3 world_a = world_positions[triangle.x];
+        const vec3 normal = cross(world_positions[triangle.y] - world_a, world_positions[triangle.z] - world_a);
+        if(dot(normal, world_a - globals.camera_pos) >= 0.0) {
+            return true;
+        }
+    }
+
+    //The pixel centers are at the halves, so no center lies between the bounds if they round to the same integer
+    const vec2 bounds_min = min(min(a.xy, b.xy), c.xy);
+    const vec2 bounds_max = max(max(a.xy, b.xy), c.xy);
+    return any(equal(round(bounds_min), round(bounds_max)));
+}
+
+vec4 calculate_pos(mat4 view_projection_matrix, vec3 position, mat4 world_matrix) {
+	return view_projection_matrix * world_matrix * vec4(position, 1.0);
 }
 
 void main() {
     const uint liid = gl_LocalInvocationIndex;
+#ifdef NO_TASK_SHADER
     const uint meshlet_idx = gl_WorkGroupID.x;
+#else
+    const uint meshlet_idx = payload.meshlet_indices[gl_WorkGroupID.x];
+#endif
 
-    MeshLevel mesh_level = meshes[push_constants.mesh_idx].levels[push_constants.level_idx].value;
+    const Instance instance = instances[draw_instance_idx];
+    const Mesh mesh = meshes[instance.mesh_idx];
+    MeshLevel mesh_level = mesh.levels[draw_level_idx].value;
 
     const Meshlet meshlet = mesh_level.meshlets[meshlet_idx].value;
     SetMeshOutputsEXT(meshlet.vertex_count, meshlet.triangle_count);
 
-    const vec3 meshlet_color = murmur_hash_11_color(meshlet_idx ^ floatBitsToInt(length(vec3(push_constants.translation_x, push_constants.translation_y, push_constants.translation_z))));
+    const vec3 meshlet_color = murmur_hash_11_color(meshlet_idx ^ murmur_hash_11(draw_instance_idx));
 
     MeshletDataRef meshlet_data = mesh_level.meshlet_data;
 
     for(uint i = liid; i < meshlet.vertex_count; i += 32) {
         const uint vertex_idx = meshlet_data[meshlet.data_offset + i].value;
-        const Vertex vertex = mesh_level.vertices[vertex_idx].value;
-
-        gl_MeshVerticesEXT[i].gl_Position = calculate_pos(globals.view_projection_matrix,
-			vec3(vertex.position_x, vertex.position_y, vertex.position_z), 
-			vec3(push_constants.translation_x, push_constants.translation_y, push_constants.translation_z), push_constants.scale, push_constants.rotation);
-
-        out_tex_coords[i] = vec2(vertex.tex_coord_x, vertex.tex_coord_y);
-        out_normals[i] = vec3(vertex.normal_x, vertex.normal_y, vertex.normal_z);
+        const Vertex vertex = decode_vertex(mesh_level.vertices[vertex_idx].value, mesh.quantization);
+
+        const vec4 position = calculate_pos(globals.view_projection_matrices[gl_ViewIndex],
+            vertex.position, instance.world_matrix);
+        gl_MeshVerticesEXT[i].gl_Position = position;
+        if(TRIANGLE_CULLING) {
+            world_positions[i] = (instance.world_matrix * vec4(vertex.position, 1.0)).xyz;
+            screen_positions[i] = vec3((position.xy / position.w * 0.5 + 0.5) * globals.viewport_size, position.w);
+        }
+
+        //The jitter is removed, so surfaces which stand still have no velocity
+        out_clip_positions[i] = vec4(position.xy - globals.jitter * position.w, position.zw);
+        out_prev_clip_positions[i] = calculate_pos(globals.prev_view_projection_matrix,
+            vertex.position, instance.prev_world_matrix);
+
+        out_tex_coords[i] = vertex.tex_coord;
+        out_normals[i] = vertex.normal;
         out_colors[i] = meshlet_color;
+        if(DEBUG_VIEW == DEBUG_VIEW_MESHLET_BORDERS) {
+            out_positions[i] = vertex.position;
+        }
     }
 
-    const uint index_offset = meshlet.data_offset + meshlet.vertex_count;
+    const uint index_offset = meshlet.primitive_offset;
+
+    if(TRIANGLE_CULLING) {
+        if(liid == 0) {
+            num_triangles_culled = 0;
+        }
+        barrier();
+    }
+    const bool two_sided =
+        (mesh.materials.value[meshlet.material_idx].flags & MATERIAL_ALPHA_TEST) != 0;
+
+#ifdef PRIMITIVE_SHADING_RATE
+    //The coarser levels are picked further away, where their triangles only cover a few pixels anyway. 1x2, 2x1 and
+    //2x2 are supported by every device with primitive shading rates
+    int shading_rate = 0;
+    if(VARIABLE_SHADING_RATE && draw_level_idx == 1) {
+        shading_rate = gl_ShadingRateFlag2HorizontalPixelsEXT;
+    } else if(VARIABLE_SHADING_RATE && draw_level_idx > 1) {
+        shading_rate = gl_ShadingRateFlag2HorizontalPixelsEXT | gl_ShadingRateFlag2VerticalPixelsEXT;
+    }
+#endif
 
     for(uint i = liid; i < meshlet.triangle_count; i += 32) {
-        const uint triangle_idx = 3 * i;
-        gl_PrimitiveTriangleIndicesEXT[i] = uvec3(get_index(meshlet_data, index_offset, triangle_idx),
-            get_index(meshlet_data, index_offset,  triangle_idx + 1),
-            get_index(meshlet_data, index_offset, triangle_idx + 2));
+        const uvec3 triangle = get_triangle(meshlet_data, index_offset, i);
+        gl_PrimitiveTriangleIndicesEXT[i] = triangle;
+        out_material_indices[i] = meshlet.material_idx;
+#ifdef PRIMITIVE_SHADING_RATE
+        gl_MeshPrimitivesEXT[i].gl_PrimitiveShadingRateEXT = shading_rate;
+#endif
+        if(TRIANGLE_CULLING) {
+            const bool culled = is_triangle_culled(triangle, two_sided);
+            gl_MeshPrimitivesEXT[i].gl_CullPrimitiveEXT = culled;
+            if(CULLING_STATS && culled) {
+                atomicAdd(num_triangles_culled, 1);
+            }
+        }
+
+        //Edge j goes from vertex j to vertex j + 1 of the triangle, it's a border if no other triangle of the meshlet
+        //has it. Only a debug view, so every other triangle is simply tested
+        if(DEBUG_VIEW == DEBUG_VIEW_MESHLET_BORDERS) {
+            uint border_edges = 7;
+            for(uint j = 0; j < meshlet.triangle_count && border_edges != 0; j++) {
+                const uvec3 other_triangle = get_triangle(meshlet_data, index_offset, j);
+                if(j != i) {
+                    for(uint k = 0; k < 3; k++) {
+                        if(has_edge(other_triangle, triangle[k], triangle[(k + 1) % 3])) {
+                            border_edges &= ~(1u << k);
+                        }
+                    }
+                }
+            }
+
+            mat3 triangle_positions;
+            for(uint k = 0; k < 3; k++) {
+                const uint vertex_idx = meshlet_data[meshlet.data_offset + triangle[k]].value;
+                triangle_positions[k] = decode_vertex(mesh_level.vertices[vertex_idx].value, mesh.quantization).position;
+            }
+
+            out_triangle_positions[i] = triangle_positions;
+            out_border_edges[i] = border_edges;
+        }
+    }
+
+    if(CULLING_STATS) {
+        if(TRIANGLE_CULLING) {
+            barrier();
+        }
+        if(liid == 0) {
+            CullingStatsRef culling_stats = CullingStatsRef(draw_constants.culling_stats_address);
+            atomicAdd(culling_stats.value.triangles_tested, meshlet.triangle_count);
+            if(TRIANGLE_CULLING) {
+                atomicAdd(culling_stats.value.triangles_culled, num_triangles_culled);
+            }
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,63 @@
+#version 460
+
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_buffer_reference2 : require
+#extension GL_EXT_buffer_reference_uvec2 : require
+#extension GL_EXT_mesh_shader : require
+
+#include "types.glsl"
+#include "culling.glsl"
+
+layout(local_size_x = MESHLET_GROUP_SIZE) in;
+
+layout(constant_id = 4) const bool CULLING = true;
+layout(constant_id = 5) const bool CULLING_STATS = true;
+
+#include "draw_constants.glsl"
+#include "geometry_resources.glsl"
+
+taskPayloadSharedEXT MeshletPayload payload;
+
+shared uint num_visible_meshlets;
+
+void main() {
+    const uint liid = gl_LocalInvocationIndex;
+    const uint group_idx = gl_WorkGroupID.x;
+
+    const Instance instance = instances[draw_instance_idx];
+    MeshLevel mesh_level = meshes[instance.mesh_idx].levels[draw_level_idx].value;
+
+    const MeshletGroup group = mesh_level.meshlet_groups[group_idx].value;
+
+    //One test for the whole group, the meshlets of an invisible group are never tested
+    const bool group_visible = !CULLING || is_aabb_visible(group.aabb, instance.world_matrix, globals.frustum_planes);
+
+    if(liid == 0) {
+        num_visible_meshlets = 0;
+    }
+    barrier();
+
+    if(group_visible && liid < group.meshlet_count) {
+        const uint meshlet_idx = group.meshlet_offset + liid;
+        const Meshlet meshlet = mesh_level.meshlets[meshlet_idx].value;
+
+        if(!CULLING || is_aabb_visible(meshlet.aabb, instance.world_matrix, globals.frustum_planes)) {
+            payload.meshlet_indices[atomicAdd(num_visible_meshlets, 1)] = meshlet_idx;
+        }
+    }
+    barrier();
+
+    if(CULLING_STATS && liid == 0) {
+        CullingStatsRef culling_stats = CullingStatsRef(draw_constants.culling_stats_address);
+        atomicAdd(culling_stats.value.meshlet_groups_tested, 1);
+        if(group_visible) {
+            atomicAdd(culling_stats.value.meshlets_tested, group.meshlet_count);
+            atomicAdd(culling_stats.value.meshlets_culled, group.meshlet_count - num_visible_meshlets);
+        } else {
+            atomicAdd(culling_stats.value.meshlet_groups_culled, 1);
+            atomicAdd(culling_stats.value.meshlets_skipped, group.meshlet_count);
+        }
+    }
+
+    EmitMeshTasksEXT(num_visible_meshlets, 1, 1);
+}
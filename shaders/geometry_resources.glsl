@@ -0,0 +1,16 @@
+//Read through the addresses in the draw constants, so the geometry pipelines only need descriptor sets for images
+layout(buffer_reference, std430, buffer_reference_align = 16) readonly buffer GlobalsRef {
+    Globals value;
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 8) readonly buffer MeshesRef {
+    Mesh value[];
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 16) readonly buffer InstancesRef {
+    Instance value[];
+};
+
+#define globals (GlobalsRef(draw_constants.globals_address).value)
+#define meshes (MeshesRef(draw_constants.meshes_address).value)
+#define instances (InstancesRef(draw_constants.instances_address).value)
@@ -2,47 +2,49 @@
 
 #extension GL_EXT_buffer_reference : require
 #extension GL_EXT_buffer_reference2 : require
+#extension GL_EXT_buffer_reference_uvec2 : require
 #extension GL_EXT_mesh_shader : require
+#extension GL_EXT_multiview : require
 
-layout(local_size_x = LOCAL_SIZE_X) in;
-layout(max_vertices = 64, max_primitives = 124, triangles) out;
+#include "meshlet_limits.glsl"
+
+layout(local_size_x_id = 0) in;
+layout(max_vertices = MESHLET_MAX_VERTICES, max_primitives = MESHLET_MAX_TRIANGLES, triangles) out;
 
 #include "types.glsl"
+#include "vertex_format.glsl"
 
 layout(location = 0) out vec2[] out_tex_coords;
 layout(location = 1) out vec3[] out_normals;
 
-layout(set = 0, binding = 0) uniform GlobalsBuffer {
-    Globals globals;
-};
-
-layout(set = 1, binding = 0) readonly buffer MeshBuffersBuffer {
-    Mesh meshes[];
-};
+#include "draw_constants.glsl"
+#include "geometry_resources.glsl"
 
-layout(push_constant) uniform PushConstants {
-    float translation_x, translation_y, translation_z, scale;
-	vec4 rotation;
-    uint mesh_idx;
-    uint level_idx;
-} push_constants;
+//Without task shaders every meshlet of the level is drawn by a workgroup of its own, none of them is culled
+#ifndef NO_TASK_SHADER
+taskPayloadSharedEXT MeshletPayload payload;
+#endif
 
 uint get_index(MeshletDataRef meshlet_data, uint index_offset, uint index) {
     const uint byte_offset = ((index & 3)) << 3;
     return (meshlet_data[index_offset + (index >> 2)].value & (0xFF << byte_offset)) >> byte_offset;
 }
 
-vec4 calculate_pos(mat4 view_projection_matrix, vec3 position, vec3 translation, float scale, vec4 rotation) {
-	vec3 translated_pos = scale * position + translation;
-	vec3 target_pos = translated_pos + 2.0 * cross(rotation.xyz, cross(rotation.xyz, translated_pos) + rotation.w * translated_pos);
-	return view_projection_matrix * vec4(target_pos, 1.0);
+vec4 calculate_pos(mat4 view_projection_matrix, vec3 position, mat4 world_matrix) {
+	return view_projection_matrix * world_matrix * vec4(position, 1.0);
 }
 
 void main() {
     const uint liid = gl_LocalInvocationIndex;
+#ifdef NO_TASK_SHADER
     const uint meshlet_idx = gl_WorkGroupID.x;
+#else
+    const uint meshlet_idx = payload.meshlet_indices[gl_WorkGroupID.x];
+#endif
 
-    MeshLevel mesh_level = meshes[push_constants.mesh_idx].levels[push_constants.level_idx].value;
+    const Instance instance = instances[draw_instance_idx];
+    const Mesh mesh = meshes[instance.mesh_idx];
+    MeshLevel mesh_level = mesh.levels[draw_level_idx].value;
 
     const Meshlet meshlet = mesh_level.meshlets[meshlet_idx].value;
     SetMeshOutputsEXT(meshlet.vertex_count, meshlet.triangle_count);
@@ -51,17 +53,16 @@ void main() {
 
     for(uint i = liid; i < meshlet.vertex_count; i += 32) {
         const uint vertex_idx = meshlet_data[meshlet.data_offset + i].value;
-        const Vertex vertex = mesh_level.vertices[vertex_idx].value;
+        const Vertex vertex = decode_vertex(mesh_level.vertices[vertex_idx].value, mesh.quantization);
 
-        gl_MeshVerticesEXT[i].gl_Position = calculate_pos(globals.view_projection_matrix,
-			vec3(vertex.position_x, vertex.position_y, vertex.position_z),
-			vec3(push_constants.translation_x, push_constants.translation_y, push_constants.translation_z), push_constants.scale, push_constants.rotation);
+        gl_MeshVerticesEXT[i].gl_Position = calculate_pos(globals.view_projection_matrices[gl_ViewIndex],
+			vertex.position, instance.world_matrix);
 
-        out_tex_coords[i] = vec2(vertex.tex_coord_x, vertex.tex_coord_y);
-        out_normals[i] = vec3(vertex.normal_x, vertex.normal_y, vertex.normal_z);
+        out_tex_coords[i] = vertex.tex_coord;
+        out_normals[i] = vertex.normal;
     }
 
-    const uint index_offset = meshlet.data_offset + meshlet.vertex_count;
+    const uint index_offset = meshlet.primitive_offset;
 
     for(uint i = liid; i < meshlet.triangle_count; i += 32) {
         const uint triangle_idx = 3 * i;
@@ -0,0 +1,21 @@
+#version 460
+
+layout(location = 0) in vec3 color;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec4 clip_position;
+layout(location = 3) in vec4 prev_clip_position;
+
+layout(location = 0) out vec4 out_color;
+//Only stored if the pipeline has a velocity attachment, which it has with TAA
+layout(location = 1) out vec2 out_velocity;
+
+const vec3 SUN_DIRECTION = vec3(0.32, 0.9, 0.3);
+
+void main() {
+    //The blades are seen from both sides
+    const vec3 facing_normal = normalize(gl_FrontFacing ? normal : -normal);
+    const float diffuse = max(dot(facing_normal, normalize(SUN_DIRECTION)), 0.0);
+
+    out_color = vec4(color * (0.35 + 0.65 * diffuse), 1.0);
+    out_velocity = (clip_position.xy / clip_position.w - prev_clip_position.xy / prev_clip_position.w) * 0.5;
+}
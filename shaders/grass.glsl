@@ -0,0 +1,54 @@
+//Has to match PATCHES_PER_TASK in src/render/passes/grass.rs
+const uint PATCHES_PER_TASK = 32;
+const uint MAX_BLADES = 32;
+//Two quads which get narrower towards the tip of the blade
+const uint BLADE_VERTICES = 5;
+const uint BLADE_TRIANGLES = 3;
+const float MAX_BLADE_HEIGHT = 0.8;
+
+layout(buffer_reference, std430, buffer_reference_align = 4) readonly buffer HeightmapRef {
+    float value;
+};
+
+//Has to match GrassConstants in src/render/passes/grass.rs
+layout(push_constant) uniform PushConstants {
+    HeightmapRef heightmap;
+    vec2 field_origin;
+    float ground_height;
+    float height_scale;
+    uint resolution;
+    float max_distance;
+} push_constants;
+
+//The task shader passes the visible patches and how many blades grow on them to the mesh shader
+struct GrassPayload {
+    uint patch_indices[PATCHES_PER_TASK];
+    uint num_blades[PATCHES_PER_TASK];
+};
+
+//Every patch covers one square meter between four texels of the heightmap
+uvec2 patch_coord(uint patch_idx) {
+    const uint patches_per_row = push_constants.resolution - 1;
+    return uvec2(patch_idx % patches_per_row, patch_idx / patches_per_row);
+}
+
+//Between 0 and 1, multiplied by height_scale for the height above the ground
+float texel_height(uvec2 texel) {
+    texel = min(texel, uvec2(push_constants.resolution - 1));
+    return push_constants.heightmap[texel.y * push_constants.resolution + texel.x].value;
+}
+
+float terrain_height(vec2 position) {
+    const vec2 texel = clamp(position, vec2(0.0), vec2(push_constants.resolution - 1));
+    const uvec2 base = uvec2(texel);
+    const vec2 t = texel - vec2(base);
+
+    return mix(mix(texel_height(base), texel_height(base + uvec2(1, 0)), t.x),
+        mix(texel_height(base + uvec2(0, 1)), texel_height(base + uvec2(1, 1)), t.x), t.y);
+}
+
+//Position is in texels of the heightmap, which are one meter apart
+vec3 world_position(vec2 position, float height) {
+    return vec3(push_constants.field_origin.x + position.x, push_constants.ground_height + height * push_constants.height_scale,
+        push_constants.field_origin.y + position.y);
+}
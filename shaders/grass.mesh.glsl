@@ -0,0 +1,101 @@
+#version 460
+
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_mesh_shader : require
+
+#include "types.glsl"
+#include "utils.glsl"
+#include "grass.glsl"
+
+layout(local_size_x = MAX_BLADES) in;
+layout(max_vertices = 4 + MAX_BLADES * BLADE_VERTICES, max_primitives = 2 + MAX_BLADES * BLADE_TRIANGLES, triangles) out;
+
+layout(set = 0, binding = 0) uniform GlobalsBuffer {
+    Globals globals;
+};
+
+taskPayloadSharedEXT GrassPayload payload;
+
+layout(location = 0) out vec3 out_colors[];
+layout(location = 1) out vec3 out_normals[];
+layout(location = 2) out vec4 out_clip_positions[];
+layout(location = 3) out vec4 out_prev_clip_positions[];
+
+const float BLADE_WIDTH = 0.05;
+const vec3 GROUND_COLOR = vec3(0.28, 0.22, 0.12);
+const vec3 ROOT_COLOR = vec3(0.08, 0.25, 0.05);
+const vec3 TIP_COLOR = vec3(0.45, 0.65, 0.2);
+const vec3 WIND_DIRECTION = vec3(0.8, 0.0, 0.6);
+
+void emit_vertex(uint idx, vec3 position, vec3 normal, vec3 color) {
+    const vec4 clip_position = globals.view_projection_matrix * vec4(position, 1.0);
+
+    gl_MeshVerticesEXT[idx].gl_Position = clip_position;
+    out_colors[idx] = color;
+    out_normals[idx] = normal;
+    //The grass stands still, only the camera moves. The sway isn't part of the velocity
+    out_clip_positions[idx] = vec4(clip_position.xy - globals.jitter * clip_position.w, clip_position.zw);
+    out_prev_clip_positions[idx] = globals.prev_view_projection_matrix * vec4(position, 1.0);
+}
+
+float random(inout uint hash) {
+    hash = murmur_hash_11(hash);
+    return float(hash & 0xFFFF) / 65535.0;
+}
+
+void main() {
+    const uint liid = gl_LocalInvocationIndex;
+    const uint patch_idx = payload.patch_indices[gl_WorkGroupID.x];
+    const uint num_blades = payload.num_blades[gl_WorkGroupID.x];
+    const uvec2 coord = patch_coord(patch_idx);
+
+    SetMeshOutputsEXT(4 + num_blades * BLADE_VERTICES, 2 + num_blades * BLADE_TRIANGLES);
+
+    //The ground of the patch, its corners are shared with the neighbouring patches so there are no cracks
+    if(liid < 4) {
+        const uvec2 texel = coord + uvec2(liid & 1, liid >> 1);
+        const float slope_x = texel_height(texel + uvec2(1, 0)) - texel_height(texel - min(texel, uvec2(1, 0)));
+        const float slope_z = texel_height(texel + uvec2(0, 1)) - texel_height(texel - min(texel, uvec2(0, 1)));
+        const vec3 normal = normalize(vec3(-slope_x * push_constants.height_scale, 2.0, -slope_z * push_constants.height_scale));
+
+        emit_vertex(liid, world_position(vec2(texel), texel_height(texel)), normal, GROUND_COLOR);
+    }
+    if(liid < 2) {
+        gl_PrimitiveTriangleIndicesEXT[liid] = liid == 0 ? uvec3(0, 2, 1) : uvec3(1, 2, 3);
+    }
+
+    if(liid >= num_blades) {
+        return;
+    }
+
+    //Every blade is placed, shaped and colored by its own hash, so it looks the same every frame
+    uint hash = patch_idx * MAX_BLADES + liid;
+    const vec2 position = vec2(coord) + vec2(random(hash), random(hash));
+    const float facing = random(hash) * 6.2831853;
+    const float height = MAX_BLADE_HEIGHT * (0.5 + 0.5 * random(hash));
+    const vec3 color_variation = vec3(0.9 + 0.2 * random(hash));
+
+    const vec3 root = world_position(position, terrain_height(position));
+    const vec3 side = vec3(cos(facing), 0.0, sin(facing));
+    const vec3 forward = vec3(-side.z, 0.0, side.x);
+
+    //The tip bends forward and sways in the wind, every blade a bit out of phase
+    const float sway = 0.15 * sin(2.0 * globals.time + 0.35 * root.x + 0.2 * root.z + random(hash));
+    const vec3 bend = 0.2 * height * forward + sway * height * WIND_DIRECTION;
+    const vec3 normal = normalize(forward + vec3(0.0, 0.5, 0.0));
+
+    const uint base_vertex = 4 + liid * BLADE_VERTICES;
+    for(uint i = 0; i < BLADE_VERTICES; i++) {
+        //Two vertices for the root and the middle of the blade, one for the tip
+        const float t = float(i / 2) * 0.5;
+        const float width = BLADE_WIDTH * (1.0 - t) * (i == BLADE_VERTICES - 1 ? 0.0 : ((i & 1) != 0 ? 1.0 : -1.0));
+        const vec3 vertex_position = root + vec3(0.0, height * t, 0.0) + bend * t * t + side * width;
+
+        emit_vertex(base_vertex + i, vertex_position, normal, mix(ROOT_COLOR, TIP_COLOR, t) * color_variation);
+    }
+
+    const uint base_triangle = 2 + liid * BLADE_TRIANGLES;
+    gl_PrimitiveTriangleIndicesEXT[base_triangle] = base_vertex + uvec3(0, 1, 2);
+    gl_PrimitiveTriangleIndicesEXT[base_triangle + 1] = base_vertex + uvec3(1, 3, 2);
+    gl_PrimitiveTriangleIndicesEXT[base_triangle + 2] = base_vertex + uvec3(2, 3, 4);
+}
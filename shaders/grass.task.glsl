@@ -0,0 +1,64 @@
+#version 460
+
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_mesh_shader : require
+
+#include "types.glsl"
+#include "culling.glsl"
+#include "grass.glsl"
+
+layout(local_size_x = PATCHES_PER_TASK) in;
+
+layout(set = 0, binding = 0) uniform GlobalsBuffer {
+    Globals globals;
+};
+
+taskPayloadSharedEXT GrassPayload payload;
+
+shared uint num_visible_patches;
+
+//Grass grows in the valleys but not on the hilltops or steep slopes
+float patch_density(float mean_height, float slope) {
+    return smoothstep(0.1, 0.25, mean_height) * (1.0 - smoothstep(0.6, 0.8, mean_height))
+        * (1.0 - smoothstep(0.4, 0.8, slope));
+}
+
+void main() {
+    const uint liid = gl_LocalInvocationIndex;
+    const uint patch_idx = gl_GlobalInvocationID.x;
+    const uint patches_per_row = push_constants.resolution - 1;
+
+    if(liid == 0) {
+        num_visible_patches = 0;
+    }
+    barrier();
+
+    //Every invocation tests one patch, the visible ones are compacted into the payload
+    if(patch_idx < patches_per_row * patches_per_row) {
+        const uvec2 coord = patch_coord(patch_idx);
+        const vec4 heights = vec4(texel_height(coord), texel_height(coord + uvec2(1, 0)), texel_height(coord + uvec2(0, 1)),
+            texel_height(coord + uvec2(1, 1)));
+        const float min_height = min(min(heights.x, heights.y), min(heights.z, heights.w));
+        const float max_height = max(max(heights.x, heights.y), max(heights.z, heights.w));
+
+        const vec3 aabb_min = world_position(vec2(coord), min_height);
+        const vec3 aabb_max = world_position(vec2(coord + 1u), max_height) + vec3(0.0, MAX_BLADE_HEIGHT, 0.0);
+        const AABB aabb = AABB(aabb_min.x, aabb_min.y, aabb_min.z, aabb_max.x, aabb_max.y, aabb_max.z);
+
+        if(is_aabb_visible(aabb, mat4(1.0), globals.frustum_planes)) {
+            //The grass thins out over the second half of the distance, the ground is always drawn
+            const float camera_distance = distance(globals.camera_pos, 0.5 * (aabb_min + aabb_max));
+            const float distance_factor = 1.0 - smoothstep(0.5 * push_constants.max_distance, push_constants.max_distance,
+                camera_distance);
+            const float density = patch_density(dot(heights, vec4(0.25)),
+                (max_height - min_height) * push_constants.height_scale);
+
+            const uint i = atomicAdd(num_visible_patches, 1);
+            payload.patch_indices[i] = patch_idx;
+            payload.num_blades[i] = uint(float(MAX_BLADES) * density * distance_factor + 0.5);
+        }
+    }
+    barrier();
+
+    EmitMeshTasksEXT(num_visible_patches, 1, 1);
+}
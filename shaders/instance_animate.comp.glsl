@@ -0,0 +1,57 @@
+#version 460
+
+#include "types.glsl"
+
+layout(local_size_x = 64) in;
+
+layout(set = 0, binding = 0) uniform GlobalsBuffer {
+    Globals globals;
+};
+
+layout(set = 1, binding = 0) readonly buffer InstanceAnimationBuffer {
+    InstanceAnimation instance_animations[];
+};
+
+layout(set = 1, binding = 1) buffer InstanceBuffer {
+    Instance instances[];
+};
+
+layout(push_constant) uniform PushConstants {
+    uint num_instances;
+} push_constants;
+
+mat4 rotation_y(float angle) {
+    const float s = sin(angle);
+    const float c = cos(angle);
+    return mat4(
+        vec4(c, 0.0, -s, 0.0),
+        vec4(0.0, 1.0, 0.0, 0.0),
+        vec4(s, 0.0, c, 0.0),
+        vec4(0.0, 0.0, 0.0, 1.0));
+}
+
+void main() {
+    const uint giid = gl_GlobalInvocationID.x;
+    if(giid >= push_constants.num_instances) {
+        return;
+    }
+
+    const InstanceAnimation animation = instance_animations[giid];
+
+    const mat4 translation = mat4(
+        vec4(1.0, 0.0, 0.0, 0.0),
+        vec4(0.0, 1.0, 0.0, 0.0),
+        vec4(0.0, 0.0, 1.0, 0.0),
+        vec4(animation.position_x, animation.position_y, animation.position_z, 1.0));
+    const mat4 scale = mat4(mat3(animation.scale));
+
+    //The base angle and the orbit rotate around the world origin, the animated angle around the instance itself
+    const mat4 world_matrix = rotation_y(animation.angle + animation.orbit_velocity * globals.time) * translation
+        * rotation_y(animation.angular_velocity * globals.time) * scale;
+
+    //The buffer starts out zeroed, so there is no previous matrix on the first frame
+    const mat4 prev_world_matrix = instances[giid].world_matrix;
+    instances[giid].prev_world_matrix = prev_world_matrix[3][3] == 0.0 ? world_matrix : prev_world_matrix;
+    instances[giid].world_matrix = world_matrix;
+    instances[giid].mesh_idx = animation.mesh_idx;
+}
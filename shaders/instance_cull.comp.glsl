@@ -1,9 +1,12 @@
 #version 460
 
 #extension GL_EXT_buffer_reference : require
-#extension GL_EXT_buffer_reference2 : require
+#extension GL_EXT_buffer_reference_uvec2 : require
 
 #include "types.glsl"
+#include "culling.glsl"
+
+layout(local_size_x = 64) in;
 
 layout(set = 0, binding = 0) uniform GlobalsBuffer {
     Globals globals;
@@ -13,27 +16,39 @@ layout(set = 1, binding = 0) readonly buffer MeshBuffersBuffer {
     Mesh meshes[];
 };
 
-layout(set = 2, binding = 0) readonly buffer InstanceBuffer {
-    Instance[] instances;
-};
-
-layout(set = 2, binding = 1) readonly buffer NumInstancesBuffer {
-    uint num_instances;
+layout(buffer_reference, std430, buffer_reference_align = 16) readonly buffer InstancesRef {
+    Instance value[];
 };
 
-layout(set = 2, binding = 2) buffer VisibleInstanceBuffer {
-    VisibleInstance[] visible_instances;
+layout(buffer_reference, std430, buffer_reference_align = 4) writeonly buffer VisibilityRef {
+    uint value[];
 };
 
-layout(set = 2, binding = 3) buffer NumVisibleInstances {
-    uint num_visible_instances;
-};
+//Has to match InstanceCullConstants in src/render/passes/instance_cull.rs
+layout(push_constant) uniform PushConstants {
+    uvec2 instances_address;
+    uvec2 visibility_address;
+    uint num_instances;
+} push_constants;
 
 void main() {
     const uint giid = gl_GlobalInvocationID.x;
-    if(giid.x >= num_instances) {
+    if(giid >= push_constants.num_instances) {
         return;
     }
 
-
-}
\ No newline at end of file
+    const Instance instance = InstancesRef(push_constants.instances_address).value[giid];
+
+    //The quantization covers every vertex of the mesh, its offset is the center and its scale half the extent
+    const VertexQuantization quantization = meshes[instance.mesh_idx].quantization;
+    AABB aabb;
+    aabb.min_x = quantization.position_offset_x - quantization.position_scale_x;
+    aabb.min_y = quantization.position_offset_y - quantization.position_scale_y;
+    aabb.min_z = quantization.position_offset_z - quantization.position_scale_z;
+    aabb.max_x = quantization.position_offset_x + quantization.position_scale_x;
+    aabb.max_y = quantization.position_offset_y + quantization.position_scale_y;
+    aabb.max_z = quantization.position_offset_z + quantization.position_scale_z;
+
+    VisibilityRef(push_constants.visibility_address).value[giid] =
+        is_aabb_visible(aabb, instance.world_matrix, globals.frustum_planes) ? 1 : 0;
+}
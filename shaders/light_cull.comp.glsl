@@ -0,0 +1,66 @@
+#version 460
+
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_buffer_reference_uvec2 : require
+
+#include "types.glsl"
+
+layout(local_size_x = 64) in;
+
+layout(set = 0, binding = 0) uniform GlobalsBuffer {
+    Globals globals;
+};
+
+layout(push_constant) uniform PushConstants {
+    float near_plane;
+} push_constants;
+
+#define LIGHT_CLUSTERS_WRITABLE
+#include "lights.glsl"
+
+//Spot lights are culled like point lights, by the sphere around them
+bool sphere_intersects_box(vec3 center, float radius, vec3 box_min, vec3 box_max) {
+    const vec3 closest = clamp(center, box_min, box_max);
+    return dot(closest - center, closest - center) <= radius * radius;
+}
+
+void main() {
+    const uint cluster_idx = gl_GlobalInvocationID.x;
+    if(cluster_idx >= NUM_CLUSTERS) {
+        return;
+    }
+
+    const uvec3 cluster = uvec3(
+        cluster_idx % CLUSTERS_X,
+        cluster_idx / CLUSTERS_X % CLUSTERS_Y,
+        cluster_idx / (CLUSTERS_X * CLUSTERS_Y));
+    const vec2 ndc_min = vec2(cluster.xy) / vec2(CLUSTERS_X, CLUSTERS_Y) * 2.0 - 1.0;
+    const vec2 ndc_max = vec2(cluster.xy + 1) / vec2(CLUSTERS_X, CLUSTERS_Y) * 2.0 - 1.0;
+    const float depth_min = slice_depth(cluster.z, push_constants.near_plane);
+    const float depth_max = slice_depth(cluster.z + 1, push_constants.near_plane);
+
+    //Bounded by the corners of its tile on both of its depths in view space
+    vec3 box_min = vec3(1e30);
+    vec3 box_max = vec3(-1e30);
+    for(uint i = 0; i < 8; i++) {
+        const vec2 ndc = vec2((i & 1) == 0 ? ndc_min.x : ndc_max.x, (i & 2) == 0 ? ndc_min.y : ndc_max.y);
+        const vec3 corner = light_view_position(ndc, (i & 4) == 0 ? depth_min : depth_max);
+        box_min = min(box_min, corner);
+        box_max = max(box_max, corner);
+    }
+
+    const LightClustersRef clusters = LightClustersRef(globals.light_clusters_address);
+    const LightsRef lights = LightsRef(globals.lights_address);
+
+    //Lights beyond the capacity of the cluster are dropped
+    uint num_lights = 0;
+    for(uint i = 0; i < globals.num_lights && num_lights < MAX_LIGHTS_PER_CLUSTER; i++) {
+        const Light light = lights.value[i];
+        const vec3 center = (globals.view_matrix * vec4(light.position, 1.0)).xyz;
+        if(sphere_intersects_box(center, light.range, box_min, box_max)) {
+            clusters.indices[cluster_idx * MAX_LIGHTS_PER_CLUSTER + num_lights] = i;
+            num_lights++;
+        }
+    }
+    clusters.counts[cluster_idx] = num_lights;
+}
@@ -0,0 +1,78 @@
+//Needs GL_EXT_buffer_reference, GL_EXT_buffer_reference_uvec2 and types.glsl, the globals come from the including shader.
+//Define LIGHT_CLUSTERS_WRITABLE to write the clusters
+
+//Has to match src/render/passes/light_cull.rs
+const uint CLUSTERS_X = 16;
+const uint CLUSTERS_Y = 9;
+const uint CLUSTERS_Z = 24;
+const uint NUM_CLUSTERS = CLUSTERS_X * CLUSTERS_Y * CLUSTERS_Z;
+const uint MAX_LIGHTS_PER_CLUSTER = 64;
+//The depth slices grow exponentially from the near plane up to this distance. Fragments further away use the last
+//slice, which only has the lights that reach into it
+const float CLUSTERS_FAR = 200.0;
+
+//Has to match Light in src/render/passes/light_cull.rs
+struct Light {
+    vec3 position;
+    float range;
+    vec3 color;
+    //Both cosines are -1 for point lights
+    float spot_cos_outer;
+    vec3 direction;
+    float spot_cos_inner;
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 16) readonly buffer LightsRef {
+    Light value[];
+};
+
+//The number of lights of every cluster, followed by the indices of its lights
+layout(buffer_reference, std430, buffer_reference_align = 4)
+#ifndef LIGHT_CLUSTERS_WRITABLE
+readonly
+#endif
+buffer LightClustersRef {
+    uint counts[NUM_CLUSTERS];
+    uint indices[NUM_CLUSTERS * MAX_LIGHTS_PER_CLUSTER];
+};
+
+//View space position of a point given by its normalized device coordinates and its distance from the camera
+vec3 light_view_position(vec2 ndc, float view_depth) {
+    return vec3(ndc * view_depth / globals.projection_scale, view_depth);
+}
+
+float slice_depth(uint slice, float near_plane) {
+    return near_plane * pow(CLUSTERS_FAR / near_plane, float(slice) / float(CLUSTERS_Z));
+}
+
+uint cluster_index(vec2 ndc, float view_depth, float near_plane) {
+    const uvec2 tile = min(uvec2(clamp(ndc * 0.5 + 0.5, 0.0, 1.0) * vec2(CLUSTERS_X, CLUSTERS_Y)),
+        uvec2(CLUSTERS_X - 1, CLUSTERS_Y - 1));
+    const float slice = log(max(view_depth / near_plane, 1.0)) / log(CLUSTERS_FAR / near_plane) * float(CLUSTERS_Z);
+    return (min(uint(slice), CLUSTERS_Z - 1) * CLUSTERS_Y + tile.y) * CLUSTERS_X + tile.x;
+}
+
+//Diffuse light of the lights of the cluster, the position and normal are in view space
+vec3 shade_lights(vec3 albedo, vec3 position, vec3 normal, uint cluster_idx) {
+    const LightClustersRef clusters = LightClustersRef(globals.light_clusters_address);
+    const LightsRef lights = LightsRef(globals.lights_address);
+
+    vec3 radiance = vec3(0.0);
+    for(uint i = 0; i < clusters.counts[cluster_idx]; i++) {
+        const Light light = lights.value[clusters.indices[cluster_idx * MAX_LIGHTS_PER_CLUSTER + i]];
+        const vec3 to_light = (globals.view_matrix * vec4(light.position, 1.0)).xyz - position;
+        const float distance = length(to_light);
+        const vec3 light_direction = to_light / max(distance, 1e-4);
+
+        //Inverse square falloff, windowed so it reaches zero at the range of the light
+        const float window = clamp(1.0 - pow(distance / light.range, 4.0), 0.0, 1.0);
+        float attenuation = window * window / (distance * distance + 1.0);
+        if(light.spot_cos_outer > -1.0) {
+            const vec3 spot_direction = mat3(globals.view_matrix) * light.direction;
+            attenuation *= smoothstep(light.spot_cos_outer, light.spot_cos_inner, dot(-light_direction, spot_direction));
+        }
+
+        radiance += light.color * attenuation * max(dot(normal, light_direction), 0.0);
+    }
+    return albedo * radiance;
+}
@@ -0,0 +1,7 @@
+//The geometry pass defines them when MeshletConfig differs from its defaults, which have to match these
+#ifndef MESHLET_MAX_VERTICES
+#define MESHLET_MAX_VERTICES 64
+#endif
+#ifndef MESHLET_MAX_TRIANGLES
+#define MESHLET_MAX_TRIANGLES 124
+#endif
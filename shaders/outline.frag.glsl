@@ -0,0 +1,37 @@
+#version 460
+
+//Index of the instance drawn to every pixel, see PickingPass
+layout(set = 0, binding = 0) uniform usampler2D instance_ids;
+
+layout(push_constant) uniform PushConstants {
+    uint selected_instance;
+} push_constants;
+
+layout(location = 0) out vec4 out_color;
+
+//In pixels
+#define OUTLINE_WIDTH 2
+#define OUTLINE_COLOR vec3(1.0, 0.6, 0.0)
+
+bool is_selected(ivec2 position) {
+    const ivec2 max_position = textureSize(instance_ids, 0) - 1;
+    return texelFetch(instance_ids, clamp(position, ivec2(0), max_position), 0).r == push_constants.selected_instance;
+}
+
+void main() {
+    const ivec2 position = ivec2(gl_FragCoord.xy);
+    if(is_selected(position)) {
+        discard;
+    }
+
+    //Pixels next to the selected instance are part of the outline
+    for(int y = -OUTLINE_WIDTH; y <= OUTLINE_WIDTH; y++) {
+        for(int x = -OUTLINE_WIDTH; x <= OUTLINE_WIDTH; x++) {
+            if(is_selected(position + ivec2(x, y))) {
+                out_color = vec4(OUTLINE_COLOR, 1.0);
+                return;
+            }
+        }
+    }
+    discard;
+}
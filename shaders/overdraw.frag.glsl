@@ -0,0 +1,7 @@
+#version 460
+
+layout(set = 0, binding = 0, r32ui) uniform uimage2D overdraw_image;
+
+void main() {
+    imageAtomicAdd(overdraw_image, ivec2(gl_FragCoord.xy), 1);
+}
@@ -0,0 +1,21 @@
+#version 460
+
+#define MAX_OVERDRAW 16.0
+
+layout(set = 0, binding = 0, r32ui) uniform readonly uimage2D overdraw_image;
+
+layout(location = 0) out vec4 out_color;
+
+vec3 heatmap(float t) {
+    return clamp(vec3(1.5) - abs(4.0 * vec3(t) - vec3(3.0, 2.0, 1.0)), 0.0, 1.0);
+}
+
+void main() {
+    const uint overdraw = imageLoad(overdraw_image, ivec2(gl_FragCoord.xy)).r;
+    if(overdraw == 0) {
+        out_color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    out_color = vec4(heatmap(clamp(float(overdraw) / MAX_OVERDRAW, 0.0, 1.0)), 1.0);
+}
@@ -0,0 +1,16 @@
+#version 460
+
+layout(location = 0) in vec2 tex_coord;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    //A round particle which fades towards its border
+    const float alpha = color.a * (1.0 - smoothstep(0.3, 1.0, length(tex_coord)));
+    if(alpha < 1.0 / 255.0) {
+        discard;
+    }
+
+    out_color = vec4(color.rgb, alpha);
+}
@@ -0,0 +1,70 @@
+#version 460
+
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_mesh_shader : require
+
+#include "types.glsl"
+
+//Has to match PARTICLES_PER_MESH in src/render/passes/particles.rs
+const uint PARTICLES_PER_MESH = 32;
+
+layout(local_size_x = PARTICLES_PER_MESH) in;
+layout(max_vertices = PARTICLES_PER_MESH * 4, max_primitives = PARTICLES_PER_MESH * 2, triangles) out;
+
+layout(set = 0, binding = 0) uniform GlobalsBuffer {
+    Globals globals;
+};
+
+//Has to match Particle in src/render/passes/particles.rs
+struct Particle {
+    vec3 position;
+    float size;
+    vec4 color;
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 16) readonly buffer ParticleRef {
+    Particle value;
+};
+
+//Has to match ParticleConstants in src/render/passes/particles.rs
+layout(push_constant) uniform PushConstants {
+    ParticleRef particles;
+    uint num_particles;
+} push_constants;
+
+layout(location = 0) out vec2 out_tex_coords[];
+layout(location = 1) out vec4 out_colors[];
+
+void main() {
+    const uint liid = gl_LocalInvocationIndex;
+    const uint first_particle = gl_WorkGroupID.x * PARTICLES_PER_MESH;
+    const uint num_particles = min(push_constants.num_particles - first_particle, PARTICLES_PER_MESH);
+
+    SetMeshOutputsEXT(num_particles * 4, num_particles * 2);
+
+    if(liid >= num_particles) {
+        return;
+    }
+
+    const Particle particle = push_constants.particles[first_particle + liid].value;
+
+    //The quad faces the camera position, so it doesn't turn when the camera only rotates
+    const vec3 to_camera = normalize(globals.camera_pos - particle.position);
+    const vec3 right = normalize(cross(abs(to_camera.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0), to_camera));
+    const vec3 up = cross(to_camera, right);
+
+    for(uint i = 0; i < 4; i++) {
+        const vec2 corner = vec2(i & 1, i >> 1);
+        const vec3 position = particle.position + (right * (corner.x - 0.5) + up * (corner.y - 0.5)) * particle.size;
+        const vec4 clip_position = globals.view_projection_matrix * vec4(position, 1.0);
+
+        //Drawn after TAA, so the jitter is removed again
+        gl_MeshVerticesEXT[liid * 4 + i].gl_Position = vec4(clip_position.xy - globals.jitter * clip_position.w, clip_position.zw);
+        out_tex_coords[liid * 4 + i] = corner * 2.0 - 1.0;
+        out_colors[liid * 4 + i] = particle.color;
+    }
+
+    //Primitives are blended in the order of their workgroup and index, which is the order of the sorted particles
+    gl_PrimitiveTriangleIndicesEXT[liid * 2] = liid * 4 + uvec3(0, 2, 1);
+    gl_PrimitiveTriangleIndicesEXT[liid * 2 + 1] = liid * 4 + uvec3(1, 2, 3);
+}
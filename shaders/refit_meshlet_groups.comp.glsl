@@ -0,0 +1,40 @@
+#version 460
+
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_buffer_reference2 : require
+
+#include "types.glsl"
+
+layout(local_size_x = 64) in;
+
+//Has to match RefitConstants in src/render/passes/skinning.rs
+layout(push_constant) uniform PushConstants {
+    VertexRef vertices;
+    MeshletRef meshlets;
+    MeshletDataRef meshlet_data;
+    MeshletGroupRef meshlet_groups;
+    VertexQuantization quantization;
+    uint num_meshlet_groups;
+    uint padding;
+} push_constants;
+
+//Merges the bounds of the meshlets of every group after refit_meshlets fitted them
+void main() {
+    const uint giid = gl_GlobalInvocationID.x;
+    if(giid >= push_constants.num_meshlet_groups) {
+        return;
+    }
+
+    const MeshletGroup group = push_constants.meshlet_groups[giid].value;
+
+    vec3 aabb_min = vec3(3.402823466e38);
+    vec3 aabb_max = vec3(-3.402823466e38);
+    for(uint i = 0; i < group.meshlet_count; i++) {
+        const AABB aabb = push_constants.meshlets[group.meshlet_offset + i].value.aabb;
+
+        aabb_min = min(aabb_min, vec3(aabb.min_x, aabb.min_y, aabb.min_z));
+        aabb_max = max(aabb_max, vec3(aabb.max_x, aabb.max_y, aabb.max_z));
+    }
+
+    push_constants.meshlet_groups[giid].value.aabb = AABB(aabb_min.x, aabb_min.y, aabb_min.z, aabb_max.x, aabb_max.y, aabb_max.z);
+}
@@ -0,0 +1,42 @@
+#version 460
+
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_buffer_reference2 : require
+
+#include "types.glsl"
+#include "vertex_format.glsl"
+
+layout(local_size_x = 64) in;
+
+//Has to match RefitConstants in src/render/passes/skinning.rs
+layout(push_constant) uniform PushConstants {
+    VertexRef vertices;
+    MeshletRef meshlets;
+    MeshletDataRef meshlet_data;
+    MeshletGroupRef meshlet_groups;
+    VertexQuantization quantization;
+    uint num_meshlets;
+    uint padding;
+} push_constants;
+
+//Fits the bounds of every meshlet to its skinned vertices, the groups are fitted afterwards by refit_meshlet_groups
+void main() {
+    const uint giid = gl_GlobalInvocationID.x;
+    if(giid >= push_constants.num_meshlets) {
+        return;
+    }
+
+    const Meshlet meshlet = push_constants.meshlets[giid].value;
+
+    vec3 aabb_min = vec3(3.402823466e38);
+    vec3 aabb_max = vec3(-3.402823466e38);
+    for(uint i = 0; i < meshlet.vertex_count; i++) {
+        const uint vertex_idx = push_constants.meshlet_data[meshlet.data_offset + i].value;
+        const vec3 position = decode_vertex(push_constants.vertices[vertex_idx].value, push_constants.quantization).position;
+
+        aabb_min = min(aabb_min, position);
+        aabb_max = max(aabb_max, position);
+    }
+
+    push_constants.meshlets[giid].value.aabb = AABB(aabb_min.x, aabb_min.y, aabb_min.z, aabb_max.x, aabb_max.y, aabb_max.z);
+}
@@ -0,0 +1,98 @@
+#version 460
+
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_buffer_reference2 : require
+
+#include "types.glsl"
+#include "vertex_format.glsl"
+
+layout(local_size_x = 64) in;
+
+//Has to match VertexSkin in src/render/skin.rs, joints holds four uint16 and weights four unorm16
+struct VertexSkin {
+    uint joints_xy;
+    uint joints_zw;
+    uint weights_xy;
+    uint weights_zw;
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 4) readonly buffer VertexSkinRef {
+    VertexSkin value;
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 16) readonly buffer JointMatrixRef {
+    mat4 value;
+};
+
+//Has to match MorphDelta in src/render/skin.rs
+struct MorphDelta {
+    float position_x, position_y, position_z;
+    float normal_x, normal_y, normal_z;
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 4) readonly buffer MorphDeltaRef {
+    MorphDelta value;
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 4) readonly buffer MorphWeightRef {
+    float value;
+};
+
+//Has to match SkinningConstants in src/render/passes/skinning.rs
+layout(push_constant) uniform PushConstants {
+    VertexRef rest_vertices;
+    VertexSkinRef vertex_skins;
+    VertexRef skinned_vertices;
+    JointMatrixRef joint_matrices;
+    MorphDeltaRef morph_deltas;
+    MorphWeightRef morph_weights;
+    VertexQuantization quantization;
+    uint num_vertices;
+    uint num_joints;
+    uint num_morph_targets;
+    uint padding;
+} push_constants;
+
+mat4 joint_matrix(uint joint) {
+    return push_constants.joint_matrices[min(joint, push_constants.num_joints - 1)].value;
+}
+
+void main() {
+    const uint giid = gl_GlobalInvocationID.x;
+    if(giid >= push_constants.num_vertices) {
+        return;
+    }
+
+    Vertex vertex = decode_vertex(push_constants.rest_vertices[giid].value, push_constants.quantization);
+
+    //Morph targets move the rest pose, which is skinned afterwards
+    if(push_constants.num_morph_targets > 0) {
+        for(uint i = 0; i < push_constants.num_morph_targets; i++) {
+            const float weight = push_constants.morph_weights[i].value;
+            if(weight == 0.0) {
+                continue;
+            }
+
+            const MorphDelta delta = push_constants.morph_deltas[i * push_constants.num_vertices + giid].value;
+            vertex.position += weight * vec3(delta.position_x, delta.position_y, delta.position_z);
+            vertex.normal += weight * vec3(delta.normal_x, delta.normal_y, delta.normal_z);
+        }
+        vertex.normal = normalize(vertex.normal);
+    }
+
+    const VertexSkin skin = push_constants.vertex_skins[giid].value;
+    const uvec4 joints = uvec4(skin.joints_xy & 0xFFFF, skin.joints_xy >> 16, skin.joints_zw & 0xFFFF, skin.joints_zw >> 16);
+    const vec4 weights = vec4(unpackUnorm2x16(skin.weights_xy), unpackUnorm2x16(skin.weights_zw));
+
+    //Vertices without weights don't belong to the skeleton, the weights of the others might not add up to one exactly
+    const float weight_sum = dot(weights, vec4(1.0));
+    if(weight_sum > 0.0) {
+        const mat4 skin_matrix = (weights.x * joint_matrix(joints.x) + weights.y * joint_matrix(joints.y)
+            + weights.z * joint_matrix(joints.z) + weights.w * joint_matrix(joints.w)) / weight_sum;
+
+        vertex.position = (skin_matrix * vec4(vertex.position, 1.0)).xyz;
+        vertex.normal = normalize(mat3(skin_matrix) * vertex.normal);
+    }
+
+    push_constants.skinned_vertices[giid].value = encode_vertex(vertex, push_constants.quantization);
+}
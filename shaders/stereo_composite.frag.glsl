@@ -0,0 +1,20 @@
+#version 460
+
+//Layer 0 is the left eye, layer 1 the right eye
+layout(set = 0, binding = 0) uniform sampler2DArray eye_images;
+
+layout(push_constant) uniform PushConstants {
+    vec2 inverse_extent;
+} push_constants;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    const vec2 uv = gl_FragCoord.xy * push_constants.inverse_extent;
+
+    //The left half of the screen shows the left eye, the right half the right eye
+    const float eye = uv.x < 0.5 ? 0.0 : 1.0;
+    const vec2 eye_uv = vec2(uv.x * 2.0 - eye, uv.y);
+
+    out_color = vec4(texture(eye_images, vec3(eye_uv, eye)).rgb, 1.0);
+}
@@ -0,0 +1,44 @@
+#version 460
+
+layout(set = 0, binding = 0) uniform sampler2D color_image;
+layout(set = 0, binding = 1) uniform sampler2D velocity_image;
+layout(set = 0, binding = 2) uniform sampler2D history_image;
+
+layout(push_constant) uniform PushConstants {
+    vec2 inverse_extent;
+    //Zero if there is no history yet
+    float history_weight;
+} push_constants;
+
+layout(location = 0) out vec4 out_color;
+layout(location = 1) out vec4 out_history;
+
+void main() {
+    const ivec2 pixel = ivec2(gl_FragCoord.xy);
+    const ivec2 max_pixel = textureSize(color_image, 0) - 1;
+    const vec3 color = texelFetch(color_image, pixel, 0).rgb;
+
+    //The history is clamped to the colors around the pixel, which rejects the history of surfaces that were occluded
+    vec3 neighborhood_min = color;
+    vec3 neighborhood_max = color;
+    for(int y = -1; y <= 1; y++) {
+        for(int x = -1; x <= 1; x++) {
+            const vec3 neighbor = texelFetch(color_image, clamp(pixel + ivec2(x, y), ivec2(0), max_pixel), 0).rgb;
+            neighborhood_min = min(neighborhood_min, neighbor);
+            neighborhood_max = max(neighborhood_max, neighbor);
+        }
+    }
+
+    const vec2 uv = gl_FragCoord.xy * push_constants.inverse_extent;
+    const vec2 history_uv = uv - texelFetch(velocity_image, pixel, 0).xy;
+    const vec3 history = clamp(texture(history_image, history_uv).rgb, neighborhood_min, neighborhood_max);
+
+    //Surfaces which were outside of the screen have no history
+    const bool history_on_screen = all(equal(history_uv, clamp(history_uv, 0.0, 1.0)));
+    //Without history the image holds garbage, which must not leak into the result even if it's weighted with 0
+    const float history_weight = history_on_screen ? push_constants.history_weight : 0.0;
+    const vec3 result = history_weight > 0.0 ? mix(color, history, history_weight) : color;
+
+    out_color = vec4(result, 1.0);
+    out_history = vec4(result, 1.0);
+}
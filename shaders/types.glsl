@@ -1,14 +1,47 @@
+const uint MESHLET_GROUP_SIZE = 32;
+
 struct Globals {
     mat4 view_projection_matrix;
     vec4 frustum_planes[6];
     vec3 camera_pos;
     float time;
+    //Not jittered, the velocity is computed from it
+    mat4 prev_view_projection_matrix;
+    //Offset of view_projection_matrix in normalized device coordinates, zero without TAA
+    vec2 jitter;
+    //Size of the rendered image in pixels, the mesh shader culls the triangles which miss every pixel center
+    vec2 viewport_size;
+    //Indexed by gl_ViewIndex, both are view_projection_matrix if there is a single view
+    mat4 view_projection_matrices[2];
+    //The lights are shaded in view space, see lights.glsl
+    mat4 view_matrix;
+    //Diagonal of the unjittered projection in x and y, it maps view space to normalized device coordinates
+    vec2 projection_scale;
+    uint num_lights;
+    uint padding_lights;
+    uvec2 lights_address;
+    uvec2 light_clusters_address;
 };
 
 struct Vertex {
-    float position_x, position_y, position_z;
-    float tex_coord_x, tex_coord_y;
-    float normal_x, normal_y, normal_z;
+    vec3 position;
+    vec2 tex_coord;
+    vec3 normal;
+};
+
+//Has to match PackedVertex, position_xy and position_zw hold half floats, normal two snorm16 and tex_coord two unorm16
+struct PackedVertex {
+    uint position_xy;
+    uint position_zw;
+    uint normal;
+    uint tex_coord;
+};
+
+struct VertexQuantization {
+    float position_offset_x, position_offset_y, position_offset_z;
+    float position_scale_x, position_scale_y, position_scale_z;
+    float tex_coord_offset_x, tex_coord_offset_y;
+    float tex_coord_scale_x, tex_coord_scale_y;
 };
 
 struct AABB {
@@ -21,16 +54,48 @@ struct Meshlet {
     uint data_offset;
     uint vertex_count;
     uint triangle_count;
+    uint primitive_offset;
+    uint material_idx;
+};
+
+struct MeshletGroup {
+    AABB aabb;
+    uint meshlet_offset;
+    uint meshlet_count;
+};
+
+struct MeshletPayload {
+    uint meshlet_indices[MESHLET_GROUP_SIZE];
+};
+
+struct CullingStats {
+    uint meshlet_groups_tested;
+    uint meshlet_groups_culled;
+    uint meshlets_tested;
+    uint meshlets_culled;
+    uint meshlets_skipped;
+    //The triangles of the visible meshlets
+    uint triangles_tested;
+    //Only counted with triangle culling
+    uint triangles_culled;
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 4) buffer CullingStatsRef {
+    CullingStats value;
 };
 
 layout(buffer_reference, std430, buffer_reference_align = 4) buffer VertexRef {
-    Vertex value;
+    PackedVertex value;
 };
 
 layout(buffer_reference, std430, buffer_reference_align = 4) buffer MeshletRef {
     Meshlet value;
 };
 
+layout(buffer_reference, std430, buffer_reference_align = 4) buffer MeshletGroupRef {
+    MeshletGroup value;
+};
+
 layout(buffer_reference, std430, buffer_reference_align = 4) buffer MeshletDataRef {
     uint value;
 };
@@ -38,27 +103,52 @@ layout(buffer_reference, std430, buffer_reference_align = 4) buffer MeshletDataR
 struct MeshLevel {
     VertexRef vertices;
     MeshletRef meshlets;
+    MeshletGroupRef meshlet_groups;
     MeshletDataRef meshlet_data;
     uint num_meshlets;
+    uint num_meshlet_groups;
 };
 
 layout(buffer_reference, std430, buffer_reference_align = 4) buffer MeshLevelRef {
     MeshLevel value;
 };
 
+//Has to match MATERIAL_ALPHA_TEST and NO_MASK_TEXTURE in src/render/mesh.rs
+#define MATERIAL_ALPHA_TEST 1
+#define NO_MASK_TEXTURE 0xFFFFFFFF
+
+//Has to match Material in src/render/mesh.rs
+struct Material {
+    vec4 base_color;
+    uint flags;
+    float alpha_cutoff;
+    uint mask_texture_idx;
+    uint padding;
+};
+
+layout(buffer_reference, std430, buffer_reference_align = 16) readonly buffer MaterialsRef {
+    Material value[];
+};
+
 struct Mesh {
     MeshLevelRef levels;
+    MaterialsRef materials;
     uint num_levels;
+    VertexQuantization quantization;
 };
 
-struct Instance {
+struct InstanceAnimation {
     float position_x, position_y, position_z;
     float scale;
-    float rotation_x, rotation_y, rotation_z, rotation_w;
+    float angle;
+    float angular_velocity;
     uint mesh_idx;
+    float orbit_velocity;
 };
 
-struct VisibleInstance {
-    uint index;
-    uint level;
+struct Instance {
+    mat4 world_matrix;
+    mat4 prev_world_matrix;
+    uint mesh_idx;
+    uint padding_0, padding_1, padding_2;
 };
\ No newline at end of file
@@ -0,0 +1,46 @@
+vec3 decode_octahedral(vec2 encoded) {
+    vec3 normal = vec3(encoded, 1.0 - abs(encoded.x) - abs(encoded.y));
+    const float t = max(-normal.z, 0.0);
+    normal.x += normal.x >= 0.0 ? -t : t;
+    normal.y += normal.y >= 0.0 ? -t : t;
+    return normalize(normal);
+}
+
+Vertex decode_vertex(PackedVertex packed, VertexQuantization quantization) {
+    const vec3 position_offset = vec3(quantization.position_offset_x, quantization.position_offset_y, quantization.position_offset_z);
+    const vec3 position_scale = vec3(quantization.position_scale_x, quantization.position_scale_y, quantization.position_scale_z);
+    const vec2 tex_coord_offset = vec2(quantization.tex_coord_offset_x, quantization.tex_coord_offset_y);
+    const vec2 tex_coord_scale = vec2(quantization.tex_coord_scale_x, quantization.tex_coord_scale_y);
+
+    const vec3 position = vec3(unpackHalf2x16(packed.position_xy), unpackHalf2x16(packed.position_zw).x);
+
+    Vertex vertex;
+    vertex.position = position * position_scale + position_offset;
+    vertex.tex_coord = unpackUnorm2x16(packed.tex_coord) * tex_coord_scale + tex_coord_offset;
+    vertex.normal = decode_octahedral(unpackSnorm2x16(packed.normal));
+    return vertex;
+}
+vec2 encode_octahedral(vec3 normal) {
+    normal /= max(abs(normal.x) + abs(normal.y) + abs(normal.z), 1e-6);
+    if(normal.z >= 0.0) {
+        return normal.xy;
+    }
+    return (1.0 - abs(normal.yx)) * vec2(normal.x >= 0.0 ? 1.0 : -1.0, normal.y >= 0.0 ? 1.0 : -1.0);
+}
+
+//The inverse of decode_vertex, matches VertexQuantization::pack
+PackedVertex encode_vertex(Vertex vertex, VertexQuantization quantization) {
+    const vec3 position_offset = vec3(quantization.position_offset_x, quantization.position_offset_y, quantization.position_offset_z);
+    const vec3 position_scale = vec3(quantization.position_scale_x, quantization.position_scale_y, quantization.position_scale_z);
+    const vec2 tex_coord_offset = vec2(quantization.tex_coord_offset_x, quantization.tex_coord_offset_y);
+    const vec2 tex_coord_scale = vec2(quantization.tex_coord_scale_x, quantization.tex_coord_scale_y);
+
+    const vec3 position = (vertex.position - position_offset) / position_scale;
+
+    PackedVertex packed;
+    packed.position_xy = packHalf2x16(position.xy);
+    packed.position_zw = packHalf2x16(vec2(position.z, 0.0));
+    packed.normal = packSnorm2x16(encode_octahedral(vertex.normal));
+    packed.tex_coord = packUnorm2x16((vertex.tex_coord - tex_coord_offset) / tex_coord_scale);
+    return packed;
+}
@@ -0,0 +1,137 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+
+//Longer frames, like while the window is dragged, would otherwise teleport the camera
+const MAX_DELTA_TIME: f32 = 0.1;
+//Sleeping overshoots by up to a scheduler tick, so the last part of the wait is spun
+const SPIN_DURATION: Duration = Duration::from_millis(2);
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameTimerConfig {
+    //Advances every frame by this many seconds instead of the measured frame time, for deterministic replays
+    pub fixed_time_step: Option<f32>,
+    pub max_frame_rate: Option<f32>,
+}
+
+//The timing options of the command line
+#[derive(clap::Args, Debug)]
+pub struct FrameTimerArgs {
+    #[arg(
+        long = "fixed-timestep",
+        value_name = "HZ",
+        value_parser = parse_rate,
+        help = "Advance the camera and animations by 1/hz seconds per frame"
+    )]
+    fixed_time_step_rate: Option<f32>,
+    #[arg(
+        long,
+        value_name = "FPS",
+        value_parser = parse_rate,
+        help = "Limit the frame rate, frames are paced to an even cadence"
+    )]
+    fps_cap: Option<f32>,
+}
+
+fn parse_rate(rate: &str) -> Result<f32> {
+    let rate: f32 = rate.parse()?;
+    if rate <= 0.0 {
+        bail!("The rate has to be positive")
+    }
+    Ok(rate)
+}
+
+impl FrameTimerArgs {
+    pub fn apply(self, config: &mut FrameTimerConfig) {
+        if let Some(rate) = self.fixed_time_step_rate {
+            config.fixed_time_step = Some(1.0 / rate);
+        }
+        if let Some(max_frame_rate) = self.fps_cap {
+            config.max_frame_rate = Some(max_frame_rate);
+        }
+    }
+}
+
+pub struct FrameTimer {
+    config: FrameTimerConfig,
+    last_frame: Instant,
+    //Deadlines advance by the frame period instead of restarting at the actual frame start, which keeps the cadence even
+    next_frame_deadline: Instant,
+    frame_duration: Duration,
+    delta_time: f32,
+    time: f32,
+}
+
+impl FrameTimer {
+    #[inline]
+    pub fn new(config: FrameTimerConfig) -> Self {
+        Self {
+            delta_time: config.fixed_time_step.unwrap_or(0.0),
+            config,
+            last_frame: Instant::now(),
+            next_frame_deadline: Instant::now(),
+            frame_duration: Duration::ZERO,
+            time: 0.0,
+        }
+    }
+
+    //Blocks until the next frame may start, does nothing without a frame rate cap
+    pub fn wait_for_next_frame(&mut self) {
+        let Some(max_frame_rate) = self.config.max_frame_rate else {
+            return
+        };
+        let frame_period = Duration::from_secs_f32(1.0 / max_frame_rate);
+
+        let now = Instant::now();
+        if now > self.next_frame_deadline + frame_period {
+            //Too far behind, catching up would render a burst of frames
+            self.next_frame_deadline = now;
+        }
+
+        if let Some(sleep_duration) = self
+            .next_frame_deadline
+            .checked_duration_since(now)
+            .and_then(|remaining| remaining.checked_sub(SPIN_DURATION))
+        {
+            thread::sleep(sleep_duration);
+        }
+        while Instant::now() < self.next_frame_deadline {
+            std::hint::spin_loop();
+        }
+
+        self.next_frame_deadline += frame_period;
+    }
+
+    //Called once at the start of every frame
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        self.frame_duration = now - self.last_frame;
+        self.last_frame = now;
+
+        self.delta_time = self
+            .config
+            .fixed_time_step
+            .unwrap_or_else(|| self.frame_duration.as_secs_f32().min(MAX_DELTA_TIME));
+        self.time += self.delta_time;
+    }
+
+    //The measured wall clock time of the last frame, independent of the fixed time step
+    #[inline]
+    pub fn frame_duration(&self) -> Duration {
+        self.frame_duration
+    }
+
+    #[inline]
+    pub fn delta_time(&self) -> f32 {
+        self.delta_time
+    }
+
+    //Sum of all delta times, drives the animations
+    #[inline]
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+}
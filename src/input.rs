@@ -0,0 +1,255 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{de::IntoDeserializer, Deserialize};
+use winit::event::{MouseButton, VirtualKeyCode};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Sprint,
+    RollLeft,
+    RollRight,
+    //Triggered once when one of their bindings is pressed
+    Quit,
+    ToggleTriangleView,
+    NextDebugView,
+    ToggleOverdraw,
+    ToggleWireframe,
+    OpenSecondaryWindow,
+    ToggleCulling,
+    ToggleInfiniteFarPlane,
+    ToggleDepthPrepass,
+    ToggleTaa,
+    ToggleGrass,
+    ToggleParticles,
+    ToggleClusteredLighting,
+    ToggleTriangleCulling,
+    ToggleConditionalRendering,
+    ToggleVariableShadingRate,
+    ToggleLodFreeze,
+    ToggleCullingFreeze,
+    ToggleAlphaToCoverage,
+    ToggleSampleShading,
+    PrintStats,
+    Screenshot,
+    IncreaseLodBias,
+    DecreaseLodBias,
+    PrintDeviceInfo,
+    ToggleMeshletLayout,
+    NextLodSimplification,
+    StartBenchmark,
+}
+
+const ACTION_NAMES: [(Action, &str); 37] = [
+    (Action::MoveForward, "move_forward"),
+    (Action::MoveBackward, "move_backward"),
+    (Action::MoveLeft, "move_left"),
+    (Action::MoveRight, "move_right"),
+    (Action::MoveUp, "move_up"),
+    (Action::MoveDown, "move_down"),
+    (Action::Sprint, "sprint"),
+    (Action::RollLeft, "roll_left"),
+    (Action::RollRight, "roll_right"),
+    (Action::Quit, "quit"),
+    (Action::ToggleTriangleView, "toggle_triangle_view"),
+    (Action::NextDebugView, "next_debug_view"),
+    (Action::ToggleOverdraw, "toggle_overdraw"),
+    (Action::ToggleWireframe, "toggle_wireframe"),
+    (Action::OpenSecondaryWindow, "open_secondary_window"),
+    (Action::ToggleCulling, "toggle_culling"),
+    (Action::ToggleInfiniteFarPlane, "toggle_infinite_far_plane"),
+    (Action::ToggleDepthPrepass, "toggle_depth_prepass"),
+    (Action::ToggleTaa, "toggle_taa"),
+    (Action::ToggleGrass, "toggle_grass"),
+    (Action::ToggleParticles, "toggle_particles"),
+    (Action::ToggleClusteredLighting, "toggle_clustered_lighting"),
+    (Action::ToggleTriangleCulling, "toggle_triangle_culling"),
+    (
+        Action::ToggleConditionalRendering,
+        "toggle_conditional_rendering",
+    ),
+    (
+        Action::ToggleVariableShadingRate,
+        "toggle_variable_shading_rate",
+    ),
+    (Action::ToggleLodFreeze, "toggle_lod_freeze"),
+    (Action::ToggleCullingFreeze, "toggle_culling_freeze"),
+    (Action::ToggleAlphaToCoverage, "toggle_alpha_to_coverage"),
+    (Action::ToggleSampleShading, "toggle_sample_shading"),
+    (Action::PrintStats, "print_stats"),
+    (Action::Screenshot, "screenshot"),
+    (Action::IncreaseLodBias, "increase_lod_bias"),
+    (Action::DecreaseLodBias, "decrease_lod_bias"),
+    (Action::PrintDeviceInfo, "print_device_info"),
+    (Action::ToggleMeshletLayout, "toggle_meshlet_layout"),
+    (Action::NextLodSimplification, "next_lod_simplification"),
+    (Action::StartBenchmark, "start_benchmark"),
+];
+
+//winit has no gamepad support, so only keys and mouse buttons can be bound
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+impl Binding {
+    //Keys use the winit names (W, Space, LShift), mouse buttons are MouseLeft, MouseRight, MouseMiddle or Mouse<n>
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "MouseLeft" => Self::Mouse(MouseButton::Left),
+            "MouseRight" => Self::Mouse(MouseButton::Right),
+            "MouseMiddle" => Self::Mouse(MouseButton::Middle),
+            _ => {
+                if let Some(button) = name.strip_prefix("Mouse") {
+                    Self::Mouse(MouseButton::Other(button.parse()?))
+                } else {
+                    let key = VirtualKeyCode::deserialize(name.into_deserializer())
+                        .map_err(|_: serde::de::value::Error| anyhow!("Unknown key {name}"))?;
+                    Self::Key(key)
+                }
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InputBindings {
+    bindings: Vec<(Action, Vec<Binding>)>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        use VirtualKeyCode::*;
+
+        let keys = |keys: &[VirtualKeyCode]| keys.iter().copied().map(Binding::Key).collect();
+
+        Self {
+            bindings: vec![
+                (Action::MoveForward, keys(&[W])),
+                (Action::MoveBackward, keys(&[S])),
+                (Action::MoveLeft, keys(&[A])),
+                (Action::MoveRight, keys(&[D])),
+                (Action::MoveUp, keys(&[Space])),
+                (Action::MoveDown, keys(&[LShift])),
+                (Action::Sprint, keys(&[LControl, RControl])),
+                (Action::RollLeft, keys(&[Q])),
+                (Action::RollRight, keys(&[E])),
+                (Action::Quit, keys(&[Escape])),
+                (Action::ToggleTriangleView, keys(&[T])),
+                (Action::NextDebugView, keys(&[V])),
+                (Action::ToggleOverdraw, keys(&[O])),
+                (Action::ToggleWireframe, keys(&[F])),
+                (Action::OpenSecondaryWindow, keys(&[U])),
+                (Action::ToggleCulling, keys(&[C])),
+                (Action::ToggleInfiniteFarPlane, keys(&[R])),
+                (Action::ToggleDepthPrepass, keys(&[G])),
+                (Action::ToggleTaa, keys(&[H])),
+                (Action::ToggleGrass, keys(&[Y])),
+                (Action::ToggleParticles, keys(&[Key1])),
+                (Action::ToggleClusteredLighting, keys(&[Key2])),
+                (Action::ToggleTriangleCulling, keys(&[Key3])),
+                (Action::ToggleConditionalRendering, keys(&[Key4])),
+                (Action::ToggleVariableShadingRate, keys(&[J])),
+                (Action::ToggleLodFreeze, keys(&[X])),
+                (Action::ToggleCullingFreeze, keys(&[Z])),
+                (Action::ToggleAlphaToCoverage, keys(&[M])),
+                (Action::ToggleSampleShading, keys(&[N])),
+                (Action::PrintStats, keys(&[P])),
+                (Action::Screenshot, keys(&[F12])),
+                (Action::IncreaseLodBias, keys(&[PageUp])),
+                (Action::DecreaseLodBias, keys(&[PageDown])),
+                (Action::PrintDeviceInfo, keys(&[I])),
+                (Action::ToggleMeshletLayout, keys(&[L])),
+                (Action::NextLodSimplification, keys(&[K])),
+                (Action::StartBenchmark, keys(&[B])),
+            ],
+        }
+    }
+}
+
+//The input options of the command line
+#[derive(clap::Args, Debug)]
+pub struct InputArgs {
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Input bindings, one \"action = binding, ...\" per line"
+    )]
+    bindings: Option<PathBuf>,
+}
+
+impl InputArgs {
+    pub fn apply(self, input_bindings: &mut InputBindings) -> Result<()> {
+        if let Some(path) = self.bindings {
+            *input_bindings = InputBindings::load(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl InputBindings {
+    //Actions missing in the file keep their default bindings
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let mut input_bindings = Self::default();
+
+        for (line_idx, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue
+            }
+
+            let (action_name, bindings) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Line {}: expected action = binding", line_idx + 1))?;
+
+            let action_name = action_name.trim();
+            let Some((action, _)) = ACTION_NAMES.iter().find(|(_, name)| *name == action_name)
+            else {
+                bail!("Line {}: unknown action {action_name}", line_idx + 1)
+            };
+
+            let bindings = bindings
+                .split(',')
+                .map(|binding| Binding::parse(binding.trim()))
+                .collect::<Result<Vec<_>>>()?;
+
+            input_bindings
+                .bindings
+                .iter_mut()
+                .find(|(a, _)| a == action)
+                .unwrap()
+                .1 = bindings;
+        }
+
+        Ok(input_bindings)
+    }
+
+    #[inline]
+    pub fn is_active(&self, action: Action, pressed: &HashSet<Binding>) -> bool {
+        self.bindings
+            .iter()
+            .filter(|(a, _)| *a == action)
+            .flat_map(|(_, bindings)| bindings)
+            .any(|binding| pressed.contains(binding))
+    }
+
+    //The actions bound to the binding, in the order they are declared
+    #[inline]
+    pub fn actions(&self, binding: Binding) -> impl Iterator<Item = Action> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| *action)
+    }
+}
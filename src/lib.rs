@@ -0,0 +1,4 @@
+#[cfg(not(any(feature = "shader-compiler", feature = "embedded-shaders")))]
+compile_error!("Either the shader-compiler or the embedded-shaders feature is required");
+
+pub mod render;
@@ -1,77 +1,433 @@
-use std::collections::HashSet;
-
-use dolly::{
-    drivers::{Position, YawPitch},
-    rig::CameraRig,
+use std::{
+    collections::HashSet,
+    process, thread,
+    time::{Duration, Instant},
 };
+
+use anyhow::{Context, Result};
+use ash::vk;
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use dolly::drivers::{Position, YawPitch};
 use glam::Vec3;
+use vk_ext_mesh_shader_example::render::{
+    capture,
+    capture::{CaptureArgs, CaptureConfig, FrameSequenceCapture},
+    headless,
+    headless::{HeadlessArgs, HeadlessConfig},
+    hitch_detector::HitchDetector,
+    memory_budget,
+    memory_budget::MemoryBudgetMonitor,
+    mesh::{LodConfig, LodSimplification, MeshletConfig, MeshletLayout},
+    meshlet_benchmark::MeshletBenchmark,
+    passes::{grass::GrassPass, instance_cull::InstanceCullPass, particles::ParticlePass},
+    render_config::{RenderArgs, RenderConfig},
+    render_ctx::{RenderCtx, RenderTarget, MAX_DESCRIPTOR_SETS},
+    renderer,
+    resource_registry::ResourceCounts,
+    scene::Scene,
+    secondary_window::SecondaryWindow,
+    terrain::TerrainConfig,
+    workers::{WorkerArgs, WorkerConfig},
+};
+#[cfg(feature = "openxr")]
+use vk_ext_mesh_shader_example::render::{
+    xr,
+    xr::{XrArgs, XrConfig},
+};
 use winit::{
     dpi::{LogicalSize, PhysicalSize, Size},
-    event::{DeviceEvent, ElementState, Event, VirtualKeyCode, WindowEvent},
+    event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     platform::run_return::EventLoopExtRunReturn,
-    window::{CursorGrabMode, WindowBuilder},
+    window::{CursorGrabMode, Fullscreen, Window, WindowBuilder},
 };
 
-use crate::render::{render_ctx::RenderCtx, renderer};
+use crate::{
+    frame_timer::{FrameTimer, FrameTimerArgs, FrameTimerConfig},
+    input::{Action, Binding, InputArgs, InputBindings},
+    settings::{Settings, SETTINGS_PATH},
+};
 
-pub mod render;
+mod frame_timer;
+mod input;
+mod settings;
 
-fn update_camera_rig(
-    pressed_keys: &HashSet<VirtualKeyCode>,
-    camera_rig: &mut CameraRig,
+//While unfocused only a few frames per second are rendered, minimized windows render nothing at all
+const UNFOCUSED_FRAME_INTERVAL: Duration = Duration::from_millis(100);
+const MINIMIZED_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const LOD_BIAS_STEP: f32 = 1.25;
+const SPRINT_SPEED_FACTOR: f32 = 4.0;
+const ROLL_SPEED: f32 = 90.0;
+const ZOOM_SPEED: f32 = 5.0;
+const MIN_FIELD_OF_VIEW: f32 = 10.0;
+const MAX_FIELD_OF_VIEW: f32 = 120.0;
+//A shader which crashes the GPU loses the new device right away again, so it's only recreated a few times
+const MAX_DEVICE_LOST_RECOVERIES: u32 = 3;
+
+fn update_camera(
+    input_bindings: &InputBindings,
+    pressed_inputs: &HashSet<Binding>,
+    render_ctx: &mut RenderCtx,
     delta_time: f32,
 ) {
+    let active = |action| input_bindings.is_active(action, pressed_inputs);
+    let camera_rig = &mut render_ctx.camera_rig;
+
     let mut delta_pos = Vec3::ZERO;
-    if pressed_keys.contains(&VirtualKeyCode::W) {
+    if active(Action::MoveForward) {
         delta_pos += Vec3::new(0.0, 0.0, 1.0);
     }
-    if pressed_keys.contains(&VirtualKeyCode::A) {
+    if active(Action::MoveLeft) {
         delta_pos += Vec3::new(-1.0, 0.0, 0.0);
     }
-    if pressed_keys.contains(&VirtualKeyCode::S) {
+    if active(Action::MoveBackward) {
         delta_pos += Vec3::new(0.0, 0.0, -1.0);
     }
-    if pressed_keys.contains(&VirtualKeyCode::D) {
+    if active(Action::MoveRight) {
         delta_pos += Vec3::new(1.0, 0.0, 0.0);
     }
     delta_pos = camera_rig.final_transform.rotation * delta_pos * 2.0;
 
-    if pressed_keys.contains(&VirtualKeyCode::Space) {
+    if active(Action::MoveUp) {
         delta_pos += Vec3::new(0.0, -1.0, 0.0);
     }
-    if pressed_keys.contains(&VirtualKeyCode::LShift) {
+    if active(Action::MoveDown) {
         delta_pos += Vec3::new(0.0, 1.0, 0.0);
     }
 
+    if active(Action::Sprint) {
+        delta_pos *= SPRINT_SPEED_FACTOR;
+    }
+
     camera_rig
         .driver_mut::<Position>()
         .translate(-delta_pos * delta_time * 10.0);
     camera_rig.update(delta_time);
+
+    let mut delta_roll = 0.0;
+    if active(Action::RollLeft) {
+        delta_roll -= 1.0;
+    }
+    if active(Action::RollRight) {
+        delta_roll += 1.0;
+    }
+    render_ctx.camera_roll += (delta_roll * ROLL_SPEED * delta_time).to_radians();
+}
+
+fn update_pressed_inputs(
+    pressed_inputs: &mut HashSet<Binding>,
+    binding: Binding,
+    state: ElementState,
+) {
+    match state {
+        ElementState::Pressed => pressed_inputs.insert(binding),
+        ElementState::Released => pressed_inputs.remove(&binding),
+    };
+}
+
+fn zoom_camera(render_ctx: &mut RenderCtx, delta: MouseScrollDelta) {
+    //Pixel deltas come from touchpads, roughly treat 20 pixels as one wheel step
+    let steps = match delta {
+        MouseScrollDelta::LineDelta(_, y) => y,
+        MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+    };
+
+    render_ctx.field_of_view =
+        (render_ctx.field_of_view - steps * ZOOM_SPEED).clamp(MIN_FIELD_OF_VIEW, MAX_FIELD_OF_VIEW);
+}
+
+//The options of the example itself, the renderer options are flattened in from the modules they configure
+#[derive(Parser, Debug)]
+#[command(about = "Renders meshlets with VK_EXT_mesh_shader")]
+struct Args {
+    #[arg(
+        long = "model",
+        value_name = "PATH",
+        conflicts_with = "scene",
+        help = "OBJ model shown instead of the demo models, can be repeated"
+    )]
+    models: Vec<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "terrain",
+        help = "JSON scene file with the meshes and instances to draw"
+    )]
+    scene: Option<String>,
+    #[arg(
+        long,
+        conflicts_with_all = ["scene", "models"],
+        help = "Show procedural primitives instead of the demo models, which need no model files"
+    )]
+    primitives: bool,
+    #[arg(
+        long,
+        value_name = "RESOLUTION",
+        help = "Replace the ground plane with a generated terrain of this many vertices per side"
+    )]
+    terrain: Option<u32>,
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Width of the window"
+    )]
+    width: Option<u32>,
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Height of the window"
+    )]
+    height: Option<u32>,
+    #[arg(long, help = "Run the meshlet layout benchmark and quit")]
+    benchmark: bool,
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        help = "Compress new meshlet caches with this zstd level, 0 disables it"
+    )]
+    cache_compression: Option<i32>,
+    #[arg(
+        long,
+        help = "Store vertices and meshlet data of new caches with the meshopt codecs"
+    )]
+    cache_meshopt: bool,
+    #[command(flatten)]
+    workers: WorkerArgs,
+    #[command(flatten)]
+    capture: CaptureArgs,
+    #[command(flatten)]
+    headless: HeadlessArgs,
+    #[cfg(feature = "openxr")]
+    #[command(flatten)]
+    xr: XrArgs,
+    #[command(flatten)]
+    input: InputArgs,
+    #[command(flatten)]
+    frame_timer: FrameTimerArgs,
+    #[command(flatten)]
+    render: RenderArgs,
+}
+
+//The configs the arguments were applied to
+struct Options {
+    models: Vec<String>,
+    scene: Option<String>,
+    primitives: bool,
+    terrain: Option<TerrainConfig>,
+    width: u32,
+    height: u32,
+    benchmark: bool,
+    worker_config: WorkerConfig,
+    capture_config: CaptureConfig,
+    headless_config: HeadlessConfig,
+    #[cfg(feature = "openxr")]
+    xr_config: XrConfig,
+    input_bindings: InputBindings,
+    frame_timer_config: FrameTimerConfig,
+    render_config: RenderConfig,
 }
 
-fn main() {
+impl Options {
+    //The persisted settings are the defaults, so command line options take precedence
+    fn new(args: Args, settings: &Settings) -> Result<Self> {
+        let mut options = Self {
+            models: args.models,
+            scene: args.scene,
+            primitives: args.primitives,
+            terrain: args.terrain.map(|resolution| {
+                TerrainConfig {
+                    resolution,
+                    ..Default::default()
+                }
+            }),
+            width: args.width.unwrap_or(settings.width),
+            height: args.height.unwrap_or(settings.height),
+            benchmark: args.benchmark,
+            worker_config: WorkerConfig::default(),
+            capture_config: CaptureConfig::default(),
+            headless_config: HeadlessConfig::default(),
+            #[cfg(feature = "openxr")]
+            xr_config: XrConfig::default(),
+            input_bindings: InputBindings::default(),
+            frame_timer_config: FrameTimerConfig::default(),
+            render_config: RenderConfig {
+                present_mode: settings.present_mode(),
+                meshlet_config: MeshletConfig {
+                    lod: settings.lod,
+                    weld_tolerance: settings.weld_tolerance,
+                    cache_compression_level: args.cache_compression.unwrap_or(0),
+                    cache_meshopt_encoding: args.cache_meshopt,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        };
+
+        args.workers.apply(&mut options.worker_config);
+        args.capture.apply(&mut options.capture_config);
+        args.headless.apply(&mut options.headless_config);
+        #[cfg(feature = "openxr")]
+        args.xr.apply(&mut options.xr_config);
+        args.input.apply(&mut options.input_bindings)?;
+        args.frame_timer.apply(&mut options.frame_timer_config);
+        args.render.apply(&mut options.render_config)?;
+
+        Ok(options)
+    }
+}
+
+fn main() -> Result<()> {
+    //Exits with the help or the usage errors before anything is loaded
+    let args = Args::parse();
+
+    let mut settings = Settings::load(SETTINGS_PATH).unwrap_or_else(|error| {
+        eprintln!("Failed to load {SETTINGS_PATH}, using the defaults: {error}");
+        Settings::default()
+    });
+
+    let Options {
+        models,
+        scene,
+        primitives,
+        terrain,
+        width,
+        height,
+        benchmark: benchmark_mode,
+        worker_config,
+        capture_config,
+        headless_config,
+        #[cfg(feature = "openxr")]
+        xr_config,
+        input_bindings,
+        mut frame_timer_config,
+        render_config,
+    } = Options::new(args, &settings).unwrap_or_else(|error| {
+        Args::command()
+            .error(ErrorKind::ValueValidation, format!("{error:#}"))
+            .exit()
+    });
+
+    let scene = match scene {
+        Some(path) => {
+            Scene::load(&path).unwrap_or_else(|error| {
+                eprintln!("Failed to load the scene {path}: {error}");
+                process::exit(1);
+            })
+        }
+        None => {
+            let scene = if primitives {
+                Scene::with_primitives()
+            } else {
+                Scene::with_models(&models)
+            };
+            match &terrain {
+                Some(terrain_config) => scene.with_terrain(terrain_config),
+                None => scene,
+            }
+        }
+    };
+
+    if headless_config.enabled {
+        if let Err(error) = headless::run(&headless_config, scene, &worker_config, &render_config) {
+            eprintln!("Headless rendering failed: {error:#}");
+            process::exit(1);
+        }
+        return Ok(())
+    }
+
+    #[cfg(feature = "openxr")]
+    if xr_config.enabled {
+        if let Err(error) = xr::run(scene, &worker_config, &render_config) {
+            eprintln!("XR rendering failed: {error:#}");
+            process::exit(1);
+        }
+        return Ok(())
+    }
+
+    //Captured sequences play back at the capture frame rate, so they need a matching fixed time step
+    if capture_config.output.is_some() && frame_timer_config.fixed_time_step.is_none() {
+        frame_timer_config.fixed_time_step =
+            Some(1.0 / (capture_config.frame_rate as f32 * capture_config.interval as f32));
+    }
+
+    let mut sequence_capture =
+        FrameSequenceCapture::new(capture_config).context("Failed to start the frame capture")?;
+
     let mut event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title("vk-ext-mesh-shader-example")
-        .with_inner_size(Size::Logical(LogicalSize::new(1600.0, 900.0)))
+        .with_inner_size(Size::Logical(LogicalSize::new(width as f64, height as f64)))
+        //Exclusive fullscreen can only be acquired for a window which covers the monitor
+        .with_fullscreen(
+            render_config
+                .exclusive_fullscreen
+                .then_some(Fullscreen::Borderless(None)),
+        )
         .build(&event_loop)
-        .unwrap();
+        .context("Failed to create the window")?;
 
     window.set_cursor_visible(false);
-    window.set_cursor_grab(CursorGrabMode::Confined).unwrap();
+    window
+        .set_cursor_grab(CursorGrabMode::Confined)
+        .context("Failed to grab the cursor")?;
+
+    let mut render_ctx = RenderCtx::new(
+        RenderTarget::Window(&window),
+        &scene,
+        &worker_config,
+        &render_config,
+    )
+    .unwrap_or_else(|error| {
+        eprintln!("Failed to initialize the renderer: {}", error.report());
+        process::exit(1);
+    });
+    //Only a size the swapchain is resized to at runtime is persisted, not the one of the command line
+    let initial_extent = render_ctx.swapchain.extent;
+
+    //Captured sequences have to be deterministic, so they can't start with the placeholder meshes
+    if sequence_capture.is_some() {
+        unsafe {
+            render_ctx
+                .scene_resources
+                .mesh_collection
+                .wait_until_loaded(&mut render_ctx.deletion_queue)
+        }
+        .context("Failed to load the meshes")?;
+    }
 
-    let mut render_ctx = RenderCtx::new(&window);
+    render_ctx.render_settings.lod_bias = settings.lod_bias;
+    render_ctx.render_settings.culling = settings.culling;
 
     let mut frame_count = 0;
     let mut frame_index = 0;
+    let mut device_lost_recoveries = 0;
 
-    let mut pressed_keys = HashSet::new();
+    let mut pressed_inputs = HashSet::new();
+    //The actions whose bindings were pressed since the last frame
+    let mut triggered_actions = Vec::new();
     let mut running = true;
+    let mut focused = true;
+    let mut minimized = false;
 
-    let delta_time = 1.0 / 165.0;
+    let mut frame_timer = FrameTimer::new(frame_timer_config);
+
+    let mut hitch_detector = HitchDetector::new("hitches.log");
+    let mut last_hud_update = Instant::now();
+    let mut memory_budget_monitor = MemoryBudgetMonitor::default();
+    let mut meshlet_benchmark = benchmark_mode
+        .then(|| MeshletBenchmark::new(&mut render_ctx))
+        .transpose()?;
+    let mut screenshot_requested = false;
+    //Each one is dropped before its window, since its surface must not outlive the window
+    let mut secondary_windows: Vec<(SecondaryWindow, Window)> = Vec::new();
+    let mut secondary_window_requested = false;
+    let mut cursor_position = (0, 0);
 
     while running {
+        frame_timer.wait_for_next_frame();
+        frame_timer.tick();
+        let delta_time = frame_timer.delta_time();
+
         event_loop.run_return(|event, _, control_flow| {
             *control_flow = ControlFlow::Wait;
 
@@ -80,33 +436,66 @@ fn main() {
                     if window.id() == window_id {
                         match event {
                             WindowEvent::CloseRequested => running = false,
+                            WindowEvent::Focused(is_focused) => {
+                                focused = is_focused;
+                                //Releases while unfocused are never reported, so keys would get stuck
+                                if !focused {
+                                    pressed_inputs.clear();
+                                }
+                            }
+                            WindowEvent::Resized(size) => {
+                                minimized = size.width == 0 || size.height == 0;
+                                //The swapchain is created again with the new size before the next frame
+                                let extent = render_ctx.swapchain.extent;
+                                render_ctx.swapchain.out_of_date |= !minimized
+                                    && (size.width, size.height) != (extent.width, extent.height);
+                            }
+                            WindowEvent::CursorMoved { position, .. } => {
+                                cursor_position = (position.x as u32, position.y as u32)
+                            }
+                            WindowEvent::MouseInput { state, button, .. } => {
+                                //Clicking selects the instance under the cursor
+                                if button == MouseButton::Left && state == ElementState::Pressed {
+                                    if render_ctx.geometry_pass.picking_supported(&render_ctx) {
+                                        render_ctx.pick_requested = Some(cursor_position);
+                                    } else {
+                                        println!(
+                                            "Picking is not supported with the current settings"
+                                        );
+                                    }
+                                }
+                                if state == ElementState::Pressed {
+                                    triggered_actions
+                                        .extend(input_bindings.actions(Binding::Mouse(button)));
+                                }
+                                update_pressed_inputs(
+                                    &mut pressed_inputs,
+                                    Binding::Mouse(button),
+                                    state,
+                                );
+                            }
+                            WindowEvent::MouseWheel { delta, .. } => {
+                                zoom_camera(&mut render_ctx, delta)
+                            }
                             WindowEvent::KeyboardInput { input, .. } => {
                                 if let Some(key_code) = input.virtual_keycode {
-                                    if key_code == VirtualKeyCode::Escape {
-                                        running = false;
-                                    } else if key_code == VirtualKeyCode::T
-                                        && input.state == ElementState::Pressed
-                                    {
-                                        render_ctx.geometry_pass.triangle_view =
-                                            !render_ctx.geometry_pass.triangle_view;
+                                    if input.state == ElementState::Pressed {
+                                        triggered_actions
+                                            .extend(input_bindings.actions(Binding::Key(key_code)));
                                     }
 
-                                    match input.state {
-                                        ElementState::Pressed => {
-                                            if !pressed_keys.contains(&key_code) {
-                                                pressed_keys.insert(key_code);
-                                            }
-                                        }
-                                        ElementState::Released => {
-                                            if pressed_keys.contains(&key_code) {
-                                                pressed_keys.remove(&key_code);
-                                            }
-                                        }
-                                    }
+                                    update_pressed_inputs(
+                                        &mut pressed_inputs,
+                                        Binding::Key(key_code),
+                                        input.state,
+                                    );
                                 }
                             }
                             _ => {}
                         }
+                    } else if let WindowEvent::CloseRequested = event {
+                        secondary_windows
+                            .retain(|(_, secondary_window)| secondary_window.id() != window_id);
                     }
                 }
                 Event::MainEventsCleared => {
@@ -115,9 +504,10 @@ fn main() {
                 Event::DeviceEvent { event, .. } => {
                     if let DeviceEvent::MouseMotion { delta } = event {
                         let camera_rig = &mut render_ctx.camera_rig;
-                        camera_rig
-                            .driver_mut::<YawPitch>()
-                            .rotate_yaw_pitch(0.3 * delta.0 as f32, -0.3 * delta.1 as f32);
+                        camera_rig.driver_mut::<YawPitch>().rotate_yaw_pitch(
+                            settings.camera_sensitivity * delta.0 as f32,
+                            -settings.camera_sensitivity * delta.1 as f32,
+                        );
                         camera_rig.update(delta_time);
                     }
                 }
@@ -125,11 +515,400 @@ fn main() {
             }
         });
 
-        update_camera_rig(&pressed_keys, &mut render_ctx.camera_rig, delta_time);
+        //The movement actions are held instead, see update_camera
+        for action in triggered_actions.drain(..) {
+            match action {
+                Action::Quit => running = false,
+                Action::ToggleTriangleView => {
+                    render_ctx.render_settings.triangle_view =
+                        !render_ctx.render_settings.triangle_view;
+                }
+                Action::NextDebugView => {
+                    render_ctx.render_settings.debug_view =
+                        render_ctx.render_settings.debug_view.next();
+                }
+                Action::ToggleOverdraw => {
+                    //The heatmap would be covered by the composited eyes
+                    if !render_ctx.device.fragment_stores_and_atomics_supported {
+                        println!("Overdraw is not supported by the device");
+                    } else if render_ctx.stereo_pass.is_none() {
+                        render_ctx.overdraw_pass.enabled = !render_ctx.overdraw_pass.enabled;
+                    } else {
+                        println!("Overdraw is not supported with stereo rendering");
+                    }
+                }
+                Action::ToggleWireframe => {
+                    //Line polygon mode is an optional device feature
+                    if render_ctx.geometry_pass.fill_mode_non_solid_supported {
+                        render_ctx.render_settings.wireframe =
+                            !render_ctx.render_settings.wireframe;
+                    } else {
+                        println!("Wireframe rendering is not supported");
+                    }
+                }
+                Action::OpenSecondaryWindow => secondary_window_requested = true,
+                Action::ToggleCulling => {
+                    render_ctx.render_settings.culling = !render_ctx.render_settings.culling;
+                }
+                Action::ToggleInfiniteFarPlane => {
+                    render_ctx.render_settings.infinite_far_plane =
+                        !render_ctx.render_settings.infinite_far_plane;
+                }
+                Action::ToggleDepthPrepass => {
+                    render_ctx.render_settings.depth_prepass =
+                        !render_ctx.render_settings.depth_prepass;
+                }
+                Action::ToggleTaa => {
+                    render_ctx.render_settings.taa = !render_ctx.render_settings.taa;
+                }
+                Action::ToggleGrass => {
+                    render_ctx.render_settings.grass = !render_ctx.render_settings.grass;
+                    if render_ctx.render_settings.grass && !GrassPass::enabled(&render_ctx) {
+                        println!(
+                            "Grass is not drawn with MSAA, stereo rendering or the overdraw view"
+                        );
+                    }
+                }
+                Action::ToggleParticles => {
+                    render_ctx.render_settings.particles = !render_ctx.render_settings.particles;
+                    if render_ctx.render_settings.particles && !ParticlePass::enabled(&render_ctx) {
+                        println!("Particles are not drawn with MSAA, stereo rendering or the overdraw view");
+                    }
+                }
+                Action::ToggleClusteredLighting => {
+                    render_ctx.render_settings.clustered_lighting =
+                        !render_ctx.render_settings.clustered_lighting;
+                    if render_ctx.render_settings.clustered_lighting
+                        && !render_ctx.geometry_pass.lighting_enabled(&render_ctx)
+                    {
+                        println!(
+                            "Clustered lighting only shades the meshlet views of a single eye"
+                        );
+                    }
+                }
+                Action::ToggleTriangleCulling => {
+                    render_ctx.render_settings.triangle_culling =
+                        !render_ctx.render_settings.triangle_culling;
+                    if render_ctx.render_settings.triangle_culling
+                        && !render_ctx
+                            .geometry_pass
+                            .triangle_culling_enabled(&render_ctx)
+                    {
+                        println!("Triangles are only culled in the filled meshlet views without MSAA or stereo rendering");
+                    }
+                }
+                Action::ToggleConditionalRendering => {
+                    render_ctx.render_settings.conditional_rendering =
+                        !render_ctx.render_settings.conditional_rendering;
+                    if render_ctx.render_settings.conditional_rendering
+                        && !InstanceCullPass::enabled(&render_ctx)
+                    {
+                        println!("Conditional rendering is not supported by the device");
+                    }
+                }
+                Action::ToggleVariableShadingRate => {
+                    //Primitive shading rates are an optional device feature
+                    if render_ctx.geometry_pass.primitive_shading_rate_supported {
+                        render_ctx.render_settings.variable_shading_rate =
+                            !render_ctx.render_settings.variable_shading_rate;
+                    } else {
+                        println!("Primitive shading rates are not supported");
+                    }
+                }
+                Action::ToggleLodFreeze => {
+                    let camera_position = render_ctx.camera_rig.final_transform.position;
+                    render_ctx
+                        .render_settings
+                        .toggle_lod_freeze(camera_position);
+                }
+                Action::ToggleCullingFreeze => {
+                    let view_projection_matrix =
+                        renderer::compute_view_projection_matrix(&render_ctx);
+                    render_ctx
+                        .render_settings
+                        .toggle_culling_freeze(view_projection_matrix);
+                }
+                Action::ToggleAlphaToCoverage => {
+                    render_ctx.geometry_pass.toggle_alpha_to_coverage()
+                }
+                Action::ToggleSampleShading => render_ctx.geometry_pass.toggle_sample_shading(),
+                Action::PrintStats => {
+                    for (name, average) in render_ctx.pass_timings.averages() {
+                        println!(
+                            "{name}: {average:?} {:?}",
+                            render_ctx.pipeline_statistics.get(name)
+                        );
+                    }
+                    println!("{:?}", render_ctx.culling_stats);
+
+                    let allocator_statistics = render_ctx.device.allocator_statistics();
+                    for (memory_type_idx, memory_type_statistics) in
+                        allocator_statistics.memory_types.iter().enumerate()
+                    {
+                        if memory_type_statistics.block_count > 0 {
+                            println!(
+                                "Memory type {memory_type_idx} (heap {}): {memory_type_statistics}",
+                                memory_type_statistics.heap_idx
+                            );
+                        }
+                    }
+                }
+                Action::Screenshot => {
+                    render_ctx.capture_requested = true;
+                    screenshot_requested = true;
+                }
+                Action::IncreaseLodBias | Action::DecreaseLodBias => {
+                    let lod_bias = &mut render_ctx.render_settings.lod_bias;
+                    if action == Action::IncreaseLodBias {
+                        *lod_bias *= LOD_BIAS_STEP;
+                    } else {
+                        *lod_bias /= LOD_BIAS_STEP;
+                    }
+                    println!("LOD bias: {lod_bias:.2}");
+                }
+                Action::PrintDeviceInfo => println!("{}", render_ctx.device.device_info),
+                //Rebuilding the meshes would distort a running benchmark
+                Action::ToggleMeshletLayout if meshlet_benchmark.is_none() => {
+                    let layout = match render_ctx.scene_resources.meshlet_config.layout {
+                        MeshletLayout::Interleaved => MeshletLayout::StructOfArrays,
+                        MeshletLayout::StructOfArrays => MeshletLayout::Interleaved,
+                    };
+                    render_ctx.set_meshlet_config(MeshletConfig {
+                        layout,
+                        ..render_ctx.scene_resources.meshlet_config
+                    })?;
+                }
+                Action::NextLodSimplification if meshlet_benchmark.is_none() => {
+                    let meshlet_config = render_ctx.scene_resources.meshlet_config;
+                    let simplification = match meshlet_config.lod.simplification {
+                        LodSimplification::Sloppy => LodSimplification::SharedVertices,
+                        LodSimplification::SharedVertices => LodSimplification::Attributes,
+                        LodSimplification::Attributes => LodSimplification::Sloppy,
+                    };
+                    println!("LOD simplification: {simplification:?}");
+                    render_ctx.set_meshlet_config(MeshletConfig {
+                        lod: LodConfig {
+                            simplification,
+                            ..meshlet_config.lod
+                        },
+                        ..meshlet_config
+                    })?;
+                }
+                Action::StartBenchmark if meshlet_benchmark.is_none() => {
+                    meshlet_benchmark = Some(MeshletBenchmark::new(&mut render_ctx)?);
+                }
+                _ => {}
+            }
+        }
+
+        //Shows the scene from where the camera is now, while the frustum of the main window keeps moving
+        if secondary_window_requested {
+            secondary_window_requested = false;
+
+            let secondary_window = WindowBuilder::new()
+                .with_title("vk-ext-mesh-shader-example | frozen camera")
+                .with_inner_size(window.inner_size())
+                .build(&event_loop)
+                .context("Failed to create the secondary window")?;
+            match SecondaryWindow::new(&render_ctx, &secondary_window, render_ctx.camera()) {
+                Ok(target) => secondary_windows.push((target, secondary_window)),
+                Err(error) => eprintln!("Failed to open a window: {}", error.report()),
+            }
+        }
+
+        if minimized {
+            thread::sleep(MINIMIZED_POLL_INTERVAL);
+            continue
+        }
+
+        //Throttling would distort benchmarks and captures, which often run in the background
+        if !focused && meshlet_benchmark.is_none() && sequence_capture.is_none() {
+            thread::sleep(UNFOCUSED_FRAME_INTERVAL);
+        }
+
+        update_camera(
+            &input_bindings,
+            &pressed_inputs,
+            &mut render_ctx,
+            delta_time,
+        );
+
+        render_ctx.fixed_time = Some(frame_timer.time());
 
-        renderer::render_frame(&mut render_ctx, &window, &mut frame_index);
+        let capture_sequence_frame = if let Some(sequence_capture) = &mut sequence_capture {
+            sequence_capture.begin_frame()
+        } else {
+            false
+        };
+        render_ctx.capture_requested |= capture_sequence_frame;
+        let picking = render_ctx.pick_requested.is_some();
+
+        let result = if render_ctx.swapchain.out_of_date {
+            let size = window.inner_size();
+            render_ctx.resize(vk::Extent2D {
+                width: size.width,
+                height: size.height,
+            })
+        } else {
+            Ok(())
+        }
+        .and_then(|()| renderer::render_frame(&mut render_ctx, &mut frame_index))
+        .and_then(|()| {
+            secondary_windows
+                .iter_mut()
+                .try_for_each(|(target, secondary_window)| {
+                    if target.out_of_date() {
+                        //Minimized windows can't have a swapchain
+                        let size = secondary_window.inner_size();
+                        if size.width == 0 || size.height == 0 {
+                            return Ok(())
+                        }
+                        target.resize(&render_ctx, secondary_window)?;
+                    }
+                    renderer::render_secondary_window(&mut render_ctx, target)
+                })
+        });
+        match result {
+            Ok(()) => {}
+            Err(error)
+                if error.is_device_lost()
+                    && device_lost_recoveries < MAX_DEVICE_LOST_RECOVERIES =>
+            {
+                eprintln!("{}, creating the device again", error.report());
+                render_ctx.device.report_device_fault();
+                device_lost_recoveries += 1;
+
+                //They were created on the lost device
+                secondary_windows.clear();
+
+                render_ctx = render_ctx
+                    .recreate(RenderTarget::Window(&window))
+                    .unwrap_or_else(|error| {
+                        eprintln!("Failed to recreate the renderer: {}", error.report());
+                        process::exit(1);
+                    });
+                frame_index = 0;
+
+                if sequence_capture.is_some() {
+                    unsafe {
+                        render_ctx
+                            .scene_resources
+                            .mesh_collection
+                            .wait_until_loaded(&mut render_ctx.deletion_queue)
+                    }
+                    .context("Failed to load the meshes")?;
+                }
+            }
+            //The device can't be used anymore, so the context is leaked instead of waiting for it to become idle
+            Err(error) => {
+                eprintln!("Rendering failed: {}", error.report());
+                if error.is_device_lost() {
+                    render_ctx.device.report_device_fault();
+                }
+                process::exit(1);
+            }
+        }
+
+        if picking && render_ctx.pick_requested.is_none() {
+            match render_ctx.selected_instance {
+                Some(instance_idx) => {
+                    println!(
+                        "Selected instance {instance_idx} of mesh {}",
+                        render_ctx
+                            .scene_resources
+                            .instance_buffers
+                            .instance_animations[instance_idx as usize]
+                            .mesh_idx
+                    )
+                }
+                None => println!("Nothing selected"),
+            }
+        }
+
+        if let Some(captured_frame) = render_ctx.captured_frame.take() {
+            if screenshot_requested {
+                let path = capture::screenshot_path();
+                match captured_frame.save_png(&path) {
+                    Ok(()) => println!("Saved screenshot to {path}"),
+                    Err(error) => eprintln!("Failed to save screenshot to {path}: {error}"),
+                }
+                screenshot_requested = false;
+            }
+
+            if let (true, Some(sequence_capture)) = (capture_sequence_frame, &mut sequence_capture)
+            {
+                if let Err(error) = sequence_capture.write(&captured_frame) {
+                    eprintln!("Failed to capture frame: {error}");
+                    running = false;
+                }
+            }
+        }
+
+        if let Some(sequence_capture) = &sequence_capture {
+            if sequence_capture.finished() {
+                println!("Captured {} frames", sequence_capture.num_captured());
+                running = false;
+            }
+        }
+
+        if let Some(benchmark) = &mut meshlet_benchmark {
+            if !benchmark.update(&mut render_ctx)? {
+                meshlet_benchmark = None;
+                //Started from the command line, so there's nothing left to do
+                running &= !benchmark_mode;
+            }
+        }
+
+        //The wall clock time, a fixed time step would hide the hitches
+        hitch_detector.end_frame(frame_timer.frame_duration(), &render_ctx.pass_timings);
+
+        let now = Instant::now();
+
+        if now - last_hud_update > Duration::from_millis(500) {
+            let resource_counts = ResourceCounts::snapshot(MAX_DESCRIPTOR_SETS);
+            let heap_budgets = render_ctx.device.heap_budgets();
+            memory_budget_monitor.update(&heap_budgets);
+            let selection = render_ctx
+                .selected_instance
+                .map(|instance_idx| {
+                    format!(
+                        " | selected: instance {instance_idx}, mesh {}",
+                        render_ctx
+                            .scene_resources
+                            .instance_buffers
+                            .instance_animations[instance_idx as usize]
+                            .mesh_idx
+                    )
+                })
+                .unwrap_or_default();
+            window.set_title(&format!(
+                "vk-ext-mesh-shader-example | {} | {resource_counts} | VRAM {} | allocator: {}{selection}",
+                render_ctx.culling_stats,
+                memory_budget::device_local_budget(&heap_budgets),
+                render_ctx.device.allocator_statistics().total
+            ));
+            last_hud_update = now;
+        }
 
         frame_count += 1;
-        frame_index = frame_count % render_ctx.frames.len();
+        frame_index = frame_count % render_ctx.frame_resources.frames.len();
+    }
+
+    //Only what can be changed at runtime is written back, the rest keeps the loaded values. The size is the one last
+    //rendered at, a fullscreen window has the size of the monitor
+    let extent = render_ctx.swapchain.extent;
+    if extent != initial_extent && window.fullscreen().is_none() {
+        let window_size =
+            PhysicalSize::new(extent.width, extent.height).to_logical::<u32>(window.scale_factor());
+        settings.width = window_size.width;
+        settings.height = window_size.height;
+    }
+    settings.lod_bias = render_ctx.render_settings.lod_bias;
+    settings.culling = render_ctx.render_settings.culling;
+
+    if let Err(error) = settings.save(SETTINGS_PATH) {
+        eprintln!("Failed to save {SETTINGS_PATH}: {error}");
     }
+
+    Ok(())
 }
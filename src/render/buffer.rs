@@ -1,4 +1,4 @@
-use std::{slice, sync::Arc};
+use std::sync::Arc;
 
 use anyhow::Result;
 use ash::{vk, Device};
@@ -7,6 +7,11 @@ use vk_mem_alloc::{
     Allocation, AllocationCreateFlags, AllocationCreateInfo, AllocationInfo, Allocator, MemoryUsage,
 };
 
+use crate::render::{
+    resource_registry::{self, ResourceKind},
+    staging_belt::{StagingBelt, TransferQueue},
+};
+
 #[derive(Clone)]
 pub struct Buffer {
     pub buffer: vk::Buffer,
@@ -14,21 +19,137 @@ pub struct Buffer {
     pub allocation_info: AllocationInfo,
     pub device_address: vk::DeviceAddress,
     pub size: vk::DeviceSize,
-    _device: Arc<Device>,
+    device: Arc<Device>,
     allocator: Allocator,
 }
 
 impl Buffer {
+    //Can also be read through its device address, it's shared like new_device
     pub unsafe fn new_uniform(
         device: Arc<Device>,
         allocator: Allocator,
         size: usize,
+        sharing_family_indices: &[u32],
+    ) -> Result<Self> {
+        let mut buffer_create_info = vk::BufferCreateInfo::default().size(size as _).usage(
+            vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        );
+        if !sharing_family_indices.is_empty() {
+            buffer_create_info = buffer_create_info
+                .sharing_mode(vk::SharingMode::CONCURRENT)
+                .queue_family_indices(sharing_family_indices);
+        }
+
+        let (buffer, allocation, allocation_info) = vk_mem_alloc::create_buffer(
+            allocator,
+            &buffer_create_info,
+            &AllocationCreateInfo {
+                flags: AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE
+                    | AllocationCreateFlags::MAPPED,
+                usage: MemoryUsage::AUTO_PREFER_HOST,
+                ..Default::default()
+            },
+        )?;
+        resource_registry::track_created(ResourceKind::Buffer);
+
+        let device_address = device
+            .get_buffer_device_address(&vk::BufferDeviceAddressInfo::default().buffer(buffer));
+
+        Ok(Buffer {
+            buffer,
+            allocation,
+            allocation_info,
+            device_address,
+            size: size as _,
+            device,
+            allocator,
+        })
+    }
+
+    //Written by shaders through its device address or by copies and read back on the host once the frame finished
+    pub unsafe fn new_readback(
+        device: Arc<Device>,
+        allocator: Allocator,
+        size: usize,
+    ) -> Result<Self> {
+        let (buffer, allocation, allocation_info) = vk_mem_alloc::create_buffer(
+            allocator,
+            &vk::BufferCreateInfo::default().size(size as _).usage(
+                vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                    | vk::BufferUsageFlags::TRANSFER_DST,
+            ),
+            &AllocationCreateInfo {
+                flags: AllocationCreateFlags::HOST_ACCESS_RANDOM | AllocationCreateFlags::MAPPED,
+                usage: MemoryUsage::AUTO_PREFER_HOST,
+                required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
+                    | vk::MemoryPropertyFlags::HOST_COHERENT,
+                ..Default::default()
+            },
+        )?;
+        resource_registry::track_created(ResourceKind::Buffer);
+
+        let device_address = device
+            .get_buffer_device_address(&vk::BufferDeviceAddressInfo::default().buffer(buffer));
+
+        Ok(Buffer {
+            buffer,
+            allocation,
+            allocation_info,
+            device_address,
+            size: size as _,
+            device,
+            allocator,
+        })
+    }
+
+    //Written by the host every frame and read by shaders through its device address
+    pub unsafe fn new_upload(
+        device: Arc<Device>,
+        allocator: Allocator,
+        size: usize,
+    ) -> Result<Self> {
+        let (buffer, allocation, allocation_info) = vk_mem_alloc::create_buffer(
+            allocator,
+            &vk::BufferCreateInfo::default().size(size as _).usage(
+                vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            ),
+            &AllocationCreateInfo {
+                flags: AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE
+                    | AllocationCreateFlags::MAPPED,
+                usage: MemoryUsage::AUTO_PREFER_HOST,
+                required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
+                    | vk::MemoryPropertyFlags::HOST_COHERENT,
+                ..Default::default()
+            },
+        )?;
+        resource_registry::track_created(ResourceKind::Buffer);
+
+        let device_address = device
+            .get_buffer_device_address(&vk::BufferDeviceAddressInfo::default().buffer(buffer));
+
+        Ok(Buffer {
+            buffer,
+            allocation,
+            allocation_info,
+            device_address,
+            size: size as _,
+            device,
+            allocator,
+        })
+    }
+
+    //Host visible source of copies, see StagingBelt
+    pub unsafe fn new_staging(
+        device: Arc<Device>,
+        allocator: Allocator,
+        size: usize,
     ) -> Result<Self> {
         let (buffer, allocation, allocation_info) = vk_mem_alloc::create_buffer(
             allocator,
             &vk::BufferCreateInfo::default()
                 .size(size as _)
-                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER),
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC),
             &AllocationCreateInfo {
                 flags: AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE
                     | AllocationCreateFlags::MAPPED,
@@ -36,6 +157,7 @@ impl Buffer {
                 ..Default::default()
             },
         )?;
+        resource_registry::track_created(ResourceKind::Buffer);
 
         Ok(Buffer {
             buffer,
@@ -43,94 +165,83 @@ impl Buffer {
             allocation_info,
             device_address: 0,
             size: size as _,
-            _device: device,
+            device,
             allocator,
         })
     }
 
-    pub unsafe fn new_device_local<T: Pod>(
+    //Device local storage buffer which is filled by upload, it's shared between the given queue families if there are any
+    pub unsafe fn new_device(
         device: Arc<Device>,
-        queue: vk::Queue,
         allocator: Allocator,
-        data: &[T],
+        size: usize,
+        sharing_family_indices: &[u32],
     ) -> Result<Self> {
-        let size = std::mem::size_of_val(data);
-
-        let (staging_buffer, staging_buffer_allocation, staging_buffer_allocation_info) =
-            vk_mem_alloc::create_buffer(
-                allocator,
-                &vk::BufferCreateInfo::default()
-                    .size(size as _)
-                    .usage(vk::BufferUsageFlags::TRANSFER_SRC),
-                &AllocationCreateInfo {
-                    flags: AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE
-                        | AllocationCreateFlags::MAPPED,
-                    usage: MemoryUsage::AUTO_PREFER_HOST,
-                    ..Default::default()
-                },
-            )?;
-
-        libc::memcpy(
-            staging_buffer_allocation_info.mapped_data.cast(),
-            data.as_ptr().cast(),
-            size,
+        let mut buffer_create_info = vk::BufferCreateInfo::default().size(size as _).usage(
+            vk::BufferUsageFlags::TRANSFER_DST
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
         );
+        if !sharing_family_indices.is_empty() {
+            buffer_create_info = buffer_create_info
+                .sharing_mode(vk::SharingMode::CONCURRENT)
+                .queue_family_indices(sharing_family_indices);
+        }
 
         let (buffer, allocation, allocation_info) = vk_mem_alloc::create_buffer(
             allocator,
-            &vk::BufferCreateInfo::default().size(size as _).usage(
-                vk::BufferUsageFlags::TRANSFER_DST
-                    | vk::BufferUsageFlags::STORAGE_BUFFER
-                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-            ),
+            &buffer_create_info,
             &AllocationCreateInfo {
                 usage: MemoryUsage::AUTO_PREFER_DEVICE,
                 ..Default::default()
             },
         )?;
+        resource_registry::track_created(ResourceKind::Buffer);
 
         let device_address = device
             .get_buffer_device_address(&vk::BufferDeviceAddressInfo::default().buffer(buffer));
 
-        let command_pool =
-            device.create_command_pool(&vk::CommandPoolCreateInfo::default(), None)?;
-        let command_buffer = device.allocate_command_buffers(
-            &vk::CommandBufferAllocateInfo::default()
-                .command_pool(command_pool)
-                .command_buffer_count(1),
-        )?[0];
-        let fence = device.create_fence(&vk::FenceCreateInfo::default(), None)?;
-
-        device
-            .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
-            .unwrap();
-        device.cmd_copy_buffer(
-            command_buffer,
-            staging_buffer,
+        Ok(Buffer {
             buffer,
-            slice::from_ref(&vk::BufferCopy::default().size(size as _)),
-        );
-        device.end_command_buffer(command_buffer).unwrap();
-
-        device
-            .queue_submit(
-                queue,
-                slice::from_ref(
-                    &vk::SubmitInfo::default().command_buffers(slice::from_ref(&command_buffer)),
-                ),
-                fence,
-            )
-            .unwrap();
+            allocation,
+            allocation_info,
+            device_address,
+            size: size as _,
+            device,
+            allocator,
+        })
+    }
 
-        device
-            .wait_for_fences(slice::from_ref(&fence), true, u64::MAX)
-            .unwrap();
+    //Written by shaders and read as the predicate of conditional rendering, shared like new_device
+    pub unsafe fn new_predicate(
+        device: Arc<Device>,
+        allocator: Allocator,
+        size: usize,
+        sharing_family_indices: &[u32],
+    ) -> Result<Self> {
+        let mut buffer_create_info = vk::BufferCreateInfo::default().size(size as _).usage(
+            vk::BufferUsageFlags::CONDITIONAL_RENDERING_EXT
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        );
+        if !sharing_family_indices.is_empty() {
+            buffer_create_info = buffer_create_info
+                .sharing_mode(vk::SharingMode::CONCURRENT)
+                .queue_family_indices(sharing_family_indices);
+        }
 
-        device.destroy_fence(fence, None);
-        device.free_command_buffers(command_pool, slice::from_ref(&command_buffer));
-        device.destroy_command_pool(command_pool, None);
+        let (buffer, allocation, allocation_info) = vk_mem_alloc::create_buffer(
+            allocator,
+            &buffer_create_info,
+            &AllocationCreateInfo {
+                usage: MemoryUsage::AUTO_PREFER_DEVICE,
+                ..Default::default()
+            },
+        )?;
+        resource_registry::track_created(ResourceKind::Buffer);
 
-        vk_mem_alloc::destroy_buffer(allocator, staging_buffer, staging_buffer_allocation);
+        let device_address = device
+            .get_buffer_device_address(&vk::BufferDeviceAddressInfo::default().buffer(buffer));
 
         Ok(Buffer {
             buffer,
@@ -138,10 +249,72 @@ impl Buffer {
             allocation_info,
             device_address,
             size: size as _,
-            _device: device,
+            device,
             allocator,
         })
     }
+
+    pub unsafe fn new_device_local<T: Pod>(
+        device: Arc<Device>,
+        queue: TransferQueue,
+        allocator: Allocator,
+        data: &[T],
+        sharing_family_indices: &[u32],
+    ) -> Result<Self> {
+        let buffer = Self::new_device(
+            device,
+            allocator,
+            std::mem::size_of_val(data),
+            sharing_family_indices,
+        )?;
+        buffer.upload(queue, 0, data)?;
+        Ok(buffer)
+    }
+
+    //Copies data to offset through its own staging belt and blocks until the copy finished
+    pub unsafe fn upload<T: Pod>(
+        &self,
+        queue: TransferQueue,
+        offset: vk::DeviceSize,
+        data: &[T],
+    ) -> Result<()> {
+        let mut staging_belt = StagingBelt::new(
+            self.device.clone(),
+            queue,
+            self.allocator,
+            std::mem::size_of_val(data).max(1),
+        )?;
+        staging_belt.upload(self, offset, data)?;
+        staging_belt.flush()
+    }
+
+    //Only valid for buffers which were created mapped
+    #[inline]
+    pub unsafe fn read<T: Pod>(&self) -> T {
+        self.allocation_info
+            .mapped_data
+            .cast::<T>()
+            .read_unaligned()
+    }
+
+    #[inline]
+    pub unsafe fn write<T: Pod>(&self, value: &T) {
+        self.allocation_info
+            .mapped_data
+            .cast::<T>()
+            .write_unaligned(*value)
+    }
+
+    #[inline]
+    pub unsafe fn write_at<T: Pod>(&self, offset: usize, value: &T) {
+        debug_assert!(offset + std::mem::size_of::<T>() <= self.size as usize);
+        self.allocation_info
+            .mapped_data
+            .cast::<u8>()
+            .add(offset)
+            .cast::<T>()
+            .write_unaligned(*value)
+    }
 }
 
 impl Drop for Buffer {
@@ -150,5 +323,6 @@ impl Drop for Buffer {
         unsafe {
             vk_mem_alloc::destroy_buffer(self.allocator, self.buffer, self.allocation);
         }
+        resource_registry::track_destroyed(ResourceKind::Buffer);
     }
 }
@@ -0,0 +1,109 @@
+use std::{mem, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use bytemuck::Pod;
+use vk_mem_alloc::Allocator;
+
+use crate::render::{
+    buffer::Buffer,
+    staging_belt::{StagingBelt, TransferQueue, STAGING_BELT_SIZE},
+};
+
+//Data which doesn't fit into a block gets a block of its own
+pub const ARENA_BLOCK_SIZE: usize = 64 << 20;
+//Enough for every type the shaders read through device addresses
+const ARENA_ALIGNMENT: usize = 16;
+
+//A part of one of the arena blocks, keeps the block alive
+#[derive(Clone)]
+pub struct BufferRange {
+    pub buffer: Arc<Buffer>,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    pub device_address: vk::DeviceAddress,
+}
+
+struct ArenaBlock {
+    buffer: Arc<Buffer>,
+    used: usize,
+}
+
+//Suballocates device local storage buffers, ranges are never freed individually but with the whole arena
+pub struct BufferArena {
+    blocks: Vec<ArenaBlock>,
+    staging_belt: StagingBelt,
+    //Written on the transfer queue and read on the direct and compute queues
+    sharing_family_indices: Vec<u32>,
+    transfer_queue: TransferQueue,
+    allocator: Allocator,
+    device: Arc<Device>,
+}
+
+impl BufferArena {
+    pub unsafe fn new(
+        device: Arc<Device>,
+        transfer_queue: TransferQueue,
+        allocator: Allocator,
+        sharing_family_indices: Vec<u32>,
+    ) -> Result<Self> {
+        Ok(Self {
+            blocks: Vec::new(),
+            staging_belt: StagingBelt::new(
+                device.clone(),
+                transfer_queue,
+                allocator,
+                STAGING_BELT_SIZE,
+            )?,
+            sharing_family_indices,
+            transfer_queue,
+            allocator,
+            device,
+        })
+    }
+
+    unsafe fn allocate(&mut self, size: usize) -> Result<(Arc<Buffer>, usize)> {
+        let block_idx = match self.blocks.iter().position(|block| {
+            block.used.next_multiple_of(ARENA_ALIGNMENT) + size <= block.buffer.size as usize
+        }) {
+            Some(block_idx) => block_idx,
+            None => {
+                let buffer = Buffer::new_device(
+                    self.device.clone(),
+                    self.allocator,
+                    size.max(ARENA_BLOCK_SIZE),
+                    &self.sharing_family_indices,
+                )?;
+                self.blocks.push(ArenaBlock {
+                    buffer: Arc::new(buffer),
+                    used: 0,
+                });
+                self.blocks.len() - 1
+            }
+        };
+
+        let block = &mut self.blocks[block_idx];
+        let offset = block.used.next_multiple_of(ARENA_ALIGNMENT);
+        block.used = offset + size;
+        Ok((block.buffer.clone(), offset))
+    }
+
+    //The range must not be read before the next flush
+    pub unsafe fn upload<T: Pod>(&mut self, data: &[T]) -> Result<BufferRange> {
+        let size = mem::size_of_val(data);
+        let (buffer, offset) = self.allocate(size)?;
+        self.staging_belt.upload(&buffer, offset as _, data)?;
+
+        Ok(BufferRange {
+            device_address: buffer.device_address + offset as vk::DeviceAddress,
+            buffer,
+            offset: offset as _,
+            size: size as _,
+        })
+    }
+
+    #[inline]
+    pub unsafe fn flush(&mut self) -> Result<()> {
+        self.staging_belt.flush()
+    }
+}
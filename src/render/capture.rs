@@ -0,0 +1,274 @@
+use std::{
+    fs,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    slice,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+use ash::vk;
+
+use crate::render::{buffer::Buffer, render_ctx::RenderCtx};
+
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    //Tightly packed RGBA8 rows
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum CaptureOutput {
+    //Numbered PNGs in this directory
+    Png(PathBuf),
+    //Raw frames piped to ffmpeg, which encodes them to this file
+    Ffmpeg(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub struct CaptureConfig {
+    pub output: Option<CaptureOutput>,
+    pub interval: usize,
+    pub max_frames: Option<usize>,
+    pub frame_rate: u32,
+}
+
+impl Default for CaptureConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            output: None,
+            interval: 1,
+            max_frames: None,
+            frame_rate: 60,
+        }
+    }
+}
+
+//The capture options of the command line
+#[derive(clap::Args, Debug)]
+pub struct CaptureArgs {
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "capture_ffmpeg",
+        help = "Dump rendered frames to numbered PNGs in this directory"
+    )]
+    capture_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Pipe rendered frames to ffmpeg, which encodes them to this file"
+    )]
+    capture_ffmpeg: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Only capture every nth rendered frame"
+    )]
+    capture_every: Option<u32>,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Quit after this many frames were captured"
+    )]
+    capture_frames: Option<usize>,
+    #[arg(long, value_name = "FPS", help = "Frame rate of the encoded video")]
+    capture_fps: Option<u32>,
+}
+
+impl CaptureArgs {
+    pub fn apply(self, config: &mut CaptureConfig) {
+        if let Some(dir) = self.capture_dir {
+            config.output = Some(CaptureOutput::Png(dir));
+        }
+        if let Some(file) = self.capture_ffmpeg {
+            config.output = Some(CaptureOutput::Ffmpeg(file));
+        }
+        if let Some(interval) = self.capture_every {
+            config.interval = interval as _;
+        }
+        if let Some(max_frames) = self.capture_frames {
+            config.max_frames = Some(max_frames);
+        }
+        if let Some(frame_rate) = self.capture_fps {
+            config.frame_rate = frame_rate;
+        }
+    }
+}
+
+//Captures every nth frame of the capture mode, the camera and animations run with a fixed time step meanwhile
+pub struct FrameSequenceCapture {
+    config: CaptureConfig,
+    output: CaptureOutput,
+    frame: usize,
+    num_captured: usize,
+    ffmpeg: Option<(Child, u32, u32)>,
+}
+
+impl FrameSequenceCapture {
+    pub fn new(config: CaptureConfig) -> Result<Option<Self>> {
+        let Some(output) = config.output.clone() else {
+            return Ok(None)
+        };
+
+        if let CaptureOutput::Png(dir) = &output {
+            fs::create_dir_all(dir)?;
+        }
+
+        Ok(Some(Self {
+            config,
+            output,
+            frame: 0,
+            num_captured: 0,
+            ffmpeg: None,
+        }))
+    }
+
+    //Has to be called once before every rendered frame, returns true if the frame has to be captured
+    pub fn begin_frame(&mut self) -> bool {
+        let capture = self.frame % self.config.interval == 0;
+        self.frame += 1;
+        capture
+    }
+
+    pub fn write(&mut self, captured_frame: &RgbaImage) -> Result<()> {
+        match &self.output {
+            CaptureOutput::Png(dir) => {
+                captured_frame.save_png(dir.join(format!("frame_{:06}.png", self.num_captured)))?
+            }
+            CaptureOutput::Ffmpeg(path) => {
+                if self.ffmpeg.is_none() {
+                    let child = Command::new("ffmpeg")
+                        .args(["-loglevel", "error", "-y", "-f", "rawvideo"])
+                        .args(["-pixel_format", "rgba", "-video_size"])
+                        .arg(format!(
+                            "{}x{}",
+                            captured_frame.width, captured_frame.height
+                        ))
+                        .arg("-framerate")
+                        .arg(self.config.frame_rate.to_string())
+                        .args(["-i", "-", "-pix_fmt", "yuv420p"])
+                        .arg(path)
+                        .stdin(Stdio::piped())
+                        .spawn()
+                        .map_err(|error| anyhow!("Failed to start ffmpeg: {error}"))?;
+                    self.ffmpeg = Some((child, captured_frame.width, captured_frame.height));
+                }
+
+                let (child, width, height) = self.ffmpeg.as_mut().unwrap();
+                if (*width, *height) != (captured_frame.width, captured_frame.height) {
+                    bail!("The window size changed while capturing to ffmpeg")
+                }
+                child
+                    .stdin
+                    .as_mut()
+                    .unwrap()
+                    .write_all(&captured_frame.pixels)?;
+            }
+        }
+
+        self.num_captured += 1;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn finished(&self) -> bool {
+        self.config
+            .max_frames
+            .map_or(false, |max_frames| self.num_captured >= max_frames)
+    }
+
+    #[inline]
+    pub fn num_captured(&self) -> usize {
+        self.num_captured
+    }
+}
+
+impl Drop for FrameSequenceCapture {
+    fn drop(&mut self) {
+        //Closing stdin lets ffmpeg finish the file
+        if let Some((mut child, _, _)) = self.ffmpeg.take() {
+            drop(child.stdin.take());
+            if let Err(error) = child.wait() {
+                eprintln!("Failed to wait for ffmpeg: {error}");
+            }
+        }
+    }
+}
+
+pub fn screenshot_path() -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "screenshot_{}_{:03}.png",
+        timestamp.as_secs(),
+        timestamp.subsec_millis()
+    )
+}
+
+//The image has to be in TRANSFER_SRC_OPTIMAL
+pub unsafe fn record_copy(
+    ctx: &RenderCtx,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    extent: vk::Extent2D,
+    buffer: &Buffer,
+) {
+    let device_loader = &ctx.device.device_loader;
+    let image = ctx.swapchain.images[image_index];
+
+    let buffer_image_copy = vk::BufferImageCopy::default()
+        .image_subresource(
+            vk::ImageSubresourceLayers::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .layer_count(1),
+        )
+        .image_extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        });
+
+    device_loader.cmd_copy_image_to_buffer(
+        command_buffer,
+        image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        buffer.buffer,
+        slice::from_ref(&buffer_image_copy),
+    );
+}
+
+//Only call this after the copy finished, the swapchain is BGRA so red and blue are swapped
+pub unsafe fn read_captured_frame(buffer: &Buffer, extent: vk::Extent2D) -> RgbaImage {
+    let size = (extent.width * extent.height * 4) as usize;
+    let mut pixels =
+        slice::from_raw_parts(buffer.allocation_info.mapped_data.cast::<u8>(), size).to_vec();
+    pixels
+        .chunks_exact_mut(4)
+        .for_each(|pixel| pixel.swap(0, 2));
+
+    RgbaImage {
+        width: extent.width,
+        height: extent.height,
+        pixels,
+    }
+}
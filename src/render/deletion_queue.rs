@@ -0,0 +1,111 @@
+use std::{slice, sync::Arc};
+
+use ash::{vk, Device};
+
+use crate::render::{
+    buffer::Buffer,
+    frame::NUM_FRAMES,
+    resource_registry::{self, ResourceKind},
+    utils,
+};
+
+//Holds resources which might still be used by frames in flight until every one of them finished
+pub struct DeletionQueue {
+    //The pipeline and the number of frames which still have to finish
+    pipelines: Vec<(vk::Pipeline, usize)>,
+    //Dropping the buffer destroys it
+    buffers: Vec<(Buffer, usize)>,
+    //The set and the pool it was allocated from
+    descriptor_sets: Vec<(vk::DescriptorSet, vk::DescriptorPool, usize)>,
+    device: Arc<Device>,
+}
+
+impl Drop for DeletionQueue {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { self.clear() }
+    }
+}
+
+unsafe fn free_descriptor_set(
+    device: &Device,
+    descriptor_set: vk::DescriptorSet,
+    descriptor_pool: vk::DescriptorPool,
+) {
+    device
+        .free_descriptor_sets(descriptor_pool, slice::from_ref(&descriptor_set))
+        .unwrap();
+    resource_registry::track_destroyed(ResourceKind::DescriptorSet);
+}
+
+impl DeletionQueue {
+    #[inline]
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
+            pipelines: Vec::new(),
+            buffers: Vec::new(),
+            descriptor_sets: Vec::new(),
+            device,
+        }
+    }
+
+    #[inline]
+    pub fn push_pipeline(&mut self, pipeline: vk::Pipeline) {
+        self.pipelines.push((pipeline, NUM_FRAMES));
+    }
+
+    #[inline]
+    pub fn push_buffer(&mut self, buffer: Buffer) {
+        self.buffers.push((buffer, NUM_FRAMES));
+    }
+
+    #[inline]
+    pub fn push_descriptor_set(
+        &mut self,
+        descriptor_set: vk::DescriptorSet,
+        descriptor_pool: vk::DescriptorPool,
+    ) {
+        self.descriptor_sets
+            .push((descriptor_set, descriptor_pool, NUM_FRAMES));
+    }
+
+    //Deletes everything right away, the device has to be idle
+    pub unsafe fn clear(&mut self) {
+        self.pipelines
+            .drain(..)
+            .for_each(|(pipeline, _)| utils::pipelines::destroy(&self.device, pipeline));
+        self.buffers.clear();
+        self.descriptor_sets
+            .drain(..)
+            .for_each(|(descriptor_set, descriptor_pool, _)| {
+                free_descriptor_set(&self.device, descriptor_set, descriptor_pool)
+            });
+    }
+
+    //Has to be called once per frame, after waiting for the fence of the frame
+    pub unsafe fn advance(&mut self) {
+        self.pipelines.retain_mut(|(pipeline, frames_left)| {
+            *frames_left -= 1;
+            if *frames_left > 0 {
+                return true
+            }
+
+            utils::pipelines::destroy(&self.device, *pipeline);
+            false
+        });
+        self.buffers.retain_mut(|(_, frames_left)| {
+            *frames_left -= 1;
+            *frames_left > 0
+        });
+        self.descriptor_sets
+            .retain_mut(|(descriptor_set, descriptor_pool, frames_left)| {
+                *frames_left -= 1;
+                if *frames_left > 0 {
+                    return true
+                }
+
+                free_descriptor_set(&self.device, *descriptor_set, *descriptor_pool);
+                false
+            });
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    fmt::Write as _,
+    fs, mem, ptr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use ash::{vk, Device, Instance};
+
+use crate::render::device_info;
+
+//VK_EXT_device_fault, tells why the device was lost
+pub struct DeviceFault {
+    fp: vk::ExtDeviceFaultFn,
+    device: vk::Device,
+    vendor_binary_supported: bool,
+}
+
+pub struct DeviceFaultReport {
+    pub description: String,
+    pub address_infos: Vec<vk::DeviceFaultAddressInfoEXT>,
+    pub vendor_infos: Vec<vk::DeviceFaultVendorInfoEXT>,
+    //Only understood by the vendor's tools, it's written to a file of its own
+    pub vendor_binary: Vec<u8>,
+}
+
+impl DeviceFault {
+    pub fn new(instance: &Instance, device: &Device, vendor_binary_supported: bool) -> Self {
+        Self {
+            fp: vk::ExtDeviceFaultFn::load(|name| unsafe {
+                mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }),
+            device: device.handle(),
+            vendor_binary_supported,
+        }
+    }
+
+    //Only valid once the device was lost
+    pub unsafe fn query(&self) -> Result<DeviceFaultReport> {
+        let mut counts = vk::DeviceFaultCountsEXT::default();
+        (self.fp.get_device_fault_info_ext)(self.device, &mut counts, ptr::null_mut()).result()?;
+        if !self.vendor_binary_supported {
+            counts.vendor_binary_size = 0;
+        }
+
+        let mut address_infos =
+            vec![vk::DeviceFaultAddressInfoEXT::default(); counts.address_info_count as usize];
+        let mut vendor_infos =
+            vec![vk::DeviceFaultVendorInfoEXT::default(); counts.vendor_info_count as usize];
+        let mut vendor_binary = vec![0u8; counts.vendor_binary_size as usize];
+
+        let mut info = vk::DeviceFaultInfoEXT::default();
+        info.p_address_infos = address_infos.as_mut_ptr();
+        info.p_vendor_infos = vendor_infos.as_mut_ptr();
+        info.p_vendor_binary_data = vendor_binary.as_mut_ptr().cast();
+
+        //Incomplete results are still worth reporting, the counts say how much was written
+        match (self.fp.get_device_fault_info_ext)(self.device, &mut counts, &mut info) {
+            vk::Result::SUCCESS | vk::Result::INCOMPLETE => {}
+            result => return Err(result.into()),
+        }
+        address_infos.truncate(counts.address_info_count as usize);
+        vendor_infos.truncate(counts.vendor_info_count as usize);
+        vendor_binary.truncate(counts.vendor_binary_size as usize);
+
+        Ok(DeviceFaultReport {
+            description: device_info::string_from_c_chars(&info.description),
+            address_infos,
+            vendor_infos,
+            vendor_binary,
+        })
+    }
+}
+
+impl DeviceFaultReport {
+    //Returns the path of the report, the vendor binary is written next to it
+    pub fn write(&self, device_name: &str) -> Result<String> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = format!("device_fault_{timestamp}.txt");
+
+        let mut report = format!("Device lost on {device_name}\n{}\n", self.description);
+
+        writeln!(report, "\nAddresses:")?;
+        for address_info in &self.address_infos {
+            //The fault happened somewhere in the naturally aligned range of precision bytes around the address
+            let precision = address_info.address_precision.max(1);
+            let start = address_info.reported_address & !(precision - 1);
+            writeln!(
+                report,
+                "  {:?} {:#x} in {start:#x}..{:#x}",
+                address_info.address_type,
+                address_info.reported_address,
+                start + precision
+            )?;
+        }
+
+        writeln!(report, "\nVendor infos:")?;
+        for vendor_info in &self.vendor_infos {
+            writeln!(
+                report,
+                "  {}: code {:#x}, data {:#x}",
+                unsafe { device_info::string_from_c_chars(&vendor_info.description) },
+                vendor_info.vendor_fault_code,
+                vendor_info.vendor_fault_data
+            )?;
+        }
+
+        if !self.vendor_binary.is_empty() {
+            let binary_path = format!("device_fault_{timestamp}.bin");
+            fs::write(&binary_path, &self.vendor_binary)?;
+            writeln!(
+                report,
+                "\nVendor binary: {binary_path} ({} bytes)",
+                self.vendor_binary.len()
+            )?;
+        }
+
+        fs::write(&path, report)?;
+        Ok(path)
+    }
+}
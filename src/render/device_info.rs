@@ -0,0 +1,234 @@
+use std::{
+    ffi::{c_char, CStr},
+    fmt,
+};
+
+use ash::vk;
+
+use crate::render::mesh::{MeshletConfig, MESHLET_GROUP_SIZE};
+
+const VENDOR_ID_NVIDIA: u32 = 0x10de;
+const VENDOR_ID_INTEL: u32 = 0x8086;
+
+#[derive(Clone, Debug)]
+pub struct DeviceProperty {
+    pub name: &'static str,
+    pub value: String,
+    //What the example itself uses for this limit, if anything
+    pub used: Option<String>,
+    pub exceeded: bool,
+}
+
+impl DeviceProperty {
+    fn new(name: &'static str, value: impl fmt::Debug) -> Self {
+        Self {
+            name,
+            value: format!("{value:?}"),
+            used: None,
+            exceeded: false,
+        }
+    }
+
+    fn with_used(mut self, used: u32, limit: u32) -> Self {
+        self.used = Some(used.to_string());
+        self.exceeded = used > limit;
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub device_name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub api_version: String,
+    pub driver_name: String,
+    pub driver_info: String,
+    pub driver_version: String,
+    pub mesh_shader_properties: Vec<DeviceProperty>,
+}
+
+pub unsafe fn string_from_c_chars(chars: &[c_char]) -> String {
+    CStr::from_ptr(chars.as_ptr())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn format_api_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
+//Drivers are free to encode their version, so only the common vendor schemes are known
+fn format_driver_version(vendor_id: u32, version: u32) -> String {
+    match vendor_id {
+        VENDOR_ID_NVIDIA => {
+            format!(
+                "{}.{}.{}.{}",
+                version >> 22,
+                (version >> 14) & 0xff,
+                (version >> 6) & 0xff,
+                version & 0x3f
+            )
+        }
+        VENDOR_ID_INTEL if cfg!(windows) => format!("{}.{}", version >> 14, version & 0x3fff),
+        _ => format_api_version(version),
+    }
+}
+
+impl DeviceInfo {
+    pub fn new(
+        properties: &vk::PhysicalDeviceProperties,
+        vulkan_12_properties: &vk::PhysicalDeviceVulkan12Properties,
+        mesh_shader_properties: &vk::PhysicalDeviceMeshShaderPropertiesEXT,
+        meshlet_config: &MeshletConfig,
+    ) -> Self {
+        let p = mesh_shader_properties;
+
+        //The mesh shader runs with the preferred size and the task shader with one meshlet group
+        let mesh_local_size = p.max_preferred_mesh_work_group_invocations;
+        let task_local_size = MESHLET_GROUP_SIZE as u32;
+        let task_payload_size = (MESHLET_GROUP_SIZE * std::mem::size_of::<u32>()) as u32;
+
+        let mesh_shader_properties = vec![
+            DeviceProperty::new(
+                "max_task_work_group_total_count",
+                p.max_task_work_group_total_count,
+            ),
+            DeviceProperty::new("max_task_work_group_count", p.max_task_work_group_count),
+            DeviceProperty::new(
+                "max_task_work_group_invocations",
+                p.max_task_work_group_invocations,
+            )
+            .with_used(task_local_size, p.max_task_work_group_invocations),
+            DeviceProperty::new("max_task_work_group_size", p.max_task_work_group_size)
+                .with_used(task_local_size, p.max_task_work_group_size[0]),
+            DeviceProperty::new("max_task_payload_size", p.max_task_payload_size)
+                .with_used(task_payload_size, p.max_task_payload_size),
+            DeviceProperty::new("max_task_shared_memory_size", p.max_task_shared_memory_size),
+            DeviceProperty::new(
+                "max_task_payload_and_shared_memory_size",
+                p.max_task_payload_and_shared_memory_size,
+            ),
+            DeviceProperty::new(
+                "max_mesh_work_group_total_count",
+                p.max_mesh_work_group_total_count,
+            ),
+            DeviceProperty::new("max_mesh_work_group_count", p.max_mesh_work_group_count)
+                .with_used(task_local_size, p.max_mesh_work_group_count[0]),
+            DeviceProperty::new(
+                "max_mesh_work_group_invocations",
+                p.max_mesh_work_group_invocations,
+            )
+            .with_used(mesh_local_size, p.max_mesh_work_group_invocations),
+            DeviceProperty::new("max_mesh_work_group_size", p.max_mesh_work_group_size)
+                .with_used(mesh_local_size, p.max_mesh_work_group_size[0]),
+            DeviceProperty::new("max_mesh_shared_memory_size", p.max_mesh_shared_memory_size),
+            DeviceProperty::new(
+                "max_mesh_payload_and_shared_memory_size",
+                p.max_mesh_payload_and_shared_memory_size,
+            ),
+            DeviceProperty::new("max_mesh_output_memory_size", p.max_mesh_output_memory_size),
+            DeviceProperty::new(
+                "max_mesh_payload_and_output_memory_size",
+                p.max_mesh_payload_and_output_memory_size,
+            ),
+            DeviceProperty::new("max_mesh_output_components", p.max_mesh_output_components),
+            DeviceProperty::new("max_mesh_output_vertices", p.max_mesh_output_vertices)
+                .with_used(meshlet_config.max_vertices as _, p.max_mesh_output_vertices),
+            DeviceProperty::new("max_mesh_output_primitives", p.max_mesh_output_primitives)
+                .with_used(
+                    meshlet_config.max_triangles as _,
+                    p.max_mesh_output_primitives,
+                ),
+            DeviceProperty::new("max_mesh_output_layers", p.max_mesh_output_layers),
+            DeviceProperty::new(
+                "max_mesh_multiview_view_count",
+                p.max_mesh_multiview_view_count,
+            ),
+            DeviceProperty::new(
+                "mesh_output_per_vertex_granularity",
+                p.mesh_output_per_vertex_granularity,
+            ),
+            DeviceProperty::new(
+                "mesh_output_per_primitive_granularity",
+                p.mesh_output_per_primitive_granularity,
+            ),
+            DeviceProperty::new(
+                "max_preferred_task_work_group_invocations",
+                p.max_preferred_task_work_group_invocations,
+            )
+            .with_used(task_local_size, p.max_preferred_task_work_group_invocations),
+            DeviceProperty::new(
+                "max_preferred_mesh_work_group_invocations",
+                p.max_preferred_mesh_work_group_invocations,
+            )
+            .with_used(mesh_local_size, p.max_preferred_mesh_work_group_invocations),
+            DeviceProperty::new(
+                "prefers_local_invocation_vertex_output",
+                p.prefers_local_invocation_vertex_output == vk::TRUE,
+            ),
+            DeviceProperty::new(
+                "prefers_local_invocation_primitive_output",
+                p.prefers_local_invocation_primitive_output == vk::TRUE,
+            ),
+            DeviceProperty::new(
+                "prefers_compact_vertex_output",
+                p.prefers_compact_vertex_output == vk::TRUE,
+            ),
+            DeviceProperty::new(
+                "prefers_compact_primitive_output",
+                p.prefers_compact_primitive_output == vk::TRUE,
+            ),
+        ];
+
+        unsafe {
+            Self {
+                device_name: string_from_c_chars(&properties.device_name),
+                device_type: properties.device_type,
+                vendor_id: properties.vendor_id,
+                api_version: format_api_version(properties.api_version),
+                driver_name: string_from_c_chars(&vulkan_12_properties.driver_name),
+                driver_info: string_from_c_chars(&vulkan_12_properties.driver_info),
+                driver_version: format_driver_version(
+                    properties.vendor_id,
+                    properties.driver_version,
+                ),
+                mesh_shader_properties,
+            }
+        }
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Device: {} ({:?}, vendor {:#06x})",
+            self.device_name, self.device_type, self.vendor_id
+        )?;
+        writeln!(
+            f,
+            "Driver: {} {} ({}), Vulkan {}",
+            self.driver_name, self.driver_version, self.driver_info, self.api_version
+        )?;
+        writeln!(f, "VkPhysicalDeviceMeshShaderPropertiesEXT:")?;
+
+        for property in &self.mesh_shader_properties {
+            write!(f, "  {:<42} {:>16}", property.name, property.value)?;
+            if let Some(used) = &property.used {
+                write!(f, "  <- used: {used}")?;
+                if property.exceeded {
+                    write!(f, " (EXCEEDS LIMIT)")?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use std::error::Error;
+
+use ash::vk;
+use thiserror::Error;
+
+//Errors which stop the renderer, they are reported to the user before exiting
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("Failed to load the Vulkan library")]
+    Loading(#[from] ash::LoadingError),
+    #[error("No GPU matches {selector}, available GPUs: {available:?}")]
+    NoMatchingGpu {
+        selector: String,
+        available: Vec<String>,
+    },
+    #[error("{extension} is not supported by {device}")]
+    MissingExtension { device: String, extension: String },
+    #[error("{feature} is not supported by {device}")]
+    MissingFeature {
+        device: String,
+        feature: &'static str,
+    },
+    #[error("{context} failed: {result}")]
+    Vulkan {
+        context: &'static str,
+        result: vk::Result,
+    },
+    #[error("{context} failed")]
+    Other {
+        context: &'static str,
+        #[source]
+        source: anyhow::Error,
+    },
+}
+
+impl RenderError {
+    //The device and everything created from it has to be created again
+    #[inline]
+    pub fn is_device_lost(&self) -> bool {
+        matches!(
+            self,
+            Self::Vulkan {
+                result: vk::Result::ERROR_DEVICE_LOST,
+                ..
+            }
+        )
+    }
+
+    //The error followed by all of its causes, one per line
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = self.source();
+        while let Some(error) = source {
+            report += &format!("\n  Caused by: {error}");
+            source = error.source();
+        }
+        report
+    }
+}
+
+//Describes what was done when the error occurred, e.g. "Creating the swapchain"
+pub trait ErrorContext<T> {
+    fn during(self, context: &'static str) -> Result<T, RenderError>;
+}
+
+impl<T> ErrorContext<T> for Result<T, vk::Result> {
+    #[inline]
+    fn during(self, context: &'static str) -> Result<T, RenderError> {
+        self.map_err(|result| RenderError::Vulkan { context, result })
+    }
+}
+
+impl<T> ErrorContext<T> for anyhow::Result<T> {
+    #[inline]
+    fn during(self, context: &'static str) -> Result<T, RenderError> {
+        self.map_err(|source| RenderError::Other { context, source })
+    }
+}
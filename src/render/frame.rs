@@ -1,54 +1,267 @@
-use std::{slice, sync::Arc};
+use std::{mem, slice, sync::Arc, time::Duration};
 
-use ash::{vk, Device};
+use anyhow::Result;
+use ash::{prelude::VkResult, vk, Device};
+use vk_mem_alloc::Allocator;
+
+use crate::render::{
+    buffer::Buffer,
+    passes::geometry::{CullingStats, DrawConstants},
+    query_pool::{PipelineStatistics, PipelineStatisticsQueryPool, QueryPool},
+    resource_registry::{self, ResourceKind},
+};
 
 pub const NUM_FRAMES: usize = 2;
+const MAX_PASSES: u32 = 8;
+
+#[derive(Clone, Debug)]
+pub struct PassResult {
+    pub name: String,
+    pub duration: Duration,
+    pub pipeline_statistics: PipelineStatistics,
+}
 
 pub struct Frame {
     pub command_pool: vk::CommandPool,
     pub command_buffer: vk::CommandBuffer,
+    //Holds the InstanceAnimatePass, it's submitted ahead of command_buffer so the culling of the next frame only has to
+    //wait for the animation instead of the whole frame
+    pub animate_command_buffer: vk::CommandBuffer,
+
+    //Recorded and submitted to the compute queue before command_buffer, which waits for cull_semaphore
+    pub compute_command_pool: vk::CommandPool,
+    pub compute_command_buffer: vk::CommandBuffer,
+
+    //One pool per recording thread, since a command pool must only be used by one thread at a time. Empty if the draws
+    //are recorded on the render thread
+    pub secondary_command_pools: Vec<vk::CommandPool>,
+    pub secondary_command_buffers: Vec<vk::CommandBuffer>,
 
     pub present_semaphore: vk::Semaphore,
     pub render_semaphore: vk::Semaphore,
+    pub cull_semaphore: vk::Semaphore,
 
     pub fence: vk::Fence,
 
+    pub timestamp_query_pool: QueryPool,
+    pub pipeline_statistics_query_pool: PipelineStatisticsQueryPool,
+    pass_names: Vec<String>,
+
+    pub culling_stats_buffer: Buffer,
+    //Written once per frame, every draw pushes its address next to the index of its instance
+    pub draw_constants_buffer: Buffer,
+    //The level of detail every instance is drawn with this frame, indexed by the instance
+    pub instance_levels_buffer: Buffer,
+    //Written by the InstanceCullPass on the compute queue, the draws of an instance are skipped while its u32 is zero
+    pub visibility_buffer: Buffer,
+
     device: Arc<Device>,
 }
 
 impl Frame {
-    pub fn new(device: Arc<Device>) -> Self {
-        let command_pool =
-            unsafe { device.create_command_pool(&vk::CommandPoolCreateInfo::default(), None) }
-                .unwrap();
+    pub fn new(
+        device: Arc<Device>,
+        allocator: Allocator,
+        timestamp_period: f32,
+        num_instances: usize,
+        direct_queue_family_index: u32,
+        compute_queue_family_index: u32,
+        num_recording_threads: usize,
+        mesh_shader_queries_supported: bool,
+    ) -> Result<Self> {
+        let command_pool = unsafe {
+            device.create_command_pool(
+                &vk::CommandPoolCreateInfo::default().queue_family_index(direct_queue_family_index),
+                None,
+            )
+        }?;
         let command_buffer = unsafe {
             device.allocate_command_buffers(
                 &vk::CommandBufferAllocateInfo::default()
                     .command_pool(command_pool)
                     .command_buffer_count(1),
             )
-        }
-        .unwrap()[0];
+        }?[0];
+        resource_registry::track_created(ResourceKind::CommandBuffer);
+        let animate_command_buffer = unsafe {
+            device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(command_pool)
+                    .command_buffer_count(1),
+            )
+        }?[0];
+        resource_registry::track_created(ResourceKind::CommandBuffer);
+        let compute_command_pool = unsafe {
+            device.create_command_pool(
+                &vk::CommandPoolCreateInfo::default()
+                    .queue_family_index(compute_queue_family_index),
+                None,
+            )
+        }?;
+        let compute_command_buffer = unsafe {
+            device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(compute_command_pool)
+                    .command_buffer_count(1),
+            )
+        }?[0];
+        resource_registry::track_created(ResourceKind::CommandBuffer);
+        let (secondary_command_pools, secondary_command_buffers) = (0..num_recording_threads)
+            .map(|_| {
+                let command_pool = unsafe {
+                    device.create_command_pool(
+                        &vk::CommandPoolCreateInfo::default()
+                            .queue_family_index(direct_queue_family_index),
+                        None,
+                    )
+                }?;
+                let command_buffer = unsafe {
+                    device.allocate_command_buffers(
+                        &vk::CommandBufferAllocateInfo::default()
+                            .command_pool(command_pool)
+                            .level(vk::CommandBufferLevel::SECONDARY)
+                            .command_buffer_count(1),
+                    )
+                }?[0];
+                resource_registry::track_created(ResourceKind::CommandBuffer);
+                Ok((command_pool, command_buffer))
+            })
+            .collect::<VkResult<Vec<_>>>()?
+            .into_iter()
+            .unzip();
         let present_semaphore =
-            unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) }.unwrap();
+            unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) }?;
         let render_semaphore =
-            unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) }.unwrap();
+            unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) }?;
+        let cull_semaphore =
+            unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) }?;
         let fence = unsafe {
             device.create_fence(
                 &vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED),
                 None,
             )
-        }
-        .unwrap();
+        }?;
+        let timestamp_query_pool = unsafe {
+            QueryPool::new(
+                &device,
+                2 * MAX_PASSES,
+                vk::QueryType::TIMESTAMP,
+                timestamp_period,
+            )
+        }?;
+        let pipeline_statistics_query_pool = unsafe {
+            PipelineStatisticsQueryPool::new(&device, MAX_PASSES, mesh_shader_queries_supported)
+        }?;
+        let culling_stats_buffer = unsafe {
+            Buffer::new_readback(device.clone(), allocator, mem::size_of::<CullingStats>())
+        }?;
+        unsafe { culling_stats_buffer.write(&CullingStats::default()) };
+        let draw_constants_buffer = unsafe {
+            Buffer::new_upload(device.clone(), allocator, mem::size_of::<DrawConstants>())
+        }?;
+        let instance_levels_buffer = unsafe {
+            Buffer::new_upload(
+                device.clone(),
+                allocator,
+                num_instances.max(1) * mem::size_of::<u32>(),
+            )
+        }?;
+        //Written on the compute queue and read by the direct queue
+        let visibility_buffer = unsafe {
+            Buffer::new_predicate(
+                device.clone(),
+                allocator,
+                num_instances.max(1) * mem::size_of::<u32>(),
+                if compute_queue_family_index != direct_queue_family_index {
+                    &[direct_queue_family_index, compute_queue_family_index]
+                } else {
+                    &[]
+                },
+            )
+        }?;
 
-        Self {
+        Ok(Self {
             command_pool,
             command_buffer,
+            animate_command_buffer,
+            compute_command_pool,
+            compute_command_buffer,
+            secondary_command_pools,
+            secondary_command_buffers,
             present_semaphore,
             render_semaphore,
+            cull_semaphore,
             fence,
+            timestamp_query_pool,
+            pipeline_statistics_query_pool,
+            pass_names: Vec::new(),
+            culling_stats_buffer,
+            draw_constants_buffer,
+            instance_levels_buffer,
+            visibility_buffer,
             device,
-        }
+        })
+    }
+
+    #[inline]
+    pub unsafe fn reset_queries(&mut self, command_buffer: vk::CommandBuffer) {
+        self.pass_names.clear();
+        self.timestamp_query_pool.reset(command_buffer);
+        self.pipeline_statistics_query_pool.reset(command_buffer);
+    }
+
+    #[inline]
+    pub unsafe fn begin_pass(&mut self, command_buffer: vk::CommandBuffer, name: &str) {
+        self.timestamp_query_pool.write_timestamp(
+            command_buffer,
+            vk::PipelineStageFlags2::TOP_OF_PIPE,
+            format!("{name}_begin"),
+        );
+        self.pipeline_statistics_query_pool
+            .begin(command_buffer, name);
+        self.pass_names.push(name.to_owned());
+    }
+
+    #[inline]
+    pub unsafe fn end_pass(&mut self, command_buffer: vk::CommandBuffer) {
+        let name = self.pass_names.last().unwrap();
+
+        self.pipeline_statistics_query_pool.end(command_buffer);
+        self.timestamp_query_pool.write_timestamp(
+            command_buffer,
+            vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+            format!("{name}_end"),
+        );
+    }
+
+    //Only call this after waiting for the fence, so reading the results never stalls
+    pub unsafe fn get_pass_results(&self) -> VkResult<Vec<PassResult>> {
+        let timestamps = self.timestamp_query_pool.get_results()?;
+        let mut pipeline_statistics = self.pipeline_statistics_query_pool.get_results()?;
+
+        Ok(self
+            .pass_names
+            .iter()
+            .filter_map(|name| {
+                let begin = timestamps.get(&format!("{name}_begin"))?;
+                let end = timestamps.get(&format!("{name}_end"))?;
+
+                Some(PassResult {
+                    name: name.clone(),
+                    duration: end.saturating_sub(*begin),
+                    pipeline_statistics: pipeline_statistics.remove(name).unwrap_or_default(),
+                })
+            })
+            .collect())
+    }
+}
+
+impl Frame {
+    //Only call this after waiting for the fence, the stats are reset for the next submission
+    pub unsafe fn take_culling_stats(&self) -> CullingStats {
+        let culling_stats = self.culling_stats_buffer.read::<CullingStats>();
+        self.culling_stats_buffer.write(&CullingStats::default());
+        culling_stats
     }
 }
 
@@ -57,12 +270,38 @@ impl Drop for Frame {
         unsafe {
             self.device.destroy_fence(self.fence, None);
 
+            self.device.destroy_semaphore(self.cull_semaphore, None);
             self.device.destroy_semaphore(self.render_semaphore, None);
             self.device.destroy_semaphore(self.present_semaphore, None);
 
             self.device
                 .free_command_buffers(self.command_pool, slice::from_ref(&self.command_buffer));
+            resource_registry::track_destroyed(ResourceKind::CommandBuffer);
+            self.device.free_command_buffers(
+                self.command_pool,
+                slice::from_ref(&self.animate_command_buffer),
+            );
+            resource_registry::track_destroyed(ResourceKind::CommandBuffer);
             self.device.destroy_command_pool(self.command_pool, None);
+
+            self.device.free_command_buffers(
+                self.compute_command_pool,
+                slice::from_ref(&self.compute_command_buffer),
+            );
+            resource_registry::track_destroyed(ResourceKind::CommandBuffer);
+            self.device
+                .destroy_command_pool(self.compute_command_pool, None);
+
+            for (command_pool, command_buffer) in self
+                .secondary_command_pools
+                .iter()
+                .zip(&self.secondary_command_buffers)
+            {
+                self.device
+                    .free_command_buffers(*command_pool, slice::from_ref(command_buffer));
+                resource_registry::track_destroyed(ResourceKind::CommandBuffer);
+                self.device.destroy_command_pool(*command_pool, None);
+            }
         }
     }
 }
@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use ash::{vk, Device};
+use vk_mem_alloc::{Allocation, Allocator};
+
+use crate::render::{
+    error::{ErrorContext, RenderError},
+    frame,
+    frame::Frame,
+    render_ctx::{DEPTH_FORMAT, SWAPCHAIN_FORMAT},
+    render_device::RenderDevice,
+    utils,
+    utils::globals::GlobalsBuffers,
+};
+
+pub struct MultisampledImage {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    pub allocation: Allocation,
+}
+
+//Everything the frames in flight render with besides the swapchain images
+pub struct FrameResources {
+    pub frames: Vec<Frame>,
+    pub globals_buffers: GlobalsBuffers,
+    //Has as many samples as the color image the geometry pass renders to
+    pub depth_image: vk::Image,
+    pub depth_image_view: vk::ImageView,
+    pub depth_image_allocation: Allocation,
+    pub samples: vk::SampleCountFlags,
+    //Only created with MSAA, the geometry pass resolves it into the swapchain image
+    pub msaa_color_image: Option<MultisampledImage>,
+    num_recording_threads: usize,
+    device: Arc<Device>,
+    allocator: Allocator,
+}
+
+unsafe fn create_frames(
+    device: &RenderDevice,
+    num_instances: usize,
+    num_recording_threads: usize,
+) -> Result<Vec<Frame>, RenderError> {
+    (0..frame::NUM_FRAMES)
+        .map(|_| {
+            Frame::new(
+                device.device_loader.clone(),
+                device.allocator,
+                device.timestamp_period,
+                num_instances,
+                device.direct_queue_family_index,
+                device.compute_queue_family_index,
+                num_recording_threads,
+                device.mesh_shader_queries_supported,
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .during("Creating the frames")
+}
+
+//The images which have the size of the swapchain images
+unsafe fn create_targets(
+    device: &RenderDevice,
+    extent: vk::Extent2D,
+    samples: vk::SampleCountFlags,
+) -> Result<
+    (
+        (vk::Image, Allocation, vk::ImageView),
+        Option<MultisampledImage>,
+    ),
+    RenderError,
+> {
+    let depth_image = utils::create_depth_stencil_image(
+        &device.device_loader,
+        device.direct_transfer_queue(),
+        device.allocator,
+        extent.width,
+        extent.height,
+        DEPTH_FORMAT,
+        samples,
+    )
+    .during("Creating the depth image")?;
+
+    let msaa_color_image = if samples == vk::SampleCountFlags::TYPE_1 {
+        None
+    } else {
+        let (image, allocation, image_view) = utils::create_color_image(
+            &device.device_loader,
+            device.allocator,
+            extent.width,
+            extent.height,
+            SWAPCHAIN_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            samples,
+        )
+        .during("Creating the multisampled color image")?;
+
+        Some(MultisampledImage {
+            image,
+            image_view,
+            allocation,
+        })
+    };
+
+    Ok((depth_image, msaa_color_image))
+}
+
+impl FrameResources {
+    //The globals buffers are created up front, since the passes are created with their layout
+    pub fn new(
+        device: &RenderDevice,
+        globals_buffers: GlobalsBuffers,
+        extent: vk::Extent2D,
+        num_instances: usize,
+        num_recording_threads: usize,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self, RenderError> {
+        let ((depth_image, depth_image_allocation, depth_image_view), msaa_color_image) =
+            unsafe { create_targets(device, extent, samples) }?;
+
+        Ok(Self {
+            frames: unsafe { create_frames(device, num_instances, num_recording_threads) }?,
+            globals_buffers,
+            depth_image,
+            depth_image_view,
+            depth_image_allocation,
+            samples,
+            msaa_color_image,
+            num_recording_threads,
+            device: device.device_loader.clone(),
+            allocator: device.allocator,
+        })
+    }
+
+    //None of the frames may be in flight
+    pub unsafe fn recreate_frames(
+        &mut self,
+        device: &RenderDevice,
+        num_instances: usize,
+    ) -> Result<(), RenderError> {
+        self.frames.clear();
+        self.frames = create_frames(device, num_instances, self.num_recording_threads)?;
+        Ok(())
+    }
+
+    //None of the frames may be in flight
+    pub unsafe fn resize(
+        &mut self,
+        device: &RenderDevice,
+        extent: vk::Extent2D,
+    ) -> Result<(), RenderError> {
+        let ((depth_image, depth_image_allocation, depth_image_view), msaa_color_image) =
+            create_targets(device, extent, self.samples)?;
+
+        self.destroy_targets();
+        self.depth_image = depth_image;
+        self.depth_image_allocation = depth_image_allocation;
+        self.depth_image_view = depth_image_view;
+        self.msaa_color_image = msaa_color_image;
+        Ok(())
+    }
+
+    unsafe fn destroy_targets(&mut self) {
+        utils::destroy_depth_stencil_image(
+            &self.device,
+            self.allocator,
+            self.depth_image,
+            self.depth_image_allocation,
+            self.depth_image_view,
+        );
+
+        if let Some(msaa_color_image) = self.msaa_color_image.take() {
+            utils::destroy_image(
+                &self.device,
+                self.allocator,
+                msaa_color_image.image,
+                msaa_color_image.allocation,
+                msaa_color_image.image_view,
+            );
+        }
+    }
+}
+
+impl Drop for FrameResources {
+    fn drop(&mut self) {
+        unsafe { self.destroy_targets() }
+    }
+}
@@ -0,0 +1,87 @@
+use std::mem;
+
+use ash::{prelude::VkResult, vk, Device, Entry, Instance};
+#[cfg(windows)]
+use winit::platform::windows::MonitorHandleExtWindows;
+use winit::window::Window;
+
+//VK_EXT_full_screen_exclusive, the swapchain owns the monitor of the window and presents without the compositor
+#[derive(Clone)]
+pub struct FullScreenExclusive {
+    fp: vk::ExtFullScreenExclusiveFn,
+    surface_capabilities_fp: vk::KhrGetSurfaceCapabilities2Fn,
+    device: vk::Device,
+    //The HMONITOR of the window, kept as an integer so the device can still be sent to other threads
+    monitor: isize,
+}
+
+//Exclusive fullscreen is only supported on Windows
+#[cfg(windows)]
+pub fn window_monitor(window: &Window) -> Option<isize> {
+    window.current_monitor().map(|monitor| monitor.hmonitor())
+}
+
+#[cfg(not(windows))]
+pub fn window_monitor(_window: &Window) -> Option<isize> {
+    None
+}
+
+impl FullScreenExclusive {
+    pub fn new(entry: &Entry, instance: &Instance, device: &Device, monitor: isize) -> Self {
+        Self {
+            fp: vk::ExtFullScreenExclusiveFn::load(|name| unsafe {
+                mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }),
+            surface_capabilities_fp: vk::KhrGetSurfaceCapabilities2Fn::load(|name| unsafe {
+                mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+            }),
+            device: device.handle(),
+            monitor,
+        }
+    }
+
+    #[inline]
+    pub fn monitor(&self) -> vk::HMONITOR {
+        self.monitor as vk::HMONITOR
+    }
+
+    //Whether the surface can be presented to exclusively on the monitor of the window
+    pub unsafe fn supported(
+        &self,
+        physical_device: vk::PhysicalDevice,
+        surface: vk::SurfaceKHR,
+    ) -> VkResult<bool> {
+        let mut win32_info =
+            vk::SurfaceFullScreenExclusiveWin32InfoEXT::default().hmonitor(self.monitor());
+        let surface_info = vk::PhysicalDeviceSurfaceInfo2KHR::default()
+            .surface(surface)
+            .push_next(&mut win32_info);
+
+        let mut full_screen_exclusive_capabilities =
+            vk::SurfaceCapabilitiesFullScreenExclusiveEXT::default();
+        let mut capabilities = vk::SurfaceCapabilities2KHR::default()
+            .push_next(&mut full_screen_exclusive_capabilities);
+        (self
+            .surface_capabilities_fp
+            .get_physical_device_surface_capabilities2_khr)(
+            physical_device,
+            &surface_info,
+            &mut capabilities,
+        )
+        .result()?;
+
+        Ok(full_screen_exclusive_capabilities.full_screen_exclusive_supported == vk::TRUE)
+    }
+
+    //The swapchain has to be created with APPLICATION_CONTROLLED, it keeps presenting through the compositor if this
+    //fails
+    #[inline]
+    pub unsafe fn acquire(&self, swapchain: vk::SwapchainKHR) -> VkResult<()> {
+        (self.fp.acquire_full_screen_exclusive_mode_ext)(self.device, swapchain).result()
+    }
+
+    #[inline]
+    pub unsafe fn release(&self, swapchain: vk::SwapchainKHR) -> VkResult<()> {
+        (self.fp.release_full_screen_exclusive_mode_ext)(self.device, swapchain).result()
+    }
+}
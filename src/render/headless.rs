@@ -0,0 +1,194 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use ash::vk;
+
+use crate::render::{
+    capture::RgbaImage,
+    render_config::RenderConfig,
+    render_ctx::{RenderCtx, RenderTarget},
+    renderer,
+    scene::{Camera, Scene},
+    workers::WorkerConfig,
+};
+
+//Headless runs aren't interactive, so the animations advance by a fixed step per frame
+const DELTA_TIME: f32 = 1.0 / 60.0;
+
+#[derive(Clone, Debug)]
+pub struct HeadlessConfig {
+    pub enabled: bool,
+    pub extent: vk::Extent2D,
+    pub num_frames: usize,
+    pub output: PathBuf,
+}
+
+impl Default for HeadlessConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            extent: vk::Extent2D {
+                width: 1600,
+                height: 900,
+            },
+            num_frames: 60,
+            output: "headless.png".into(),
+        }
+    }
+}
+
+//The headless options of the command line
+#[derive(clap::Args, Debug)]
+pub struct HeadlessArgs {
+    #[arg(
+        long,
+        help = "Render without a window and write the last frame to disk"
+    )]
+    headless: bool,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of frames rendered headless"
+    )]
+    headless_frames: Option<u32>,
+    #[arg(
+        long,
+        value_name = "WxH",
+        value_parser = parse_extent,
+        help = "Resolution of the headless frames"
+    )]
+    headless_size: Option<vk::Extent2D>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "PNG the last headless frame is written to"
+    )]
+    headless_output: Option<PathBuf>,
+}
+
+fn parse_extent(size: &str) -> Result<vk::Extent2D> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| anyhow!("Invalid headless size {size}"))?;
+    let extent = vk::Extent2D {
+        width: width.parse()?,
+        height: height.parse()?,
+    };
+
+    if extent.width == 0 || extent.height == 0 {
+        bail!("Headless rendering needs a non-empty resolution")
+    }
+    Ok(extent)
+}
+
+impl HeadlessArgs {
+    pub fn apply(self, config: &mut HeadlessConfig) {
+        config.enabled |= self.headless;
+        if let Some(num_frames) = self.headless_frames {
+            config.num_frames = num_frames as _;
+        }
+        if let Some(extent) = self.headless_size {
+            config.extent = extent;
+        }
+        if let Some(output) = self.headless_output {
+            config.output = output;
+        }
+    }
+}
+
+//Renders scenes into offscreen images without a window or event loop, each render waits for the GPU
+pub struct HeadlessRenderer {
+    ctx: RenderCtx,
+    scene: Scene,
+    frame_index: usize,
+}
+
+impl HeadlessRenderer {
+    pub fn new(
+        extent: vk::Extent2D,
+        scene: &Scene,
+        worker_config: &WorkerConfig,
+        render_config: &RenderConfig,
+    ) -> Result<Self> {
+        scene.validate()?;
+
+        Ok(Self {
+            ctx: RenderCtx::new(
+                RenderTarget::Headless(extent),
+                scene,
+                worker_config,
+                render_config,
+            )?,
+            scene: scene.clone(),
+            frame_index: 0,
+        })
+    }
+
+    pub fn render(&mut self, scene: &Scene, camera: &Camera) -> Result<RgbaImage> {
+        //Uploading meshes is expensive, so the GPU buffers are only rebuilt if the geometry changed
+        if !self.scene.same_geometry(scene) {
+            scene.validate()?;
+            self.ctx.set_scene(scene)?;
+        }
+        self.scene = scene.clone();
+
+        //Every render is a final image, so it must not contain placeholders
+        unsafe {
+            self.ctx
+                .scene_resources
+                .mesh_collection
+                .wait_until_loaded(&mut self.ctx.deletion_queue)
+        }?;
+
+        self.ctx.fixed_time = Some(scene.time);
+        self.ctx.camera_override = Some(*camera);
+        self.ctx.capture_requested = true;
+
+        if let Err(error) = renderer::render_frame(&mut self.ctx, &mut self.frame_index) {
+            if error.is_device_lost() {
+                self.ctx.device.report_device_fault();
+            }
+            return Err(error.into())
+        }
+        self.frame_index = (self.frame_index + 1) % self.ctx.frame_resources.frames.len();
+
+        self.ctx
+            .captured_frame
+            .take()
+            .ok_or_else(|| anyhow!("The headless frame wasn't captured"))
+    }
+
+    #[inline]
+    pub fn render_ctx(&mut self) -> &mut RenderCtx {
+        &mut self.ctx
+    }
+}
+
+pub fn run(
+    config: &HeadlessConfig,
+    scene: Scene,
+    worker_config: &WorkerConfig,
+    render_config: &RenderConfig,
+) -> Result<()> {
+    let mut scene = scene;
+    let mut renderer = HeadlessRenderer::new(config.extent, &scene, worker_config, render_config)?;
+
+    let mut image = None;
+    for frame in 0..config.num_frames {
+        scene.time = frame as f32 * DELTA_TIME;
+        image = Some(renderer.render(&scene, &Camera::default())?);
+    }
+
+    image
+        .ok_or_else(|| anyhow!("No headless frame was rendered"))?
+        .save_png(&config.output)?;
+
+    println!(
+        "Rendered {} headless frames, the last one was written to {}",
+        config.num_frames,
+        config.output.display()
+    );
+    Ok(())
+}
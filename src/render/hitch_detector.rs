@@ -0,0 +1,117 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs,
+    fs::OpenOptions,
+    io::Write as _,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+
+use crate::render::pass_timings::PassTimings;
+
+const FRAME_TIME_HISTORY: usize = 120;
+const MIN_FRAMES_FOR_DETECTION: usize = 30;
+const HITCH_FACTOR: u32 = 2;
+
+const MAX_EVENTS: usize = 64;
+const EVENT_WINDOW: Duration = Duration::from_secs(1);
+
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+
+static EVENTS: Mutex<VecDeque<(Instant, String)>> = Mutex::new(VecDeque::new());
+
+//Records an event like an upload or a pipeline compile, which is attached to the next hitches
+pub fn record_event(description: impl Into<String>) {
+    let mut events = EVENTS.lock().unwrap();
+    if events.len() == MAX_EVENTS {
+        events.pop_front();
+    }
+    events.push_back((Instant::now(), description.into()));
+}
+
+pub struct HitchDetector {
+    frame_times: VecDeque<Duration>,
+    frame_count: u64,
+    log_path: PathBuf,
+}
+
+impl HitchDetector {
+    pub fn new(log_path: impl Into<PathBuf>) -> Self {
+        Self {
+            frame_times: VecDeque::with_capacity(FRAME_TIME_HISTORY),
+            frame_count: 0,
+            log_path: log_path.into(),
+        }
+    }
+
+    pub fn end_frame(&mut self, frame_time: Duration, pass_timings: &PassTimings) {
+        self.frame_count += 1;
+
+        if self.frame_times.len() >= MIN_FRAMES_FOR_DETECTION {
+            let median = self.median();
+            if frame_time > median * HITCH_FACTOR {
+                if let Err(e) = self.log_hitch(frame_time, median, pass_timings) {
+                    eprintln!("Failed to write hitch log: {e}");
+                }
+            }
+        }
+
+        if self.frame_times.len() == FRAME_TIME_HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+    }
+
+    fn median(&self) -> Duration {
+        let mut frame_times: Vec<_> = self.frame_times.iter().copied().collect();
+        frame_times.sort_unstable();
+        frame_times[frame_times.len() / 2]
+    }
+
+    fn log_hitch(
+        &self,
+        frame_time: Duration,
+        median: Duration,
+        pass_timings: &PassTimings,
+    ) -> Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?;
+
+        let mut entry = String::new();
+        writeln!(
+            entry,
+            "[{}.{:03}] frame {}: {frame_time:?} (median {median:?})",
+            timestamp.as_secs(),
+            timestamp.subsec_millis(),
+            self.frame_count
+        )?;
+
+        for (name, duration) in pass_timings.latest() {
+            writeln!(entry, "    pass {name}: {duration:?}")?;
+        }
+
+        let now = Instant::now();
+        for (time, description) in EVENTS.lock().unwrap().iter() {
+            let age = now.duration_since(*time);
+            if age <= EVENT_WINDOW {
+                writeln!(entry, "    event {age:?} ago: {description}")?;
+            }
+        }
+
+        //Keep a single previous log around, so the log never grows unbounded
+        if fs::metadata(&self.log_path).is_ok_and(|metadata| metadata.len() > MAX_LOG_SIZE) {
+            fs::rename(&self.log_path, self.log_path.with_extension("log.1"))?;
+        }
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_path)?
+            .write_all(entry.as_bytes())?;
+
+        Ok(())
+    }
+}
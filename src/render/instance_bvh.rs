@@ -0,0 +1,173 @@
+use glam::Vec4;
+
+use crate::render::mesh_util::AABB;
+
+const MAX_LEAF_SIZE: usize = 4;
+//Refitting lets the bounds of the nodes grow as the instances move apart, the hierarchy is built again once their
+//summed surface area is this many times the one after the last build
+const REBUILD_AREA_RATIO: f32 = 2.0;
+
+#[derive(Copy, Clone, Debug, Default)]
+struct BvhNode {
+    aabb: AABB,
+    //Leaves reference count instances starting at offset in instance_indices. Inner nodes have a count of zero, their
+    //children are at offset and offset + 1
+    offset: u32,
+    count: u32,
+}
+
+//Bounding volume hierarchy over the world space bounds of the instances, so the host only tests the nodes around the
+//frustum instead of every instance. The instances move every frame, so it's refitted and only built again when it
+//degraded too much
+#[derive(Clone, Debug, Default)]
+pub struct InstanceBvh {
+    //Children always come after their parent
+    nodes: Vec<BvhNode>,
+    //The instances in the order of the leaves
+    instance_indices: Vec<u32>,
+    //Indexed like the instances, the ones of leaves which intersect the frustum are tested on their own
+    instance_aabbs: Vec<AABB>,
+    built_area: f32,
+}
+
+impl InstanceBvh {
+    //Splits the instances at the median of their centers along the longest axis, until they fit into a leaf
+    fn build(&mut self, aabbs: &[AABB]) {
+        self.nodes.clear();
+        self.instance_indices = (0..aabbs.len() as u32).collect();
+        if aabbs.is_empty() {
+            self.built_area = 0.0;
+            return
+        }
+
+        self.nodes.push(BvhNode {
+            aabb: AABB::default(),
+            offset: 0,
+            count: aabbs.len() as _,
+        });
+
+        let mut stack = vec![0];
+        while let Some(node_idx) = stack.pop() {
+            let BvhNode { offset, count, .. } = self.nodes[node_idx];
+            let indices = &mut self.instance_indices[offset as usize..(offset + count) as usize];
+            self.nodes[node_idx].aabb =
+                AABB::from_aabbs(indices.iter().map(|idx| &aabbs[*idx as usize]));
+            if indices.len() <= MAX_LEAF_SIZE {
+                continue
+            }
+
+            let centers = indices.iter().fold(
+                AABB {
+                    min: aabbs[indices[0] as usize].center(),
+                    max: aabbs[indices[0] as usize].center(),
+                },
+                |centers, idx| {
+                    let center = aabbs[*idx as usize].center();
+                    AABB {
+                        min: centers.min.min(center),
+                        max: centers.max.max(center),
+                    }
+                },
+            );
+            let size = centers.max - centers.min;
+            let axis = if size.x >= size.y && size.x >= size.z {
+                0
+            } else if size.y >= size.z {
+                1
+            } else {
+                2
+            };
+
+            let mid = indices.len() / 2;
+            indices.select_nth_unstable_by(mid, |a, b| {
+                aabbs[*a as usize].center()[axis].total_cmp(&aabbs[*b as usize].center()[axis])
+            });
+
+            let left = self.nodes.len();
+            self.nodes.push(BvhNode {
+                aabb: AABB::default(),
+                offset,
+                count: mid as _,
+            });
+            self.nodes.push(BvhNode {
+                aabb: AABB::default(),
+                offset: offset + mid as u32,
+                count: count - mid as u32,
+            });
+            self.nodes[node_idx].offset = left as _;
+            self.nodes[node_idx].count = 0;
+            stack.extend([left, left + 1]);
+        }
+
+        self.built_area = self.area();
+    }
+
+    #[inline]
+    fn area(&self) -> f32 {
+        self.nodes.iter().map(|node| node.aabb.surface_area()).sum()
+    }
+
+    //Takes the bounds of every instance, indexed like the instances. The hierarchy is built on the first call and
+    //whenever the number of instances changed
+    pub fn refit(&mut self, aabbs: &[AABB]) {
+        self.instance_aabbs.clear();
+        self.instance_aabbs.extend_from_slice(aabbs);
+
+        if self.instance_indices.len() != aabbs.len() {
+            self.build(aabbs);
+            return
+        }
+
+        for node_idx in (0..self.nodes.len()).rev() {
+            let BvhNode { offset, count, .. } = self.nodes[node_idx];
+            self.nodes[node_idx].aabb = if count > 0 {
+                AABB::from_aabbs(
+                    self.instance_indices[offset as usize..(offset + count) as usize]
+                        .iter()
+                        .map(|idx| &aabbs[*idx as usize]),
+                )
+            } else {
+                self.nodes[offset as usize]
+                    .aabb
+                    .union(&self.nodes[offset as usize + 1].aabb)
+            };
+        }
+
+        if self.area() > REBUILD_AREA_RATIO * self.built_area {
+            self.build(aabbs);
+        }
+    }
+
+    //Calls visible with every instance whose bounds intersect the frustum, the instances of nodes which are completely
+    //inside it aren't tested any further
+    pub fn for_each_visible(&self, frustum_planes: &[Vec4; 6], mut visible: impl FnMut(u32)) {
+        if self.nodes.is_empty() {
+            return
+        }
+
+        let mut stack = vec![(0, false)];
+        while let Some((node_idx, inside)) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            if !inside && !node.aabb.intersects_frustum(frustum_planes) {
+                continue
+            }
+            let inside = inside || node.aabb.inside_frustum(frustum_planes);
+
+            if node.count > 0 {
+                self.instance_indices[node.offset as usize..(node.offset + node.count) as usize]
+                    .iter()
+                    .filter(|idx| {
+                        inside
+                            || self.instance_aabbs[**idx as usize]
+                                .intersects_frustum(frustum_planes)
+                    })
+                    .for_each(|idx| visible(*idx));
+            } else {
+                stack.extend([
+                    (node.offset as usize, inside),
+                    (node.offset as usize + 1, inside),
+                ]);
+            }
+        }
+    }
+}
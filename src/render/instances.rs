@@ -0,0 +1,206 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    slice,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Quat, Vec3};
+use vk_mem_alloc::Allocator;
+
+use crate::render::{
+    buffer::Buffer,
+    resource_registry::{self, ResourceKind},
+    staging_belt::TransferQueue,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Zeroable, Pod)]
+pub struct InstanceAnimation {
+    pub position: Vec3,
+    pub scale: f32,
+    pub angle: f32,
+    pub angular_velocity: f32,
+    pub mesh_idx: u32,
+    //Radians per second the instance orbits around the world origin, starting at the base angle
+    pub orbit_velocity: f32,
+}
+
+impl InstanceAnimation {
+    #[inline]
+    pub fn new(
+        position: Vec3,
+        scale: f32,
+        angle: f32,
+        angular_velocity: f32,
+        mesh_idx: u32,
+    ) -> Self {
+        Self {
+            position,
+            scale,
+            angle,
+            angular_velocity,
+            mesh_idx,
+            orbit_velocity: 0.0,
+        }
+    }
+
+    #[inline]
+    pub fn with_orbit_velocity(self, orbit_velocity: f32) -> Self {
+        Self {
+            orbit_velocity,
+            ..self
+        }
+    }
+
+    //Matches instance_animate.comp.glsl, the rotation around the instance itself doesn't move it
+    #[inline]
+    pub fn position_at(&self, time: f32) -> Vec3 {
+        Quat::from_rotation_y(self.angle + self.orbit_velocity * time) * self.position
+    }
+
+    //Matches instance_animate.comp.glsl
+    #[inline]
+    pub fn world_matrix_at(&self, time: f32) -> Mat4 {
+        Mat4::from_rotation_y(self.angle + self.orbit_velocity * time)
+            * Mat4::from_translation(self.position)
+            * Mat4::from_rotation_y(self.angular_velocity * time)
+            * Mat4::from_scale(Vec3::splat(self.scale))
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Zeroable, Pod)]
+pub struct Instance {
+    pub world_matrix: Mat4,
+    //The world matrix of the last frame, for the velocity
+    pub prev_world_matrix: Mat4,
+    pub mesh_idx: u32,
+    pub padding: [u32; 3],
+}
+
+pub struct InstanceBuffers {
+    pub instance_animations: Vec<InstanceAnimation>,
+    pub instance_animation_buffer: Buffer,
+    pub instance_buffer: Buffer,
+    pub descriptor_set: vk::DescriptorSet,
+}
+
+impl InstanceBuffers {
+    pub unsafe fn new(
+        device: &Arc<Device>,
+        queue: TransferQueue,
+        allocator: Allocator,
+        descriptor_pool: vk::DescriptorPool,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        instance_animations: Vec<InstanceAnimation>,
+        sharing_family_indices: &[u32],
+    ) -> Result<Self> {
+        let instance_animation_buffer =
+            Buffer::new_device_local(device.clone(), queue, allocator, &instance_animations, &[])?;
+
+        //The world matrices are written by the InstanceAnimatePass every frame and read by the culling on the compute
+        //queue
+        let instance_buffer = Buffer::new_device_local(
+            device.clone(),
+            queue,
+            allocator,
+            &vec![Instance::default(); instance_animations.len()],
+            sharing_family_indices,
+        )?;
+
+        let descriptor_set = device.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(slice::from_ref(&descriptor_set_layout)),
+        )?[0];
+        resource_registry::track_created(ResourceKind::DescriptorSet);
+
+        //Update storage buffers
+        let descriptor_buffer_infos = [
+            vk::DescriptorBufferInfo::default()
+                .buffer(instance_animation_buffer.buffer)
+                .range(instance_animation_buffer.size),
+            vk::DescriptorBufferInfo::default()
+                .buffer(instance_buffer.buffer)
+                .range(instance_buffer.size),
+        ];
+
+        let write_descriptor_sets: Vec<_> = descriptor_buffer_infos
+            .iter()
+            .enumerate()
+            .map(|(i, descriptor_buffer_info)| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(i as _)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(slice::from_ref(descriptor_buffer_info))
+            })
+            .collect();
+
+        device.update_descriptor_sets(&write_descriptor_sets, &[]);
+
+        Ok(Self {
+            instance_animations,
+            instance_animation_buffer,
+            instance_buffer,
+            descriptor_set,
+        })
+    }
+
+    #[inline]
+    pub fn num_instances(&self) -> usize {
+        self.instance_animations.len()
+    }
+}
+
+//The ground plane is a square which starts at this corner
+pub const GROUND_ORIGIN: Vec3 = Vec3::new(-120.43, -2.325, -160.1);
+pub const GROUND_SIZE: f32 = 280.20;
+
+//Scale and height above the ground plane of the models of the demo scene
+pub const DEMO_MODEL_PLACEMENTS: [(f32, f32); 3] = [(1.0, -2.6), (0.1, 2.8), (22.0, -3.25)];
+
+//Mesh 0 is the ground plane, the grid cycles through the models placed by model_placements, which start at mesh 1
+pub fn create_instance_grid(model_placements: &[(f32, f32)]) -> Vec<InstanceAnimation> {
+    let mut instance_animations = vec![InstanceAnimation::new(
+        GROUND_ORIGIN,
+        GROUND_SIZE,
+        0.0,
+        0.0,
+        0,
+    )];
+
+    for i in 0..25 {
+        for j in 0..25 {
+            let hash_code = {
+                let mut hasher = DefaultHasher::new();
+                (i * 1128889).hash(&mut hasher);
+                (j * 1254739).hash(&mut hasher);
+                (i + j).hash(&mut hasher);
+
+                hasher.finish()
+            };
+
+            let angle = (hash_code & 255) as f32 / 255.0 * std::f32::consts::PI;
+            let angular_velocity = ((hash_code >> 8) & 255) as f32 / 255.0 * 2.0 - 1.0;
+
+            let model_idx = (i + j) % model_placements.len();
+            let mesh_idx = model_idx + 1;
+            let (scale, y_offset) = model_placements[model_idx];
+
+            instance_animations.push(InstanceAnimation::new(
+                Vec3::new(i as f32 * 7.0, y_offset, j as f32 * 5.0),
+                scale,
+                angle,
+                angular_velocity,
+                mesh_idx as _,
+            ));
+        }
+    }
+
+    instance_animations
+}
@@ -0,0 +1,182 @@
+use std::{mem, slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use vk_mem_alloc::{Allocation, Allocator};
+
+use crate::render::{
+    deletion_queue::DeletionQueue,
+    mesh::MaskTexture,
+    resource_registry::{self, ResourceKind},
+    staging_belt::TransferQueue,
+    utils,
+};
+
+//Has to match MAX_MASK_TEXTURES in shaders/alpha_test.glsl
+pub const MAX_MASK_TEXTURES: usize = 64;
+
+//The mask textures of every mesh of a collection in a single descriptor array, which the alpha tested geometry
+//pipelines index with the mask texture of the material. Slots without a texture hold an opaque placeholder
+pub struct MaskTextures {
+    images: Vec<(vk::Image, Allocation, vk::ImageView)>,
+    placeholder: (vk::Image, Allocation, vk::ImageView),
+    pub sampler: vk::Sampler,
+    pub descriptor_set: vk::DescriptorSet,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    //Textures were added since the descriptor set was written
+    dirty: bool,
+    queue: TransferQueue,
+    allocator: Allocator,
+    device: Arc<Device>,
+}
+
+impl Drop for MaskTextures {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .free_descriptor_sets(self.descriptor_pool, slice::from_ref(&self.descriptor_set))
+                .unwrap();
+            resource_registry::track_destroyed(ResourceKind::DescriptorSet);
+
+            for (image, allocation, image_view) in self.images.drain(..).chain([self.placeholder]) {
+                utils::destroy_image(&self.device, self.allocator, image, allocation, image_view);
+            }
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+impl MaskTextures {
+    pub unsafe fn new(
+        device: &Arc<Device>,
+        queue: TransferQueue,
+        allocator: Allocator,
+        descriptor_pool: vk::DescriptorPool,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+    ) -> Result<Self> {
+        let placeholder = utils::create_texture_image(
+            device,
+            queue,
+            allocator,
+            1,
+            1,
+            vk::Format::R8_UNORM,
+            &[u8::MAX],
+        )?;
+
+        //Foliage textures usually tile
+        let sampler = device.create_sampler(
+            &vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::REPEAT)
+                .address_mode_v(vk::SamplerAddressMode::REPEAT)
+                .address_mode_w(vk::SamplerAddressMode::REPEAT),
+            None,
+        )?;
+
+        let descriptor_set =
+            allocate_descriptor_set(device, descriptor_pool, descriptor_set_layout)?;
+
+        let mask_textures = Self {
+            images: Vec::new(),
+            placeholder,
+            sampler,
+            descriptor_set,
+            descriptor_pool,
+            descriptor_set_layout,
+            dirty: false,
+            queue,
+            allocator,
+            device: device.clone(),
+        };
+        mask_textures.write_descriptor_set();
+
+        Ok(mask_textures)
+    }
+
+    //Returns the slot of the first texture, the others follow it. None if they don't fit into the remaining slots, the
+    //materials are only tested against the alpha of their base color then. They are sampled after the next flush
+    pub unsafe fn add(&mut self, mask_textures: &[MaskTexture]) -> Result<Option<u32>> {
+        let first_slot = self.images.len();
+        if first_slot + mask_textures.len() > MAX_MASK_TEXTURES {
+            eprintln!(
+                "Warning: Only {MAX_MASK_TEXTURES} mask textures are supported, dropping {} of them",
+                mask_textures.len()
+            );
+            return Ok(None)
+        }
+
+        for mask_texture in mask_textures {
+            self.images.push(utils::create_texture_image(
+                &self.device,
+                self.queue,
+                self.allocator,
+                mask_texture.width,
+                mask_texture.height,
+                vk::Format::R8_UNORM,
+                &mask_texture.texels,
+            )?);
+        }
+        self.dirty |= !mask_textures.is_empty();
+
+        Ok(Some(first_slot as _))
+    }
+
+    //Frames in flight may still use the current descriptor set, so the textures are written to a new one
+    pub unsafe fn flush(&mut self, deletion_queue: &mut DeletionQueue) -> Result<()> {
+        if !self.dirty {
+            return Ok(())
+        }
+
+        let descriptor_set = allocate_descriptor_set(
+            &self.device,
+            self.descriptor_pool,
+            self.descriptor_set_layout,
+        )?;
+        deletion_queue.push_descriptor_set(
+            mem::replace(&mut self.descriptor_set, descriptor_set),
+            self.descriptor_pool,
+        );
+        self.write_descriptor_set();
+        self.dirty = false;
+
+        Ok(())
+    }
+
+    unsafe fn write_descriptor_set(&self) {
+        let descriptor_image_infos: Vec<_> = (0..MAX_MASK_TEXTURES)
+            .map(|slot| {
+                let (_, _, image_view) = self.images.get(slot).unwrap_or(&self.placeholder);
+                vk::DescriptorImageInfo::default()
+                    .sampler(self.sampler)
+                    .image_view(*image_view)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            })
+            .collect();
+
+        let write_descriptor_set = vk::WriteDescriptorSet::default()
+            .dst_set(self.descriptor_set)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&descriptor_image_infos);
+
+        self.device
+            .update_descriptor_sets(slice::from_ref(&write_descriptor_set), &[]);
+    }
+}
+
+unsafe fn allocate_descriptor_set(
+    device: &Device,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+) -> Result<vk::DescriptorSet> {
+    let descriptor_set = device.allocate_descriptor_sets(
+        &vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(slice::from_ref(&descriptor_set_layout)),
+    )?[0];
+    resource_registry::track_created(ResourceKind::DescriptorSet);
+
+    Ok(descriptor_set)
+}
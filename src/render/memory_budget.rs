@@ -0,0 +1,185 @@
+use std::fmt;
+
+use ash::{vk, Instance};
+use vk_mem_alloc::Allocator;
+
+//The meshlet buffers are by far the largest allocations, near the budget the driver starts paging them out
+const WARNING_THRESHOLD: f32 = 0.9;
+
+const MIB: vk::DeviceSize = 1 << 20;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HeapBudget {
+    pub device_local: bool,
+    //Used by the whole process, not only by the allocator
+    pub usage: vk::DeviceSize,
+    pub budget: vk::DeviceSize,
+}
+
+impl HeapBudget {
+    #[inline]
+    pub fn fraction_used(&self) -> f32 {
+        if self.budget == 0 {
+            0.0
+        } else {
+            self.usage as f32 / self.budget as f32
+        }
+    }
+}
+
+impl fmt::Display for HeapBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} MiB ({:.0}%)",
+            self.usage / MIB,
+            self.budget / MIB,
+            self.fraction_used() * 100.0
+        )
+    }
+}
+
+//Without VK_EXT_memory_budget the usage is unknown and the budget is the size of the heap
+pub unsafe fn query_heap_budgets(
+    instance_loader: &Instance,
+    physical_device: vk::PhysicalDevice,
+    memory_budget_supported: bool,
+) -> Vec<HeapBudget> {
+    let mut memory_budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut memory_properties = vk::PhysicalDeviceMemoryProperties2::default();
+    if memory_budget_supported {
+        memory_properties = memory_properties.push_next(&mut memory_budget_properties);
+    }
+    instance_loader.get_physical_device_memory_properties2(physical_device, &mut memory_properties);
+    let memory_properties = memory_properties.memory_properties;
+
+    memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+        .iter()
+        .enumerate()
+        .map(|(heap_idx, heap)| {
+            HeapBudget {
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                usage: memory_budget_properties.heap_usage[heap_idx],
+                budget: if memory_budget_supported {
+                    memory_budget_properties.heap_budget[heap_idx]
+                } else {
+                    heap.size
+                },
+            }
+        })
+        .collect()
+}
+
+//All device local heaps together, which is what the meshes are allocated from
+pub fn device_local_budget(heap_budgets: &[HeapBudget]) -> HeapBudget {
+    let device_local_heaps = heap_budgets
+        .iter()
+        .filter(|heap_budget| heap_budget.device_local);
+
+    HeapBudget {
+        device_local: true,
+        usage: device_local_heaps
+            .clone()
+            .map(|heap_budget| heap_budget.usage)
+            .sum(),
+        budget: device_local_heaps
+            .map(|heap_budget| heap_budget.budget)
+            .sum(),
+    }
+}
+
+//Warns once when a heap gets close to its budget and again if it drops below and comes close again
+#[derive(Default)]
+pub struct MemoryBudgetMonitor {
+    near_budget: Vec<bool>,
+}
+
+impl MemoryBudgetMonitor {
+    pub fn update(&mut self, heap_budgets: &[HeapBudget]) {
+        self.near_budget.resize(heap_budgets.len(), false);
+
+        for (heap_idx, (heap_budget, near_budget)) in
+            heap_budgets.iter().zip(&mut self.near_budget).enumerate()
+        {
+            let was_near_budget = *near_budget;
+            *near_budget = heap_budget.fraction_used() >= WARNING_THRESHOLD;
+            if *near_budget && !was_near_budget {
+                eprintln!(
+                    "Warning: Memory heap {heap_idx} is close to its budget, {heap_budget} are used"
+                );
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MemoryTypeStatistics {
+    pub heap_idx: u32,
+    pub allocation_count: u32,
+    pub block_count: u32,
+    //Bytes of all allocations
+    pub used: vk::DeviceSize,
+    //Bytes of all blocks the allocations are placed in
+    pub reserved: vk::DeviceSize,
+}
+
+impl MemoryTypeStatistics {
+    #[inline]
+    pub fn free(&self) -> vk::DeviceSize {
+        self.reserved - self.used
+    }
+}
+
+impl fmt::Display for MemoryTypeStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations in {} blocks, {}/{} MiB used",
+            self.allocation_count,
+            self.block_count,
+            self.used / MIB,
+            self.reserved / MIB
+        )
+    }
+}
+
+//What the allocator itself allocated, unlike the heap budgets which include everything in the process
+#[derive(Clone, Debug, Default)]
+pub struct AllocatorStatistics {
+    //Indexed by the memory type, only the types of the device are included
+    pub memory_types: Vec<MemoryTypeStatistics>,
+    pub total: MemoryTypeStatistics,
+}
+
+//Walks over every allocation, so it shouldn't be done every frame
+pub unsafe fn calculate_allocator_statistics(
+    instance_loader: &Instance,
+    physical_device: vk::PhysicalDevice,
+    allocator: Allocator,
+) -> AllocatorStatistics {
+    let memory_properties = instance_loader.get_physical_device_memory_properties(physical_device);
+    let statistics = vk_mem_alloc::calculate_statistics(allocator);
+
+    let memory_type_statistics =
+        |heap_idx, detailed_statistics: &vk_mem_alloc::DetailedStatistics| {
+            MemoryTypeStatistics {
+                heap_idx,
+                allocation_count: detailed_statistics.statistics.allocation_count,
+                block_count: detailed_statistics.statistics.block_count,
+                used: detailed_statistics.statistics.allocation_bytes,
+                reserved: detailed_statistics.statistics.block_bytes,
+            }
+        };
+
+    AllocatorStatistics {
+        memory_types: memory_properties.memory_types
+            [..memory_properties.memory_type_count as usize]
+            .iter()
+            .zip(&statistics.memory_type)
+            .map(|(memory_type, detailed_statistics)| {
+                memory_type_statistics(memory_type.heap_index, detailed_statistics)
+            })
+            .collect(),
+        total: memory_type_statistics(0, &statistics.total),
+    }
+}
@@ -1,18 +1,46 @@
-use std::{mem, slice, sync::Arc};
+use std::{
+    mem,
+    ops::Deref,
+    path::Path,
+    slice,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+        mpsc::Receiver,
+        Arc, Mutex,
+    },
+    thread,
+};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use ash::{vk, Device};
 use bytemuck::{Pod, Zeroable};
-use glam::{Quat, Vec2, Vec3};
-use meshopt::{DecodePosition, VertexDataAdapter};
+use glam::{Vec2, Vec3, Vec4};
+use memmap2::Mmap;
+use meshopt::{DecodePosition, SimplifyOptions, VertexDataAdapter};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use vk_mem_alloc::Allocator;
 
-use crate::{
-    render::{buffer::Buffer, mesh_util::AABB},
-    RenderCtx,
+use crate::render::{
+    buffer::Buffer,
+    buffer_arena::{BufferArena, BufferRange},
+    deletion_queue::DeletionQueue,
+    frame::Frame,
+    mask_textures::MaskTextures,
+    mesh_builder::MeshBuilder,
+    mesh_cache, mesh_import, mesh_util,
+    mesh_util::AABB,
+    passes::geometry::{DrawPushConstants, MeshDraw},
+    resource_registry::{self, ResourceKind},
+    skin,
+    skin::{MeshSkin, MorphDelta, VertexSkin},
+    staging_belt::TransferQueue,
+    vertex_format::{PackedVertex, VertexQuantization},
+    workers::WorkerPool,
 };
 
-#[derive(Copy, Clone, Debug, Default, Zeroable, Pod)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Zeroable, Pod)]
 #[repr(C)]
 pub struct Vertex {
     pub position: Vec3,
@@ -45,420 +73,1429 @@ pub struct Meshlet {
     pub data_offset: u32,
     pub vertex_count: u32,
     pub triangle_count: u32,
+    pub primitive_offset: u32,
+    //Index into the materials of the mesh
+    pub material_idx: u32,
 }
 
 impl Meshlet {
     #[inline]
-    pub fn new(aabb: AABB, data_offset: u32, vertex_count: u32, triangle_count: u32) -> Self {
+    pub fn new(
+        aabb: AABB,
+        data_offset: u32,
+        vertex_count: u32,
+        triangle_count: u32,
+        primitive_offset: u32,
+        material_idx: u32,
+    ) -> Self {
         Self {
             aabb,
             data_offset,
             vertex_count,
             triangle_count,
+            primitive_offset,
+            material_idx,
+        }
+    }
+}
+
+//Has to match MATERIAL_ALPHA_TEST and NO_MASK_TEXTURE in shaders/types.glsl
+pub const MATERIAL_ALPHA_TEST: u32 = 1;
+pub const NO_MASK_TEXTURE: u32 = u32::MAX;
+
+//Has to match Material in shaders/types.glsl
+#[derive(Copy, Clone, Debug, PartialEq, Zeroable, Pod)]
+#[repr(C)]
+pub struct Material {
+    pub base_color: Vec4,
+    pub flags: u32,
+    //Alpha tested fragments are discarded if the alpha of the base color times the mask is below it
+    pub alpha_cutoff: f32,
+    //Index into the mask textures of the mesh, the mesh collection turns it into an index into all of its textures
+    pub mask_texture_idx: u32,
+    pub padding: u32,
+}
+
+impl Default for Material {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            base_color: Vec4::new(0.8, 0.8, 0.8, 1.0),
+            flags: 0,
+            alpha_cutoff: 0.5,
+            mask_texture_idx: NO_MASK_TEXTURE,
+            padding: 0,
+        }
+    }
+}
+
+impl Material {
+    #[inline]
+    pub fn alpha_tested(&self) -> bool {
+        self.flags & MATERIAL_ALPHA_TEST != 0
+    }
+}
+
+//The alpha of a texture with one byte per texel, which cuts the shape of foliage out of its triangles
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MaskTexture {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<u8>,
+}
+
+//A range of the indices with one material, submeshes with the same material are merged while baking
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Submesh {
+    pub index_offset: usize,
+    pub index_count: usize,
+    pub material_idx: u32,
+}
+
+impl Submesh {
+    #[inline]
+    pub fn new(index_offset: usize, index_count: usize, material_idx: u32) -> Self {
+        Self {
+            index_offset,
+            index_count,
+            material_idx,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Zeroable, Pod)]
+#[repr(C)]
+pub struct MeshletGroup {
+    pub aabb: AABB,
+    pub meshlet_offset: u32,
+    pub meshlet_count: u32,
+}
+
+impl MeshletGroup {
+    #[inline]
+    pub fn new(aabb: AABB, meshlet_offset: u32, meshlet_count: u32) -> Self {
+        Self {
+            aabb,
+            meshlet_offset,
+            meshlet_count,
         }
     }
 }
 
-const MAX_VERTICES: usize = 64;
-const MAX_TRIANGLES: usize = 124;
-const CONE_WEIGHT: f32 = 0.0;
+//Has to match MESHLET_GROUP_SIZE in shaders/types.glsl, one task shader workgroup culls one group
+pub const MESHLET_GROUP_SIZE: usize = 32;
+
+//Defaults of MeshletConfig, have to match shaders/meshlet_limits.glsl
+pub const MAX_VERTICES: usize = 64;
+pub const MAX_TRIANGLES: usize = 124;
+pub const MAX_LOD_LEVELS: usize = 12;
+//Weights of the normal and texture coordinate error against the position error with LodSimplification::Attributes
+const NORMAL_WEIGHT: f32 = 0.5;
+const TEX_COORD_WEIGHT: f32 = 1.0;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MeshletLayout {
+    //Vertex indices directly followed by the packed primitive indices of each meshlet
+    #[default]
+    Interleaved,
+    //All vertex indices first, then all packed primitive indices, every run starting aligned
+    StructOfArrays,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LodSimplification {
+    //Reaches every target triangle count, but each level needs its own compacted vertex buffer
+    #[default]
+    Sloppy,
+    //Preserves the topology, so every level indexes the vertex buffer of level 0
+    SharedVertices,
+    //Like SharedVertices, but also weighs the error of the normals and texture coordinates and keeps the vertices on
+    //borders in place, so seams and silhouettes survive
+    Attributes,
+}
+
+//How the levels of detail of every mesh are generated, levels with less than 100 indices are always left out
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LodConfig {
+    //Including level 0, at most MAX_LOD_LEVELS
+    pub num_levels: usize,
+    //Level i aims for this ratio to the power of i of the indices of level 0
+    pub target_ratio: f32,
+    //Relative to the extent of the mesh, the simplifier stops early instead of exceeding it
+    pub target_error: f32,
+    pub simplification: LodSimplification,
+}
+
+impl Default for LodConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            num_levels: MAX_LOD_LEVELS,
+            target_ratio: 0.75,
+            target_error: 1e2,
+            simplification: LodSimplification::default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MeshletConfig {
+    pub layout: MeshletLayout,
+    //Alignment of the meshlet runs in u32s, only used by MeshletLayout::StructOfArrays
+    pub alignment: usize,
+    pub lod: LodConfig,
+    //Positions closer than this are merged before baking, zero disables it. Helps the simplification of scanned meshes,
+    //which are full of duplicated vertices
+    pub weld_tolerance: f32,
+    //The geometry shaders are compiled for them when the renderer is created, so they can't change afterwards
+    pub max_vertices: usize,
+    pub max_triangles: usize,
+    //Trades the culling of meshlets by their normal cone against the meshlets being more compact, from 0 to 1
+    pub cone_weight: f32,
+    //Zstd level of the written caches. Zero writes uncompressed caches, which load faster but take more space
+    pub cache_compression_level: i32,
+    //Meant for caches which are distributed, the encoded streams also compress a lot better with zstd
+    pub cache_meshopt_encoding: bool,
+}
+
+impl Default for MeshletConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            layout: MeshletLayout::Interleaved,
+            alignment: 4,
+            lod: LodConfig::default(),
+            weld_tolerance: 0.0,
+            max_vertices: MAX_VERTICES,
+            max_triangles: MAX_TRIANGLES,
+            cone_weight: 0.0,
+            cache_compression_level: 0,
+            cache_meshopt_encoding: false,
+        }
+    }
+}
+
+impl MeshletConfig {
+    //The triangles are packed into bytes, and meshopt needs the triangle count to be a multiple of 4
+    pub fn validate(&self) -> Result<()> {
+        if !(3..=255).contains(&self.max_vertices) {
+            bail!("Meshlets need between 3 and 255 vertices")
+        }
+        if !(4..=512).contains(&self.max_triangles) || self.max_triangles % 4 != 0 {
+            bail!("Meshlets need between 4 and 512 triangles, in steps of 4")
+        }
+        if !(0.0..=1.0).contains(&self.cone_weight) {
+            bail!("The cone weight has to be between 0 and 1")
+        }
+        Ok(())
+    }
+}
+
+fn pack_triangles(triangles: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    triangles.chunks(4).map(|chunk| {
+        chunk
+            .iter()
+            .enumerate()
+            .fold(0, |packed, (i, index)| packed | (*index as u32) << (i << 3))
+    })
+}
+
+#[inline]
+fn pad_to_alignment(data: &mut Vec<u32>, alignment: usize) {
+    data.resize(data.len().next_multiple_of(alignment.max(1)), 0);
+}
+
+//The meshlets of every submesh are laid out one after another
+fn pack_meshlets(
+    submesh_meshlets: &[(u32, meshopt::Meshlets)],
+    vertices: &[Vertex],
+    config: &MeshletConfig,
+) -> (Vec<Meshlet>, Vec<u32>) {
+    //Packing the triangles and computing the bounds is done in parallel, only laying out the streams is serial
+    let meshlets: Vec<_> = submesh_meshlets
+        .iter()
+        .flat_map(|(material_idx, meshlets)| {
+            meshlets.iter().map(move |meshlet| (*material_idx, meshlet))
+        })
+        .collect();
+    let packed: Vec<_> = meshlets
+        .par_iter()
+        .map(|(_, meshlet)| {
+            let triangles: Vec<_> = pack_triangles(meshlet.triangles).collect();
+            let aabb = AABB::from_vertices(meshlet.vertices.iter().map(|i| &vertices[*i as usize]));
+            (triangles, aabb)
+        })
+        .collect();
+
+    let mut vertex_stream = Vec::new();
+    let mut primitive_stream = Vec::new();
+
+    let mut packed_meshlets: Vec<_> = meshlets
+        .iter()
+        .zip(packed)
+        .map(|((material_idx, meshlet), (triangles, aabb))| {
+            let (data_offset, primitive_offset) = match config.layout {
+                MeshletLayout::Interleaved => {
+                    let data_offset = vertex_stream.len();
+                    vertex_stream.extend_from_slice(meshlet.vertices);
+
+                    let primitive_offset = vertex_stream.len();
+                    vertex_stream.extend_from_slice(&triangles);
+
+                    (data_offset, primitive_offset)
+                }
+                MeshletLayout::StructOfArrays => {
+                    pad_to_alignment(&mut vertex_stream, config.alignment);
+                    let data_offset = vertex_stream.len();
+                    vertex_stream.extend_from_slice(meshlet.vertices);
+
+                    pad_to_alignment(&mut primitive_stream, config.alignment);
+                    let primitive_offset = primitive_stream.len();
+                    primitive_stream.extend_from_slice(&triangles);
+
+                    (data_offset, primitive_offset)
+                }
+            };
+
+            Meshlet::new(
+                aabb,
+                data_offset as _,
+                meshlet.vertices.len() as _,
+                (meshlet.triangles.len() / 3) as _,
+                primitive_offset as _,
+                *material_idx,
+            )
+        })
+        .collect();
+
+    //The primitive stream is appended after the vertex stream, so the offsets have to be rebased
+    if config.layout == MeshletLayout::StructOfArrays {
+        pad_to_alignment(&mut vertex_stream, config.alignment);
+        let primitive_base = vertex_stream.len() as u32;
+
+        packed_meshlets
+            .iter_mut()
+            .for_each(|meshlet| meshlet.primitive_offset += primitive_base);
+        vertex_stream.append(&mut primitive_stream);
+    }
+
+    (packed_meshlets, vertex_stream)
+}
+
+//Meshlets are built from a vertex cache optimized index buffer, so consecutive meshlets are close to each other
+fn build_meshlet_groups(meshlets: &[Meshlet]) -> Vec<MeshletGroup> {
+    meshlets
+        .chunks(MESHLET_GROUP_SIZE)
+        .enumerate()
+        .map(|(i, group_meshlets)| {
+            MeshletGroup::new(
+                AABB::from_aabbs(group_meshlets.iter().map(|meshlet| &meshlet.aabb)),
+                (i * MESHLET_GROUP_SIZE) as _,
+                group_meshlets.len() as _,
+            )
+        })
+        .collect()
+}
+
+//Either baked in memory or borrowed from a memory mapped meshlet cache, which is uploaded without copying it first
+#[derive(Clone, Debug)]
+pub enum MeshData<T> {
+    Owned(Vec<T>),
+    Mapped {
+        mapping: Arc<Mmap>,
+        offset: usize,
+        len: usize,
+    },
+}
+
+impl<T> Default for MeshData<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::Owned(Vec::new())
+    }
+}
+
+impl<T> From<Vec<T>> for MeshData<T> {
+    #[inline]
+    fn from(vec: Vec<T>) -> Self {
+        Self::Owned(vec)
+    }
+}
+
+impl<T: Pod> Deref for MeshData<T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        match self {
+            Self::Owned(vec) => vec,
+            //The offsets were checked for alignment when the cache was loaded
+            Self::Mapped {
+                mapping,
+                offset,
+                len,
+            } => bytemuck::cast_slice(&mapping[*offset..*offset + *len * mem::size_of::<T>()]),
+        }
+    }
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct MeshLevel {
-    pub vertices: Vec<Vertex>,
-    pub meshlets: Vec<Meshlet>,
-    pub meshlet_data: Vec<u32>,
+    //Empty if the level shares the vertices of level 0
+    pub vertices: MeshData<Vertex>,
+    pub meshlets: MeshData<Meshlet>,
+    pub meshlet_groups: MeshData<MeshletGroup>,
+    pub meshlet_data: MeshData<u32>,
+    pub shared_vertices: bool,
 }
 
 impl MeshLevel {
     #[inline]
-    pub fn new(vertices: Vec<Vertex>, meshlets: Vec<Meshlet>, meshlet_data: Vec<u32>) -> Self {
+    pub fn new(
+        vertices: impl Into<MeshData<Vertex>>,
+        meshlets: impl Into<MeshData<Meshlet>>,
+        meshlet_groups: impl Into<MeshData<MeshletGroup>>,
+        meshlet_data: impl Into<MeshData<u32>>,
+        shared_vertices: bool,
+    ) -> Self {
         Self {
-            vertices,
-            meshlets,
-            meshlet_data,
+            vertices: vertices.into(),
+            meshlets: meshlets.into(),
+            meshlet_groups: meshlet_groups.into(),
+            meshlet_data: meshlet_data.into(),
+            shared_vertices,
         }
     }
 }
 
+//Removes triangles which would produce NaN bounds or invisible meshlet primitives, returns the number of removed triangles
+fn remove_degenerate_triangles(vertices: &[Vertex], indices: &mut Vec<u32>) -> usize {
+    let num_triangles = indices.len() / 3;
+
+    let triangles: Vec<_> = indices
+        .chunks_exact(3)
+        .filter(|triangle| {
+            let Some(positions) = triangle
+                .iter()
+                .map(|index| vertices.get(*index as usize).map(|vertex| vertex.position))
+                .collect::<Option<Vec<_>>>()
+            else {
+                return false
+            };
+
+            positions.iter().all(|position| position.is_finite())
+                && (positions[1] - positions[0])
+                    .cross(positions[2] - positions[0])
+                    .length_squared()
+                    > 0.0
+        })
+        .flatten()
+        .copied()
+        .collect();
+
+    *indices = triangles;
+    num_triangles - indices.len() / 3
+}
+
+//Runs the closure over the indices of all submeshes as one index buffer, it has to keep the number of indices
+fn with_joined_indices<T>(
+    submeshes: &mut [(u32, Vec<u32>)],
+    f: impl FnOnce(&mut Vec<u32>) -> T,
+) -> T {
+    let mut indices: Vec<_> = submeshes
+        .iter()
+        .flat_map(|(_, indices)| indices.iter().copied())
+        .collect();
+    let result = f(&mut indices);
+
+    let mut remaining = &indices[..];
+    for (_, submesh_indices) in submeshes {
+        let (head, tail) = remaining.split_at(submesh_indices.len());
+        submesh_indices.copy_from_slice(head);
+        remaining = tail;
+    }
+    result
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Mesh {
     pub levels: Vec<MeshLevel>,
+    //Referenced by the meshlets of every level
+    pub materials: Vec<Material>,
+    //Referenced by the materials
+    pub mask_textures: Vec<MaskTexture>,
+    //Only glTF files have skins, they are read from the file again instead of being cached
+    pub skin: Option<Arc<MeshSkin>>,
 }
 
 impl Mesh {
-    pub fn new(source: MeshSource) -> Result<Self> {
-        let (mut vertices, mut indices) = match source {
-            MeshSource::Path(path) => {
-                let mesh = fast_obj::Mesh::new(path)?;
-
-                let mut vertices = vec![Default::default(); mesh.indices().len()];
-
-                let positions = mesh.positions();
-                let tex_coords = mesh.texcoords();
-                let normals = mesh.normals();
-                let indices = mesh.indices();
-
-                for (i, index) in indices.iter().enumerate() {
-                    let position_idx = 3 * index.p as usize;
-                    let tex_coord_idx = 2 * index.t as usize;
-                    let normal_idx = 3 * index.n as usize;
-
-                    vertices[i] = Vertex::new(
-                        Vec3::new(
-                            positions[position_idx],
-                            positions[position_idx + 1],
-                            positions[position_idx + 2],
-                        ),
-                        Vec2::new(tex_coords[tex_coord_idx], tex_coords[tex_coord_idx + 1]),
-                        Vec3::new(
-                            normals[normal_idx],
-                            normals[normal_idx + 1],
-                            normals[normal_idx + 2],
-                        ),
-                    );
-                }
+    //Has to be called on the asset workers, see WorkerPool::install, otherwise the levels and meshlets are baked on
+    //rayon's global pool
+    pub fn new(source: MeshSource, config: &MeshletConfig) -> Result<Self> {
+        let path = match source {
+            MeshSource::Path(path) => path,
+            MeshSource::Builtin(vertices, indices) => {
+                return Self::from_raw(vertices, indices, config)
+            }
+        };
+        let path = Path::new(&path);
+
+        let mut mesh = Self::load(path, config)?;
+        mesh.skin = skin::load(path, &mesh)?.map(Arc::new);
+        Ok(mesh)
+    }
+
+    //Bakes generated geometry like the one of MeshBuilder the same way as imported models, it just isn't cached
+    pub fn from_raw(
+        mut vertices: Vec<Vertex>,
+        mut indices: Vec<u32>,
+        config: &MeshletConfig,
+    ) -> Result<Self> {
+        if let Some(idx) = indices.iter().find(|idx| **idx as usize >= vertices.len()) {
+            bail!(
+                "Mesh references the vertex {idx}, but only has {} vertices",
+                vertices.len()
+            )
+        }
+        //meshopt only remaps whole triangles
+        if indices.len() % 3 != 0 {
+            eprintln!(
+                "Warning: Mesh has faces which are not triangles, dropping the trailing indices"
+            );
+            indices.truncate(indices.len() - indices.len() % 3);
+        }
 
-                let (vertex_count, remap) = meshopt::generate_vertex_remap(&vertices, None);
-                vertices.shrink_to(vertex_count);
+        mesh_util::weld_positions(&mut vertices, config.weld_tolerance);
 
-                (
-                    meshopt::remap_vertex_buffer(&vertices, vertex_count, &remap),
-                    meshopt::remap_index_buffer(None, indices.len(), &remap),
+        let (vertex_count, remap) = meshopt::generate_vertex_remap(&vertices, Some(&indices));
+        Self::bake(
+            "builtin mesh",
+            meshopt::remap_vertex_buffer(&vertices, vertex_count, &remap),
+            meshopt::remap_index_buffer(Some(&indices), indices.len(), &remap),
+            &[Submesh::new(0, indices.len(), 0)],
+            vec![Material::default()],
+            Vec::new(),
+            config,
+        )
+    }
+
+    fn load(path: &Path, config: &MeshletConfig) -> Result<Self> {
+        let source_hash = mesh_cache::hash_file(path)?;
+        match mesh_cache::load(path, source_hash, config) {
+            Ok(Some(mesh)) => return Ok(mesh),
+            Ok(None) => {}
+            Err(error) => {
+                eprintln!(
+                    "Warning: Ignoring the broken meshlet cache of {}: {error}",
+                    path.display()
                 )
             }
-            MeshSource::Builtin(vertices, indices) => (vertices, indices),
-        };
+        }
+
+        let mut imported = mesh_import::import(path)?;
+        //The skins of glTF files are matched to the baked vertices by their value, so they can't be moved
+        if !mesh_import::is_gltf(path) {
+            let num_welded =
+                mesh_util::weld_positions(&mut imported.vertices, config.weld_tolerance);
+            if num_welded > 0 {
+                println!("Welded {num_welded} vertices of {}", path.display());
+            }
+        }
+
+        //Remapping keeps the order of the indices, so the submeshes stay valid
+        let (vertex_count, remap) =
+            meshopt::generate_vertex_remap(&imported.vertices, Some(&imported.indices));
+        let mesh = Self::bake(
+            &path.display().to_string(),
+            meshopt::remap_vertex_buffer(&imported.vertices, vertex_count, &remap),
+            meshopt::remap_index_buffer(Some(&imported.indices), imported.indices.len(), &remap),
+            &imported.submeshes,
+            imported.materials,
+            imported.mask_textures,
+            config,
+        )?;
+
+        //A missing cache only costs time on the next run, so failing to write it isn't an error
+        if let Err(error) = mesh_cache::store(path, source_hash, config, &mesh) {
+            eprintln!(
+                "Warning: Failed to write the meshlet cache of {}: {error}",
+                path.display()
+            );
+        }
+
+        Ok(mesh)
+    }
+
+    fn bake(
+        name: &str,
+        mut vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+        submeshes: &[Submesh],
+        materials: Vec<Material>,
+        mask_textures: Vec<MaskTexture>,
+        config: &MeshletConfig,
+    ) -> Result<Self> {
+        if let Some(material) = materials.iter().find(|material| {
+            material.mask_texture_idx != NO_MASK_TEXTURE
+                && material.mask_texture_idx as usize >= mask_textures.len()
+        }) {
+            bail!(
+                "{name} references the mask texture {} which doesn't exist",
+                material.mask_texture_idx
+            )
+        }
+        if mask_textures.iter().any(|mask_texture| {
+            mask_texture.width == 0
+                || mask_texture.texels.len() != (mask_texture.width * mask_texture.height) as usize
+        }) {
+            bail!("{name} has a mask texture whose size doesn't match its texels")
+        }
+
+        //Every submesh is optimized and simplified on its own, so no meshlet mixes materials
+        let mut submesh_indices: Vec<(u32, Vec<u32>)> = Vec::new();
+        for submesh in submeshes {
+            let Some(range) =
+                indices.get(submesh.index_offset..submesh.index_offset + submesh.index_count)
+            else {
+                bail!("{name} has a submesh which exceeds its indices")
+            };
+            if submesh.material_idx as usize >= materials.len() {
+                bail!(
+                    "{name} references the material {} which doesn't exist",
+                    submesh.material_idx
+                )
+            }
+
+            if range.len() % 3 != 0 {
+                eprintln!(
+                    "Warning: {name} has faces which are not triangles, dropping the trailing indices"
+                );
+            }
+            let range = &range[..range.len() - range.len() % 3];
+
+            match submesh_indices
+                .iter_mut()
+                .find(|(material_idx, _)| *material_idx == submesh.material_idx)
+            {
+                Some((_, indices)) => indices.extend_from_slice(range),
+                None => submesh_indices.push((submesh.material_idx, range.to_vec())),
+            }
+        }
+
+        let num_degenerate_triangles: usize = submesh_indices
+            .iter_mut()
+            .map(|(_, indices)| remove_degenerate_triangles(&vertices, indices))
+            .sum();
+        if num_degenerate_triangles > 0 {
+            eprintln!("Warning: Skipped {num_degenerate_triangles} degenerate triangles in {name}");
+        }
+
+        submesh_indices.retain(|(_, indices)| !indices.is_empty());
+        if submesh_indices.is_empty() {
+            bail!("{name} has no triangles left after welding and removing degenerate triangles")
+        }
 
-        meshopt::optimize_vertex_cache_in_place(&mut indices, vertices.len());
-        meshopt::optimize_overdraw_in_place_decoder(&mut indices, &vertices, 1.01);
-        meshopt::optimize_vertex_fetch_in_place(&mut indices, &mut vertices);
+        for (_, indices) in &mut submesh_indices {
+            meshopt::optimize_vertex_cache_in_place(indices, vertices.len());
+            meshopt::optimize_overdraw_in_place_decoder(indices, &vertices, 1.01);
+        }
+        let num_indices = with_joined_indices(&mut submesh_indices, |indices| {
+            meshopt::optimize_vertex_fetch_in_place(indices, &mut vertices);
+            indices.len()
+        });
 
-        let num_levels = 12;
+        //Interleaved normals and texture coordinates for the attribute aware simplifier
+        let attributes: Vec<f32> = if config.lod.simplification == LodSimplification::Attributes {
+            vertices
+                .iter()
+                .flat_map(|vertex| {
+                    [
+                        vertex.normal.x,
+                        vertex.normal.y,
+                        vertex.normal.z,
+                        vertex.tex_coord.x,
+                        vertex.tex_coord.y,
+                    ]
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
 
+        //Every level is simplified from level 0, so they are independent of each other
         Ok(Self {
-            levels: (0..num_levels)
+            skin: None,
+            materials,
+            mask_textures,
+            levels: (0..config.lod.num_levels.clamp(1, MAX_LOD_LEVELS))
+                .into_par_iter()
                 .filter_map(|i| {
-                    let (level_vertices, level_indices) = if i == 0 {
-                        (vertices.clone(), indices.clone())
+                    let shared_vertices =
+                        i > 0 && config.lod.simplification != LodSimplification::Sloppy;
+
+                    let (level_vertices, level_submeshes) = if i == 0 {
+                        (vertices.clone(), submesh_indices.clone())
                     } else {
-                        let target_count = (indices.len() as f64 * 0.75f64.powf(i as f64)) as usize;
+                        let target_scale = (config.lod.target_ratio as f64).powi(i as i32);
 
-                        if target_count < 100 {
+                        if ((num_indices as f64 * target_scale) as usize) < 100 {
                             return None
                         }
 
-                        let mut indices = meshopt::simplify_sloppy_decoder(
-                            &indices,
-                            &vertices,
-                            target_count,
-                            1e2,
-                        );
-                        let vertices = meshopt::optimize_vertex_fetch(&mut indices, &vertices);
-                        (vertices, indices)
-                    };
+                        //Submeshes which are simplified away entirely are dropped from the level
+                        let mut level_submeshes: Vec<_> = submesh_indices
+                            .iter()
+                            .filter_map(|(material_idx, indices)| {
+                                let target_count = (indices.len() as f64 * target_scale) as usize;
 
-                    let meshlets = meshopt::build_meshlets(
-                        &level_indices,
-                        &VertexDataAdapter::new(
-                            bytemuck::cast_slice(&level_vertices),
-                            mem::size_of::<Vertex>(),
-                            0,
-                        )
-                        .unwrap(),
-                        MAX_VERTICES,
-                        MAX_TRIANGLES,
-                        CONE_WEIGHT,
-                    );
+                                let indices = if shared_vertices {
+                                    let mut indices = if attributes.is_empty() {
+                                        meshopt::simplify_decoder(
+                                            indices,
+                                            &vertices,
+                                            target_count,
+                                            config.lod.target_error,
+                                        )
+                                    } else {
+                                        let vertex_data_adapter = VertexDataAdapter::new(
+                                            bytemuck::cast_slice(&vertices),
+                                            mem::size_of::<Vertex>(),
+                                            0,
+                                        )
+                                        .unwrap();
+                                        meshopt::simplify_with_attributes_and_locks(
+                                            indices,
+                                            &vertex_data_adapter,
+                                            &attributes,
+                                            &[
+                                                NORMAL_WEIGHT,
+                                                NORMAL_WEIGHT,
+                                                NORMAL_WEIGHT,
+                                                TEX_COORD_WEIGHT,
+                                                TEX_COORD_WEIGHT,
+                                            ],
+                                            5 * mem::size_of::<f32>(),
+                                            &[],
+                                            target_count,
+                                            config.lod.target_error,
+                                            SimplifyOptions::LockBorder,
+                                            None,
+                                        )
+                                    };
+                                    meshopt::optimize_vertex_cache_in_place(
+                                        &mut indices,
+                                        vertices.len(),
+                                    );
+                                    indices
+                                } else {
+                                    meshopt::simplify_sloppy_decoder(
+                                        indices,
+                                        &vertices,
+                                        target_count,
+                                        config.lod.target_error,
+                                    )
+                                };
 
-                    let num_meshlet_data = meshlets
-                        .iter()
-                        .map(|meshlet| {
-                            meshlet.vertices.len() + ((meshlet.triangles.len() * 3 + 3) >> 2)
-                        })
-                        .sum();
+                                (!indices.is_empty()).then_some((*material_idx, indices))
+                            })
+                            .collect();
+                        if level_submeshes.is_empty() {
+                            return None
+                        }
+
+                        if shared_vertices {
+                            (Vec::new(), level_submeshes)
+                        } else {
+                            let vertices = with_joined_indices(&mut level_submeshes, |indices| {
+                                meshopt::optimize_vertex_fetch(indices, &vertices)
+                            });
+                            (vertices, level_submeshes)
+                        }
+                    };
 
-                    let mut meshlet_data = vec![0; num_meshlet_data];
+                    //Shared levels index the vertices of level 0
+                    let meshlet_vertices = if shared_vertices {
+                        &vertices
+                    } else {
+                        &level_vertices
+                    };
 
-                    let mut index = 0;
-                    let meshlets = meshlets
+                    let vertex_data_adapter = VertexDataAdapter::new(
+                        bytemuck::cast_slice(meshlet_vertices),
+                        mem::size_of::<Vertex>(),
+                        0,
+                    )
+                    .unwrap();
+                    let submesh_meshlets: Vec<_> = level_submeshes
                         .iter()
-                        .map(|meshlet| {
-                            let data_offset = index;
-
-                            for vertex in meshlet.vertices {
-                                meshlet_data[index] = *vertex;
-                                index += 1;
-                            }
-
-                            let num_packed_indices = (meshlet.triangles.len() + 3) >> 2;
-                            for j in 0..num_packed_indices {
-                                let triangle_offset = j << 2;
-                                meshlet_data[index] = (meshlet.triangles[triangle_offset] as u32)
-                                    | (meshlet
-                                        .triangles
-                                        .get(triangle_offset + 1)
-                                        .copied()
-                                        .unwrap_or_default()
-                                        as u32)
-                                        << 8
-                                    | (meshlet
-                                        .triangles
-                                        .get(triangle_offset + 2)
-                                        .copied()
-                                        .unwrap_or_default()
-                                        as u32)
-                                        << 16
-                                    | (meshlet
-                                        .triangles
-                                        .get(triangle_offset + 3)
-                                        .copied()
-                                        .unwrap_or_default()
-                                        as u32)
-                                        << 24;
-                                index += 1;
-                            }
-
-                            let aabb = AABB::from_vertices(
-                                meshlet.vertices.iter().map(|i| &vertices[*i as usize]),
-                            );
-                            Meshlet::new(
-                                aabb,
-                                data_offset as _,
-                                meshlet.vertices.len() as _,
-                                (meshlet.triangles.len() / 3) as _,
+                        .map(|(material_idx, indices)| {
+                            (
+                                *material_idx,
+                                meshopt::build_meshlets(
+                                    indices,
+                                    &vertex_data_adapter,
+                                    config.max_vertices,
+                                    config.max_triangles,
+                                    config.cone_weight,
+                                ),
                             )
                         })
                         .collect();
 
-                    Some(MeshLevel {
-                        vertices: level_vertices,
+                    let (meshlets, meshlet_data) =
+                        pack_meshlets(&submesh_meshlets, meshlet_vertices, config);
+                    let meshlet_groups = build_meshlet_groups(&meshlets);
+
+                    Some(MeshLevel::new(
+                        level_vertices,
                         meshlets,
+                        meshlet_groups,
                         meshlet_data,
-                    })
+                        shared_vertices,
+                    ))
                 })
                 .collect(),
         })
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct MeshBuffers {
     pub levels: Vec<MeshLevelBuffers>,
+    //Holds Material, None for meshes which failed to load
+    pub material_buffer: Option<BufferRange>,
+    //Drawn with the alpha tested pipelines if any of the materials is
+    pub alpha_tested: bool,
+    pub quantization: VertexQuantization,
+    pub skin: Option<SkinBuffers>,
+}
+
+//The vertex buffers of the levels of a skinned mesh hold the skinned vertices, which are written by the SkinningPass
+//every frame. It also fits the bounds of the meshlets to them
+#[derive(Clone)]
+pub struct SkinBuffers {
+    pub mesh_skin: Arc<MeshSkin>,
+    //Only the levels with their own vertices
+    pub levels: Vec<SkinnedLevelBuffers>,
+}
+
+#[derive(Clone)]
+pub struct SkinnedLevelBuffers {
+    pub level_idx: usize,
+    pub rest_vertex_buffer: BufferRange,
+    //Holds VertexSkin
+    pub vertex_skin_buffer: BufferRange,
+    //Holds MorphDelta, None without morph targets
+    pub morph_delta_buffer: Option<BufferRange>,
+    pub num_vertices: usize,
 }
 
 impl MeshBuffers {
+    //Covers every level, the quantization is centered on the bounds of the first one and scaled to half their extent
     #[inline]
+    pub fn aabb(&self) -> AABB {
+        AABB {
+            min: self.quantization.position_offset - self.quantization.position_scale,
+            max: self.quantization.position_offset + self.quantization.position_scale,
+        }
+    }
+
+    //The mask textures of the mesh start at first_mask_texture in the MaskTextures, without it the materials don't
+    //sample any
     pub unsafe fn new(
-        device: Arc<Device>,
-        queue: vk::Queue,
-        allocator: Allocator,
-        source: MeshSource,
+        arena: &mut BufferArena,
+        mesh: &Mesh,
+        first_mask_texture: Option<u32>,
     ) -> Result<Self> {
-        let mesh = Mesh::new(source)?;
+        let mut levels: Vec<MeshLevelBuffers> = Vec::with_capacity(mesh.levels.len());
+
+        //Every level is simplified from level 0, so its bounds cover all of them
+        let quantization = match mesh.levels.first() {
+            Some(first_level) => VertexQuantization::from_vertices(first_level.vertices.iter()),
+            None => VertexQuantization::default(),
+        };
+
+        let mut skinned_levels = Vec::new();
+        for (level_idx, level) in mesh.levels.iter().enumerate() {
+            let vertex_buffer = match levels.first() {
+                Some(first_level) if level.shared_vertices => first_level.vertex_buffer.clone(),
+                _ => {
+                    let vertices: Vec<_> = level
+                        .vertices
+                        .iter()
+                        .map(|vertex| quantization.pack(vertex))
+                        .collect();
+
+                    //The skinned vertices start out in the rest pose as well
+                    if let Some(mesh_skin) = &mesh.skin {
+                        let vertex_skins: &[VertexSkin] = &mesh_skin.level_vertex_skins[level_idx];
+                        let morph_deltas: &[MorphDelta] = &mesh_skin.level_morph_deltas[level_idx];
+                        skinned_levels.push(SkinnedLevelBuffers {
+                            level_idx,
+                            rest_vertex_buffer: arena.upload(&vertices)?,
+                            vertex_skin_buffer: arena.upload(vertex_skins)?,
+                            morph_delta_buffer: if morph_deltas.is_empty() {
+                                None
+                            } else {
+                                Some(arena.upload(morph_deltas)?)
+                            },
+                            num_vertices: vertices.len(),
+                        });
+                    }
+
+                    arena.upload(&vertices)?
+                }
+            };
+
+            levels.push(MeshLevelBuffers::new(
+                arena,
+                vertex_buffer,
+                &level.meshlets,
+                &level.meshlet_groups,
+                &level.meshlet_data,
+            )?);
+        }
 
-        let levels = mesh
-            .levels
+        let materials: Vec<_> = mesh
+            .materials
             .iter()
-            .map(|level| {
-                MeshLevelBuffers::new(
-                    device.clone(),
-                    queue,
-                    allocator,
-                    &level.vertices,
-                    &level.meshlets,
-                    &level.meshlet_data,
-                )
+            .map(|material| {
+                Material {
+                    mask_texture_idx: match first_mask_texture {
+                        Some(first_mask_texture)
+                            if material.mask_texture_idx != NO_MASK_TEXTURE =>
+                        {
+                            first_mask_texture + material.mask_texture_idx
+                        }
+                        _ => NO_MASK_TEXTURE,
+                    },
+                    ..*material
+                }
             })
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(Self { levels })
+            .collect();
+
+        Ok(Self {
+            levels,
+            material_buffer: Some(arena.upload(&materials)?),
+            alpha_tested: materials.iter().any(Material::alpha_tested),
+            quantization,
+            skin: mesh.skin.clone().map(|mesh_skin| {
+                SkinBuffers {
+                    mesh_skin,
+                    levels: skinned_levels,
+                }
+            }),
+        })
     }
 }
 
 #[derive(Clone)]
 pub struct MeshLevelBuffers {
-    pub vertex_buffer: Buffer,
-    pub meshlet_buffer: Buffer,
-    pub meshlet_data_buffer: Buffer,
+    //Holds PackedVertex, shared between the levels of a mesh unless it was simplified with LodSimplification::Sloppy
+    pub vertex_buffer: BufferRange,
+    pub meshlet_buffer: BufferRange,
+    pub meshlet_group_buffer: BufferRange,
+    pub meshlet_data_buffer: BufferRange,
     pub num_meshlets: usize,
+    pub num_meshlet_groups: usize,
 }
 
 impl MeshLevelBuffers {
     #[inline]
     pub unsafe fn new(
-        device: Arc<Device>,
-        queue: vk::Queue,
-        allocator: Allocator,
-        vertices: &[Vertex],
+        arena: &mut BufferArena,
+        vertex_buffer: BufferRange,
         meshlets: &[Meshlet],
+        meshlet_groups: &[MeshletGroup],
         meshlet_data: &[u32],
     ) -> Result<Self> {
-        let vertex_buffer = Buffer::new_device_local(device.clone(), queue, allocator, vertices)?;
-        let meshlet_buffer = Buffer::new_device_local(device.clone(), queue, allocator, meshlets)?;
-        let meshlet_data_buffer = Buffer::new_device_local(device, queue, allocator, meshlet_data)?;
+        let meshlet_buffer = arena.upload(meshlets)?;
+        let meshlet_group_buffer = arena.upload(meshlet_groups)?;
+        let meshlet_data_buffer = arena.upload(meshlet_data)?;
 
         Ok(Self {
             vertex_buffer,
             meshlet_buffer,
+            meshlet_group_buffer,
             meshlet_data_buffer,
             num_meshlets: meshlets.len(),
+            num_meshlet_groups: meshlet_groups.len(),
         })
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum MeshSource {
-    Path(&'static str),
+    Path(String),
     Builtin(Vec<Vertex>, Vec<u32>),
 }
 
-#[derive(Clone)]
+impl From<MeshBuilder> for MeshSource {
+    #[inline]
+    fn from(builder: MeshBuilder) -> Self {
+        let (vertices, indices) = builder.build();
+        Self::Builtin(vertices, indices)
+    }
+}
+
 pub struct MeshCollection {
-    mesh_buffers: Vec<MeshBuffers>,
+    //Meshes which are still loading share the placeholder buffers
+    mesh_buffers: Vec<Arc<MeshBuffers>>,
+    //Baked in the background and uploaded on the render thread, in the order they finish
+    loaded_meshes: Receiver<(usize, Result<Mesh>)>,
+    num_loading: usize,
+    cancel_loading: Arc<AtomicBool>,
+    //Holds the buffers of every mesh, so the collection only needs a few large allocations
+    arena: BufferArena,
     _mesh_level_addresses: Buffer,
-    _mesh_addresses: Buffer,
+    //Written to the draw constants of every frame, see DrawConstants
+    mesh_addresses: Buffer,
+    pub mask_textures: MaskTextures,
     pub descriptor_set: vk::DescriptorSet,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    //The address tables are read by the culling on the compute queue
+    sharing_family_indices: Vec<u32>,
+    queue: TransferQueue,
+    allocator: Allocator,
+    device: Arc<Device>,
+}
+
+impl Drop for MeshCollection {
+    fn drop(&mut self) {
+        self.cancel_loading.store(true, Ordering::Relaxed);
+
+        unsafe {
+            self.device
+                .free_descriptor_sets(self.descriptor_pool, slice::from_ref(&self.descriptor_set))
+                .unwrap();
+        }
+        resource_registry::track_destroyed(ResourceKind::DescriptorSet);
+    }
+}
+
+//Has to match Mesh in shaders/types.glsl
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+struct GpuMesh {
+    levels: vk::DeviceAddress,
+    materials: vk::DeviceAddress,
+    num_levels: u32,
+    quantization: VertexQuantization,
+    padding: u32,
+}
+
+//Returns the table of level addresses and the table of meshes pointing into it
+unsafe fn create_address_buffers(
+    device: &Arc<Device>,
+    queue: TransferQueue,
+    allocator: Allocator,
+    mesh_buffers: &[Arc<MeshBuffers>],
+    sharing_family_indices: &[u32],
+) -> Result<(Buffer, Buffer)> {
+    let mut mesh_level_addresses: Vec<_> = mesh_buffers
+        .iter()
+        .flat_map(|mesh_buffers| mesh_buffers.levels.iter())
+        .flat_map(|level_buffer| {
+            [
+                level_buffer.vertex_buffer.device_address,
+                level_buffer.meshlet_buffer.device_address,
+                level_buffer.meshlet_group_buffer.device_address,
+                level_buffer.meshlet_data_buffer.device_address,
+                level_buffer.num_meshlets as u64 | (level_buffer.num_meshlet_groups as u64) << 32,
+            ]
+        })
+        .collect();
+
+    //Empty buffers can't be created
+    if mesh_level_addresses.is_empty() {
+        mesh_level_addresses.resize(5, 0);
+    }
+
+    let mesh_level_addresses_buffer = Buffer::new_device_local(
+        device.clone(),
+        queue,
+        allocator,
+        &mesh_level_addresses,
+        sharing_family_indices,
+    )?;
+
+    let mesh_addresses: Vec<_> = {
+        let mut offset = 0;
+        mesh_buffers
+            .iter()
+            .map(|mesh_buffers| {
+                let result = GpuMesh {
+                    levels: mesh_level_addresses_buffer.device_address
+                        + (offset * (5 * mem::size_of::<vk::DeviceAddress>())) as u64,
+                    materials: mesh_buffers
+                        .material_buffer
+                        .as_ref()
+                        .map_or(0, |material_buffer| material_buffer.device_address),
+                    num_levels: mesh_buffers.levels.len() as _,
+                    quantization: mesh_buffers.quantization,
+                    padding: 0,
+                };
+                offset += mesh_buffers.levels.len();
+                result
+            })
+            .collect()
+    };
+
+    let mesh_addresses_buffer = Buffer::new_device_local(
+        device.clone(),
+        queue,
+        allocator,
+        &mesh_addresses,
+        sharing_family_indices,
+    )?;
+
+    Ok((mesh_level_addresses_buffer, mesh_addresses_buffer))
+}
+
+unsafe fn write_descriptor_set(
+    device: &Device,
+    descriptor_set: vk::DescriptorSet,
+    mesh_addresses_buffer: &Buffer,
+) {
+    let descriptor_buffer_info = vk::DescriptorBufferInfo::default()
+        .buffer(mesh_addresses_buffer.buffer)
+        .range(mesh_addresses_buffer.size);
+
+    let write_descriptor_set = vk::WriteDescriptorSet::default()
+        .dst_set(descriptor_set)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(slice::from_ref(&descriptor_buffer_info));
+
+    device.update_descriptor_sets(slice::from_ref(&write_descriptor_set), &[]);
 }
 
 impl MeshCollection {
+    //Returns immediately with placeholders, the meshes are streamed in by poll_loaded once they are baked
     pub unsafe fn new(
         device: &Arc<Device>,
-        queue: vk::Queue,
+        queue: TransferQueue,
+        transfer_queue: TransferQueue,
         allocator: Allocator,
         descriptor_pool: vk::DescriptorPool,
         descriptor_set_layout: vk::DescriptorSetLayout,
-        sources: impl IntoIterator<Item = MeshSource>,
+        mask_descriptor_set_layout: vk::DescriptorSetLayout,
+        sources: impl IntoIterator<Item = impl Into<MeshSource>>,
+        config: &MeshletConfig,
+        asset_workers: &WorkerPool,
+        sharing_family_indices: Vec<u32>,
     ) -> Result<Self> {
-        let mesh_buffers = sources
-            .into_iter()
-            .map(|source| MeshBuffers::new(device.clone(), queue, allocator, source))
-            .collect::<Result<Vec<_>>>()?;
+        let sources: Vec<MeshSource> = sources.into_iter().map(Into::into).collect();
+        let num_loading = sources.len();
 
-        let mesh_level_addresses: Vec<_> = mesh_buffers
-            .iter()
-            .flat_map(|mesh_buffers| mesh_buffers.levels.iter())
-            .flat_map(|level_buffer| {
-                [
-                    level_buffer.vertex_buffer.device_address,
-                    level_buffer.meshlet_buffer.device_address,
-                    level_buffer.meshlet_data_buffer.device_address,
-                    0,
-                ]
-            })
-            .collect();
+        let mut arena = BufferArena::new(
+            device.clone(),
+            transfer_queue,
+            allocator,
+            sharing_family_indices.clone(),
+        )?;
 
-        let mesh_level_addresses_buffer =
-            Buffer::new_device_local(device.clone(), queue, allocator, &mesh_level_addresses)?;
+        //Drawn in place of the meshes which are still loading. Baked on the asset workers like the loaded meshes, so
+        //the render thread doesn't start rayon's global pool
+        let (vertices, indices) = MeshBuilder::new().cube(1.0).build();
+        let placeholder_mesh =
+            asset_workers.install(|| Mesh::from_raw(vertices, indices, config))?;
+        let placeholder = Arc::new(MeshBuffers::new(&mut arena, &placeholder_mesh, None)?);
+        arena.flush()?;
+        let mesh_buffers = vec![placeholder; num_loading];
 
-        let mesh_addresses: Vec<_> = {
-            let mut offset = 0;
-            mesh_buffers
-                .iter()
-                .flat_map(|mesh_buffers| {
-                    let result = [
-                        mesh_level_addresses_buffer.device_address
-                            + (offset * (4 * mem::size_of::<vk::DeviceAddress>())) as u64,
-                        mesh_buffers.levels.len() as _,
-                    ];
-                    offset += mesh_buffers.levels.len();
-                    result
-                })
-                .collect()
-        };
+        let (sender, loaded_meshes) = mpsc::channel();
+        let cancel_loading = Arc::new(AtomicBool::new(false));
+
+        {
+            let asset_workers = asset_workers.clone();
+            let config = *config;
+            let cancel_loading = cancel_loading.clone();
+
+            thread::Builder::new()
+                .name("asset-loader".to_owned())
+                .spawn(move || {
+                    let sender = Mutex::new(sender);
+                    asset_workers.map(sources.into_iter().enumerate(), |(idx, source)| {
+                        //The collection was replaced, so nobody is waiting for the remaining meshes
+                        if cancel_loading.load(Ordering::Relaxed) {
+                            return
+                        }
+
+                        let mesh = Mesh::new(source, &config);
+                        sender.lock().unwrap().send((idx, mesh)).ok();
+                    });
+                })?;
+        }
 
-        let mesh_addresses_buffer =
-            Buffer::new_device_local(device.clone(), queue, allocator, &mesh_addresses)?;
+        let (mesh_level_addresses_buffer, mesh_addresses_buffer) = create_address_buffers(
+            device,
+            queue,
+            allocator,
+            &mesh_buffers,
+            &sharing_family_indices,
+        )?;
 
         let descriptor_set = device.allocate_descriptor_sets(
             &vk::DescriptorSetAllocateInfo::default()
                 .descriptor_pool(descriptor_pool)
                 .set_layouts(slice::from_ref(&descriptor_set_layout)),
         )?[0];
+        resource_registry::track_created(ResourceKind::DescriptorSet);
 
-        //Update storage buffer
-        let descriptor_buffer_info = vk::DescriptorBufferInfo::default()
-            .buffer(mesh_addresses_buffer.buffer)
-            .range(mesh_addresses_buffer.size);
-
-        let write_descriptor_set = vk::WriteDescriptorSet::default()
-            .dst_set(descriptor_set)
-            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-            .buffer_info(slice::from_ref(&descriptor_buffer_info));
+        write_descriptor_set(device, descriptor_set, &mesh_addresses_buffer);
 
-        device.update_descriptor_sets(slice::from_ref(&write_descriptor_set), &[]);
+        let mask_textures = MaskTextures::new(
+            device,
+            queue,
+            allocator,
+            descriptor_pool,
+            mask_descriptor_set_layout,
+        )?;
 
         Ok(Self {
             mesh_buffers,
+            loaded_meshes,
+            num_loading,
+            cancel_loading,
+            arena,
             _mesh_level_addresses: mesh_level_addresses_buffer,
-            _mesh_addresses: mesh_addresses_buffer,
+            mesh_addresses: mesh_addresses_buffer,
+            mask_textures,
             descriptor_set,
+            descriptor_pool,
+            descriptor_set_layout,
+            sharing_family_indices,
+            queue,
+            allocator,
+            device: device.clone(),
         })
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub unsafe fn draw_mesh(
+    #[inline]
+    pub fn num_loading(&self) -> usize {
+        self.num_loading
+    }
+
+    //Uploads the meshes which finished baking since the last call, returns true if any were added
+    pub unsafe fn poll_loaded(&mut self, deletion_queue: &mut DeletionQueue) -> Result<bool> {
+        let loaded: Vec<_> = self.loaded_meshes.try_iter().collect();
+        self.upload_loaded(loaded, deletion_queue)
+    }
+
+    //Blocks until every mesh is uploaded, for captures and benchmarks which must not see placeholders
+    pub unsafe fn wait_until_loaded(&mut self, deletion_queue: &mut DeletionQueue) -> Result<()> {
+        let loaded: Vec<_> = self.loaded_meshes.iter().take(self.num_loading).collect();
+        self.upload_loaded(loaded, deletion_queue)?;
+        Ok(())
+    }
+
+    unsafe fn upload_loaded(
+        &mut self,
+        loaded: Vec<(usize, Result<Mesh>)>,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<bool> {
+        if loaded.is_empty() {
+            return Ok(false)
+        }
+
+        //A broken mesh is replaced by an empty one, so the mesh indices of the instances stay valid
+        for (idx, mesh) in loaded {
+            let mesh_buffers = mesh
+                .and_then(|mesh| {
+                    let first_mask_texture = self.mask_textures.add(&mesh.mask_textures)?;
+                    MeshBuffers::new(&mut self.arena, &mesh, first_mask_texture)
+                })
+                .map(|mesh_buffers| {
+                    //Helps tuning the meshlet size, fewer meshlets mean fewer task shader invocations
+                    let num_meshlets: Vec<_> = mesh_buffers
+                        .levels
+                        .iter()
+                        .map(|level| level.num_meshlets.to_string())
+                        .collect();
+                    println!(
+                        "Mesh {idx} has {} meshlets per level",
+                        num_meshlets.join("/")
+                    );
+                    mesh_buffers
+                })
+                .unwrap_or_else(|error| {
+                    eprintln!("Warning: Skipping mesh: {error}");
+                    MeshBuffers::default()
+                });
+
+            self.mesh_buffers[idx] = Arc::new(mesh_buffers);
+            self.num_loading -= 1;
+        }
+
+        //Everything which finished baking is uploaded with as few submits as the staging belt allows
+        self.arena.flush()?;
+        self.mask_textures.flush(deletion_queue)?;
+
+        //The address tables and the descriptor set are replaced, since the frames in flight may still read them
+        let (mesh_level_addresses_buffer, mesh_addresses_buffer) = create_address_buffers(
+            &self.device,
+            self.queue,
+            self.allocator,
+            &self.mesh_buffers,
+            &self.sharing_family_indices,
+        )?;
+        let descriptor_set = self.device.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(slice::from_ref(&self.descriptor_set_layout)),
+        )?[0];
+        resource_registry::track_created(ResourceKind::DescriptorSet);
+        write_descriptor_set(&self.device, descriptor_set, &mesh_addresses_buffer);
+
+        deletion_queue.push_buffer(mem::replace(
+            &mut self._mesh_level_addresses,
+            mesh_level_addresses_buffer,
+        ));
+        deletion_queue.push_buffer(mem::replace(
+            &mut self.mesh_addresses,
+            mesh_addresses_buffer,
+        ));
+        deletion_queue.push_descriptor_set(
+            mem::replace(&mut self.descriptor_set, descriptor_set),
+            self.descriptor_pool,
+        );
+
+        Ok(true)
+    }
+
+    //Address of the addresses of every mesh, which the geometry shaders index with the mesh of the instance
+    #[inline]
+    pub fn meshes_address(&self) -> vk::DeviceAddress {
+        self.mesh_addresses.device_address
+    }
+
+    //None if the mesh has no levels to draw. The level is written to the frame, the draw only pushes the instance
+    pub unsafe fn mesh_draw(
         &self,
-        ctx: &RenderCtx,
-        command_buffer: vk::CommandBuffer,
-        position: &Vec3,
-        scale: f32,
-        rotation: &Quat,
+        instance_idx: u32,
         mesh_idx: u32,
         level_idx: u32,
-    ) {
-        #[repr(C)]
-        struct Constants {
-            translation_x: f32,
-            translation_y: f32,
-            translation_z: f32,
-            scale: f32,
-            rotation_x: f32,
-            rotation_y: f32,
-            rotation_z: f32,
-            rotation_w: f32,
-            mesh_idx: u32,
-            level_idx: u32,
+        frame: &Frame,
+    ) -> Option<MeshDraw> {
+        let mesh_buffers = &self.mesh_buffers[mesh_idx as usize];
+        if mesh_buffers.levels.is_empty() {
+            return None
         }
 
-        let mesh_buffers = &self.mesh_buffers[mesh_idx as usize];
         let level_idx = level_idx.clamp(0, (mesh_buffers.levels.len() - 1) as u32);
+        frame
+            .instance_levels_buffer
+            .write_at(instance_idx as usize * mem::size_of::<u32>(), &level_idx);
 
-        let constants = Constants {
-            translation_x: position.x,
-            translation_y: position.y,
-            translation_z: position.z,
-            scale,
-            rotation_x: rotation.x,
-            rotation_y: rotation.y,
-            rotation_z: rotation.z,
-            rotation_w: rotation.w,
-            mesh_idx,
-            level_idx,
-        };
+        Some(MeshDraw {
+            push_constants: DrawPushConstants {
+                draw_constants_address: frame.draw_constants_buffer.device_address,
+                instance_idx,
+                padding: 0,
+            },
+            //One task shader workgroup per meshlet group, which launches the mesh shaders of its visible meshlets
+            num_meshlet_groups: mesh_buffers.levels[level_idx as usize].num_meshlet_groups as _,
+            num_meshlets: mesh_buffers.levels[level_idx as usize].num_meshlets as _,
+            alpha_test: mesh_buffers.alpha_tested,
+            //The bounds the instances are culled with only cover the rest pose
+            conditional: mesh_buffers.skin.is_none(),
+        })
+    }
 
-        ctx.device_loader.cmd_push_constants(
-            command_buffer,
-            ctx.geometry_pass.pipeline_layout,
-            vk::ShaderStageFlags::MESH_EXT,
-            0,
-            slice::from_raw_parts(
-                &constants as *const Constants as *const _,
-                mem::size_of::<Constants>(),
-            ),
-        );
+    pub fn mesh_buffers_at(&self, idx: usize) -> &MeshBuffers {
+        &self.mesh_buffers[idx]
+    }
 
-        let num_meshlets = mesh_buffers.levels[level_idx as usize].num_meshlets;
+    #[inline]
+    pub fn skinned_meshes(&self) -> impl Iterator<Item = &MeshBuffers> {
+        self.mesh_buffers
+            .iter()
+            .map(|mesh_buffers| &**mesh_buffers)
+            .filter(|mesh_buffers| mesh_buffers.skin.is_some())
+    }
+}
 
-        ctx.mesh_shader_loader.cmd_draw_mesh_tasks(
-            command_buffer,
-            ((num_meshlets * ctx.workgroup_size as usize + ctx.workgroup_size as usize - 1)
-                / ctx.workgroup_size as usize) as u32,
-            1,
-            1,
-        )
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex::new(Vec3::new(x, y, z), Vec2::ZERO, Vec3::Z)
     }
 
-    pub fn mesh_buffers_at(&self, idx: usize) -> &MeshBuffers {
-        &self.mesh_buffers[idx]
+    //A quad of two triangles
+    fn quad() -> Vec<Vertex> {
+        vec![
+            vertex(0.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 0.0),
+            vertex(1.0, 1.0, 0.0),
+            vertex(0.0, 1.0, 0.0),
+        ]
+    }
+
+    fn is_finite(aabb: &AABB) -> bool {
+        aabb.min.is_finite() && aabb.max.is_finite()
+    }
+
+    #[test]
+    fn out_of_range_index() {
+        assert!(Mesh::from_raw(quad(), vec![0, 1, 4], &MeshletConfig::default()).is_err());
+    }
+
+    #[test]
+    fn all_degenerate() {
+        let mut vertices = quad();
+        vertices.push(vertex(f32::NAN, 0.0, 0.0));
+        //Collinear, repeated and non-finite triangles
+        let indices = vec![0, 1, 1, 0, 0, 0, 0, 1, 4];
+
+        assert!(Mesh::from_raw(vertices, indices, &MeshletConfig::default()).is_err());
+    }
+
+    #[test]
+    fn trailing_indices() {
+        let mesh =
+            Mesh::from_raw(quad(), vec![0, 1, 2, 0, 2, 3, 0], &MeshletConfig::default()).unwrap();
+
+        let triangle_count: u32 = mesh.levels[0]
+            .meshlets
+            .iter()
+            .map(|meshlet| meshlet.triangle_count)
+            .sum();
+        assert_eq!(triangle_count, 2);
+    }
+
+    #[test]
+    fn empty_mesh() {
+        assert!(Mesh::from_raw(Vec::new(), Vec::new(), &MeshletConfig::default()).is_err());
+    }
+
+    #[test]
+    fn finite_bounds() {
+        let mut vertices = quad();
+        vertices.push(vertex(f32::INFINITY, 0.0, 0.0));
+        let indices = vec![0, 1, 2, 0, 2, 3, 0, 1, 4];
+
+        let mut remaining_indices = indices.clone();
+        assert_eq!(
+            remove_degenerate_triangles(&vertices, &mut remaining_indices),
+            1
+        );
+        assert_eq!(remaining_indices, [0, 1, 2, 0, 2, 3]);
+
+        let mesh = Mesh::from_raw(vertices, indices, &MeshletConfig::default()).unwrap();
+        for level in &mesh.levels {
+            assert!(level
+                .meshlets
+                .iter()
+                .all(|meshlet| is_finite(&meshlet.aabb)));
+            assert!(level
+                .meshlet_groups
+                .iter()
+                .all(|meshlet_group| is_finite(&meshlet_group.aabb)));
+        }
     }
 }
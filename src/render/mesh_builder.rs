@@ -0,0 +1,290 @@
+use std::{
+    collections::HashMap,
+    f32::consts::{PI, TAU},
+};
+
+use glam::{Vec2, Vec3};
+
+use crate::render::mesh::Vertex;
+
+//The corners and faces of an icosahedron, every face winds counter-clockwise around its outward normal
+const ICOSAHEDRON_FACES: [[u32; 3]; 20] = [
+    [0, 11, 5],
+    [0, 5, 1],
+    [0, 1, 7],
+    [0, 7, 10],
+    [0, 10, 11],
+    [1, 5, 9],
+    [5, 11, 4],
+    [11, 10, 2],
+    [10, 7, 6],
+    [7, 1, 8],
+    [3, 9, 4],
+    [3, 4, 2],
+    [3, 2, 6],
+    [3, 6, 8],
+    [3, 8, 9],
+    [4, 9, 5],
+    [2, 4, 11],
+    [6, 2, 10],
+    [8, 6, 7],
+    [9, 8, 1],
+];
+
+fn icosahedron_corners() -> Vec<Vec3> {
+    let t = 0.5 * (1.0 + 5.0f32.sqrt());
+
+    [
+        Vec3::new(-1.0, t, 0.0),
+        Vec3::new(1.0, t, 0.0),
+        Vec3::new(-1.0, -t, 0.0),
+        Vec3::new(1.0, -t, 0.0),
+        Vec3::new(0.0, -1.0, t),
+        Vec3::new(0.0, 1.0, t),
+        Vec3::new(0.0, -1.0, -t),
+        Vec3::new(0.0, 1.0, -t),
+        Vec3::new(t, 0.0, -1.0),
+        Vec3::new(t, 0.0, 1.0),
+        Vec3::new(-t, 0.0, -1.0),
+        Vec3::new(-t, 0.0, 1.0),
+    ]
+    .into_iter()
+    .map(Vec3::normalize)
+    .collect()
+}
+
+//Same mapping as the UV sphere, so textures line up on both
+#[inline]
+fn spherical_tex_coord(direction: Vec3) -> Vec2 {
+    Vec2::new(
+        (direction.z.atan2(direction.x) / TAU).rem_euclid(1.0),
+        direction.y.clamp(-1.0, 1.0).acos() / PI,
+    )
+}
+
+//Collects the vertices and indices of procedural primitives, every primitive is centered at the origin and added to the
+//ones before. Front faces wind counter-clockwise around their normal like the models
+#[derive(Clone, Debug, Default)]
+pub struct MeshBuilder {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //Adds a grid of columns * rows quads, surface returns the position and normal at a texture coordinate. The
+    //triangles face the cross product of the derivatives along u and v, the ones collapsed at poles are left out
+    fn grid(&mut self, columns: u32, rows: u32, surface: impl Fn(f32, f32) -> (Vec3, Vec3)) {
+        let base = self.vertices.len() as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let tex_coord = Vec2::new(column as f32 / columns as f32, row as f32 / rows as f32);
+                let (position, normal) = surface(tex_coord.x, tex_coord.y);
+                self.vertices.push(Vertex::new(position, tex_coord, normal));
+            }
+        }
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let i = base + row * (columns + 1) + column;
+                let j = i + columns + 1;
+
+                for triangle in [[i, i + 1, j], [j, i + 1, j + 1]] {
+                    let [a, b, c] = triangle.map(|idx| self.vertices[idx as usize].position);
+                    if a != b && b != c && c != a {
+                        self.indices.extend(triangle);
+                    }
+                }
+            }
+        }
+    }
+
+    //Every face has its own vertices for flat normals
+    pub fn cube(mut self, size: f32) -> Self {
+        for normal in [
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ] {
+            let tangent = normal.any_orthonormal_vector();
+            let bitangent = normal.cross(tangent);
+
+            self.grid(1, 1, |u, v| {
+                let position =
+                    0.5 * size * (normal + (2.0 * u - 1.0) * tangent + (2.0 * v - 1.0) * bitangent);
+                (position, normal)
+            });
+        }
+
+        self
+    }
+
+    //Faces up, the texture coordinates span it once
+    pub fn plane(mut self, width: f32, depth: f32, subdivisions: u32) -> Self {
+        let quads = subdivisions + 1;
+        self.grid(quads, quads, |u, v| {
+            (
+                Vec3::new((u - 0.5) * width, 0.0, (0.5 - v) * depth),
+                Vec3::Y,
+            )
+        });
+
+        self
+    }
+
+    //Segments go around the y axis and rings from pole to pole. Has a seam where the texture coordinates wrap around
+    pub fn uv_sphere(mut self, radius: f32, segments: u32, rings: u32) -> Self {
+        let segments = segments.max(3);
+        let rings = rings.max(2);
+
+        self.grid(segments, rings, |u, v| {
+            let phi = u * TAU;
+            //Both poles are exact, so their collapsed triangles are found
+            let (sin_theta, cos_theta) = if v >= 1.0 {
+                (0.0, -1.0)
+            } else {
+                (v * PI).sin_cos()
+            };
+            let normal = Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+            (radius * normal, normal)
+        });
+
+        self
+    }
+
+    //Subdivides an icosahedron, so the triangles have about the same size everywhere unlike on the UV sphere. Every
+    //subdivision quadruples the 20 triangles
+    pub fn icosphere(mut self, radius: f32, subdivisions: u32) -> Self {
+        let mut directions = icosahedron_corners();
+        let mut triangles = ICOSAHEDRON_FACES.to_vec();
+
+        for _ in 0..subdivisions {
+            let mut midpoints = HashMap::new();
+            let mut midpoint = |a: u32, b: u32| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    directions.push((directions[a as usize] + directions[b as usize]).normalize());
+                    directions.len() as u32 - 1
+                })
+            };
+
+            triangles = triangles
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let ab = midpoint(a, b);
+                    let bc = midpoint(b, c);
+                    let ca = midpoint(c, a);
+                    [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        let base = self.vertices.len() as u32;
+        self.vertices.extend(directions.iter().map(|direction| {
+            Vertex::new(
+                radius * *direction,
+                spherical_tex_coord(*direction),
+                *direction,
+            )
+        }));
+
+        //Triangles across the seam would interpolate over the whole texture, their vertices left of it get a copy
+        //which continues past the right edge
+        let mut wrapped = HashMap::new();
+        for triangle in triangles {
+            let mut triangle = triangle.map(|idx| base + idx);
+            let tex_coords = triangle.map(|idx| self.vertices[idx as usize].tex_coord.x);
+            let max = tex_coords.iter().copied().fold(f32::MIN, f32::max);
+            let min = tex_coords.iter().copied().fold(f32::MAX, f32::min);
+
+            if max - min > 0.5 {
+                for idx in &mut triangle {
+                    let original = *idx;
+                    if self.vertices[original as usize].tex_coord.x < 0.5 {
+                        *idx = *wrapped.entry(original).or_insert_with(|| {
+                            let mut vertex = self.vertices[original as usize];
+                            vertex.tex_coord.x += 1.0;
+                            self.vertices.push(vertex);
+                            self.vertices.len() as u32 - 1
+                        });
+                    }
+                }
+            }
+            self.indices.extend(triangle);
+        }
+
+        self
+    }
+
+    //Lies flat around the y axis, the major segments go around it and the minor ones around the tube
+    pub fn torus(
+        mut self,
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: u32,
+        minor_segments: u32,
+    ) -> Self {
+        self.grid(major_segments.max(3), minor_segments.max(3), |u, v| {
+            let (sin_phi, cos_phi) = (u * TAU).sin_cos();
+            let (sin_theta, cos_theta) = (v * TAU).sin_cos();
+            let normal = Vec3::new(cos_theta * cos_phi, -sin_theta, cos_theta * sin_phi);
+            (
+                major_radius * Vec3::new(cos_phi, 0.0, sin_phi) + minor_radius * normal,
+                normal,
+            )
+        });
+
+        self
+    }
+
+    //Stands on the y axis and is closed by a cap at both ends, which have their own vertices for flat normals
+    pub fn cylinder(mut self, radius: f32, height: f32, segments: u32) -> Self {
+        let segments = segments.max(3);
+
+        self.grid(segments, 1, |u, v| {
+            let (sin_phi, cos_phi) = (u * TAU).sin_cos();
+            (
+                Vec3::new(radius * cos_phi, (0.5 - v) * height, radius * sin_phi),
+                Vec3::new(cos_phi, 0.0, sin_phi),
+            )
+        });
+
+        for normal in [Vec3::Y, Vec3::NEG_Y] {
+            let center = self.vertices.len() as u32;
+            self.vertices
+                .push(Vertex::new(0.5 * height * normal, Vec2::splat(0.5), normal));
+
+            for segment in 0..segments {
+                let (sin_phi, cos_phi) = (segment as f32 / segments as f32 * TAU).sin_cos();
+                self.vertices.push(Vertex::new(
+                    Vec3::new(radius * cos_phi, 0.5 * height * normal.y, radius * sin_phi),
+                    Vec2::new(0.5 + 0.5 * cos_phi, 0.5 + 0.5 * sin_phi),
+                    normal,
+                ));
+            }
+
+            for segment in 0..segments {
+                let current = center + 1 + segment;
+                let next = center + 1 + (segment + 1) % segments;
+                if normal.y > 0.0 {
+                    self.indices.extend([center, next, current]);
+                } else {
+                    self.indices.extend([center, current, next]);
+                }
+            }
+        }
+
+        self
+    }
+
+    #[inline]
+    pub fn build(self) -> (Vec<Vertex>, Vec<u32>) {
+        (self.vertices, self.indices)
+    }
+}
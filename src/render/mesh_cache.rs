@@ -0,0 +1,395 @@
+use std::{
+    fs,
+    fs::File,
+    io::{BufWriter, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Result};
+use bytemuck::Pod;
+use memmap2::Mmap;
+
+use crate::render::mesh::{
+    LodSimplification, MaskTexture, Material, Mesh, MeshData, MeshLevel, MeshletConfig,
+    MeshletLayout, Vertex, MAX_LOD_LEVELS, MESHLET_GROUP_SIZE,
+};
+
+const MAGIC: [u8; 4] = *b"MSHC";
+//Bump whenever the file layout or the baking in Mesh::new changes, older caches are rebuilt then
+const VERSION: u32 = 7;
+//The levels following the header are compressed as a whole, compressed caches can't be memory mapped
+const FLAG_ZSTD: u32 = 1;
+//Vertices and meshlet data are stored with the meshopt codecs and decoded into the usual layout on load
+const FLAG_MESHOPT: u32 = 2;
+
+//Everything the baked data depends on besides the source file
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct CacheKey {
+    source_hash: u64,
+    max_vertices: u32,
+    max_triangles: u32,
+    //The bits of the floats, so the key stays comparable
+    cone_weight: u32,
+    max_lod_levels: u32,
+    meshlet_group_size: u32,
+    layout: u32,
+    alignment: u32,
+    lod_levels: u32,
+    lod_target_ratio: u32,
+    lod_target_error: u32,
+    lod_simplification: u32,
+    weld_tolerance: u32,
+}
+
+impl CacheKey {
+    fn new(source_hash: u64, config: &MeshletConfig) -> Self {
+        Self {
+            source_hash,
+            max_vertices: config.max_vertices as _,
+            max_triangles: config.max_triangles as _,
+            cone_weight: config.cone_weight.to_bits(),
+            max_lod_levels: MAX_LOD_LEVELS as _,
+            meshlet_group_size: MESHLET_GROUP_SIZE as _,
+            layout: match config.layout {
+                MeshletLayout::Interleaved => 0,
+                MeshletLayout::StructOfArrays => 1,
+            },
+            alignment: config.alignment as _,
+            lod_levels: config.lod.num_levels as _,
+            lod_target_ratio: config.lod.target_ratio.to_bits(),
+            lod_target_error: config.lod.target_error.to_bits(),
+            lod_simplification: match config.lod.simplification {
+                LodSimplification::Sloppy => 0,
+                LodSimplification::SharedVertices => 1,
+                LodSimplification::Attributes => 2,
+            },
+            weld_tolerance: config.weld_tolerance.to_bits(),
+        }
+    }
+
+    fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&self.source_hash.to_le_bytes())?;
+        for value in [
+            self.max_vertices,
+            self.max_triangles,
+            self.cone_weight,
+            self.max_lod_levels,
+            self.meshlet_group_size,
+            self.layout,
+            self.alignment,
+            self.lod_levels,
+            self.lod_target_ratio,
+            self.lod_target_error,
+            self.lod_simplification,
+            self.weld_tolerance,
+        ] {
+            write_u32(writer, value)?;
+        }
+        Ok(())
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self> {
+        Ok(Self {
+            source_hash: u64::from_le_bytes(reader.bytes(8)?.try_into().unwrap()),
+            max_vertices: reader.u32()?,
+            max_triangles: reader.u32()?,
+            cone_weight: reader.u32()?,
+            max_lod_levels: reader.u32()?,
+            meshlet_group_size: reader.u32()?,
+            layout: reader.u32()?,
+            alignment: reader.u32()?,
+            lod_levels: reader.u32()?,
+            lod_target_ratio: reader.u32()?,
+            lod_target_error: reader.u32()?,
+            lod_simplification: reader.u32()?,
+            weld_tolerance: reader.u32()?,
+        })
+    }
+}
+
+//FNV-1a, unlike DefaultHasher it is guaranteed to stay the same between Rust versions
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn hash_file(path: &Path) -> Result<u64> {
+    Ok(hash_bytes(&fs::read(path)?))
+}
+
+//The cache is written next to the model, dragon.obj is cached in dragon.obj.meshlets
+pub fn cache_path(path: &Path) -> PathBuf {
+    let mut cache_path = path.as_os_str().to_owned();
+    cache_path.push(".meshlets");
+    cache_path.into()
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_slice<T: Pod>(writer: &mut impl Write, slice: &[T]) -> Result<()> {
+    write_u32(writer, slice.len() as _)?;
+    writer.write_all(bytemuck::cast_slice(slice))?;
+    Ok(())
+}
+
+//The element count is followed by the size of the encoded bytes, empty arrays aren't encoded at all
+fn write_encoded<T>(
+    writer: &mut impl Write,
+    slice: &[T],
+    encode: impl FnOnce(&[T]) -> Result<Vec<u8>>,
+) -> Result<()> {
+    let encoded = if slice.is_empty() {
+        Vec::new()
+    } else {
+        encode(slice)?
+    };
+
+    write_u32(writer, slice.len() as _)?;
+    write_u32(writer, encoded.len() as _)?;
+    writer.write_all(&encoded)?;
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    #[inline]
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| anyhow!("Unexpected end of the meshlet cache"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    #[inline]
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    #[inline]
+    fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.offset..]
+    }
+
+    //Borrows the array from the mapping if there is one, otherwise it's copied out of the bytes
+    fn array<T: Pod>(&mut self, mapping: Option<&Arc<Mmap>>) -> Result<MeshData<T>> {
+        let len = self.u32()? as usize;
+        let offset = self.offset;
+        let bytes = self.bytes(len * mem::size_of::<T>())?;
+
+        Ok(match mapping {
+            Some(mapping) => {
+                //Mappings are page aligned, so only the offset into the file matters
+                if offset % mem::align_of::<T>() != 0 {
+                    bail!("Misaligned array in the meshlet cache")
+                }
+
+                MeshData::Mapped {
+                    mapping: mapping.clone(),
+                    offset,
+                    len,
+                }
+            }
+            None => {
+                let mut vec = vec![T::zeroed(); len];
+                bytemuck::cast_slice_mut(&mut vec).copy_from_slice(bytes);
+                MeshData::Owned(vec)
+            }
+        })
+    }
+
+    fn encoded_array<T>(
+        &mut self,
+        decode: impl FnOnce(&[u8], usize) -> Result<Vec<T>>,
+    ) -> Result<MeshData<T>> {
+        let len = self.u32()? as usize;
+        let encoded_len = self.u32()? as usize;
+        let encoded = self.bytes(encoded_len)?;
+
+        if len == 0 {
+            return Ok(MeshData::default())
+        }
+        Ok(decode(encoded, len)?.into())
+    }
+}
+
+//The materials and mask textures are always copied, they are few and more aligned than the other arrays
+fn read_mesh(reader: &mut Reader, mapping: Option<&Arc<Mmap>>, flags: u32) -> Result<Mesh> {
+    let materials = reader.array::<Material>(None)?.to_vec();
+    let mask_textures = (0..reader.u32()?)
+        .map(|_| {
+            let width = reader.u32()?;
+            let height = reader.u32()?;
+            let texels = reader.array::<u8>(None)?.to_vec();
+            reader.bytes(texels.len().next_multiple_of(4) - texels.len())?;
+            Ok(MaskTexture {
+                width,
+                height,
+                texels,
+            })
+        })
+        .collect::<Result<_>>()?;
+    let levels = read_levels(reader, mapping, flags)?;
+
+    Ok(Mesh {
+        levels,
+        materials,
+        mask_textures,
+        skin: None,
+    })
+}
+
+fn read_levels(
+    reader: &mut Reader,
+    mapping: Option<&Arc<Mmap>>,
+    flags: u32,
+) -> Result<Vec<MeshLevel>> {
+    let num_levels = reader.u32()?;
+    if num_levels == 0 || num_levels as usize > MAX_LOD_LEVELS {
+        bail!("Invalid level count {num_levels}")
+    }
+
+    (0..num_levels)
+        .map(|i| {
+            let shared_vertices = reader.u32()? != 0;
+            if i == 0 && shared_vertices {
+                bail!("Level 0 can't share vertices")
+            }
+
+            if flags & FLAG_MESHOPT != 0 {
+                let vertices = reader.encoded_array(|encoded, len| {
+                    Ok(meshopt::decode_vertex_buffer::<Vertex>(encoded, len)?)
+                })?;
+                let meshlets = reader.array(mapping)?;
+                let meshlet_groups = reader.array(mapping)?;
+                let meshlet_data = reader.encoded_array(|encoded, len| {
+                    Ok(meshopt::decode_index_sequence::<u32>(encoded, len)?)
+                })?;
+
+                Ok(MeshLevel::new(
+                    vertices,
+                    meshlets,
+                    meshlet_groups,
+                    meshlet_data,
+                    shared_vertices,
+                ))
+            } else {
+                Ok(MeshLevel::new(
+                    reader.array(mapping)?,
+                    reader.array(mapping)?,
+                    reader.array(mapping)?,
+                    reader.array(mapping)?,
+                    shared_vertices,
+                ))
+            }
+        })
+        .collect()
+}
+
+//Returns None if there is no cache or it was baked from a different source or with different parameters
+pub fn load(path: &Path, source_hash: u64, config: &MeshletConfig) -> Result<Option<Mesh>> {
+    let file = match File::open(cache_path(path)) {
+        Ok(file) => file,
+        Err(_) => return Ok(None),
+    };
+    //The file must not be modified while it's mapped, caches are only ever replaced by renaming a new file over them
+    let mapping = Arc::new(unsafe { Mmap::map(&file) }?);
+    let mut reader = Reader::new(&mapping);
+
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        bail!("{} is not a meshlet cache", cache_path(path).display())
+    }
+
+    if reader.u32()? != VERSION
+        || CacheKey::read(&mut reader)? != CacheKey::new(source_hash, config)
+    {
+        return Ok(None)
+    }
+
+    let flags = reader.u32()?;
+    let mesh = if flags & FLAG_ZSTD != 0 {
+        let payload = zstd::decode_all(reader.remaining())?;
+        read_mesh(&mut Reader::new(&payload), None, flags)?
+    } else {
+        read_mesh(&mut reader, Some(&mapping), flags)?
+    };
+
+    Ok(Some(mesh))
+}
+
+pub fn store(path: &Path, source_hash: u64, config: &MeshletConfig, mesh: &Mesh) -> Result<()> {
+    let meshopt_encoding = config.cache_meshopt_encoding;
+
+    let mut payload = Vec::new();
+    write_slice(&mut payload, &mesh.materials)?;
+    write_u32(&mut payload, mesh.mask_textures.len() as _)?;
+    for mask_texture in &mesh.mask_textures {
+        write_u32(&mut payload, mask_texture.width)?;
+        write_u32(&mut payload, mask_texture.height)?;
+        write_slice(&mut payload, &mask_texture.texels)?;
+        //Keeps the arrays of the levels aligned, so they can still be mapped
+        payload.resize(payload.len().next_multiple_of(4), 0);
+    }
+    write_u32(&mut payload, mesh.levels.len() as _)?;
+    for level in &mesh.levels {
+        write_u32(&mut payload, level.shared_vertices as _)?;
+
+        if meshopt_encoding {
+            write_encoded(&mut payload, &level.vertices, |vertices| {
+                Ok(meshopt::encode_vertex_buffer(vertices)?)
+            })?;
+            write_slice(&mut payload, &level.meshlets)?;
+            write_slice(&mut payload, &level.meshlet_groups)?;
+            //The packed triangles aren't vertex indices, so the bound has to allow every u32
+            write_encoded(&mut payload, &level.meshlet_data, |meshlet_data| {
+                Ok(meshopt::encode_index_sequence(meshlet_data, u32::MAX as _)?)
+            })?;
+        } else {
+            write_slice(&mut payload, &level.vertices)?;
+            write_slice(&mut payload, &level.meshlets)?;
+            write_slice(&mut payload, &level.meshlet_groups)?;
+            write_slice(&mut payload, &level.meshlet_data)?;
+        }
+    }
+
+    let mut flags = if meshopt_encoding { FLAG_MESHOPT } else { 0 };
+
+    let compression_level = config.cache_compression_level;
+    let payload = if compression_level != 0 {
+        flags |= FLAG_ZSTD;
+        zstd::encode_all(&payload[..], compression_level)?
+    } else {
+        payload
+    };
+
+    //Written to a temporary file first, so an interrupted write never leaves a truncated cache behind
+    let cache_path = cache_path(path);
+    let mut temp_path = cache_path.clone().into_os_string();
+    temp_path.push(".tmp");
+
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    writer.write_all(&MAGIC)?;
+    write_u32(&mut writer, VERSION)?;
+    CacheKey::new(source_hash, config).write(&mut writer)?;
+    write_u32(&mut writer, flags)?;
+    writer.write_all(&payload)?;
+    writer.into_inner()?.sync_all()?;
+
+    fs::rename(temp_path, cache_path)?;
+    Ok(())
+}
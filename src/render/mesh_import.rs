@@ -0,0 +1,527 @@
+use std::{
+    collections::HashMap,
+    iter,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, bail, Result};
+use glam::{Mat3, Mat4, Vec2, Vec3};
+use gltf::image::Format;
+
+use crate::render::{
+    mesh::{MaskTexture, Material, Submesh, Vertex, MATERIAL_ALPHA_TEST, NO_MASK_TEXTURE},
+    mesh_util::AABB,
+    skin::{MorphDelta, MorphTargets, Skeleton, VertexSkin},
+};
+
+//A triangle list split into submeshes by material
+#[derive(Clone, Debug, Default)]
+pub struct ImportedMesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    //Together they cover all indices
+    pub submeshes: Vec<Submesh>,
+    pub materials: Vec<Material>,
+    pub mask_textures: Vec<MaskTexture>,
+}
+
+impl ImportedMesh {
+    //A single submesh with the default material
+    #[inline]
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
+        Self {
+            submeshes: vec![Submesh::new(0, indices.len(), 0)],
+            materials: vec![Material::default()],
+            mask_textures: Vec::new(),
+            vertices,
+            indices,
+        }
+    }
+}
+
+//Turns a file into a triangle list, welding, cleanup and meshlet building are done by Mesh afterwards
+pub trait MeshImporter: Send + Sync {
+    fn import(&self, path: &Path) -> Result<ImportedMesh>;
+}
+
+//Registered importers take precedence over the builtin ones, so they can also replace the OBJ importer
+static IMPORTERS: RwLock<Vec<(String, Arc<dyn MeshImporter>)>> = RwLock::new(Vec::new());
+
+//Extensions are matched without the dot and ignoring case
+pub fn register(extension: &str, importer: impl MeshImporter + 'static) {
+    let extension = extension.to_lowercase();
+
+    let mut importers = IMPORTERS.write().unwrap();
+    importers.retain(|(registered, _)| *registered != extension);
+    importers.push((extension, Arc::new(importer)));
+}
+
+fn importer_for(extension: &str) -> Option<Arc<dyn MeshImporter>> {
+    let registered = IMPORTERS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|(registered, _)| registered == extension)
+        .map(|(_, importer)| importer.clone());
+
+    registered.or_else(|| {
+        match extension {
+            "obj" => Some(Arc::new(ObjImporter)),
+            "gltf" | "glb" => Some(Arc::new(GltfImporter)),
+            _ => None,
+        }
+    })
+}
+
+pub fn import(path: &Path) -> Result<ImportedMesh> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase)
+        .ok_or_else(|| anyhow!("{} has no file extension", path.display()))?;
+
+    let importer = importer_for(&extension)
+        .ok_or_else(|| anyhow!("No importer is registered for .{extension} files"))?;
+    importer.import(path)
+}
+
+#[inline]
+pub fn is_gltf(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| {
+            extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb")
+        })
+}
+
+pub struct ObjImporter;
+
+//The material library isn't read, every face gets the default material. Faces without normals get smooth ones and
+//faces without texture coordinates get triplanar ones, see smooth_normals and triplanar_tex_coords
+impl MeshImporter for ObjImporter {
+    fn import(&self, path: &Path) -> Result<ImportedMesh> {
+        let name = path.display();
+
+        let mesh = fast_obj::Mesh::new(path.to_str().unwrap())?;
+        if mesh.indices().is_empty() {
+            bail!("{name} contains no faces")
+        }
+
+        let mut vertices = vec![Default::default(); mesh.indices().len()];
+
+        let positions = mesh.positions();
+        let tex_coords = mesh.texcoords();
+        let normals = mesh.normals();
+        let indices = mesh.indices();
+
+        //fast_obj puts a dummy at index zero of every attribute, which is referenced by face corners without it
+        let mut missing_normals = false;
+        let mut missing_tex_coords = false;
+
+        for (i, index) in indices.iter().enumerate() {
+            let position_idx = 3 * index.p as usize;
+            let tex_coord_idx = 2 * index.t as usize;
+            let normal_idx = 3 * index.n as usize;
+
+            if position_idx + 2 >= positions.len()
+                || tex_coord_idx + 1 >= tex_coords.len()
+                || normal_idx + 2 >= normals.len()
+            {
+                bail!("{name} references vertex attributes which don't exist")
+            }
+            missing_tex_coords |= index.t == 0;
+            missing_normals |= index.n == 0;
+
+            vertices[i] = Vertex::new(
+                Vec3::new(
+                    positions[position_idx],
+                    positions[position_idx + 1],
+                    positions[position_idx + 2],
+                ),
+                Vec2::new(tex_coords[tex_coord_idx], tex_coords[tex_coord_idx + 1]),
+                Vec3::new(
+                    normals[normal_idx],
+                    normals[normal_idx + 1],
+                    normals[normal_idx + 2],
+                ),
+            );
+        }
+
+        if missing_normals {
+            let position_indices: Vec<_> = indices.iter().map(|index| index.p).collect();
+            let smooth_normals = smooth_normals(&vertices, &position_indices);
+            for (vertex, index) in vertices.iter_mut().zip(indices) {
+                if index.n == 0 {
+                    vertex.normal = smooth_normals[index.p as usize];
+                }
+            }
+        }
+        if missing_tex_coords {
+            let missing: Vec<_> = indices.iter().map(|index| index.t == 0).collect();
+            triplanar_tex_coords(&mut vertices, &missing);
+        }
+
+        //Every face corner is its own vertex, Mesh welds them afterwards
+        let indices = (0..vertices.len() as u32).collect();
+        Ok(ImportedMesh::new(vertices, indices))
+    }
+}
+
+//Imports the triangles of every mesh in the default scene in their bind pose, see import_gltf
+pub struct GltfImporter;
+
+impl MeshImporter for GltfImporter {
+    fn import(&self, path: &Path) -> Result<ImportedMesh> {
+        let gltf_mesh = import_gltf(path)?;
+        Ok(ImportedMesh {
+            vertices: gltf_mesh.vertices,
+            indices: gltf_mesh.indices,
+            submeshes: gltf_mesh.submeshes,
+            materials: gltf_mesh.materials,
+            mask_textures: gltf_mesh.mask_textures,
+        })
+    }
+}
+
+pub struct GltfMesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    //One per primitive
+    pub submeshes: Vec<Submesh>,
+    //The materials of the file followed by the default material for primitives without one
+    pub materials: Vec<Material>,
+    //The alpha of the base color textures of alpha tested materials
+    pub mask_textures: Vec<MaskTexture>,
+    //One per vertex, vertices which aren't skinned have no weights. Empty without a skeleton
+    pub vertex_skins: Vec<VertexSkin>,
+    //One per vertex for every morph target, vertices of other meshes aren't moved by it
+    pub morph_deltas: Vec<Vec<MorphDelta>>,
+    //Also present for files which only have morph targets, it has no joints then
+    pub skeleton: Option<Skeleton>,
+}
+
+//Skinned primitives stay in the space of their skin while all others are transformed by their nodes. Only the skin
+//which is used first is supported, primitives with other skins are imported in their bind pose without one
+pub fn import_gltf(path: &Path) -> Result<GltfMesh> {
+    let name = path.display();
+
+    let (document, buffers, images) = gltf::import(path)?;
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| anyhow!("{name} contains no scene"))?;
+
+    let (materials, mask_textures) = import_materials(&document, &images);
+
+    let mut gltf_mesh = GltfMesh {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+        submeshes: Vec::new(),
+        materials,
+        mask_textures,
+        vertex_skins: Vec::new(),
+        morph_deltas: Vec::new(),
+        skeleton: None,
+    };
+    let mut skin: Option<gltf::Skin> = None;
+    let mut morph_targets = MorphTargets::default();
+
+    let mut nodes: Vec<_> = scene.nodes().map(|node| (node, Mat4::IDENTITY)).collect();
+    while let Some((node, parent_transform)) = nodes.pop() {
+        let transform = parent_transform * Mat4::from_cols_array_2d(&node.transform().matrix());
+        nodes.extend(node.children().map(|child| (child, transform)));
+
+        let Some(mesh) = node.mesh() else { continue };
+
+        let node_skin = node.skin().filter(|node_skin| {
+            skin.get_or_insert_with(|| node_skin.clone()).index() == node_skin.index()
+        });
+
+        //Every node gets its own morph targets, even if it shares its mesh with others
+        let first_target = morph_targets.default_weights.len();
+        let num_targets = mesh
+            .primitives()
+            .map(|primitive| primitive.morph_targets().count())
+            .max()
+            .unwrap_or(0);
+        if num_targets > 0 {
+            let weights = node.weights().or(mesh.weights()).unwrap_or_default();
+            morph_targets
+                .first_targets
+                .insert(node.index(), first_target);
+            morph_targets
+                .default_weights
+                .extend((0..num_targets).map(|i| weights.get(i).copied().unwrap_or(0.0)));
+            gltf_mesh
+                .morph_deltas
+                .resize(first_target + num_targets, Vec::new());
+        }
+
+        let transform = if node_skin.is_some() {
+            Mat4::IDENTITY
+        } else {
+            transform
+        };
+        let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                eprintln!("Warning: Skipping a primitive of {name} which isn't a triangle list");
+                continue
+            }
+
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let positions: Vec<_> = reader
+                .read_positions()
+                .ok_or_else(|| anyhow!("{name} contains a primitive without positions"))?
+                .map(|position| transform.transform_point3(position.into()))
+                .collect();
+            let indices: Vec<_> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            if indices
+                .iter()
+                .any(|index| *index as usize >= positions.len())
+            {
+                bail!("{name} references vertices which don't exist")
+            }
+
+            let normals: Vec<_> = match reader.read_normals() {
+                Some(normals) => {
+                    normals
+                        .map(|normal| (normal_matrix * Vec3::from(normal)).normalize_or_zero())
+                        .collect()
+                }
+                None => face_normals(&positions, &indices),
+            };
+            let tex_coords: Vec<_> = match reader.read_tex_coords(0) {
+                Some(tex_coords) => tex_coords.into_f32().map(Vec2::from).collect(),
+                None => vec![Vec2::ZERO; positions.len()],
+            };
+
+            gltf_mesh.submeshes.push(Submesh::new(
+                gltf_mesh.indices.len(),
+                indices.len(),
+                primitive
+                    .material()
+                    .index()
+                    .unwrap_or(gltf_mesh.materials.len() - 1) as _,
+            ));
+
+            let base_vertex = gltf_mesh.vertices.len();
+            gltf_mesh.vertices.extend(
+                positions.iter().zip(tex_coords).zip(normals).map(
+                    |((position, tex_coord), normal)| Vertex::new(*position, tex_coord, normal),
+                ),
+            );
+            gltf_mesh
+                .indices
+                .extend(indices.iter().map(|index| base_vertex as u32 + index));
+
+            let joints = reader.read_joints(0).filter(|_| node_skin.is_some());
+            let weights = reader.read_weights(0).filter(|_| node_skin.is_some());
+            match joints.zip(weights) {
+                Some((joints, weights)) => {
+                    gltf_mesh.vertex_skins.extend(
+                        joints
+                            .into_u16()
+                            .zip(weights.into_u16())
+                            .map(|(joints, weights)| VertexSkin { joints, weights }),
+                    )
+                }
+                None => {
+                    gltf_mesh
+                        .vertex_skins
+                        .resize(gltf_mesh.vertices.len(), VertexSkin::default())
+                }
+            }
+            if gltf_mesh.vertex_skins.len() != gltf_mesh.vertices.len() {
+                bail!("{name} has fewer joints or weights than vertices")
+            }
+
+            //Normal deltas are transformed like normals, but they aren't normalized
+            for (i, (position_deltas, normal_deltas, _)) in reader.read_morph_targets().enumerate()
+            {
+                let position_deltas: Vec<_> = position_deltas
+                    .into_iter()
+                    .flatten()
+                    .map(|delta| transform.transform_vector3(delta.into()))
+                    .collect();
+                let normal_deltas: Vec<_> = normal_deltas
+                    .into_iter()
+                    .flatten()
+                    .map(|delta| normal_matrix * Vec3::from(delta))
+                    .collect();
+
+                let morph_deltas = &mut gltf_mesh.morph_deltas[first_target + i];
+                morph_deltas.resize(base_vertex, MorphDelta::default());
+                morph_deltas.extend((0..positions.len()).map(|vertex_idx| {
+                    MorphDelta {
+                        position: position_deltas.get(vertex_idx).copied().unwrap_or_default(),
+                        normal: normal_deltas.get(vertex_idx).copied().unwrap_or_default(),
+                    }
+                }));
+            }
+        }
+    }
+
+    if gltf_mesh.indices.is_empty() {
+        bail!("{name} contains no triangles")
+    }
+
+    for morph_deltas in &mut gltf_mesh.morph_deltas {
+        morph_deltas.resize(gltf_mesh.vertices.len(), MorphDelta::default());
+    }
+    if skin.is_some() || !morph_targets.default_weights.is_empty() {
+        gltf_mesh.skeleton = Some(Skeleton::from_gltf(
+            &document,
+            &scene,
+            skin.as_ref(),
+            &morph_targets,
+            &buffers,
+        )?);
+    } else {
+        gltf_mesh.vertex_skins.clear();
+    }
+
+    Ok(gltf_mesh)
+}
+
+//Only materials with the MASK alpha mode are alpha tested, blended materials are drawn opaque. Materials which share
+//an image share its mask texture
+fn import_materials(
+    document: &gltf::Document,
+    images: &[gltf::image::Data],
+) -> (Vec<Material>, Vec<MaskTexture>) {
+    let mut mask_textures = Vec::new();
+    let mut mask_texture_indices = HashMap::new();
+
+    let materials = document
+        .materials()
+        .map(|material| {
+            let pbr_metallic_roughness = material.pbr_metallic_roughness();
+            let mut result = Material {
+                base_color: pbr_metallic_roughness.base_color_factor().into(),
+                ..Default::default()
+            };
+            if material.alpha_mode() != gltf::material::AlphaMode::Mask {
+                return result
+            }
+
+            result.flags |= MATERIAL_ALPHA_TEST;
+            result.alpha_cutoff = material.alpha_cutoff().unwrap_or(0.5);
+
+            //Only the first set of texture coordinates is imported
+            let Some(info) = pbr_metallic_roughness
+                .base_color_texture()
+                .filter(|info| info.tex_coord() == 0)
+            else {
+                return result
+            };
+            let image_idx = info.texture().source().index();
+            result.mask_texture_idx = *mask_texture_indices.entry(image_idx).or_insert_with(|| {
+                match images.get(image_idx).and_then(mask_texture) {
+                    Some(mask_texture) => {
+                        mask_textures.push(mask_texture);
+                        mask_textures.len() as u32 - 1
+                    }
+                    None => NO_MASK_TEXTURE,
+                }
+            });
+            result
+        })
+        .chain(iter::once(Material::default()))
+        .collect();
+
+    (materials, mask_textures)
+}
+
+//None for images without an alpha channel, they wouldn't cut anything out
+fn mask_texture(image: &gltf::image::Data) -> Option<MaskTexture> {
+    let (texel_size, alpha_offset) = match image.format {
+        Format::R8G8B8A8 => (4, 3),
+        //Only the most significant byte of the little endian alpha is kept
+        Format::R16G16B16A16 => (8, 7),
+        _ => return None,
+    };
+
+    Some(MaskTexture {
+        width: image.width,
+        height: image.height,
+        texels: image
+            .pixels
+            .chunks_exact(texel_size)
+            .map(|texel| texel[alpha_offset])
+            .collect(),
+    })
+}
+
+//Every vertex gets the normal of the last triangle using it, for primitives which come without normals
+fn face_normals(positions: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::Y; positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| positions[triangle[i] as usize]);
+        let normal = (b - a).cross(c - a).normalize_or_zero();
+        for index in triangle {
+            normals[*index as usize] = normal;
+        }
+    }
+    normals
+}
+
+//Area weighted sum of the normals of the faces around every position, indexed by position. Every face corner is its own
+//vertex, so the corners are matched by the position they reference in the file
+fn smooth_normals(vertices: &[Vertex], position_indices: &[u32]) -> Vec<Vec3> {
+    let num_positions = position_indices
+        .iter()
+        .max()
+        .map_or(0, |idx| *idx as usize + 1);
+    let mut normals = vec![Vec3::ZERO; num_positions];
+
+    for (triangle, triangle_positions) in vertices
+        .chunks_exact(3)
+        .zip(position_indices.chunks_exact(3))
+    {
+        //Not normalized, so larger faces weigh more
+        let normal = (triangle[1].position - triangle[0].position)
+            .cross(triangle[2].position - triangle[0].position);
+        for idx in triangle_positions {
+            normals[*idx as usize] += normal;
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|normal| normal.try_normalize().unwrap_or(Vec3::Y))
+        .collect()
+}
+
+//Projects every face along the axis its normal is closest to, the texture spans the largest side of the bounds once.
+//Only the corners which are missing texture coordinates are changed
+fn triplanar_tex_coords(vertices: &mut [Vertex], missing: &[bool]) {
+    let aabb = AABB::from_vertices(vertices.iter());
+    let scale = 1.0 / aabb.range().max(f32::EPSILON);
+
+    for (triangle, triangle_missing) in vertices.chunks_exact_mut(3).zip(missing.chunks_exact(3)) {
+        let normal = (triangle[1].position - triangle[0].position)
+            .cross(triangle[2].position - triangle[0].position)
+            .abs();
+        let (u_axis, v_axis) = if normal.x >= normal.y && normal.x >= normal.z {
+            (2, 1)
+        } else if normal.y >= normal.z {
+            (0, 2)
+        } else {
+            (0, 1)
+        };
+
+        for (vertex, missing) in triangle.iter_mut().zip(triangle_missing) {
+            if *missing {
+                let position = (vertex.position - aabb.min) * scale;
+                vertex.tex_coord = Vec2::new(position[u_axis], position[v_axis]);
+            }
+        }
+    }
+}
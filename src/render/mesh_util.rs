@@ -1,5 +1,7 @@
+use std::collections::HashMap;
+
 use bytemuck::{Pod, Zeroable};
-use glam::Vec3;
+use glam::{IVec3, Mat4, Vec3, Vec4};
 
 use crate::render::mesh::Vertex;
 
@@ -28,10 +30,114 @@ impl AABB {
         Self { min, max }
     }
 
+    pub fn from_aabbs<'a>(aabbs: impl Iterator<Item = &'a AABB>) -> Self {
+        aabbs.fold(
+            Self {
+                min: Vec3::splat(f32::MAX),
+                max: Vec3::splat(f32::MIN),
+            },
+            |result, aabb| {
+                Self {
+                    min: result.min.min(aabb.min),
+                    max: result.max.max(aabb.max),
+                }
+            },
+        )
+    }
+
     #[inline]
     pub fn range(&self) -> f32 {
         (self.max.x - self.min.x)
             .max(self.max.y - self.min.y)
             .max(self.max.z - self.min.z)
     }
+
+    #[inline]
+    pub fn center(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    #[inline]
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    #[inline]
+    pub fn surface_area(&self) -> f32 {
+        let size = self.max - self.min;
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    //The box around the transformed corners
+    pub fn transformed(&self, matrix: &Mat4) -> Self {
+        let center = matrix.transform_point3(self.center());
+        let half_size = 0.5 * (self.max - self.min);
+        let extents = matrix.x_axis.truncate().abs() * half_size.x
+            + matrix.y_axis.truncate().abs() * half_size.y
+            + matrix.z_axis.truncate().abs() * half_size.z;
+
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+
+    //Matches is_aabb_visible in shaders/culling.glsl for the transformed box. Conservative, boxes outside the frustum near its corners still intersect it
+    pub fn intersects_frustum(&self, frustum_planes: &[Vec4; 6]) -> bool {
+        let center = self.center();
+        let extents = 0.5 * (self.max - self.min);
+
+        frustum_planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            normal.dot(center) + plane.w >= -normal.abs().dot(extents)
+        })
+    }
+
+    pub fn inside_frustum(&self, frustum_planes: &[Vec4; 6]) -> bool {
+        let center = self.center();
+        let extents = 0.5 * (self.max - self.min);
+
+        frustum_planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            normal.dot(center) + plane.w >= normal.abs().dot(extents)
+        })
+    }
+}
+
+//Moves every position onto the first one within tolerance of it, so vertices which only differ by the precision of an
+//exporter or scanner can be merged by the remap afterwards. The positions are hashed into a grid with cells of the size
+//of the tolerance, only the neighboring cells have to be searched then. Returns how many positions were moved
+pub fn weld_positions(vertices: &mut [Vertex], tolerance: f32) -> usize {
+    if tolerance <= 0.0 {
+        return 0
+    }
+
+    let cell = |position: Vec3| (position / tolerance).floor().as_ivec3();
+    let mut grid: HashMap<IVec3, Vec<Vec3>> = HashMap::new();
+    let mut num_welded = 0;
+
+    for vertex in vertices {
+        let center = cell(vertex.position);
+        let welded = (-1..=1)
+            .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+            .filter_map(|offset| grid.get(&(center + offset)))
+            .flatten()
+            .find(|position| position.distance_squared(vertex.position) <= tolerance * tolerance)
+            .copied();
+
+        match welded {
+            Some(position) => {
+                if position != vertex.position {
+                    vertex.position = position;
+                    num_welded += 1;
+                }
+            }
+            None => grid.entry(center).or_default().push(vertex.position),
+        }
+    }
+
+    num_welded
 }
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use crate::render::{
+    error::{ErrorContext, RenderError},
+    mesh::{MeshletConfig, MeshletLayout},
+    render_ctx::RenderCtx,
+};
+
+const WARMUP_FRAMES: usize = 120;
+const MEASURED_FRAMES: usize = 600;
+const LAYOUTS: [MeshletLayout; 2] = [MeshletLayout::Interleaved, MeshletLayout::StructOfArrays];
+
+//Renders the scene with every meshlet layout and compares the GPU time of the GeometryPass
+pub struct MeshletBenchmark {
+    initial_config: MeshletConfig,
+    layout_idx: usize,
+    frame: usize,
+    samples: Vec<Duration>,
+    results: Vec<(MeshletLayout, Duration, Duration)>,
+}
+
+impl MeshletBenchmark {
+    pub fn new(ctx: &mut RenderCtx) -> Result<Self, RenderError> {
+        let initial_config = ctx.scene_resources.meshlet_config;
+        ctx.set_meshlet_config(MeshletConfig {
+            layout: LAYOUTS[0],
+            ..initial_config
+        })?;
+        //Measuring the placeholders would be meaningless
+        unsafe {
+            ctx.scene_resources
+                .mesh_collection
+                .wait_until_loaded(&mut ctx.deletion_queue)
+        }
+        .during("Loading the meshes")?;
+
+        Ok(Self {
+            initial_config,
+            layout_idx: 0,
+            frame: 0,
+            samples: Vec::with_capacity(MEASURED_FRAMES),
+            results: Vec::new(),
+        })
+    }
+
+    //Has to be called once after every rendered frame, returns false once the benchmark finished
+    pub fn update(&mut self, ctx: &mut RenderCtx) -> Result<bool, RenderError> {
+        self.frame += 1;
+
+        //The timings of the frames in flight still belong to the previous layout
+        if self.frame > WARMUP_FRAMES {
+            if let Some((_, duration)) = ctx
+                .pass_timings
+                .latest()
+                .into_iter()
+                .find(|(name, _)| *name == "GeometryPass")
+            {
+                self.samples.push(duration);
+            }
+        }
+
+        if self.samples.len() < MEASURED_FRAMES {
+            return Ok(true)
+        }
+
+        self.samples.sort();
+        let average = self.samples.iter().sum::<Duration>() / self.samples.len() as u32;
+        let median = self.samples[self.samples.len() / 2];
+        self.results
+            .push((LAYOUTS[self.layout_idx], average, median));
+
+        self.layout_idx += 1;
+        self.frame = 0;
+        self.samples.clear();
+
+        if let Some(layout) = LAYOUTS.get(self.layout_idx) {
+            ctx.set_meshlet_config(MeshletConfig {
+                layout: *layout,
+                ..self.initial_config
+            })?;
+            unsafe {
+                ctx.scene_resources
+                    .mesh_collection
+                    .wait_until_loaded(&mut ctx.deletion_queue)
+            }
+            .during("Loading the meshes")?;
+            return Ok(true)
+        }
+
+        self.print_results(ctx);
+        ctx.set_meshlet_config(self.initial_config)?;
+        Ok(false)
+    }
+
+    fn print_results(&self, ctx: &RenderCtx) {
+        println!(
+            "Meshlet layout benchmark on {} (alignment: {}, {MEASURED_FRAMES} frames each):",
+            ctx.device.device_info.device_name, self.initial_config.alignment
+        );
+        for (layout, average, median) in &self.results {
+            println!("  {layout:?}: average {average:?}, median {median:?}");
+        }
+    }
+}
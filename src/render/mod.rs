@@ -1,9 +1,46 @@
 pub mod buffer;
+pub mod buffer_arena;
+pub mod capture;
+pub mod deletion_queue;
+pub mod device_fault;
+pub mod device_info;
+pub mod error;
 pub mod frame;
+pub mod frame_resources;
+pub mod full_screen_exclusive;
+pub mod headless;
+pub mod hitch_detector;
+pub mod instance_bvh;
+pub mod instances;
+pub mod mask_textures;
+pub mod memory_budget;
 pub mod mesh;
+pub mod mesh_builder;
+pub mod mesh_cache;
+pub mod mesh_import;
 pub mod mesh_util;
+pub mod meshlet_benchmark;
+pub mod pass_timings;
 pub mod passes;
 pub mod query_pool;
+pub mod render_config;
 pub mod render_ctx;
+pub mod render_device;
+pub mod render_graph;
+pub mod render_settings;
 pub mod renderer;
+pub mod resource_registry;
+pub mod resource_state;
+pub mod scene;
+pub mod scene_resources;
+pub mod secondary_window;
+pub mod shader_watcher;
+pub mod skin;
+pub mod staging_belt;
+pub mod swapchain;
+pub mod terrain;
 pub mod utils;
+pub mod vertex_format;
+pub mod workers;
+#[cfg(feature = "openxr")]
+pub mod xr;
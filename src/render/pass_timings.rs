@@ -0,0 +1,50 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+pub const HISTORY_LENGTH: usize = 64;
+
+#[derive(Clone, Debug, Default)]
+pub struct PassTimings {
+    history: HashMap<String, VecDeque<Duration>>,
+}
+
+impl PassTimings {
+    pub fn push(&mut self, name: &str, duration: Duration) {
+        let history = self.history.entry(name.to_owned()).or_default();
+        if history.len() == HISTORY_LENGTH {
+            history.pop_front();
+        }
+        history.push_back(duration);
+    }
+
+    pub fn average(&self, name: &str) -> Option<Duration> {
+        let history = self.history.get(name)?;
+        if history.is_empty() {
+            return None
+        }
+
+        Some(history.iter().sum::<Duration>() / history.len() as u32)
+    }
+
+    pub fn latest(&self) -> Vec<(&str, Duration)> {
+        let mut latest: Vec<_> = self
+            .history
+            .iter()
+            .filter_map(|(name, history)| Some((name.as_str(), *history.back()?)))
+            .collect();
+        latest.sort_by_key(|(name, _)| *name);
+        latest
+    }
+
+    pub fn averages(&self) -> Vec<(&str, Duration)> {
+        let mut averages: Vec<_> = self
+            .history
+            .keys()
+            .filter_map(|name| Some((name.as_str(), self.average(name)?)))
+            .collect();
+        averages.sort_by_key(|(name, _)| *name);
+        averages
+    }
+}
@@ -0,0 +1,164 @@
+use std::{mem, slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use glam::{Mat4, Vec3};
+
+use crate::render::{
+    render_ctx::{RenderCtx, FAR_PLANE, NEAR_PLANE, SWAPCHAIN_FORMAT},
+    utils,
+    utils::{
+        globals::GlobalsBuffers,
+        pipelines::{MultisampleState, RasterState},
+        reflection::ShaderInterface,
+    },
+};
+
+pub struct FrustumDebugPass {
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    device: Arc<Device>,
+}
+
+impl Drop for FrustumDebugPass {
+    fn drop(&mut self) {
+        unsafe {
+            utils::pipelines::destroy(&self.device, self.pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+impl FrustumDebugPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: vk::PipelineCache,
+        globals_buffers: &GlobalsBuffers,
+    ) -> Result<Self> {
+        //Compile shaders
+        let stages = utils::pipelines::compile_mesh_stages(
+            None,
+            "shaders/frustum.mesh.glsl",
+            "main",
+            &[],
+            "shaders/frustum.frag.glsl",
+            "main",
+            &[],
+        )?;
+
+        //Create pipeline layout
+        let pipeline_layout = unsafe {
+            ShaderInterface::reflect(&stages)?.create_pipeline_layout(
+                device,
+                slice::from_ref(&globals_buffers.descriptor_set_layout),
+            )
+        }?;
+
+        //Create pipeline, the frustum is drawn on top of everything
+        let pipeline = unsafe {
+            utils::pipelines::create_mesh(
+                device,
+                pipeline_cache,
+                &stages,
+                &[],
+                &[SWAPCHAIN_FORMAT],
+                //Without depth testing there's no need for a depth attachment
+                vk::Format::UNDEFINED,
+                &MultisampleState::default(),
+                &RasterState {
+                    depth_test: false,
+                    depth_write: false,
+                    ..Default::default()
+                },
+                pipeline_layout,
+            )
+        }?;
+
+        Ok(Self {
+            pipeline_layout,
+            pipeline,
+            device: device.clone(),
+        })
+    }
+
+    pub unsafe fn draw_frustum(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        view_projection_matrix: &Mat4,
+    ) {
+        let device_loader = &ctx.device.device_loader;
+
+        //Begin rendering
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(ctx.swapchain.image_views[image_index])
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE);
+
+        let extent = ctx.swapchain.extent;
+
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D::default().extent(extent))
+            .layer_count(1)
+            .color_attachments(slice::from_ref(&color_attachment));
+
+        device_loader.cmd_begin_rendering(command_buffer, &rendering_info);
+
+        //Draw frustum lines
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline,
+        );
+
+        let viewport = vk::Viewport::default()
+            .width(extent.width as _)
+            .height(extent.height as _)
+            .max_depth(1.0);
+        let scissor = vk::Rect2D::default().extent(extent);
+
+        device_loader.cmd_set_viewport(command_buffer, 0, slice::from_ref(&viewport));
+        device_loader.cmd_set_scissor(command_buffer, 0, slice::from_ref(&scissor));
+
+        ctx.frame_resources.globals_buffers.push_descriptor_set(
+            &ctx.device.push_descriptor_loader,
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+        );
+
+        //The far corners of a frustum with an infinite far plane would be unprojected to infinity, they are drawn at
+        //FAR_PLANE instead. Such a frustum has no depth row, its depth is the constant near plane divided by w
+        let far_depth = if view_projection_matrix.row(2).truncate() == Vec3::ZERO {
+            NEAR_PLANE / FAR_PLANE
+        } else {
+            0.0
+        };
+
+        let inverse_view_projection_matrix = view_projection_matrix.inverse();
+
+        device_loader.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::MESH_EXT,
+            0,
+            bytemuck::bytes_of(&inverse_view_projection_matrix),
+        );
+        device_loader.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::MESH_EXT,
+            mem::size_of::<Mat4>() as _,
+            bytemuck::bytes_of(&far_depth),
+        );
+
+        ctx.device
+            .mesh_shader_loader
+            .cmd_draw_mesh_tasks(command_buffer, 1, 1, 1);
+
+        device_loader.cmd_end_rendering(command_buffer);
+    }
+}
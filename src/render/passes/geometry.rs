@@ -1,26 +1,481 @@
 use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
-    mem, slice,
+    collections::HashMap,
+    fmt, mem, slice,
     sync::Arc,
+    thread::{self, JoinHandle},
 };
 
-use ash::{vk, Device};
-use glam::{Quat, Vec3, Vec4};
-use winit::window::Window;
+use anyhow::{bail, Result};
+use ash::{extensions::ext::MeshShader, prelude::VkResult, vk, Device};
+use bytemuck::{Pod, Zeroable};
+use glam::Vec4;
 
 use crate::render::{
-    render_ctx::{RenderCtx, DEPTH_FORMAT, SWAPCHAIN_FORMAT},
+    deletion_queue::DeletionQueue,
+    frame::Frame,
+    hitch_detector,
+    mesh::{MeshletConfig, MAX_TRIANGLES, MAX_VERTICES},
+    passes::{
+        grass::GrassPass,
+        instance_cull::InstanceCullPass,
+        overdraw::OverdrawPass,
+        particles::ParticlePass,
+        picking::{ID_FORMAT, NO_INSTANCE},
+        taa::VELOCITY_FORMAT,
+    },
+    render_ctx::{RenderCtx, DEPTH_FORMAT, FAR_PLANE, NEAR_PLANE, SWAPCHAIN_FORMAT},
+    render_settings::RenderSettings,
     utils,
-    utils::globals::GlobalsBuffers,
+    utils::{
+        permutations::PipelinePermutations,
+        pipelines::{MultisampleState, RasterState, ShaderStage},
+        reflection::ShaderInterface,
+    },
+    workers::WorkerPool,
 };
 
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DebugView {
+    #[default]
+    MeshletId,
+    LodLevel,
+    Normals,
+    TexCoords,
+    Depth,
+    //Darkens the edges of every meshlet which aren't shared by two of its triangles
+    MeshletBorders,
+    //Shades every meshlet with the base color of its material
+    Materials,
+}
+
+impl DebugView {
+    #[inline]
+    pub fn next(self) -> Self {
+        match self {
+            Self::MeshletId => Self::LodLevel,
+            Self::LodLevel => Self::Normals,
+            Self::Normals => Self::TexCoords,
+            Self::TexCoords => Self::Depth,
+            Self::Depth => Self::MeshletBorders,
+            Self::MeshletBorders => Self::Materials,
+            Self::Materials => Self::MeshletId,
+        }
+    }
+}
+
+//Counted with atomics by the task and mesh shaders of the geometry pass, the skipped meshlets never had to be tested
+//because their group was culled. Read back once the fence of the frame is waited for, NUM_FRAMES frames later
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Zeroable, Pod)]
+pub struct CullingStats {
+    pub meshlet_groups_tested: u32,
+    pub meshlet_groups_culled: u32,
+    pub meshlets_tested: u32,
+    pub meshlets_culled: u32,
+    pub meshlets_skipped: u32,
+    //The triangles of the visible meshlets
+    pub triangles_tested: u32,
+    //Only counted with triangle culling
+    pub triangles_culled: u32,
+}
+
+impl fmt::Display for CullingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "meshlets: {}/{}, triangles: {}/{}",
+            self.meshlets_tested - self.meshlets_culled,
+            self.meshlets_tested + self.meshlets_skipped,
+            self.triangles_tested - self.triangles_culled,
+            self.triangles_tested
+        )
+    }
+}
+
+//Written once per frame, the same for every draw. Everything the shaders read is reached through the addresses, so only
+//the overdraw image and the mask textures need descriptor sets
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Zeroable, Pod)]
+pub struct DrawConstants {
+    pub culling_stats_address: vk::DeviceAddress,
+    pub globals_address: vk::DeviceAddress,
+    pub meshes_address: vk::DeviceAddress,
+    pub instances_address: vk::DeviceAddress,
+    //The level of every instance, see Frame::instance_levels_buffer
+    pub instance_levels_address: vk::DeviceAddress,
+}
+
+//Pushed for every draw, the shaders look up everything else of the instance in the buffers
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Zeroable, Pod)]
+pub struct DrawPushConstants {
+    pub draw_constants_address: vk::DeviceAddress,
+    pub instance_idx: u32,
+    pub padding: u32,
+}
+
+//Everything the geometry pass records per draw, so the draws can be recorded on other threads than the render thread
+#[derive(Copy, Clone, Debug)]
+pub struct MeshDraw {
+    pub push_constants: DrawPushConstants,
+    pub num_meshlet_groups: u32,
+    //Dispatched instead of the meshlet groups without task shaders
+    pub num_meshlets: u32,
+    //Drawn with the alpha tested permutation, see MeshBuffers::alpha_tested
+    pub alpha_test: bool,
+    //Skipped while the InstanceCullPass finds the instance outside the frustum
+    pub conditional: bool,
+}
+
+//The state bound before the draws, every secondary command buffer binds it again
+struct DrawState {
+    pipeline: vk::Pipeline,
+    //Bound for the alpha tested draws, which come after all others. The same as pipeline if there are none
+    alpha_test_pipeline: vk::Pipeline,
+    color_formats: &'static [vk::Format],
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_stages: vk::ShaderStageFlags,
+    rasterization_samples: vk::SampleCountFlags,
+    view_mask: u32,
+    extent: vk::Extent2D,
+    descriptor_set: Option<vk::DescriptorSet>,
+    //Only bound for the alpha tested draws
+    mask_descriptor_set: Option<vk::DescriptorSet>,
+    //The draws are predicated on the visibility of their instance if the InstanceCullPass ran
+    conditional_rendering: Option<(vk::ExtConditionalRenderingFn, vk::Buffer)>,
+    //Otherwise a mesh shader workgroup is dispatched per meshlet
+    task_shader: bool,
+    //Inherited by the secondary command buffers
+    pipeline_statistics: vk::QueryPipelineStatisticFlags,
+}
+
+//What GeometryPass::prepare created for the draws of a frame
+pub struct PreparedDraws {
+    depth_prepass: Option<DrawState>,
+    draw_state: DrawState,
+    //The number of secondary command buffers the draws were recorded into
+    num_draw_chunks: usize,
+}
+
+//The shaders the geometry pipelines are created from
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GeometryShaders {
+    Meshlets,
+    Triangles,
+    Overdraw,
+    //Only writes the depth for the depth pre-pass, the positions are the same as the ones of the meshlet shaders
+    DepthOnly,
+}
+
+impl GeometryShaders {
+    const ALL: [Self; 4] = [
+        Self::Meshlets,
+        Self::Triangles,
+        Self::Overdraw,
+        Self::DepthOnly,
+    ];
+
+    #[inline]
+    fn mesh_path(self) -> &'static str {
+        match self {
+            Self::Triangles => "shaders/geometry_tri.mesh.glsl",
+            _ => "shaders/geometry.mesh.glsl",
+        }
+    }
+
+    #[inline]
+    fn fragment_path(self) -> &'static str {
+        match self {
+            Self::Meshlets => "shaders/geometry.frag.glsl",
+            Self::Triangles => "shaders/geometry_tri.frag.glsl",
+            Self::Overdraw => "shaders/overdraw.frag.glsl",
+            Self::DepthOnly => "shaders/depth_only.frag.glsl",
+        }
+    }
+}
+
+//Everything a geometry pipeline is specialized for, the workgroup size is the same for all of them
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GeometryPermutation {
+    pub shaders: GeometryShaders,
+    pub wireframe: bool,
+    pub debug_view: DebugView,
+    pub culling: bool,
+    //Only the depth debug view depends on the far plane
+    pub infinite_far_plane: bool,
+    //Renders into the TAA color image and writes the velocity next to it
+    pub taa: bool,
+    //Coarser levels of detail are shaded at a coarser rate
+    pub variable_shading_rate: bool,
+    //Writes the index of the instance into the ID attachment
+    pub picking: bool,
+    //Discards the fragments which are cut out of alpha tested materials, compiled with the ALPHA_TEST define
+    pub alpha_test: bool,
+    //Shades the fragments with the lights of their cluster, see LightCullPass
+    pub lighting: bool,
+    //The mesh shader culls the triangles which can't cover a pixel center, see TRIANGLE_CULLING in the mesh shader
+    pub triangle_culling: bool,
+}
+
+impl GeometryPermutation {
+    //Overdraw takes precedence over the wireframe, which takes precedence over the triangle view. TAA and picking aren't
+    //supported with MSAA, their images are single sampled. Triangle culling tests the pixel centers, so it needs a
+    //single sample too. The lights are only clustered for a single view
+    pub fn new(
+        render_settings: &RenderSettings,
+        overdraw: bool,
+        wireframe_supported: bool,
+        taa_supported: bool,
+        primitive_shading_rate_supported: bool,
+        picking: bool,
+        lighting_supported: bool,
+    ) -> Self {
+        let wireframe = render_settings.wireframe && wireframe_supported;
+
+        Self {
+            shaders: if overdraw {
+                GeometryShaders::Overdraw
+            } else if render_settings.triangle_view && !wireframe {
+                GeometryShaders::Triangles
+            } else {
+                GeometryShaders::Meshlets
+            },
+            wireframe,
+            debug_view: render_settings.debug_view,
+            culling: render_settings.culling,
+            infinite_far_plane: render_settings.infinite_far_plane,
+            taa: render_settings.taa && taa_supported,
+            variable_shading_rate: render_settings.variable_shading_rate
+                && primitive_shading_rate_supported,
+            picking: picking && taa_supported,
+            alpha_test: false,
+            lighting: render_settings.clustered_lighting && lighting_supported,
+            triangle_culling: render_settings.triangle_culling && taa_supported,
+        }
+        .normalized()
+    }
+
+    //Only the meshlet and depth only shaders have an alpha tested variant, the others draw alpha tested meshes opaque
+    #[inline]
+    fn alpha_tested(self) -> Self {
+        Self {
+            alpha_test: true,
+            ..self
+        }
+        .normalized()
+    }
+
+    //Culls the same meshlets as the meshlet shaders, so the depth matches the geometry pass exactly
+    pub fn depth_prepass(render_settings: &RenderSettings) -> Self {
+        Self {
+            shaders: GeometryShaders::DepthOnly,
+            wireframe: false,
+            debug_view: DebugView::default(),
+            culling: render_settings.culling,
+            infinite_far_plane: false,
+            taa: false,
+            variable_shading_rate: false,
+            picking: false,
+            alpha_test: false,
+            lighting: false,
+            triangle_culling: false,
+        }
+    }
+
+    //Only the meshlet shaders have debug views, a wireframe, the velocity and ID outputs, primitive shading rates,
+    //lighting and triangle culling, resetting them for the others deduplicates their pipelines. The wireframe shows
+    //every triangle
+    #[inline]
+    fn normalized(self) -> Self {
+        match self.shaders {
+            GeometryShaders::Meshlets => {
+                Self {
+                    infinite_far_plane: self.infinite_far_plane
+                        && self.debug_view == DebugView::Depth,
+                    triangle_culling: self.triangle_culling && !self.wireframe,
+                    ..self
+                }
+            }
+            _ => {
+                Self {
+                    wireframe: false,
+                    debug_view: DebugView::default(),
+                    infinite_far_plane: false,
+                    taa: false,
+                    variable_shading_rate: false,
+                    picking: false,
+                    alpha_test: self.alpha_test && self.shaders == GeometryShaders::DepthOnly,
+                    lighting: false,
+                    triangle_culling: false,
+                    ..self
+                }
+            }
+        }
+    }
+
+    //The pre-rasterization library is shared by permutations which only differ in the fragment shader state. The mesh
+    //shader only finds the meshlet borders for their debug view
+    #[inline]
+    fn pre_rasterization_part(self) -> Self {
+        Self {
+            debug_view: if self.debug_view == DebugView::MeshletBorders {
+                DebugView::MeshletBorders
+            } else {
+                DebugView::default()
+            },
+            infinite_far_plane: false,
+            taa: false,
+            picking: false,
+            alpha_test: false,
+            lighting: false,
+            ..self
+        }
+    }
+
+    //The fragment library is shared by permutations which only differ in the pre-rasterization state
+    #[inline]
+    fn fragment_part(self) -> Self {
+        Self {
+            wireframe: false,
+            culling: true,
+            triangle_culling: false,
+            ..self
+        }
+    }
+
+    //Has to match the constant_ids in the task, mesh and fragment shaders
+    #[inline]
+    fn specialization(&self, local_size_x: u32) -> [u32; 9] {
+        [
+            local_size_x,
+            NEAR_PLANE.to_bits(),
+            if self.infinite_far_plane {
+                f32::INFINITY
+            } else {
+                FAR_PLANE
+            }
+            .to_bits(),
+            self.debug_view as _,
+            self.culling as _,
+            //The geometry pass already counts the culled meshlets
+            (self.shaders != GeometryShaders::DepthOnly) as _,
+            self.variable_shading_rate as _,
+            self.lighting as _,
+            self.triangle_culling as _,
+        ]
+    }
+
+    //The depth pre-pass renders without a color attachment, with TAA the velocity is written to a second one. The IDs
+    //are written to a third one, the second one is unused without TAA
+    #[inline]
+    fn color_formats(&self) -> &'static [vk::Format] {
+        match self.shaders {
+            GeometryShaders::DepthOnly => &[vk::Format::UNDEFINED],
+            _ if self.taa && self.picking => &[SWAPCHAIN_FORMAT, VELOCITY_FORMAT, ID_FORMAT],
+            _ if self.picking => &[SWAPCHAIN_FORMAT, vk::Format::UNDEFINED, ID_FORMAT],
+            _ if self.taa => &[SWAPCHAIN_FORMAT, VELOCITY_FORMAT],
+            _ => &[SWAPCHAIN_FORMAT],
+        }
+    }
+
+    fn raster_state(&self) -> RasterState {
+        match self.shaders {
+            //Every fragment counts towards overdraw, so depth testing is disabled
+            GeometryShaders::Overdraw => {
+                RasterState {
+                    depth_test: false,
+                    depth_write: false,
+                    color_write: false,
+                    ..Default::default()
+                }
+            }
+            GeometryShaders::DepthOnly => {
+                RasterState {
+                    color_write: false,
+                    ..Default::default()
+                }
+            }
+            _ if self.wireframe => {
+                RasterState {
+                    polygon_mode: vk::PolygonMode::LINE,
+                    primitive_shading_rate: self.variable_shading_rate,
+                    ..Default::default()
+                }
+            }
+            _ => {
+                RasterState {
+                    primitive_shading_rate: self.variable_shading_rate,
+                    ..Default::default()
+                }
+            }
+        }
+    }
+}
+
+//Keyed by the shaders and whether they are alpha tested
+type CompiledShaders = HashMap<(GeometryShaders, bool), Vec<ShaderStage>>;
+
+//Graphics pipeline libraries the permutations are linked from, so switching the debug view doesn't compile the task
+//and mesh shaders again and the other way around
+struct GeometryLibraries {
+    pre_rasterization: PipelinePermutations<GeometryPermutation>,
+    fragment: PipelinePermutations<GeometryPermutation>,
+}
+
+impl GeometryLibraries {
+    #[inline]
+    fn new(device: &Arc<Device>) -> Self {
+        Self {
+            pre_rasterization: PipelinePermutations::new(device.clone()),
+            fragment: PipelinePermutations::new(device.clone()),
+        }
+    }
+
+    //Returns every library, they have to be destroyed by the caller
+    #[inline]
+    fn take_all(self) -> Vec<vk::Pipeline> {
+        let mut libraries = self.pre_rasterization.replace_all([]);
+        libraries.extend(self.fragment.replace_all([]));
+        libraries
+    }
+}
+
+//Shaders, libraries and pipelines of the permutations in use which were created in the background, see reload_pipelines
+type ReloadedPipelines = (
+    CompiledShaders,
+    Option<GeometryLibraries>,
+    Vec<(GeometryPermutation, vk::Pipeline)>,
+);
+
 pub struct GeometryPass {
-    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    //The mesh collection allocates the descriptor set of its mask textures with it
+    pub mask_descriptor_set_layout: vk::DescriptorSetLayout,
     pub pipeline_layout: vk::PipelineLayout,
-    pub pipeline: vk::Pipeline,
-    pub pipeline_tri: vk::Pipeline,
-    pub triangle_view: bool,
+    pub pipelines: PipelinePermutations<GeometryPermutation>,
+    pub multisample_state: MultisampleState,
+    //Every pipeline renders to these views, it's fixed since stereo rendering is only chosen at startup
+    pub view_mask: u32,
+    //The mesh shaders output at most this many vertices and triangles, fixed like the view mask
+    pub max_meshlet_vertices: usize,
+    pub max_meshlet_triangles: usize,
+    pub sample_rate_shading_supported: bool,
+    pub fill_mode_non_solid_supported: bool,
+    pub graphics_pipeline_library_supported: bool,
+    pub primitive_shading_rate_supported: bool,
+    pub non_uniform_indexing_supported: bool,
+    pub task_shader_supported: bool,
+    //Shared by all pipelines, reloaded shaders have to keep it since the layouts can't change
+    pub shader_interface: ShaderInterface,
+    //Every permutation is specialized from these, so new permutations don't need to compile anything
+    shaders: CompiledShaders,
+    //Only used if the device supports graphics pipeline libraries
+    libraries: Option<GeometryLibraries>,
+    local_size_x: u32,
+    shader_workers: WorkerPool,
+    reload: Option<JoinHandle<Result<ReloadedPipelines>>>,
+    reload_queued: bool,
+    pipeline_cache: vk::PipelineCache,
     device: Arc<Device>,
 }
 
@@ -28,12 +483,20 @@ impl Drop for GeometryPass {
     #[inline]
     fn drop(&mut self) {
         unsafe {
-            self.device.destroy_pipeline(self.pipeline_tri, None);
-            self.device.destroy_pipeline(self.pipeline, None);
+            self.cancel_reload();
+
+            for pipeline in self.pipelines.replace_all([]).into_iter().chain(
+                self.libraries
+                    .take()
+                    .into_iter()
+                    .flat_map(GeometryLibraries::take_all),
+            ) {
+                utils::pipelines::destroy(&self.device, pipeline);
+            }
             self.device
                 .destroy_pipeline_layout(self.pipeline_layout, None);
             self.device
-                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+                .destroy_descriptor_set_layout(self.mask_descriptor_set_layout, None);
         }
     }
 }
@@ -41,272 +504,1045 @@ impl Drop for GeometryPass {
 impl GeometryPass {
     pub fn new(
         device: &Arc<Device>,
-        globals_buffers: &GlobalsBuffers,
+        pipeline_cache: vk::PipelineCache,
+        overdraw_pass: &OverdrawPass,
         physical_device_mesh_shader_properties: &vk::PhysicalDeviceMeshShaderPropertiesEXT,
-    ) -> Self {
-        //Create descriptor set layout
-        let descriptor_set_layout_binding = vk::DescriptorSetLayoutBinding::default()
-            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-            .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::MESH_EXT);
+        multisample_state: MultisampleState,
+        view_mask: u32,
+        meshlet_config: &MeshletConfig,
+        sample_rate_shading_supported: bool,
+        fill_mode_non_solid_supported: bool,
+        graphics_pipeline_library_supported: bool,
+        primitive_shading_rate_supported: bool,
+        non_uniform_indexing_supported: bool,
+        task_shader_supported: bool,
+        shader_workers: WorkerPool,
+    ) -> Result<Self> {
+        let max_meshlet_vertices = meshlet_config.max_vertices;
+        let max_meshlet_triangles = meshlet_config.max_triangles;
+        if max_meshlet_vertices
+            > physical_device_mesh_shader_properties.max_mesh_output_vertices as usize
+            || max_meshlet_triangles
+                > physical_device_mesh_shader_properties.max_mesh_output_primitives as usize
+        {
+            bail!(
+                "Meshlets with {max_meshlet_vertices} vertices and {max_meshlet_triangles} triangles exceed the mesh shader outputs of the device"
+            )
+        }
 
-        let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
-            .bindings(slice::from_ref(&descriptor_set_layout_binding));
+        //Compile shaders
+        let local_size_x =
+            physical_device_mesh_shader_properties.max_preferred_mesh_work_group_invocations;
 
-        let descriptor_set_layout = unsafe {
-            device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
-        }
-        .unwrap();
-
-        //Create pipeline layout
-        let push_constant_range = vk::PushConstantRange::default()
-            .stage_flags(vk::ShaderStageFlags::MESH_EXT)
-            .size((mem::size_of::<Vec4>() * 2 + mem::size_of::<u32>() * 2) as _);
-
-        let descriptor_set_layouts = [globals_buffers.descriptor_set_layout, descriptor_set_layout];
-
-        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
-            .set_layouts(&descriptor_set_layouts)
-            .push_constant_ranges(slice::from_ref(&push_constant_range));
-        let pipeline_layout =
-            unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None) }.unwrap();
-
-        //Create pipeline
-        let (pipeline, pipeline_tri) = unsafe {
-            let local_size_x = physical_device_mesh_shader_properties
-                .max_preferred_mesh_work_group_invocations
-                .to_string();
-
-            (
-                utils::pipelines::create_mesh(
-                    device,
-                    "shaders/geometry.mesh.glsl",
-                    "main",
-                    &[("LOCAL_SIZE_X", Some(&local_size_x))],
-                    "shaders/geometry.frag.glsl",
-                    "main",
-                    &[],
-                    SWAPCHAIN_FORMAT,
-                    DEPTH_FORMAT,
-                    pipeline_layout,
-                )
-                .unwrap(),
-                utils::pipelines::create_mesh(
-                    device,
-                    "shaders/geometry_tri.mesh.glsl",
-                    "main",
-                    &[("LOCAL_SIZE_X", Some(&local_size_x))],
-                    "shaders/geometry_tri.frag.glsl",
-                    "main",
-                    &[],
-                    SWAPCHAIN_FORMAT,
-                    DEPTH_FORMAT,
-                    pipeline_layout,
-                )
-                .unwrap(),
+        let shaders = compile_shaders(
+            primitive_shading_rate_supported,
+            task_shader_supported,
+            max_meshlet_vertices,
+            max_meshlet_triangles,
+            &shader_workers,
+        )?;
+        let shader_interface = ShaderInterface::reflect(shaders.values().flatten())?;
+
+        //Create pipeline layout, the overdraw image is in set 0 and the mask textures are in set 1
+        let mask_descriptor_set_layout = unsafe {
+            shader_interface.create_descriptor_set_layout(device, 1, vk::ShaderStageFlags::empty())
+        }?;
+        let pipeline_layout = unsafe {
+            shader_interface.create_pipeline_layout(
+                device,
+                &[
+                    overdraw_pass.descriptor_set_layout,
+                    mask_descriptor_set_layout,
+                ],
             )
-        };
+        }?;
 
-        Self {
-            descriptor_set_layout,
+        let geometry_pass = Self {
+            mask_descriptor_set_layout,
             pipeline_layout,
-            pipeline,
-            pipeline_tri,
-            triangle_view: false,
+            pipelines: PipelinePermutations::new(device.clone()),
+            multisample_state: multisample_state.validated(sample_rate_shading_supported),
+            view_mask,
+            max_meshlet_vertices,
+            max_meshlet_triangles,
+            sample_rate_shading_supported,
+            fill_mode_non_solid_supported,
+            graphics_pipeline_library_supported,
+            primitive_shading_rate_supported,
+            non_uniform_indexing_supported,
+            task_shader_supported,
+            shader_interface,
+            shaders,
+            libraries: graphics_pipeline_library_supported.then(|| GeometryLibraries::new(device)),
+            local_size_x,
+            shader_workers,
+            reload: None,
+            reload_queued: false,
+            pipeline_cache,
             device: device.clone(),
+        };
+
+        //The permutation of the default settings is created up front, so the first frame doesn't hitch
+        geometry_pass.pipeline(GeometryPermutation::new(
+            &RenderSettings::default(),
+            false,
+            fill_mode_non_solid_supported,
+            geometry_pass.taa_supported(),
+            primitive_shading_rate_supported,
+            false,
+            geometry_pass.view_mask == 0,
+        ))?;
+
+        Ok(geometry_pass)
+    }
+
+    //Creates the pipeline the first time the permutation is drawn
+    fn pipeline(&self, permutation: GeometryPermutation) -> Result<vk::Pipeline> {
+        self.pipelines
+            .get_or_create(permutation, |permutation| unsafe {
+                create_pipeline(
+                    &self.device,
+                    self.pipeline_cache,
+                    self.pipeline_layout,
+                    &self.shaders,
+                    self.libraries.as_ref(),
+                    permutation,
+                    self.local_size_x,
+                    &self.multisample_state,
+                    self.view_mask,
+                )
+            })
+    }
+
+    pub fn set_multisample_state(&mut self, multisample_state: MultisampleState) {
+        let multisample_state = multisample_state.validated(self.sample_rate_shading_supported);
+
+        //A running reload creates its pipelines with the previous multisample state, so it's started again
+        let reload_running = self.reload.is_some();
+        self.cancel_reload();
+
+        unsafe {
+            //The pipelines might still be in use by frames in flight
+            self.device.device_wait_idle().unwrap();
+
+            //They are created again with the new state the next time they are drawn
+            for pipeline in self.pipelines.replace_all([]) {
+                utils::pipelines::destroy(&self.device, pipeline);
+            }
+            for library in self
+                .libraries
+                .take()
+                .into_iter()
+                .flat_map(GeometryLibraries::take_all)
+            {
+                utils::pipelines::destroy(&self.device, library);
+            }
+        }
+        self.libraries = self
+            .graphics_pipeline_library_supported
+            .then(|| GeometryLibraries::new(&self.device));
+
+        self.multisample_state = multisample_state;
+
+        if reload_running {
+            self.reload_pipelines();
         }
     }
 
-    pub unsafe fn execute(
+    //Recompiles the shaders on a background thread and recreates the permutations in use, the current pipelines stay
+    //in use until poll_reload swaps them
+    pub fn reload_pipelines(&mut self) {
+        if self.reload.is_some() {
+            //The running reload might have read the shaders before they changed
+            self.reload_queued = true;
+            return
+        }
+
+        let device = self.device.clone();
+        let pipeline_cache = self.pipeline_cache;
+        let pipeline_layout = self.pipeline_layout;
+        let shader_interface = self.shader_interface.clone();
+        let permutations = self.pipelines.keys();
+        let graphics_pipeline_library_supported = self.graphics_pipeline_library_supported;
+        let local_size_x = self.local_size_x;
+        let multisample_state = self.multisample_state;
+        let view_mask = self.view_mask;
+        let primitive_shading_rate_supported = self.primitive_shading_rate_supported;
+        let task_shader_supported = self.task_shader_supported;
+        let max_meshlet_vertices = self.max_meshlet_vertices;
+        let max_meshlet_triangles = self.max_meshlet_triangles;
+        let shader_workers = self.shader_workers.clone();
+
+        self.reload = Some(
+            thread::Builder::new()
+                .name("shader-reload".into())
+                .spawn(move || unsafe {
+                    let shaders = compile_shaders(
+                        primitive_shading_rate_supported,
+                        task_shader_supported,
+                        max_meshlet_vertices,
+                        max_meshlet_triangles,
+                        &shader_workers,
+                    )?;
+
+                    if ShaderInterface::reflect(shaders.values().flatten())? != shader_interface {
+                        bail!("The descriptor sets or push constants of the shaders changed, which requires a restart")
+                    }
+
+                    //Permutations sharing a library only create it once, so the reload mostly links
+                    let libraries = graphics_pipeline_library_supported
+                        .then(|| GeometryLibraries::new(&device));
+
+                    let pipelines = shader_workers.map(permutations, |permutation| {
+                        create_pipeline(
+                            &device,
+                            pipeline_cache,
+                            pipeline_layout,
+                            &shaders,
+                            libraries.as_ref(),
+                            permutation,
+                            local_size_x,
+                            &multisample_state,
+                            view_mask,
+                        )
+                        .map(|pipeline| (permutation, pipeline))
+                    });
+
+                    //Either every pipeline is replaced or none, the ones which were created are thrown away on errors
+                    let (pipelines, errors): (Vec<_>, Vec<_>) =
+                        pipelines.into_iter().partition(Result::is_ok);
+                    let pipelines = pipelines.into_iter().map(Result::unwrap);
+                    if let Some(error) = errors.into_iter().next() {
+                        pipelines
+                            .map(|(_, pipeline)| pipeline)
+                            .chain(libraries.into_iter().flat_map(GeometryLibraries::take_all))
+                            .for_each(|pipeline| utils::pipelines::destroy(&device, pipeline));
+                        return Err(error.unwrap_err())
+                    }
+
+                    Ok((shaders, libraries, pipelines.collect()))
+                })
+                .unwrap(),
+        );
+    }
+
+    //Swaps in the reloaded shaders and pipelines once they are ready, the previous pipelines are deleted after the
+    //frames in flight finished
+    pub fn poll_reload(&mut self, deletion_queue: &mut DeletionQueue) {
+        let reload = match self.reload.take() {
+            Some(reload) if reload.is_finished() => reload,
+            reload => {
+                self.reload = reload;
+                return
+            }
+        };
+
+        match reload.join() {
+            Ok(Ok((shaders, libraries, pipelines))) => {
+                self.shaders = shaders;
+                let libraries = mem::replace(&mut self.libraries, libraries);
+                for pipeline in self
+                    .pipelines
+                    .replace_all(pipelines)
+                    .into_iter()
+                    .chain(libraries.into_iter().flat_map(GeometryLibraries::take_all))
+                {
+                    deletion_queue.push_pipeline(pipeline);
+                }
+                println!("Reloaded the geometry pipelines");
+            }
+            //Keep rendering with the previous pipelines until the shaders are fixed
+            Ok(Err(error)) => {
+                eprintln!("Warning: Failed to reload the geometry pipelines: {error:?}")
+            }
+            Err(_) => eprintln!("Warning: Reloading the geometry pipelines panicked"),
+        }
+
+        if mem::take(&mut self.reload_queued) {
+            self.reload_pipelines();
+        }
+    }
+
+    //Waits for a running reload and throws its pipelines away
+    fn cancel_reload(&mut self) {
+        if let Some(Ok(Ok((_, libraries, pipelines)))) = self.reload.take().map(JoinHandle::join) {
+            for pipeline in pipelines
+                .into_iter()
+                .map(|(_, pipeline)| pipeline)
+                .chain(libraries.into_iter().flat_map(GeometryLibraries::take_all))
+            {
+                unsafe { utils::pipelines::destroy(&self.device, pipeline) };
+            }
+        }
+        self.reload_queued = false;
+    }
+
+    pub fn toggle_alpha_to_coverage(&mut self) {
+        self.set_multisample_state(MultisampleState {
+            alpha_to_coverage: !self.multisample_state.alpha_to_coverage,
+            ..self.multisample_state
+        });
+    }
+
+    pub fn toggle_sample_shading(&mut self) {
+        self.set_multisample_state(MultisampleState {
+            min_sample_shading: match self.multisample_state.min_sample_shading {
+                Some(_) => None,
+                None => Some(1.0),
+            },
+            ..self.multisample_state
+        });
+    }
+
+    //The IDs are also written while an instance is selected, the outline is drawn around them
+    #[inline]
+    fn permutation(&self, ctx: &RenderCtx) -> GeometryPermutation {
+        self.permutation_with_picking(
+            ctx,
+            ctx.pick_requested.is_some() || ctx.selected_instance.is_some(),
+        )
+    }
+
+    #[inline]
+    fn permutation_with_picking(&self, ctx: &RenderCtx, picking: bool) -> GeometryPermutation {
+        GeometryPermutation::new(
+            &ctx.render_settings,
+            ctx.overdraw_enabled(),
+            self.fill_mode_non_solid_supported,
+            self.taa_supported() && ctx.secondary_view.is_none(),
+            self.primitive_shading_rate_supported,
+            picking,
+            self.view_mask == 0,
+        )
+    }
+
+    //Only the meshlet shaders write the IDs
+    #[inline]
+    pub fn picking_supported(&self, ctx: &RenderCtx) -> bool {
+        self.permutation_with_picking(ctx, true).picking
+    }
+
+    #[inline]
+    pub fn picking_enabled(&self, ctx: &RenderCtx) -> bool {
+        self.permutation(ctx).picking
+    }
+
+    //The lights are binned into their clusters before the geometry pass then
+    #[inline]
+    pub fn lighting_enabled(&self, ctx: &RenderCtx) -> bool {
+        self.permutation(ctx).lighting
+    }
+
+    #[inline]
+    pub fn triangle_culling_enabled(&self, ctx: &RenderCtx) -> bool {
+        self.permutation(ctx).triangle_culling
+    }
+
+    //The TAA and ID images only have a single layer and sample
+    #[inline]
+    fn taa_supported(&self) -> bool {
+        self.multisample_state.rasterization_samples == vk::SampleCountFlags::TYPE_1
+            && self.view_mask == 0
+    }
+
+    //Only the meshlet shaders write the velocity, the other permutations render straight into the swapchain image
+    #[inline]
+    pub fn taa_enabled(&self, ctx: &RenderCtx) -> bool {
+        self.permutation(ctx).taa
+    }
+
+    //The pre-pass only matches the meshlet shaders, the triangle view computes its positions differently and neither
+    //overdraw nor the wireframe test against the depth of filled triangles. It only fills the depth of a single view
+    pub fn depth_prepass_enabled(&self, ctx: &RenderCtx) -> bool {
+        let permutation = self.permutation(ctx);
+        ctx.render_settings.depth_prepass
+            && self.view_mask == 0
+            && permutation.shaders == GeometryShaders::Meshlets
+            && !permutation.wireframe
+    }
+
+    //The alpha tested permutation is only created once an alpha tested mesh is drawn. Without non-uniform indexing of
+    //the mask textures the alpha tested meshes are drawn opaque
+    #[inline]
+    fn draw_state(
+        &self,
+        ctx: &RenderCtx,
+        frame_index: usize,
+        permutation: GeometryPermutation,
+        descriptor_set: Option<vk::DescriptorSet>,
+        draws: &[MeshDraw],
+    ) -> Result<DrawState> {
+        let pipeline = self.pipeline(permutation)?;
+        let alpha_test =
+            self.non_uniform_indexing_supported && draws.iter().any(|draw| draw.alpha_test);
+
+        Ok(DrawState {
+            pipeline,
+            alpha_test_pipeline: if alpha_test {
+                self.pipeline(permutation.alpha_tested())?
+            } else {
+                pipeline
+            },
+            color_formats: permutation.color_formats(),
+            pipeline_layout: self.pipeline_layout,
+            push_constant_stages: self.shader_interface.push_constant_stages(),
+            rasterization_samples: self.multisample_state.rasterization_samples,
+            view_mask: self.view_mask,
+            extent: render_extent(ctx),
+            descriptor_set,
+            mask_descriptor_set: alpha_test.then_some(
+                ctx.scene_resources
+                    .mesh_collection
+                    .mask_textures
+                    .descriptor_set,
+            ),
+            conditional_rendering: InstanceCullPass::enabled(ctx).then(|| {
+                (
+                    ctx.device.conditional_rendering_loader.clone().unwrap(),
+                    ctx.frame_resources.frames[frame_index]
+                        .visibility_buffer
+                        .buffer,
+                )
+            }),
+            task_shader: self.task_shader_supported,
+            pipeline_statistics: ctx.frame_resources.frames[frame_index]
+                .pipeline_statistics_query_pool
+                .flags,
+        })
+    }
+
+    //Creates the pipelines the draws of this frame need and records them into the secondary command buffers. Unlike
+    //recording the passes it can fail, so it's done before the frame is recorded
+    pub unsafe fn prepare(
+        &self,
+        ctx: &RenderCtx,
+        frame_index: usize,
+        draws: &[MeshDraw],
+    ) -> Result<PreparedDraws> {
+        let depth_prepass = self
+            .depth_prepass_enabled(ctx)
+            .then(|| {
+                self.draw_state(
+                    ctx,
+                    frame_index,
+                    GeometryPermutation::depth_prepass(&ctx.render_settings),
+                    None,
+                    draws,
+                )
+            })
+            .transpose()?;
+        let draw_state = self.draw_state(
+            ctx,
+            frame_index,
+            self.permutation(ctx),
+            //The other permutations read everything through the addresses in the draw constants
+            ctx.overdraw_enabled()
+                .then_some(ctx.overdraw_pass.descriptor_set),
+            draws,
+        )?;
+
+        //Every recording thread gets an equal share of the draws
+        let secondary_command_buffers =
+            &ctx.frame_resources.frames[frame_index].secondary_command_buffers;
+        let num_draw_chunks = if secondary_command_buffers.is_empty() {
+            0
+        } else {
+            let device_loader = &ctx.device.device_loader;
+            let mesh_shader_loader = &ctx.device.mesh_shader_loader;
+            let draw_chunks =
+                draws.chunks(draws.len().div_ceil(secondary_command_buffers.len()).max(1));
+            let num_draw_chunks = draw_chunks.len();
+
+            ctx.recording_workers
+                .map(
+                    secondary_command_buffers.iter().zip(draw_chunks),
+                    |(secondary_command_buffer, draws)| {
+                        record_secondary_draws(
+                            device_loader,
+                            mesh_shader_loader,
+                            *secondary_command_buffer,
+                            &draw_state,
+                            draws,
+                        )
+                    },
+                )
+                .into_iter()
+                .collect::<VkResult<Vec<_>>>()?;
+            num_draw_chunks
+        };
+
+        Ok(PreparedDraws {
+            depth_prepass,
+            draw_state,
+            num_draw_chunks,
+        })
+    }
+
+    //Fills the depth image, so the geometry pass only shades the visible surfaces. The draws are recorded on the render
+    //thread, the secondary command buffers are used by the geometry pass
+    pub unsafe fn execute_depth_prepass(
         &self,
         ctx: &RenderCtx,
         command_buffer: vk::CommandBuffer,
-        image_index: usize,
-        window: &Window,
+        prepared_draws: &PreparedDraws,
+        draws: &[MeshDraw],
     ) {
-        let device_loader = &ctx.device_loader;
-
-        let image = ctx.swapchain_images[image_index];
-
-        //Transition image to COLOR_ATTACHMENT_OPTIMAL
-        let image_memory_barrier = vk::ImageMemoryBarrier2::default()
-            .src_stage_mask(vk::PipelineStageFlags2::TOP_OF_PIPE)
-            .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
-            .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
-            .old_layout(vk::ImageLayout::UNDEFINED)
-            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .image(image)
-            .subresource_range(
-                vk::ImageSubresourceRange::default()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .level_count(1)
-                    .layer_count(1),
-            );
+        let device_loader = &ctx.device.device_loader;
+        let Some(draw_state) = &prepared_draws.depth_prepass else {
+            return
+        };
+
+        //The pipeline has an undefined color format, which requires a color attachment without an image view
+        let color_attachment = vk::RenderingAttachmentInfo::default();
+
+        let depth_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(ctx.frame_resources.depth_image_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 0.0,
+                    stencil: 0,
+                },
+            });
 
-        device_loader.cmd_pipeline_barrier2(
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D::default().extent(ctx.swapchain.extent))
+            .layer_count(1)
+            .color_attachments(slice::from_ref(&color_attachment))
+            .depth_attachment(&depth_attachment);
+
+        device_loader.cmd_begin_rendering(command_buffer, &rendering_info);
+        record_draws(
+            device_loader,
+            &ctx.device.mesh_shader_loader,
             command_buffer,
-            &vk::DependencyInfo::default()
-                .image_memory_barriers(slice::from_ref(&image_memory_barrier)),
+            draw_state,
+            draws,
         );
+        device_loader.cmd_end_rendering(command_buffer);
+    }
+
+    //The swapchain image, or the TAA or stereo images if they are used, have to be in COLOR_ATTACHMENT_OPTIMAL, they
+    //are left there for the passes after it
+    pub unsafe fn execute(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        image_index: usize,
+        prepared_draws: &PreparedDraws,
+        draws: &[MeshDraw],
+    ) {
+        let device_loader = &ctx.device.device_loader;
+
+        if ctx.overdraw_enabled() {
+            ctx.overdraw_pass.clear(command_buffer);
+        }
+
+        let frame = &ctx.frame_resources.frames[frame_index];
+        let secondary_command_buffers = &frame.secondary_command_buffers;
 
-        //Begin rendering
-        let color_attachment = vk::RenderingAttachmentInfo::default()
-            .image_view(ctx.swapchain_image_views[image_index])
+        //Begin rendering, with MSAA the multisampled image is resolved into the swapchain image at the end of the pass.
+        //With TAA the TAA pass resolves the color into the swapchain image instead, with stereo rendering the eyes are
+        //composited into it
+        let taa = self.taa_enabled(ctx);
+        let target_image_view = match &ctx.stereo_pass {
+            Some(stereo_pass) => stereo_pass.color.image_view,
+            None if taa => ctx.taa_pass.color.image_view,
+            None => ctx.swapchain.image_views[image_index],
+        };
+        let color_attachment = match &ctx.frame_resources.msaa_color_image {
+            Some(msaa_color_image) => {
+                vk::RenderingAttachmentInfo::default()
+                    .image_view(msaa_color_image.image_view)
+                    .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                    .resolve_image_view(target_image_view)
+                    .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            }
+            None => {
+                vk::RenderingAttachmentInfo::default()
+                    .image_view(target_image_view)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+            }
+        }
+        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .clear_value(vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [100.0 / 255.0, 149.0 / 255.0, 237.0 / 255.0, 1.0],
+            },
+        });
+
+        //Nothing is drawn where the background is, so it doesn't move
+        let velocity_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(ctx.taa_pass.velocity.image_view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue::default());
+
+        //Unused without TAA, the IDs are always the third attachment
+        let velocity_attachment = if taa {
+            velocity_attachment
+        } else {
+            vk::RenderingAttachmentInfo::default()
+        };
+
+        let id_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(ctx.picking_pass.id_image_view)
             .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::STORE)
             .clear_value(vk::ClearValue {
                 color: vk::ClearColorValue {
-                    float32: [100.0 / 255.0, 149.0 / 255.0, 237.0 / 255.0, 1.0],
+                    uint32: [NO_INSTANCE; 4],
                 },
             });
 
+        let color_attachments = [color_attachment, velocity_attachment, id_attachment];
+        let num_color_attachments = if self.picking_enabled(ctx) {
+            3
+        } else if taa {
+            2
+        } else {
+            1
+        };
+
+        //The depth written by the pre-pass is kept, the fragments behind it fail the depth test before shading. The grass
+        //and the particles are tested against the depth after the pass
         let depth_attachment = vk::RenderingAttachmentInfo::default()
-            .image_view(ctx.depth_image_view)
+            .image_view(
+                ctx.stereo_pass
+                    .as_ref()
+                    .map_or(ctx.frame_resources.depth_image_view, |stereo_pass| {
+                        stereo_pass.depth.image_view
+                    }),
+            )
             .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .load_op(if self.depth_prepass_enabled(ctx) {
+                vk::AttachmentLoadOp::LOAD
+            } else {
+                vk::AttachmentLoadOp::CLEAR
+            })
+            .store_op(if GrassPass::enabled(ctx) || ParticlePass::enabled(ctx) {
+                vk::AttachmentStoreOp::STORE
+            } else {
+                vk::AttachmentStoreOp::DONT_CARE
+            })
             .clear_value(vk::ClearValue {
                 depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
+                    //Reversed-Z, the far plane is at 0
+                    depth: 0.0,
                     stencil: 0,
                 },
             });
 
         let rendering_info = vk::RenderingInfo::default()
-            .render_area(
-                vk::Rect2D::default().extent(
-                    vk::Extent2D::default()
-                        .width(window.inner_size().width)
-                        .height(window.inner_size().height),
-                ),
-            )
+            .render_area(vk::Rect2D::default().extent(render_extent(ctx)))
             .layer_count(1)
-            .color_attachments(slice::from_ref(&color_attachment))
-            .depth_attachment(&depth_attachment);
+            .view_mask(self.view_mask)
+            .color_attachments(&color_attachments[..num_color_attachments])
+            .depth_attachment(&depth_attachment)
+            .flags(if secondary_command_buffers.is_empty() {
+                vk::RenderingFlags::empty()
+            } else {
+                vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS
+            });
 
         device_loader.cmd_begin_rendering(command_buffer, &rendering_info);
 
-        //Bind pipeline, set viewport and bind descriptor set
-        ctx.device_loader.cmd_bind_pipeline(
-            command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            if self.triangle_view {
-                self.pipeline_tri
+        //The secondary command buffers were recorded by prepare
+        if secondary_command_buffers.is_empty() {
+            record_draws(
+                device_loader,
+                &ctx.device.mesh_shader_loader,
+                command_buffer,
+                &prepared_draws.draw_state,
+                draws,
+            );
+        } else if prepared_draws.num_draw_chunks > 0 {
+            device_loader.cmd_execute_commands(
+                command_buffer,
+                &secondary_command_buffers[..prepared_draws.num_draw_chunks],
+            );
+        }
+
+        //End rendering
+        device_loader.cmd_end_rendering(command_buffer);
+
+        if ctx.overdraw_enabled() {
+            ctx.overdraw_pass
+                .draw_heatmap(ctx, command_buffer, image_index);
+        }
+    }
+}
+
+//The specialization constants are the same for every shader set, so they can be compiled ahead of time
+fn compile_shaders(
+    primitive_shading_rate_supported: bool,
+    task_shader_supported: bool,
+    max_meshlet_vertices: usize,
+    max_meshlet_triangles: usize,
+    shader_workers: &WorkerPool,
+) -> Result<CompiledShaders> {
+    //The default limits are left to shaders/meshlet_limits.glsl, only those variants are embedded
+    let max_vertices = max_meshlet_vertices.to_string();
+    let max_triangles = max_meshlet_triangles.to_string();
+    let limit_defines: Vec<_> = [
+        (max_meshlet_vertices != MAX_VERTICES)
+            .then_some(("MESHLET_MAX_VERTICES", Some(max_vertices.as_str()))),
+        (max_meshlet_triangles != MAX_TRIANGLES)
+            .then_some(("MESHLET_MAX_TRIANGLES", Some(max_triangles.as_str()))),
+        //Without task shaders the mesh shaders read the meshlet index from the workgroup instead of the payload
+        (!task_shader_supported).then_some(("NO_TASK_SHADER", None)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    //The shading rate output needs the device feature even if it's never written, so it's compiled out without it
+    let mesh_defines: Vec<_> = primitive_shading_rate_supported
+        .then_some(("PRIMITIVE_SHADING_RATE", None))
+        .into_iter()
+        .chain(limit_defines.iter().copied())
+        .collect();
+    let alpha_test_defines = [("ALPHA_TEST", None)];
+
+    //The alpha tested variants only differ in the fragment shader
+    let variants = GeometryShaders::ALL
+        .into_iter()
+        .map(|shaders| (shaders, false))
+        .chain([
+            (GeometryShaders::Meshlets, true),
+            (GeometryShaders::DepthOnly, true),
+        ]);
+
+    shader_workers
+        .map(variants, |(shaders, alpha_test)| {
+            let fragment_defines = if alpha_test {
+                &alpha_test_defines[..]
             } else {
-                self.pipeline
-            },
-        );
+                &[][..]
+            };
+            //The triangle view has a mesh shader of its own, which always shades at full rate
+            let mesh_defines = match shaders {
+                GeometryShaders::Triangles => &limit_defines[..],
+                _ => &mesh_defines[..],
+            };
 
-        let viewport = vk::Viewport::default()
-            .width(window.inner_size().width as _)
-            .height(window.inner_size().height as _)
-            .max_depth(1.0);
-        let scissor = vk::Rect2D::default().extent(vk::Extent2D {
-            width: window.inner_size().width as _,
-            height: window.inner_size().height as _,
-        });
+            let stages = utils::pipelines::compile_mesh_stages(
+                task_shader_supported.then_some(("shaders/geometry.task.glsl", "main", &[][..])),
+                shaders.mesh_path(),
+                "main",
+                mesh_defines,
+                shaders.fragment_path(),
+                "main",
+                fragment_defines,
+            )?;
+            Ok(((shaders, alpha_test), stages))
+        })
+        .into_iter()
+        .collect()
+}
 
-        ctx.device_loader
-            .cmd_set_viewport(command_buffer, 0, slice::from_ref(&viewport));
+//Links the permutation from its libraries if there are any, which are created first if no other permutation shares them
+unsafe fn create_pipeline(
+    device: &Device,
+    pipeline_cache: vk::PipelineCache,
+    pipeline_layout: vk::PipelineLayout,
+    shaders: &CompiledShaders,
+    libraries: Option<&GeometryLibraries>,
+    permutation: GeometryPermutation,
+    local_size_x: u32,
+    multisample_state: &MultisampleState,
+    view_mask: u32,
+) -> Result<vk::Pipeline> {
+    hitch_detector::record_event(format!("Created the geometry pipeline {permutation:?}"));
 
-        ctx.device_loader
-            .cmd_set_scissor(command_buffer, 0, slice::from_ref(&scissor));
+    let stages = &shaders[&(permutation.shaders, permutation.alpha_test)];
+    let specialization = permutation.specialization(local_size_x);
+    let raster_state = RasterState {
+        view_mask,
+        ..permutation.raster_state()
+    };
+    let color_formats = permutation.color_formats();
 
-        ctx.device_loader.cmd_bind_descriptor_sets(
-            command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            ctx.geometry_pass.pipeline_layout,
-            0,
-            &[
-                ctx.globals_buffers.descriptor_set,
-                ctx.mesh_collection.descriptor_set,
-            ],
-            &[],
-        );
+    let Some(libraries) = libraries else {
+        return utils::pipelines::create_mesh(
+            device,
+            pipeline_cache,
+            stages,
+            &specialization,
+            color_formats,
+            DEPTH_FORMAT,
+            multisample_state,
+            &raster_state,
+            pipeline_layout,
+        )
+    };
 
-        //Execute draw
-        render_meshes(ctx, command_buffer);
+    let create_library = |library_flags, stages: &[ShaderStage]| {
+        utils::pipelines::create_mesh_library(
+            device,
+            pipeline_cache,
+            library_flags,
+            stages,
+            &specialization,
+            color_formats,
+            DEPTH_FORMAT,
+            multisample_state,
+            &raster_state,
+            pipeline_layout,
+        )
+    };
 
-        //End rendering
-        device_loader.cmd_end_rendering(command_buffer);
+    //compile_mesh_stages puts the fragment shader last
+    let (fragment_stage, pre_rasterization_stages) = stages.split_last().unwrap();
 
-        //Transition image to PRESENT_SRC_KHR
-        let image_memory_barrier = vk::ImageMemoryBarrier2::default()
-            .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
-            .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
-            .dst_stage_mask(vk::PipelineStageFlags2::BOTTOM_OF_PIPE)
-            .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-            .image(image)
-            .subresource_range(
-                vk::ImageSubresourceRange::default()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .level_count(1)
-                    .layer_count(1),
-            );
+    let pre_rasterization_library =
+        libraries
+            .pre_rasterization
+            .get_or_create(permutation.pre_rasterization_part(), |_| {
+                create_library(
+                    vk::GraphicsPipelineLibraryFlagsEXT::PRE_RASTERIZATION_SHADERS,
+                    pre_rasterization_stages,
+                )
+            })?;
+    let fragment_library = libraries
+        .fragment
+        .get_or_create(permutation.fragment_part(), |_| {
+            create_library(
+                vk::GraphicsPipelineLibraryFlagsEXT::FRAGMENT_SHADER
+                    | vk::GraphicsPipelineLibraryFlagsEXT::FRAGMENT_OUTPUT_INTERFACE,
+                slice::from_ref(fragment_stage),
+            )
+        })?;
 
-        device_loader.cmd_pipeline_barrier2(
-            command_buffer,
-            &vk::DependencyInfo::default()
-                .image_memory_barriers(slice::from_ref(&image_memory_barrier)),
-        );
+    utils::pipelines::link_mesh_libraries(
+        device,
+        pipeline_cache,
+        &[pre_rasterization_library, fragment_library],
+        pipeline_layout,
+    )
+}
+
+//Culls the instances against the frustum and picks the level of the others on the render thread, only recording the
+//draws is spread across threads. The depth pre-pass and the geometry pass share the draws, the alpha tested ones come
+//last and both are sorted front to back
+pub unsafe fn mesh_draws(
+    ctx: &RenderCtx,
+    frame: &Frame,
+    frustum_planes: &[Vec4; 6],
+) -> Vec<MeshDraw> {
+    let camera_position = ctx.camera().position;
+    let lod_position = ctx
+        .render_settings
+        .lod_freeze_position
+        .unwrap_or(camera_position);
+    let time = ctx.time();
+
+    frame.draw_constants_buffer.write(&DrawConstants {
+        culling_stats_address: frame.culling_stats_buffer.device_address,
+        globals_address: ctx.frame_resources.globals_buffers.device_address(),
+        meshes_address: ctx.scene_resources.mesh_collection.meshes_address(),
+        instances_address: ctx
+            .scene_resources
+            .instance_buffers
+            .instance_buffer
+            .device_address,
+        instance_levels_address: frame.instance_levels_buffer.device_address,
+    });
+
+    let mut visible = vec![!ctx.render_settings.culling; ctx.scene_resources.num_instances()];
+    if ctx.render_settings.culling {
+        ctx.scene_resources
+            .instance_bvh
+            .for_each_visible(frustum_planes, |instance_idx| {
+                visible[instance_idx as usize] = true
+            });
     }
+
+    let mut draws: Vec<_> = ctx
+        .scene_resources
+        .instance_buffers
+        .instance_animations
+        .iter()
+        .enumerate()
+        .filter_map(|(instance_idx, instance_animation)| {
+            let mesh_idx = instance_animation.mesh_idx;
+            let mesh_buffers = ctx
+                .scene_resources
+                .mesh_collection
+                .mesh_buffers_at(mesh_idx as _);
+
+            //Like the instance cull pass, the bounds of skinned meshes only cover the rest pose
+            if !visible[instance_idx] && mesh_buffers.skin.is_none() {
+                return None
+            }
+
+            let position = instance_animation.position_at(time);
+            let level_idx =
+                ((lod_position.distance(position) * 0.08 * ctx.render_settings.lod_bias) as u32)
+                    .min(mesh_buffers.levels.len() as _);
+
+            let draw = ctx.scene_resources.mesh_collection.mesh_draw(
+                instance_idx as _,
+                mesh_idx,
+                level_idx,
+                frame,
+            )?;
+            Some((camera_position.distance_squared(position), draw))
+        })
+        .collect();
+
+    //Closer instances are drawn first, so they occlude the ones behind them before those are shaded
+    draws.sort_by(|(distance, draw), (other_distance, other_draw)| {
+        draw.alpha_test
+            .cmp(&other_draw.alpha_test)
+            .then(distance.total_cmp(other_distance))
+    });
+    draws.into_iter().map(|(_, draw)| draw).collect()
+}
+
+//Every eye covers half of the swapchain image with stereo rendering
+#[inline]
+pub fn render_extent(ctx: &RenderCtx) -> vk::Extent2D {
+    ctx.stereo_pass
+        .as_ref()
+        .map_or(ctx.swapchain.extent, |stereo_pass| stereo_pass.eye_extent)
 }
 
-unsafe fn render_meshes(ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
-    ctx.mesh_collection.draw_mesh(
-        ctx,
+unsafe fn record_draws(
+    device: &Device,
+    mesh_shader_loader: &MeshShader,
+    command_buffer: vk::CommandBuffer,
+    draw_state: &DrawState,
+    draws: &[MeshDraw],
+) {
+    //Bind pipeline, set viewport and bind descriptor set, secondary command buffers don't inherit any of them
+    device.cmd_bind_pipeline(
         command_buffer,
-        &Vec3::new(-120.43, -2.325, -160.1),
-        280.20,
-        &Quat::IDENTITY,
-        0,
-        0,
+        vk::PipelineBindPoint::GRAPHICS,
+        draw_state.pipeline,
     );
 
-    for i in 0..25 {
-        for j in 0..25 {
-            let angle = {
-                let mut hasher = DefaultHasher::new();
-                (i * 1128889).hash(&mut hasher);
-                (j * 1254739).hash(&mut hasher);
-                (i + j).hash(&mut hasher);
+    let viewport = vk::Viewport::default()
+        .width(draw_state.extent.width as _)
+        .height(draw_state.extent.height as _)
+        .max_depth(1.0);
+    let scissor = vk::Rect2D::default().extent(draw_state.extent);
 
-                let hash_code = hasher.finish();
-                (hash_code & 255) as f32 / 255.0 * std::f32::consts::PI
-            };
+    device.cmd_set_viewport(command_buffer, 0, slice::from_ref(&viewport));
+    device.cmd_set_scissor(command_buffer, 0, slice::from_ref(&scissor));
 
-            let mesh_idx = ((i + j) % 3) + 1;
-            let (scale, y_offset) = if mesh_idx == 1 {
-                (1.0, -2.6)
-            } else if mesh_idx == 2 {
-                (0.1, 2.8)
-            } else {
-                (22.0, -3.25)
-            };
+    if let Some(descriptor_set) = draw_state.descriptor_set {
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            draw_state.pipeline_layout,
+            0,
+            slice::from_ref(&descriptor_set),
+            &[],
+        );
+    }
+    if let Some(mask_descriptor_set) = draw_state.mask_descriptor_set {
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            draw_state.pipeline_layout,
+            1,
+            slice::from_ref(&mask_descriptor_set),
+            &[],
+        );
+    }
 
-            let translation = Vec3::new(i as f32 * 7.0, y_offset, j as f32 * 5.0);
-            let rotation = Quat::from_rotation_y(angle);
+    //Execute draws, one task shader workgroup per meshlet group or one mesh shader workgroup per meshlet. The alpha tested
+    //draws are sorted last, so the pipeline is only switched once
+    let mut alpha_test_bound = false;
+    for draw in draws {
+        if draw.alpha_test
+            && !alpha_test_bound
+            && draw_state.alpha_test_pipeline != draw_state.pipeline
+        {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                draw_state.alpha_test_pipeline,
+            );
+            alpha_test_bound = true;
+        }
 
-            let max_level_idx = ctx.mesh_collection.mesh_buffers_at(mesh_idx).levels.len();
+        let conditional_rendering = draw_state
+            .conditional_rendering
+            .as_ref()
+            .filter(|_| draw.conditional);
+        if let Some((conditional_rendering_loader, visibility_buffer)) = conditional_rendering {
+            (conditional_rendering_loader.cmd_begin_conditional_rendering_ext)(
+                command_buffer,
+                &vk::ConditionalRenderingBeginInfoEXT::default()
+                    .buffer(*visibility_buffer)
+                    .offset(
+                        (draw.push_constants.instance_idx as usize * mem::size_of::<u32>()) as _,
+                    ),
+            );
+        }
 
-            let final_transform = &ctx.camera_rig.final_transform;
+        device.cmd_push_constants(
+            command_buffer,
+            draw_state.pipeline_layout,
+            draw_state.push_constant_stages,
+            0,
+            bytemuck::bytes_of(&draw.push_constants),
+        );
+        let group_count_x = if draw_state.task_shader {
+            draw.num_meshlet_groups
+        } else {
+            draw.num_meshlets
+        };
+        mesh_shader_loader.cmd_draw_mesh_tasks(command_buffer, group_count_x, 1, 1);
 
-            let level_idx = (((final_transform.position.distance(rotation * translation)) * 0.08)
-                as u32)
-                .min(max_level_idx as _);
-            unsafe {
-                ctx.mesh_collection.draw_mesh(
-                    ctx,
-                    command_buffer,
-                    &translation,
-                    scale as _,
-                    &rotation,
-                    mesh_idx as _,
-                    level_idx,
-                )
-            };
+        if let Some((conditional_rendering_loader, _)) = conditional_rendering {
+            (conditional_rendering_loader.cmd_end_conditional_rendering_ext)(command_buffer);
         }
     }
 }
+
+//Runs on one of the recording workers
+unsafe fn record_secondary_draws(
+    device: &Device,
+    mesh_shader_loader: &MeshShader,
+    command_buffer: vk::CommandBuffer,
+    draw_state: &DrawState,
+    draws: &[MeshDraw],
+) -> VkResult<()> {
+    let mut inheritance_rendering_info = vk::CommandBufferInheritanceRenderingInfo::default()
+        .color_attachment_formats(draw_state.color_formats)
+        .depth_attachment_format(DEPTH_FORMAT)
+        .rasterization_samples(draw_state.rasterization_samples)
+        .view_mask(draw_state.view_mask);
+    //The pass is measured with a pipeline statistics query, which has to be inherited
+    let inheritance_info = vk::CommandBufferInheritanceInfo::default()
+        .pipeline_statistics(draw_state.pipeline_statistics)
+        .push_next(&mut inheritance_rendering_info);
+
+    device.begin_command_buffer(
+        command_buffer,
+        &vk::CommandBufferBeginInfo::default()
+            .flags(
+                vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                    | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+            )
+            .inheritance_info(&inheritance_info),
+    )?;
+    record_draws(
+        device,
+        mesh_shader_loader,
+        command_buffer,
+        draw_state,
+        draws,
+    );
+    device.end_command_buffer(command_buffer)
+}
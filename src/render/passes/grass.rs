@@ -0,0 +1,253 @@
+use std::{slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use bytemuck::{Pod, Zeroable};
+use glam::Vec2;
+use vk_mem_alloc::Allocator;
+
+use crate::render::{
+    buffer::Buffer,
+    passes::{particles::ParticlePass, taa::VELOCITY_FORMAT},
+    render_ctx::{RenderCtx, DEPTH_FORMAT, SWAPCHAIN_FORMAT},
+    staging_belt::TransferQueue,
+    terrain, utils,
+    utils::{
+        globals::GlobalsBuffers,
+        pipelines::{MultisampleState, RasterState},
+        reflection::ShaderInterface,
+    },
+};
+
+//Has to match PATCHES_PER_TASK in shaders/grass.glsl
+const PATCHES_PER_TASK: u32 = 32;
+//The heightmap has one texel per meter, the field is one patch smaller than it in every direction
+const HEIGHTMAP_RESOLUTION: u32 = 256;
+const HEIGHT_SCALE: f32 = 1.5;
+//Covers the instance grid of the demo scene, just above its ground plane
+const FIELD_ORIGIN: Vec2 = Vec2::new(-44.0, -68.0);
+const GROUND_HEIGHT: f32 = -2.3;
+//No blades are generated for patches which are further away from the camera, only their ground
+const MAX_GRASS_DISTANCE: f32 = 60.0;
+
+//Has to match the push constants of shaders/grass.glsl
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+struct GrassConstants {
+    heightmap: vk::DeviceAddress,
+    field_origin: Vec2,
+    ground_height: f32,
+    height_scale: f32,
+    resolution: u32,
+    max_distance: f32,
+}
+
+//Generates the grass without any vertex buffers. Every task shader invocation culls a patch of the field and decides
+//how many blades grow on it from the heightmap and the distance to the camera, the mesh shader then builds the ground
+//of the patch and its blades. Drawn into the images of the geometry pass after it, so it doesn't support MSAA or
+//stereo rendering
+pub struct GrassPass {
+    pub heightmap_buffer: Buffer,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    //Also writes the velocity next to the TAA color image
+    pub taa_pipeline: vk::Pipeline,
+    push_constant_stages: vk::ShaderStageFlags,
+    device: Arc<Device>,
+}
+
+impl Drop for GrassPass {
+    fn drop(&mut self) {
+        unsafe {
+            utils::pipelines::destroy(&self.device, self.pipeline);
+            utils::pipelines::destroy(&self.device, self.taa_pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+impl GrassPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: vk::PipelineCache,
+        queue: TransferQueue,
+        allocator: Allocator,
+        globals_buffers: &GlobalsBuffers,
+    ) -> Result<Self> {
+        //Compile shaders
+        let stages = utils::pipelines::compile_mesh_stages(
+            Some(("shaders/grass.task.glsl", "main", &[])),
+            "shaders/grass.mesh.glsl",
+            "main",
+            &[],
+            "shaders/grass.frag.glsl",
+            "main",
+            &[],
+        )?;
+
+        //Create pipeline layout
+        let shader_interface = ShaderInterface::reflect(&stages)?;
+        let pipeline_layout = unsafe {
+            shader_interface.create_pipeline_layout(
+                device,
+                slice::from_ref(&globals_buffers.descriptor_set_layout),
+            )
+        }?;
+
+        //Create pipelines, the blades are seen from both sides
+        let create_pipeline = |color_formats: &[vk::Format]| unsafe {
+            utils::pipelines::create_mesh(
+                device,
+                pipeline_cache,
+                &stages,
+                &[],
+                color_formats,
+                DEPTH_FORMAT,
+                &MultisampleState::default(),
+                &RasterState::default(),
+                pipeline_layout,
+            )
+        };
+        let pipeline = create_pipeline(&[SWAPCHAIN_FORMAT])?;
+        let taa_pipeline = create_pipeline(&[SWAPCHAIN_FORMAT, VELOCITY_FORMAT])?;
+
+        let heightmap_buffer = unsafe {
+            Buffer::new_device_local(
+                device.clone(),
+                queue,
+                allocator,
+                &(0..HEIGHTMAP_RESOLUTION * HEIGHTMAP_RESOLUTION)
+                    .map(|i| {
+                        terrain::height_at(
+                            (i % HEIGHTMAP_RESOLUTION) as f32,
+                            (i / HEIGHTMAP_RESOLUTION) as f32,
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+                &[],
+            )
+        }?;
+
+        Ok(Self {
+            heightmap_buffer,
+            pipeline_layout,
+            pipeline,
+            taa_pipeline,
+            push_constant_stages: shader_interface.push_constant_stages(),
+            device: device.clone(),
+        })
+    }
+
+    //The images of the geometry pass have a single sample and layer then. The overdraw heatmap only shows the meshes
+    #[inline]
+    pub fn enabled(ctx: &RenderCtx) -> bool {
+        ctx.render_settings.grass
+            && ctx.grass_pass.is_some()
+            && ctx.frame_resources.msaa_color_image.is_none()
+            && ctx.stereo_pass.is_none()
+            && !ctx.overdraw_enabled()
+    }
+
+    //Continues on the color, velocity and depth the geometry pass left in COLOR_ATTACHMENT_OPTIMAL and
+    //DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+    pub unsafe fn execute(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        taa: bool,
+    ) {
+        let device_loader = &ctx.device.device_loader;
+
+        //Begin rendering
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(if taa {
+                ctx.taa_pass.color.image_view
+            } else {
+                ctx.swapchain.image_views[image_index]
+            })
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE);
+        let velocity_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(ctx.taa_pass.velocity.image_view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE);
+        let color_attachments = [color_attachment, velocity_attachment];
+
+        let depth_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(ctx.frame_resources.depth_image_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(if ParticlePass::enabled(ctx) {
+                vk::AttachmentStoreOp::STORE
+            } else {
+                vk::AttachmentStoreOp::DONT_CARE
+            });
+
+        let extent = ctx.swapchain.extent;
+
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D::default().extent(extent))
+            .layer_count(1)
+            .color_attachments(&color_attachments[..if taa { 2 } else { 1 }])
+            .depth_attachment(&depth_attachment);
+
+        device_loader.cmd_begin_rendering(command_buffer, &rendering_info);
+
+        //Draw the field
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            if taa {
+                self.taa_pipeline
+            } else {
+                self.pipeline
+            },
+        );
+
+        let viewport = vk::Viewport::default()
+            .width(extent.width as _)
+            .height(extent.height as _)
+            .max_depth(1.0);
+        let scissor = vk::Rect2D::default().extent(extent);
+
+        device_loader.cmd_set_viewport(command_buffer, 0, slice::from_ref(&viewport));
+        device_loader.cmd_set_scissor(command_buffer, 0, slice::from_ref(&scissor));
+
+        ctx.frame_resources.globals_buffers.push_descriptor_set(
+            &ctx.device.push_descriptor_loader,
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+        );
+
+        let constants = GrassConstants {
+            heightmap: self.heightmap_buffer.device_address,
+            field_origin: FIELD_ORIGIN,
+            ground_height: GROUND_HEIGHT,
+            height_scale: HEIGHT_SCALE,
+            resolution: HEIGHTMAP_RESOLUTION,
+            max_distance: MAX_GRASS_DISTANCE,
+        };
+        device_loader.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            self.push_constant_stages,
+            0,
+            bytemuck::bytes_of(&constants),
+        );
+
+        let num_patches = (HEIGHTMAP_RESOLUTION - 1) * (HEIGHTMAP_RESOLUTION - 1);
+        ctx.device.mesh_shader_loader.cmd_draw_mesh_tasks(
+            command_buffer,
+            num_patches.div_ceil(PATCHES_PER_TASK),
+            1,
+            1,
+        );
+
+        device_loader.cmd_end_rendering(command_buffer);
+    }
+}
@@ -0,0 +1,125 @@
+use std::{slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+
+use crate::render::{
+    render_ctx::RenderCtx,
+    utils,
+    utils::{globals::GlobalsBuffers, reflection::ShaderInterface},
+};
+
+const LOCAL_SIZE_X: u32 = 64;
+
+pub struct InstanceAnimatePass {
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    //Timeline semaphore which is signaled with num_animated once the instances of a frame are animated, the culling of
+    //the next frame waits for it on the compute queue
+    pub animated_semaphore: vk::Semaphore,
+    pub num_animated: u64,
+    device: Arc<Device>,
+}
+
+impl Drop for InstanceAnimatePass {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_semaphore(self.animated_semaphore, None);
+            utils::pipelines::destroy(&self.device, self.pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+impl InstanceAnimatePass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: vk::PipelineCache,
+        globals_buffers: &GlobalsBuffers,
+    ) -> Result<Self> {
+        //Compile shader
+        let stage = utils::pipelines::compile_shader(
+            vk::ShaderStageFlags::COMPUTE,
+            "shaders/instance_animate.comp.glsl",
+            "main",
+            &[],
+        )?;
+        let shader_interface = ShaderInterface::reflect([&stage])?;
+
+        //Create descriptor set layout
+        let descriptor_set_layout = unsafe {
+            shader_interface.create_descriptor_set_layout(device, 1, vk::ShaderStageFlags::empty())
+        }?;
+
+        //Create pipeline layout
+        let pipeline_layout = unsafe {
+            shader_interface.create_pipeline_layout(
+                device,
+                &[globals_buffers.descriptor_set_layout, descriptor_set_layout],
+            )
+        }?;
+
+        //Create pipeline
+        let pipeline = unsafe {
+            utils::pipelines::create_compute(device, pipeline_cache, &stage, pipeline_layout)
+        }?;
+
+        let animated_semaphore = unsafe { utils::create_timeline_semaphore(device) }?;
+
+        Ok(Self {
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+            animated_semaphore,
+            num_animated: 0,
+            device: device.clone(),
+        })
+    }
+
+    pub unsafe fn execute(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        let device_loader = &ctx.device.device_loader;
+        let instance_buffers = &ctx.scene_resources.instance_buffers;
+
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline,
+        );
+
+        ctx.frame_resources.globals_buffers.push_descriptor_set(
+            &ctx.device.push_descriptor_loader,
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+        );
+        device_loader.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+            1,
+            slice::from_ref(&instance_buffers.descriptor_set),
+            &[],
+        );
+
+        let num_instances = instance_buffers.num_instances() as u32;
+
+        device_loader.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &num_instances.to_ne_bytes(),
+        );
+
+        device_loader.cmd_dispatch(
+            command_buffer,
+            (num_instances + LOCAL_SIZE_X - 1) / LOCAL_SIZE_X,
+            1,
+            1,
+        );
+    }
+}
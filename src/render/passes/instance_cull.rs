@@ -1,26 +1,52 @@
-use std::sync::Arc;
+use std::{slice, sync::Arc};
 
+use anyhow::Result;
 use ash::{vk, Device};
+use bytemuck::{Pod, Zeroable};
 
 use crate::render::{
-    passes::geometry::GeometryPass, render_ctx::RenderCtx, utils, utils::globals::GlobalsBuffers,
+    render_ctx::RenderCtx,
+    utils,
+    utils::{globals::GlobalsBuffers, reflection::ShaderInterface},
 };
 
+//Has to match the local size of shaders/instance_cull.comp.glsl
+const LOCAL_SIZE_X: u32 = 64;
+
+//Has to match the push constants of shaders/instance_cull.comp.glsl
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+struct InstanceCullConstants {
+    instances_address: vk::DeviceAddress,
+    visibility_address: vk::DeviceAddress,
+    num_instances: u32,
+    padding: u32,
+}
+
+//Tests the bounds of every instance against the frustum and writes whether it's visible to the visibility buffer of the
+//frame, which the draws of the instances are predicated on with VK_EXT_conditional_rendering. Lighter than compacting
+//the visible instances into indirect draws, the draws are still recorded but skipped by the device
 pub struct InstanceCullPass {
-    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    //The meshes are only read through descriptors here, the geometry shaders use their address instead
+    pub mesh_descriptor_set_layout: vk::DescriptorSetLayout,
     pub pipeline_layout: vk::PipelineLayout,
     pub pipeline: vk::Pipeline,
+    //Timeline semaphore which is signaled with num_culled once a culling read the instances, the next animation waits
+    //for it so the world matrices aren't overwritten while they are culled
+    pub culled_semaphore: vk::Semaphore,
+    pub num_culled: u64,
     device: Arc<Device>,
 }
 
 impl Drop for InstanceCullPass {
     fn drop(&mut self) {
         unsafe {
-            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_semaphore(self.culled_semaphore, None);
+            utils::pipelines::destroy(&self.device, self.pipeline);
             self.device
                 .destroy_pipeline_layout(self.pipeline_layout, None);
             self.device
-                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+                .destroy_descriptor_set_layout(self.mesh_descriptor_set_layout, None);
         }
     }
 }
@@ -28,62 +54,111 @@ impl Drop for InstanceCullPass {
 impl InstanceCullPass {
     pub fn new(
         device: &Arc<Device>,
+        pipeline_cache: vk::PipelineCache,
         globals_buffers: &GlobalsBuffers,
-        geometry_pass: &GeometryPass,
-    ) -> Self {
+    ) -> Result<Self> {
+        //Compile shader
+        let stage = utils::pipelines::compile_shader(
+            vk::ShaderStageFlags::COMPUTE,
+            "shaders/instance_cull.comp.glsl",
+            "main",
+            &[],
+        )?;
+        let shader_interface = ShaderInterface::reflect([&stage])?;
+
         //Create descriptor set layout
-        let descriptor_set_layout_bindings = (0..4)
-            .map(|i| {
-                vk::DescriptorSetLayoutBinding::default()
-                    .binding(i)
-                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                    .descriptor_count(1)
-                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
-            })
-            .collect::<Vec<_>>();
-
-        let descriptor_set_layout_create_info =
-            vk::DescriptorSetLayoutCreateInfo::default().bindings(&descriptor_set_layout_bindings);
-
-        let descriptor_set_layout = unsafe {
-            device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
-        }
-        .unwrap();
+        let mesh_descriptor_set_layout = unsafe {
+            shader_interface.create_descriptor_set_layout(device, 1, vk::ShaderStageFlags::empty())
+        }?;
 
         //Create pipeline layout
-        let descriptor_set_layouts = [
-            globals_buffers.descriptor_set_layout,
-            geometry_pass.descriptor_set_layout,
-            descriptor_set_layout,
-        ];
-
-        let pipeline_layout_create_info =
-            vk::PipelineLayoutCreateInfo::default().set_layouts(&descriptor_set_layouts);
-
-        let pipeline_layout =
-            unsafe { device.create_pipeline_layout(&pipeline_layout_create_info, None) }.unwrap();
+        let pipeline_layout = unsafe {
+            shader_interface.create_pipeline_layout(
+                device,
+                &[
+                    globals_buffers.descriptor_set_layout,
+                    mesh_descriptor_set_layout,
+                ],
+            )
+        }?;
 
         //Create pipeline
         let pipeline = unsafe {
-            utils::pipelines::create_compute(
-                device,
-                "shaders/instance_cull.comp.glsl",
-                "main",
-                &[],
-                pipeline_layout,
-            )
-        }
-        .unwrap();
+            utils::pipelines::create_compute(device, pipeline_cache, &stage, pipeline_layout)
+        }?;
+
+        let culled_semaphore = unsafe { utils::create_timeline_semaphore(device) }?;
 
-        Self {
-            descriptor_set_layout,
+        Ok(Self {
+            mesh_descriptor_set_layout,
             pipeline_layout,
             pipeline,
+            culled_semaphore,
+            num_culled: 0,
             device: device.clone(),
-        }
+        })
     }
 
-    pub fn execute(&self, _ctx: &RenderCtx, _command_buffer: vk::CommandBuffer) {
-        //TODO:
+    //The draws are only predicated if the device supports it, otherwise the cull pass isn't dispatched either
+    #[inline]
+    pub fn enabled(ctx: &RenderCtx) -> bool {
+        ctx.render_settings.conditional_rendering
+            && ctx.device.conditional_rendering_loader.is_some()
+    }
+
+    //Runs on the compute queue after the animation of the previous frame, so it sees the instances animated by it. The
+    //globals of the frame have to be updated before
+    pub unsafe fn execute(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+    ) {
+        if !Self::enabled(ctx) {
+            return
+        }
+
+        let device_loader = &ctx.device.device_loader;
+        let instance_buffers = &ctx.scene_resources.instance_buffers;
+
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline,
+        );
+
+        ctx.frame_resources.globals_buffers.push_descriptor_set(
+            &ctx.device.push_descriptor_loader,
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+        );
+        device_loader.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+            1,
+            slice::from_ref(&ctx.scene_resources.mesh_collection.descriptor_set),
+            &[],
+        );
+
+        let num_instances = instance_buffers.num_instances() as u32;
+        let constants = InstanceCullConstants {
+            instances_address: instance_buffers.instance_buffer.device_address,
+            visibility_address: ctx.frame_resources.frames[frame_index]
+                .visibility_buffer
+                .device_address,
+            num_instances,
+            padding: 0,
+        };
+        device_loader.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            bytemuck::bytes_of(&constants),
+        );
+
+        device_loader.cmd_dispatch(command_buffer, num_instances.div_ceil(LOCAL_SIZE_X), 1, 1);
     }
 }
@@ -0,0 +1,220 @@
+use std::{f32::consts::TAU, mem, slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+use vk_mem_alloc::Allocator;
+
+use crate::render::{
+    buffer::Buffer,
+    frame::NUM_FRAMES,
+    render_ctx::{RenderCtx, NEAR_PLANE},
+    utils,
+    utils::{globals::GlobalsBuffers, reflection::ShaderInterface},
+};
+
+//Has to match shaders/lights.glsl
+const CLUSTERS_X: u32 = 16;
+const CLUSTERS_Y: u32 = 9;
+const CLUSTERS_Z: u32 = 24;
+const MAX_LIGHTS_PER_CLUSTER: u32 = 64;
+const NUM_CLUSTERS: u32 = CLUSTERS_X * CLUSTERS_Y * CLUSTERS_Z;
+//Has to match the local size of shaders/light_cull.comp.glsl
+const LOCAL_SIZE_X: u32 = 64;
+
+pub const NUM_LIGHTS: usize = 256;
+//The lights orbit the world origin like the instances, a bit above their ground
+const LIGHT_HEIGHT: f32 = 1.0;
+const LIGHT_ORBIT_RADIUS: f32 = 90.0;
+
+//Has to match Light in shaders/lights.glsl. Point lights have a cone which covers every direction
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+struct Light {
+    position: Vec3,
+    range: f32,
+    color: Vec3,
+    spot_cos_outer: f32,
+    direction: Vec3,
+    spot_cos_inner: f32,
+}
+
+//Has to match the push constants of shaders/light_cull.comp.glsl
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+struct LightCullConstants {
+    near_plane: f32,
+}
+
+//Every fourth light is a spot light which points down and sweeps around, the others are point lights
+fn simulate(time: f32) -> Vec<Light> {
+    (0..NUM_LIGHTS)
+        .map(|i| {
+            let mut hash = (i as u32).wrapping_mul(0x9e37_79b9);
+            let radius = LIGHT_ORBIT_RADIUS * utils::random(&mut hash).sqrt();
+            let angle =
+                utils::random(&mut hash) * TAU + time * (0.05 + 0.1 * utils::random(&mut hash));
+            let height = LIGHT_HEIGHT + 4.0 * utils::random(&mut hash);
+            let hue = utils::random(&mut hash) * 6.0;
+            let color = Vec3::new(
+                (hue - 3.0).abs() - 1.0,
+                2.0 - (hue - 2.0).abs(),
+                2.0 - (hue - 4.0).abs(),
+            )
+            .clamp(Vec3::ZERO, Vec3::ONE);
+
+            let (range, direction, spot_cos_outer, spot_cos_inner) = if i % 4 == 0 {
+                let sweep = time + utils::random(&mut hash) * TAU;
+                (
+                    16.0,
+                    Vec3::new(0.4 * sweep.cos(), -1.0, 0.4 * sweep.sin()).normalize(),
+                    0.8,
+                    0.9,
+                )
+            } else {
+                (
+                    6.0 + 6.0 * utils::random(&mut hash),
+                    Vec3::NEG_Y,
+                    -1.0,
+                    -1.0,
+                )
+            };
+
+            Light {
+                position: Vec3::new(radius * angle.cos(), height, radius * angle.sin()),
+                range,
+                color: color * 4.0,
+                spot_cos_outer,
+                direction,
+                spot_cos_inner,
+            }
+        })
+        .collect()
+}
+
+//Bins the lights into clusters, which slice the view frustum into tiles in screen space and exponentially growing slices
+//in depth. The geometry fragment shader then only loops over the lights of the cluster of the fragment. Both read the
+//lights and clusters through the addresses in the globals
+pub struct LightCullPass {
+    //One per frame in flight, written on the host while recording
+    pub light_buffers: Vec<Buffer>,
+    //The number of lights of every cluster, followed by their indices
+    pub cluster_buffer: Buffer,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    device: Arc<Device>,
+}
+
+impl Drop for LightCullPass {
+    fn drop(&mut self) {
+        unsafe {
+            utils::pipelines::destroy(&self.device, self.pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+impl LightCullPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: vk::PipelineCache,
+        allocator: Allocator,
+        globals_buffers: &GlobalsBuffers,
+    ) -> Result<Self> {
+        //Compile shader
+        let stage = utils::pipelines::compile_shader(
+            vk::ShaderStageFlags::COMPUTE,
+            "shaders/light_cull.comp.glsl",
+            "main",
+            &[],
+        )?;
+        let shader_interface = ShaderInterface::reflect([&stage])?;
+
+        //Create pipeline layout
+        let pipeline_layout = unsafe {
+            shader_interface.create_pipeline_layout(
+                device,
+                slice::from_ref(&globals_buffers.descriptor_set_layout),
+            )
+        }?;
+
+        //Create pipeline
+        let pipeline = unsafe {
+            utils::pipelines::create_compute(device, pipeline_cache, &stage, pipeline_layout)
+        }?;
+
+        let light_buffers = (0..NUM_FRAMES)
+            .map(|_| unsafe {
+                Buffer::new_upload(
+                    device.clone(),
+                    allocator,
+                    NUM_LIGHTS * mem::size_of::<Light>(),
+                )
+            })
+            .collect::<Result<_>>()?;
+        let cluster_buffer = unsafe {
+            Buffer::new_device(
+                device.clone(),
+                allocator,
+                (NUM_CLUSTERS * (1 + MAX_LIGHTS_PER_CLUSTER)) as usize * mem::size_of::<u32>(),
+                &[],
+            )
+        }?;
+
+        Ok(Self {
+            light_buffers,
+            cluster_buffer,
+            pipeline_layout,
+            pipeline,
+            device: device.clone(),
+        })
+    }
+
+    #[inline]
+    pub fn lights_address(&self, frame_index: usize) -> vk::DeviceAddress {
+        self.light_buffers[frame_index].device_address
+    }
+
+    //Reads the globals of the frame, so they have to be updated before
+    pub unsafe fn execute(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+    ) {
+        let device_loader = &ctx.device.device_loader;
+
+        let light_buffer = &self.light_buffers[frame_index];
+        for (i, light) in simulate(ctx.time()).iter().enumerate() {
+            light_buffer.write_at(i * mem::size_of::<Light>(), light);
+        }
+
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline,
+        );
+
+        ctx.frame_resources.globals_buffers.push_descriptor_set(
+            &ctx.device.push_descriptor_loader,
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+        );
+
+        let constants = LightCullConstants {
+            near_plane: NEAR_PLANE,
+        };
+        device_loader.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            bytemuck::bytes_of(&constants),
+        );
+
+        device_loader.cmd_dispatch(command_buffer, NUM_CLUSTERS.div_ceil(LOCAL_SIZE_X), 1, 1);
+    }
+}
@@ -1,2 +1,13 @@
+pub mod frustum_debug;
 pub mod geometry;
+pub mod grass;
+pub mod instance_animate;
 pub mod instance_cull;
+pub mod light_cull;
+pub mod outline;
+pub mod overdraw;
+pub mod particles;
+pub mod picking;
+pub mod skinning;
+pub mod stereo;
+pub mod taa;
@@ -0,0 +1,187 @@
+use std::{slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+
+use crate::render::{
+    render_ctx::{RenderCtx, SWAPCHAIN_FORMAT},
+    utils,
+    utils::{
+        pipelines::{MultisampleState, RasterState},
+        reflection::ShaderInterface,
+    },
+};
+
+//Outlines the selected instance where its IDs border the ones of other instances or the background. The outline is
+//drawn outside of the instance on top of the final image, so it's neither blended with the history nor occluded
+pub struct OutlinePass {
+    pub sampler: vk::Sampler,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    device: Arc<Device>,
+}
+
+impl Drop for OutlinePass {
+    fn drop(&mut self) {
+        unsafe {
+            utils::pipelines::destroy(&self.device, self.pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+impl OutlinePass {
+    pub fn new(device: &Arc<Device>, pipeline_cache: vk::PipelineCache) -> Result<Self> {
+        //Compile shaders
+        let stages = utils::pipelines::compile_mesh_stages(
+            None,
+            "shaders/fullscreen.mesh.glsl",
+            "main",
+            &[],
+            "shaders/outline.frag.glsl",
+            "main",
+            &[],
+        )?;
+
+        //The IDs are only fetched, integer images can't be filtered anyway
+        let sampler = unsafe {
+            device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::NEAREST)
+                    .min_filter(vk::Filter::NEAREST)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                None,
+            )
+        }?;
+
+        //Create descriptor set layout
+        let descriptor_set_layout_binding = vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+        let descriptor_set_layout = unsafe {
+            device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default()
+                    .flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR)
+                    .bindings(slice::from_ref(&descriptor_set_layout_binding)),
+                None,
+            )
+        }?;
+
+        //Create pipeline layout
+        let pipeline_layout = unsafe {
+            ShaderInterface::reflect(&stages)?
+                .create_pipeline_layout(device, slice::from_ref(&descriptor_set_layout))
+        }?;
+
+        //Create pipeline, pixels which aren't part of the outline are discarded
+        let pipeline = unsafe {
+            utils::pipelines::create_mesh(
+                device,
+                pipeline_cache,
+                &stages,
+                &[],
+                &[SWAPCHAIN_FORMAT],
+                vk::Format::UNDEFINED,
+                &MultisampleState::default(),
+                &RasterState {
+                    depth_test: false,
+                    depth_write: false,
+                    ..Default::default()
+                },
+                pipeline_layout,
+            )
+        }?;
+
+        Ok(Self {
+            sampler,
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+            device: device.clone(),
+        })
+    }
+
+    //The ID image has to be in SHADER_READ_ONLY_OPTIMAL and the swapchain image in COLOR_ATTACHMENT_OPTIMAL
+    pub unsafe fn execute(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        selected_instance: u32,
+    ) {
+        let device_loader = &ctx.device.device_loader;
+
+        //Begin rendering
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(ctx.swapchain.image_views[image_index])
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE);
+
+        let extent = ctx.swapchain.extent;
+
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D::default().extent(extent))
+            .layer_count(1)
+            .color_attachments(slice::from_ref(&color_attachment));
+
+        device_loader.cmd_begin_rendering(command_buffer, &rendering_info);
+
+        //Draw outline
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline,
+        );
+
+        let viewport = vk::Viewport::default()
+            .width(extent.width as _)
+            .height(extent.height as _)
+            .max_depth(1.0);
+        let scissor = vk::Rect2D::default().extent(extent);
+
+        device_loader.cmd_set_viewport(command_buffer, 0, slice::from_ref(&viewport));
+        device_loader.cmd_set_scissor(command_buffer, 0, slice::from_ref(&scissor));
+
+        let descriptor_image_info = vk::DescriptorImageInfo::default()
+            .sampler(self.sampler)
+            .image_view(ctx.picking_pass.id_image_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        let write_descriptor_set = vk::WriteDescriptorSet::default()
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(slice::from_ref(&descriptor_image_info));
+
+        ctx.device.push_descriptor_loader.cmd_push_descriptor_set(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            slice::from_ref(&write_descriptor_set),
+        );
+
+        device_loader.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            bytemuck::bytes_of(&selected_instance),
+        );
+
+        ctx.device
+            .mesh_shader_loader
+            .cmd_draw_mesh_tasks(command_buffer, 1, 1, 1);
+
+        device_loader.cmd_end_rendering(command_buffer);
+    }
+}
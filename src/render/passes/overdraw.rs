@@ -0,0 +1,310 @@
+use std::{cell::RefCell, slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use vk_mem_alloc::{Allocation, Allocator};
+
+use crate::render::{
+    render_ctx::{RenderCtx, SWAPCHAIN_FORMAT},
+    resource_registry::{self, ResourceKind},
+    resource_state::{ResourceAccess, ResourceStateTracker, TrackedResource},
+    staging_belt::TransferQueue,
+    utils,
+    utils::{
+        pipelines::{MultisampleState, RasterState},
+        reflection::ShaderInterface,
+    },
+};
+
+pub const OVERDRAW_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+//The geometry pass accumulates the overdraw in the fragment shader
+const OVERDRAW_ACCUMULATE: ResourceAccess = ResourceAccess::new(
+    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+    vk::AccessFlags2::from_raw(
+        vk::AccessFlags2::SHADER_STORAGE_READ.as_raw()
+            | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw(),
+    ),
+)
+.with_layout(vk::ImageLayout::GENERAL);
+
+pub struct OverdrawPass {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    pub image_allocation: Allocation,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_set: vk::DescriptorSet,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    pub enabled: bool,
+    //Carried over between frames, the heatmap of the previous frame might still read the image when it's cleared
+    image_state: RefCell<ResourceStateTracker>,
+    allocator: Allocator,
+    device: Arc<Device>,
+}
+
+impl Drop for OverdrawPass {
+    fn drop(&mut self) {
+        unsafe {
+            utils::pipelines::destroy(&self.device, self.pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            utils::destroy_image(
+                &self.device,
+                self.allocator,
+                self.image,
+                self.image_allocation,
+                self.image_view,
+            );
+        }
+    }
+}
+
+impl OverdrawPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: vk::PipelineCache,
+        queue: TransferQueue,
+        allocator: Allocator,
+        descriptor_pool: vk::DescriptorPool,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        //Create overdraw image
+        let (image, image_allocation, image_view) = unsafe {
+            utils::create_storage_image(device, queue, allocator, width, height, OVERDRAW_FORMAT)
+        }?;
+
+        //Compile shaders
+        let stages = utils::pipelines::compile_mesh_stages(
+            None,
+            "shaders/fullscreen.mesh.glsl",
+            "main",
+            &[],
+            "shaders/overdraw_heatmap.frag.glsl",
+            "main",
+            &[],
+        )?;
+        let shader_interface = ShaderInterface::reflect(&stages)?;
+
+        //Create descriptor set layout, the geometry pass writes the image through the same layout
+        let descriptor_set_layout = unsafe {
+            shader_interface.create_descriptor_set_layout(device, 0, vk::ShaderStageFlags::empty())
+        }?;
+
+        //Create descriptor set
+        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(slice::from_ref(&descriptor_set_layout));
+
+        let descriptor_set =
+            unsafe { device.allocate_descriptor_sets(&descriptor_set_allocate_info) }?[0];
+        resource_registry::track_created(ResourceKind::DescriptorSet);
+
+        //Write overdraw image to descriptor set
+        unsafe { Self::write_descriptor_set(device, descriptor_set, image_view) };
+
+        //Create pipeline layout
+        let pipeline_layout = unsafe {
+            shader_interface.create_pipeline_layout(device, slice::from_ref(&descriptor_set_layout))
+        }?;
+
+        //Create heatmap pipeline
+        let pipeline = unsafe {
+            utils::pipelines::create_mesh(
+                device,
+                pipeline_cache,
+                &stages,
+                &[],
+                &[SWAPCHAIN_FORMAT],
+                //The heatmap covers the resolved swapchain image, the depth image might be multisampled
+                vk::Format::UNDEFINED,
+                &MultisampleState::default(),
+                &RasterState {
+                    depth_test: false,
+                    depth_write: false,
+                    color_write: true,
+                    ..Default::default()
+                },
+                pipeline_layout,
+            )
+        }?;
+
+        Ok(Self {
+            image,
+            image_view,
+            image_allocation,
+            descriptor_set_layout,
+            descriptor_set,
+            pipeline_layout,
+            pipeline,
+            enabled: false,
+            image_state: RefCell::new(Self::image_state(image)),
+            allocator,
+            device: device.clone(),
+        })
+    }
+
+    //None of the frames may be in flight
+    pub unsafe fn resize(&mut self, queue: TransferQueue, width: u32, height: u32) -> Result<()> {
+        let (image, image_allocation, image_view) = utils::create_storage_image(
+            &self.device,
+            queue,
+            self.allocator,
+            width,
+            height,
+            OVERDRAW_FORMAT,
+        )?;
+        Self::write_descriptor_set(&self.device, self.descriptor_set, image_view);
+
+        utils::destroy_image(
+            &self.device,
+            self.allocator,
+            self.image,
+            self.image_allocation,
+            self.image_view,
+        );
+        self.image = image;
+        self.image_view = image_view;
+        self.image_allocation = image_allocation;
+        self.image_state = RefCell::new(Self::image_state(image));
+        Ok(())
+    }
+
+    unsafe fn write_descriptor_set(
+        device: &Device,
+        descriptor_set: vk::DescriptorSet,
+        image_view: vk::ImageView,
+    ) {
+        let descriptor_image_info = vk::DescriptorImageInfo::default()
+            .image_view(image_view)
+            .image_layout(vk::ImageLayout::GENERAL);
+
+        let write_descriptor_set = vk::WriteDescriptorSet::default()
+            .dst_set(descriptor_set)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .image_info(slice::from_ref(&descriptor_image_info));
+
+        device.update_descriptor_sets(slice::from_ref(&write_descriptor_set), &[]);
+    }
+
+    //The image was transitioned to GENERAL and waited for when it was created
+    fn image_state(image: vk::Image) -> ResourceStateTracker {
+        let mut image_state = ResourceStateTracker::new();
+        image_state.import(
+            Self::tracked_image(image),
+            ResourceAccess::new(vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE)
+                .with_layout(vk::ImageLayout::GENERAL),
+        );
+        image_state
+    }
+
+    #[inline]
+    fn tracked_image(image: vk::Image) -> TrackedResource {
+        TrackedResource::Image {
+            image,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+        }
+    }
+
+    pub unsafe fn clear(&self, command_buffer: vk::CommandBuffer) {
+        let image = Self::tracked_image(self.image);
+        let mut image_state = self.image_state.borrow_mut();
+
+        image_state.write(
+            image,
+            ResourceAccess::new(
+                vk::PipelineStageFlags2::CLEAR,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            )
+            .with_layout(vk::ImageLayout::GENERAL),
+        );
+        image_state.flush(&self.device, command_buffer);
+
+        self.device.cmd_clear_color_image(
+            command_buffer,
+            self.image,
+            vk::ImageLayout::GENERAL,
+            &vk::ClearColorValue { uint32: [0; 4] },
+            slice::from_ref(
+                &vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(1)
+                    .layer_count(1),
+            ),
+        );
+
+        image_state.write(image, OVERDRAW_ACCUMULATE);
+        image_state.flush(&self.device, command_buffer);
+    }
+
+    pub unsafe fn draw_heatmap(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let device_loader = &ctx.device.device_loader;
+
+        //Make the accumulated overdraw visible to the heatmap
+        let mut image_state = self.image_state.borrow_mut();
+        image_state.read(
+            Self::tracked_image(self.image),
+            ResourceAccess::new(
+                vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_READ,
+            )
+            .with_layout(vk::ImageLayout::GENERAL),
+        );
+        image_state.flush(device_loader, command_buffer);
+
+        //Begin rendering
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(ctx.swapchain.image_views[image_index])
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE);
+
+        let extent = ctx.swapchain.extent;
+
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D::default().extent(extent))
+            .layer_count(1)
+            .color_attachments(slice::from_ref(&color_attachment));
+
+        device_loader.cmd_begin_rendering(command_buffer, &rendering_info);
+
+        //Draw fullscreen triangle
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline,
+        );
+
+        let viewport = vk::Viewport::default()
+            .width(extent.width as _)
+            .height(extent.height as _)
+            .max_depth(1.0);
+        let scissor = vk::Rect2D::default().extent(extent);
+
+        device_loader.cmd_set_viewport(command_buffer, 0, slice::from_ref(&viewport));
+        device_loader.cmd_set_scissor(command_buffer, 0, slice::from_ref(&scissor));
+
+        device_loader.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            slice::from_ref(&self.descriptor_set),
+            &[],
+        );
+
+        ctx.device
+            .mesh_shader_loader
+            .cmd_draw_mesh_tasks(command_buffer, 1, 1, 1);
+
+        device_loader.cmd_end_rendering(command_buffer);
+    }
+}
@@ -0,0 +1,265 @@
+use std::{f32::consts::TAU, mem, slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec3, Vec4};
+use vk_mem_alloc::Allocator;
+
+use crate::render::{
+    buffer::Buffer,
+    frame::NUM_FRAMES,
+    render_ctx::{RenderCtx, DEPTH_FORMAT, SWAPCHAIN_FORMAT},
+    utils,
+    utils::{
+        globals::GlobalsBuffers,
+        pipelines::{MultisampleState, RasterState},
+        reflection::ShaderInterface,
+    },
+};
+
+//Has to match PARTICLES_PER_MESH in shaders/particles.mesh.glsl
+const PARTICLES_PER_MESH: u32 = 32;
+const NUM_PARTICLES: usize = 4096;
+//A fountain in front of the initial camera, every particle falls back to the ground before it is emitted again
+const EMITTER_POSITION: Vec3 = Vec3::new(0.0, -2.3, -12.0);
+const LIFETIME: f32 = 2.4;
+const GRAVITY: f32 = 5.0;
+
+//Has to match Particle in shaders/particles.mesh.glsl
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+struct Particle {
+    position: Vec3,
+    size: f32,
+    color: Vec4,
+}
+
+//Has to match the push constants of shaders/particles.mesh.glsl
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+struct ParticleConstants {
+    particles: vk::DeviceAddress,
+    num_particles: u32,
+    padding: u32,
+}
+
+//Every particle follows its own parabola, which is picked again whenever it is emitted
+fn simulate(time: f32) -> Vec<Particle> {
+    (0..NUM_PARTICLES)
+        .map(|i| {
+            let emission_time = time + i as f32 / NUM_PARTICLES as f32 * LIFETIME;
+            let emission_idx = (emission_time / LIFETIME).floor();
+            let age = emission_time - emission_idx * LIFETIME;
+
+            let mut hash = (i as u32).wrapping_mul(0x9e37_79b9) ^ emission_idx as u32;
+            let angle = utils::random(&mut hash) * TAU;
+            let spread = 0.5 + utils::random(&mut hash);
+            let velocity = Vec3::new(
+                angle.cos() * spread,
+                6.0 + utils::random(&mut hash),
+                angle.sin() * spread,
+            );
+
+            let t = age / LIFETIME;
+            Particle {
+                position: EMITTER_POSITION + velocity * age
+                    - Vec3::new(0.0, 0.5 * GRAVITY * age * age, 0.0),
+                size: 0.1 + 0.3 * t,
+                color: Vec3::new(0.6, 0.8, 1.0)
+                    .lerp(Vec3::ONE, utils::random(&mut hash))
+                    .extend(0.6 * (1.0 - t)),
+            }
+        })
+        .collect()
+}
+
+//Draws particles without any vertex buffers, the mesh shader expands every particle of a buffer into a quad which faces
+//the camera. They are simulated and sorted back to front on the host, so they can be blended in order over the final
+//image. Tested against the depth of the geometry pass, so it doesn't support MSAA or stereo rendering either
+pub struct ParticlePass {
+    //One per frame in flight, written on the host while recording
+    pub particle_buffers: Vec<Buffer>,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    push_constant_stages: vk::ShaderStageFlags,
+    device: Arc<Device>,
+}
+
+impl Drop for ParticlePass {
+    fn drop(&mut self) {
+        unsafe {
+            utils::pipelines::destroy(&self.device, self.pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+impl ParticlePass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: vk::PipelineCache,
+        allocator: Allocator,
+        globals_buffers: &GlobalsBuffers,
+    ) -> Result<Self> {
+        //Compile shaders
+        let stages = utils::pipelines::compile_mesh_stages(
+            None,
+            "shaders/particles.mesh.glsl",
+            "main",
+            &[],
+            "shaders/particles.frag.glsl",
+            "main",
+            &[],
+        )?;
+
+        //Create pipeline layout
+        let shader_interface = ShaderInterface::reflect(&stages)?;
+        let pipeline_layout = unsafe {
+            shader_interface.create_pipeline_layout(
+                device,
+                slice::from_ref(&globals_buffers.descriptor_set_layout),
+            )
+        }?;
+
+        //Create pipeline, the particles are hidden by the scene but don't hide each other
+        let pipeline = unsafe {
+            utils::pipelines::create_mesh(
+                device,
+                pipeline_cache,
+                &stages,
+                &[],
+                &[SWAPCHAIN_FORMAT],
+                DEPTH_FORMAT,
+                &MultisampleState::default(),
+                &RasterState {
+                    depth_write: false,
+                    alpha_blend: true,
+                    ..Default::default()
+                },
+                pipeline_layout,
+            )
+        }?;
+
+        let particle_buffers = (0..NUM_FRAMES)
+            .map(|_| unsafe {
+                Buffer::new_upload(
+                    device.clone(),
+                    allocator,
+                    NUM_PARTICLES * mem::size_of::<Particle>(),
+                )
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            particle_buffers,
+            pipeline_layout,
+            pipeline,
+            push_constant_stages: shader_interface.push_constant_stages(),
+            device: device.clone(),
+        })
+    }
+
+    //The depth image has a single sample and layer then. The overdraw heatmap only shows the meshes
+    #[inline]
+    pub fn enabled(ctx: &RenderCtx) -> bool {
+        ctx.render_settings.particles
+            && ctx.frame_resources.msaa_color_image.is_none()
+            && ctx.stereo_pass.is_none()
+            && !ctx.overdraw_enabled()
+    }
+
+    //Continues on the final color in COLOR_ATTACHMENT_OPTIMAL and the depth the geometry pass left in
+    //DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+    pub unsafe fn execute(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        image_index: usize,
+    ) {
+        let device_loader = &ctx.device.device_loader;
+        let particle_buffer = &self.particle_buffers[frame_index];
+
+        //Sort the particles back to front
+        let camera_position = ctx.camera().position;
+        let mut particles = simulate(ctx.time());
+        particles.sort_by(|a, b| {
+            b.position
+                .distance_squared(camera_position)
+                .total_cmp(&a.position.distance_squared(camera_position))
+        });
+        for (i, particle) in particles.iter().enumerate() {
+            particle_buffer.write_at(i * mem::size_of::<Particle>(), particle);
+        }
+
+        //Begin rendering
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(ctx.swapchain.image_views[image_index])
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE);
+
+        let depth_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(ctx.frame_resources.depth_image_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE);
+
+        let extent = ctx.swapchain.extent;
+
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D::default().extent(extent))
+            .layer_count(1)
+            .color_attachments(slice::from_ref(&color_attachment))
+            .depth_attachment(&depth_attachment);
+
+        device_loader.cmd_begin_rendering(command_buffer, &rendering_info);
+
+        //Draw the particles
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline,
+        );
+
+        let viewport = vk::Viewport::default()
+            .width(extent.width as _)
+            .height(extent.height as _)
+            .max_depth(1.0);
+        let scissor = vk::Rect2D::default().extent(extent);
+
+        device_loader.cmd_set_viewport(command_buffer, 0, slice::from_ref(&viewport));
+        device_loader.cmd_set_scissor(command_buffer, 0, slice::from_ref(&scissor));
+
+        ctx.frame_resources.globals_buffers.push_descriptor_set(
+            &ctx.device.push_descriptor_loader,
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+        );
+
+        let constants = ParticleConstants {
+            particles: particle_buffer.device_address,
+            num_particles: particles.len() as _,
+            padding: 0,
+        };
+        device_loader.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            self.push_constant_stages,
+            0,
+            bytemuck::bytes_of(&constants),
+        );
+
+        ctx.device.mesh_shader_loader.cmd_draw_mesh_tasks(
+            command_buffer,
+            (particles.len() as u32).div_ceil(PARTICLES_PER_MESH),
+            1,
+            1,
+        );
+
+        device_loader.cmd_end_rendering(command_buffer);
+    }
+}
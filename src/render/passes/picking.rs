@@ -0,0 +1,150 @@
+use std::{slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{prelude::VkResult, vk, Device};
+use vk_mem_alloc::{Allocation, Allocator};
+
+use crate::render::{
+    buffer::Buffer, render_ctx::RenderCtx, resource_state::TrackedResource, utils,
+};
+
+pub const ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+const ID_FORMAT_SIZE: usize = 4;
+//Written where no instance covers the pixel
+pub const NO_INSTANCE: u32 = u32::MAX;
+
+//On frames which pick or have a selected instance, the geometry pass writes the index of the instance drawn to every
+//pixel into the ID image. The pixel under the cursor is copied into the readback buffer, the outline is drawn from it
+pub struct PickingPass {
+    pub id_image: vk::Image,
+    pub id_image_view: vk::ImageView,
+    pub id_image_allocation: Allocation,
+    pub readback_buffer: Buffer,
+    extent: vk::Extent2D,
+    allocator: Allocator,
+    device: Arc<Device>,
+}
+
+impl Drop for PickingPass {
+    fn drop(&mut self) {
+        unsafe {
+            utils::destroy_image(
+                &self.device,
+                self.allocator,
+                self.id_image,
+                self.id_image_allocation,
+                self.id_image_view,
+            );
+        }
+    }
+}
+
+unsafe fn create_id_image(
+    device: &Device,
+    allocator: Allocator,
+    extent: vk::Extent2D,
+) -> VkResult<(vk::Image, Allocation, vk::ImageView)> {
+    utils::create_color_image(
+        device,
+        allocator,
+        extent.width,
+        extent.height,
+        ID_FORMAT,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::SampleCountFlags::TYPE_1,
+    )
+}
+
+impl PickingPass {
+    pub fn new(device: &Arc<Device>, allocator: Allocator, extent: vk::Extent2D) -> Result<Self> {
+        let (id_image, id_image_allocation, id_image_view) =
+            unsafe { create_id_image(device, allocator, extent) }?;
+
+        let readback_buffer =
+            unsafe { Buffer::new_readback(device.clone(), allocator, ID_FORMAT_SIZE) }?;
+
+        Ok(Self {
+            id_image,
+            id_image_view,
+            id_image_allocation,
+            readback_buffer,
+            extent,
+            allocator,
+            device: device.clone(),
+        })
+    }
+
+    //None of the frames may be in flight
+    pub unsafe fn resize(&mut self, extent: vk::Extent2D) -> Result<()> {
+        let (id_image, id_image_allocation, id_image_view) =
+            create_id_image(&self.device, self.allocator, extent)?;
+
+        utils::destroy_image(
+            &self.device,
+            self.allocator,
+            self.id_image,
+            self.id_image_allocation,
+            self.id_image_view,
+        );
+        self.id_image = id_image;
+        self.id_image_view = id_image_view;
+        self.id_image_allocation = id_image_allocation;
+        self.extent = extent;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn tracked(&self) -> TrackedResource {
+        TrackedResource::Image {
+            image: self.id_image,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+        }
+    }
+
+    //The ID image has to be in TRANSFER_SRC_OPTIMAL, positions outside of it are clamped to its edges
+    pub unsafe fn record_copy(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        position: (u32, u32),
+    ) {
+        let buffer_image_copy = vk::BufferImageCopy::default()
+            .image_subresource(
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1),
+            )
+            .image_offset(vk::Offset3D {
+                x: position.0.min(self.extent.width - 1) as _,
+                y: position.1.min(self.extent.height - 1) as _,
+                z: 0,
+            })
+            .image_extent(vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            });
+
+        ctx.device.device_loader.cmd_copy_image_to_buffer(
+            command_buffer,
+            self.id_image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self.readback_buffer.buffer,
+            slice::from_ref(&buffer_image_copy),
+        );
+    }
+
+    //Only call this after the copy finished, None if no instance was under the cursor
+    pub unsafe fn read_picked_instance(&self) -> Option<u32> {
+        Some(
+            self.readback_buffer
+                .allocation_info
+                .mapped_data
+                .cast::<u32>()
+                .read(),
+        )
+        .filter(|instance_idx| *instance_idx != NO_INSTANCE)
+    }
+}
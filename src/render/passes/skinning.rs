@@ -0,0 +1,360 @@
+use std::{mem, slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use bytemuck::{Pod, Zeroable};
+use glam::Mat4;
+use vk_mem_alloc::Allocator;
+
+use crate::render::{
+    buffer::Buffer,
+    frame::NUM_FRAMES,
+    mesh::{MeshBuffers, MeshLevelBuffers},
+    render_ctx::RenderCtx,
+    utils,
+    utils::{pipelines::ShaderStage, reflection::ShaderInterface},
+    vertex_format::VertexQuantization,
+};
+
+const LOCAL_SIZE_X: u32 = 64;
+//Skinned meshes which don't fit anymore stay in the pose of the last frame they fit into
+const MAX_JOINT_MATRICES: usize = 1 << 14;
+const MAX_MORPH_WEIGHTS: usize = 1 << 16;
+
+//Has to match the push constants of shaders/skinning.comp.glsl
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+struct SkinningConstants {
+    rest_vertices: vk::DeviceAddress,
+    vertex_skins: vk::DeviceAddress,
+    skinned_vertices: vk::DeviceAddress,
+    joint_matrices: vk::DeviceAddress,
+    morph_deltas: vk::DeviceAddress,
+    morph_weights: vk::DeviceAddress,
+    quantization: VertexQuantization,
+    num_vertices: u32,
+    num_joints: u32,
+    num_morph_targets: u32,
+    padding: u32,
+}
+
+//Has to match the push constants of shaders/refit_meshlets.comp.glsl and shaders/refit_meshlet_groups.comp.glsl
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+struct RefitConstants {
+    vertices: vk::DeviceAddress,
+    meshlets: vk::DeviceAddress,
+    meshlet_data: vk::DeviceAddress,
+    meshlet_groups: vk::DeviceAddress,
+    quantization: VertexQuantization,
+    count: u32,
+    padding: u32,
+}
+
+impl RefitConstants {
+    #[inline]
+    fn new(
+        level_buffers: &MeshLevelBuffers,
+        quantization: VertexQuantization,
+        count: usize,
+    ) -> Self {
+        Self {
+            vertices: level_buffers.vertex_buffer.device_address,
+            meshlets: level_buffers.meshlet_buffer.device_address,
+            meshlet_data: level_buffers.meshlet_data_buffer.device_address,
+            meshlet_groups: level_buffers.meshlet_group_buffer.device_address,
+            quantization,
+            count: count as _,
+            padding: 0,
+        }
+    }
+}
+
+//Poses the skinned meshes at the current time. The morph targets are applied to the vertices of every level before they
+//are skinned into its vertex buffer, so the geometry pass draws them like any other mesh, then the bounds of the meshlets
+//and their groups are fitted to them for culling. The velocity of skinned vertices only comes from the instances, the
+//previous pose isn't kept
+pub struct SkinningPass {
+    //One per frame in flight, written on the host while recording
+    pub joint_buffers: Vec<Buffer>,
+    pub morph_weight_buffers: Vec<Buffer>,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub skinning_pipeline: vk::Pipeline,
+    pub refit_meshlets_pipeline: vk::Pipeline,
+    pub refit_meshlet_groups_pipeline: vk::Pipeline,
+    device: Arc<Device>,
+}
+
+impl Drop for SkinningPass {
+    fn drop(&mut self) {
+        unsafe {
+            utils::pipelines::destroy(&self.device, self.skinning_pipeline);
+            utils::pipelines::destroy(&self.device, self.refit_meshlets_pipeline);
+            utils::pipelines::destroy(&self.device, self.refit_meshlet_groups_pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+impl SkinningPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: vk::PipelineCache,
+        allocator: Allocator,
+    ) -> Result<Self> {
+        //Compile shaders
+        let compile = |path| {
+            utils::pipelines::compile_shader(vk::ShaderStageFlags::COMPUTE, path, "main", &[])
+        };
+        let skinning_stage = compile("shaders/skinning.comp.glsl")?;
+        let refit_meshlets_stage = compile("shaders/refit_meshlets.comp.glsl")?;
+        let refit_meshlet_groups_stage = compile("shaders/refit_meshlet_groups.comp.glsl")?;
+
+        //Create pipeline layout, every shader only reads its push constants
+        let pipeline_layout = unsafe {
+            ShaderInterface::reflect([
+                &skinning_stage,
+                &refit_meshlets_stage,
+                &refit_meshlet_groups_stage,
+            ])?
+            .create_pipeline_layout(device, &[])
+        }?;
+
+        //Create pipelines
+        let create_pipeline = |stage: &ShaderStage| unsafe {
+            utils::pipelines::create_compute(device, pipeline_cache, stage, pipeline_layout)
+        };
+        let skinning_pipeline = create_pipeline(&skinning_stage)?;
+        let refit_meshlets_pipeline = create_pipeline(&refit_meshlets_stage)?;
+        let refit_meshlet_groups_pipeline = create_pipeline(&refit_meshlet_groups_stage)?;
+
+        let joint_buffers = (0..NUM_FRAMES)
+            .map(|_| unsafe {
+                Buffer::new_upload(
+                    device.clone(),
+                    allocator,
+                    MAX_JOINT_MATRICES * mem::size_of::<Mat4>(),
+                )
+            })
+            .collect::<Result<_>>()?;
+        let morph_weight_buffers = (0..NUM_FRAMES)
+            .map(|_| unsafe {
+                Buffer::new_upload(
+                    device.clone(),
+                    allocator,
+                    MAX_MORPH_WEIGHTS * mem::size_of::<f32>(),
+                )
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            joint_buffers,
+            morph_weight_buffers,
+            pipeline_layout,
+            skinning_pipeline,
+            refit_meshlets_pipeline,
+            refit_meshlet_groups_pipeline,
+            device: device.clone(),
+        })
+    }
+
+    //The arena blocks the skinned vertices and the meshlet bounds are written to, they are also read by the geometry pass
+    pub fn written_buffers(&self, ctx: &RenderCtx) -> Vec<vk::Buffer> {
+        let mut buffers: Vec<_> = ctx
+            .scene_resources
+            .mesh_collection
+            .skinned_meshes()
+            .flat_map(|mesh_buffers| mesh_buffers.levels.iter())
+            .flat_map(|level_buffers| {
+                [
+                    level_buffers.vertex_buffer.buffer.buffer,
+                    level_buffers.meshlet_buffer.buffer.buffer,
+                    level_buffers.meshlet_group_buffer.buffer.buffer,
+                ]
+            })
+            .collect();
+        buffers.sort();
+        buffers.dedup();
+        buffers
+    }
+
+    unsafe fn dispatch<T: Pod>(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        constants: &T,
+        count: u32,
+    ) {
+        let device_loader = &ctx.device.device_loader;
+
+        device_loader.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            bytemuck::bytes_of(constants),
+        );
+        device_loader.cmd_dispatch(
+            command_buffer,
+            (count + LOCAL_SIZE_X - 1) / LOCAL_SIZE_X,
+            1,
+            1,
+        );
+    }
+
+    //Every dispatch of the next step reads what the previous step wrote
+    unsafe fn barrier(ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        let memory_barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .dst_access_mask(vk::AccessFlags2::from_raw(
+                vk::AccessFlags2::SHADER_STORAGE_READ.as_raw()
+                    | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw(),
+            ));
+
+        ctx.device.device_loader.cmd_pipeline_barrier2(
+            command_buffer,
+            &vk::DependencyInfo::default().memory_barriers(slice::from_ref(&memory_barrier)),
+        );
+    }
+
+    pub unsafe fn execute(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+    ) {
+        let device_loader = &ctx.device.device_loader;
+        let joint_buffer = &self.joint_buffers[frame_index];
+        let morph_weight_buffer = &self.morph_weight_buffers[frame_index];
+        let time = ctx.time();
+
+        //Pose the skeletons, every mesh gets its own range of joint matrices and morph weights
+        let mut num_joint_matrices = 0;
+        let mut num_morph_weights = 0;
+        let skinned_meshes: Vec<(&MeshBuffers, SkinningConstants)> = ctx
+            .scene_resources
+            .mesh_collection
+            .skinned_meshes()
+            .filter_map(|mesh_buffers| {
+                let skeleton = &mesh_buffers.skin.as_ref().unwrap().mesh_skin.skeleton;
+                let num_joints = skeleton.num_joints();
+                let num_morph_targets = skeleton.num_morph_targets();
+                if (num_joints == 0 && num_morph_targets == 0)
+                    || num_joint_matrices + num_joints > MAX_JOINT_MATRICES
+                    || num_morph_weights + num_morph_targets > MAX_MORPH_WEIGHTS
+                {
+                    return None
+                }
+
+                let joint_offset = num_joint_matrices * mem::size_of::<Mat4>();
+                for (i, joint_matrix) in skeleton.joint_matrices(time).iter().enumerate() {
+                    joint_buffer.write_at(joint_offset + i * mem::size_of::<Mat4>(), joint_matrix);
+                }
+                num_joint_matrices += num_joints;
+
+                let morph_weight_offset = num_morph_weights * mem::size_of::<f32>();
+                for (i, morph_weight) in skeleton.morph_weights(time).iter().enumerate() {
+                    morph_weight_buffer.write_at(
+                        morph_weight_offset + i * mem::size_of::<f32>(),
+                        morph_weight,
+                    );
+                }
+                num_morph_weights += num_morph_targets;
+
+                //The level specific fields are filled in for every level
+                Some((
+                    mesh_buffers,
+                    SkinningConstants {
+                        joint_matrices: joint_buffer.device_address
+                            + joint_offset as vk::DeviceAddress,
+                        morph_weights: morph_weight_buffer.device_address
+                            + morph_weight_offset as vk::DeviceAddress,
+                        quantization: mesh_buffers.quantization,
+                        num_joints: num_joints as _,
+                        num_morph_targets: num_morph_targets as _,
+                        ..Zeroable::zeroed()
+                    },
+                ))
+            })
+            .collect();
+
+        //Skin the vertices
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.skinning_pipeline,
+        );
+        for (mesh_buffers, mesh_constants) in &skinned_meshes {
+            for skinned_level in &mesh_buffers.skin.as_ref().unwrap().levels {
+                let constants = SkinningConstants {
+                    rest_vertices: skinned_level.rest_vertex_buffer.device_address,
+                    vertex_skins: skinned_level.vertex_skin_buffer.device_address,
+                    skinned_vertices: mesh_buffers.levels[skinned_level.level_idx]
+                        .vertex_buffer
+                        .device_address,
+                    morph_deltas: skinned_level
+                        .morph_delta_buffer
+                        .as_ref()
+                        .map_or(0, |morph_delta_buffer| morph_delta_buffer.device_address),
+                    num_vertices: skinned_level.num_vertices as _,
+                    ..*mesh_constants
+                };
+                self.dispatch(
+                    ctx,
+                    command_buffer,
+                    &constants,
+                    skinned_level.num_vertices as _,
+                );
+            }
+        }
+        Self::barrier(ctx, command_buffer);
+
+        //Fit the meshlets to the skinned vertices, levels with shared vertices are fitted as well
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.refit_meshlets_pipeline,
+        );
+        for (mesh_buffers, _) in &skinned_meshes {
+            for level_buffers in &mesh_buffers.levels {
+                let constants = RefitConstants::new(
+                    level_buffers,
+                    mesh_buffers.quantization,
+                    level_buffers.num_meshlets,
+                );
+                self.dispatch(
+                    ctx,
+                    command_buffer,
+                    &constants,
+                    level_buffers.num_meshlets as _,
+                );
+            }
+        }
+        Self::barrier(ctx, command_buffer);
+
+        //Fit the groups to their meshlets
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.refit_meshlet_groups_pipeline,
+        );
+        for (mesh_buffers, _) in &skinned_meshes {
+            for level_buffers in &mesh_buffers.levels {
+                let constants = RefitConstants::new(
+                    level_buffers,
+                    mesh_buffers.quantization,
+                    level_buffers.num_meshlet_groups,
+                );
+                self.dispatch(
+                    ctx,
+                    command_buffer,
+                    &constants,
+                    level_buffers.num_meshlet_groups as _,
+                );
+            }
+        }
+    }
+}
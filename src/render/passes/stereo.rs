@@ -0,0 +1,333 @@
+use std::{slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use vk_mem_alloc::{Allocation, Allocator};
+
+use crate::render::{
+    render_ctx::{RenderCtx, DEPTH_FORMAT, SWAPCHAIN_FORMAT},
+    resource_state::TrackedResource,
+    scene::Camera,
+    utils,
+    utils::{
+        pipelines::{MultisampleState, RasterState},
+        reflection::ShaderInterface,
+    },
+};
+
+//Distance between the eyes in world units
+pub const EYE_SEPARATION: f32 = 0.064;
+//The first view is the left eye, the second one the right eye
+pub const VIEW_MASK: u32 = 0b11;
+const NUM_VIEWS: u32 = VIEW_MASK.count_ones();
+
+//An eye with its own, possibly asymmetric frustum. The tangents of the angles to the left and lower edges are negative
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Eye {
+    pub camera: Camera,
+    pub tan_left: f32,
+    pub tan_right: f32,
+    pub tan_up: f32,
+    pub tan_down: f32,
+}
+
+pub struct LayeredImage {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    pub aspect_mask: vk::ImageAspectFlags,
+    allocation: Allocation,
+}
+
+impl LayeredImage {
+    unsafe fn new(
+        device: &Device,
+        allocator: Allocator,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> Result<Self> {
+        let (image, allocation, image_view) = utils::create_layered_image(
+            device,
+            allocator,
+            extent.width,
+            extent.height,
+            NUM_VIEWS,
+            format,
+            usage,
+            aspect_mask,
+        )?;
+
+        Ok(Self {
+            image,
+            image_view,
+            aspect_mask,
+            allocation,
+        })
+    }
+
+    #[inline]
+    pub fn tracked(&self) -> TrackedResource {
+        TrackedResource::Image {
+            image: self.image,
+            aspect_mask: self.aspect_mask,
+        }
+    }
+
+    #[inline]
+    unsafe fn destroy(&self, device: &Device, allocator: Allocator) {
+        utils::destroy_image(
+            device,
+            allocator,
+            self.image,
+            self.allocation,
+            self.image_view,
+        );
+    }
+}
+
+//The geometry pass renders both eyes at once with multiview, one layer each. They are composited side by side into the
+//swapchain image
+pub struct StereoPass {
+    pub color: LayeredImage,
+    pub depth: LayeredImage,
+    //Half of the swapchain image unless RenderConfig::eye_extent is set
+    pub eye_extent: vk::Extent2D,
+    pub sampler: vk::Sampler,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    allocator: Allocator,
+    device: Arc<Device>,
+}
+
+impl Drop for StereoPass {
+    fn drop(&mut self) {
+        unsafe {
+            utils::pipelines::destroy(&self.device, self.pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.device.destroy_sampler(self.sampler, None);
+
+            self.color.destroy(&self.device, self.allocator);
+            self.depth.destroy(&self.device, self.allocator);
+        }
+    }
+}
+
+impl StereoPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: vk::PipelineCache,
+        allocator: Allocator,
+        eye_extent: vk::Extent2D,
+    ) -> Result<Self> {
+        //Create images
+        let (color, depth) = unsafe {
+            (
+                LayeredImage::new(
+                    device,
+                    allocator,
+                    eye_extent,
+                    SWAPCHAIN_FORMAT,
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT
+                        | vk::ImageUsageFlags::SAMPLED
+                        | vk::ImageUsageFlags::TRANSFER_SRC,
+                    vk::ImageAspectFlags::COLOR,
+                )?,
+                LayeredImage::new(
+                    device,
+                    allocator,
+                    eye_extent,
+                    DEPTH_FORMAT,
+                    vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                    vk::ImageAspectFlags::DEPTH,
+                )?,
+            )
+        };
+
+        //Compile shaders
+        let stages = utils::pipelines::compile_mesh_stages(
+            None,
+            "shaders/fullscreen.mesh.glsl",
+            "main",
+            &[],
+            "shaders/stereo_composite.frag.glsl",
+            "main",
+            &[],
+        )?;
+
+        //The swapchain image is rarely exactly twice as wide as an eye, so the eyes are filtered
+        let sampler = unsafe {
+            device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                None,
+            )
+        }?;
+
+        //Create descriptor set layout
+        let descriptor_set_layout_binding = vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+        let descriptor_set_layout = unsafe {
+            device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default()
+                    .flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR)
+                    .bindings(slice::from_ref(&descriptor_set_layout_binding)),
+                None,
+            )
+        }?;
+
+        //Create pipeline layout
+        let pipeline_layout = unsafe {
+            ShaderInterface::reflect(&stages)?
+                .create_pipeline_layout(device, slice::from_ref(&descriptor_set_layout))
+        }?;
+
+        //Create pipeline
+        let pipeline = unsafe {
+            utils::pipelines::create_mesh(
+                device,
+                pipeline_cache,
+                &stages,
+                &[],
+                &[SWAPCHAIN_FORMAT],
+                vk::Format::UNDEFINED,
+                &MultisampleState::default(),
+                &RasterState {
+                    depth_test: false,
+                    depth_write: false,
+                    ..Default::default()
+                },
+                pipeline_layout,
+            )
+        }?;
+
+        Ok(Self {
+            color,
+            depth,
+            eye_extent,
+            sampler,
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+            allocator,
+            device: device.clone(),
+        })
+    }
+
+    //The color image has to be in SHADER_READ_ONLY_OPTIMAL and the swapchain image in COLOR_ATTACHMENT_OPTIMAL
+    pub unsafe fn execute(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let device_loader = &ctx.device.device_loader;
+
+        //Begin rendering, every pixel is written
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(ctx.swapchain.image_views[image_index])
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE);
+
+        let extent = ctx.swapchain.extent;
+
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D::default().extent(extent))
+            .layer_count(1)
+            .color_attachments(slice::from_ref(&color_attachment));
+
+        device_loader.cmd_begin_rendering(command_buffer, &rendering_info);
+
+        //Composite the eyes
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline,
+        );
+
+        let viewport = vk::Viewport::default()
+            .width(extent.width as _)
+            .height(extent.height as _)
+            .max_depth(1.0);
+        let scissor = vk::Rect2D::default().extent(extent);
+
+        device_loader.cmd_set_viewport(command_buffer, 0, slice::from_ref(&viewport));
+        device_loader.cmd_set_scissor(command_buffer, 0, slice::from_ref(&scissor));
+
+        let descriptor_image_info = vk::DescriptorImageInfo::default()
+            .sampler(self.sampler)
+            .image_view(self.color.image_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        let write_descriptor_set = vk::WriteDescriptorSet::default()
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(slice::from_ref(&descriptor_image_info));
+
+        ctx.device.push_descriptor_loader.cmd_push_descriptor_set(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            slice::from_ref(&write_descriptor_set),
+        );
+
+        let inverse_extent = [1.0 / extent.width as f32, 1.0 / extent.height as f32];
+
+        device_loader.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            bytemuck::bytes_of(&inverse_extent),
+        );
+
+        ctx.device
+            .mesh_shader_loader
+            .cmd_draw_mesh_tasks(command_buffer, 1, 1, 1);
+
+        device_loader.cmd_end_rendering(command_buffer);
+    }
+
+    //The color image has to be in TRANSFER_SRC_OPTIMAL and the target in TRANSFER_DST_OPTIMAL, it needs a layer per eye
+    pub unsafe fn copy_eyes(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        target: vk::Image,
+    ) {
+        let subresource = vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(NUM_VIEWS);
+
+        let image_copy = vk::ImageCopy::default()
+            .src_subresource(subresource)
+            .dst_subresource(subresource)
+            .extent(vk::Extent3D {
+                width: self.eye_extent.width,
+                height: self.eye_extent.height,
+                depth: 1,
+            });
+
+        ctx.device.device_loader.cmd_copy_image(
+            command_buffer,
+            self.color.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            target,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            slice::from_ref(&image_copy),
+        );
+    }
+}
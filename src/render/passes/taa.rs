@@ -0,0 +1,404 @@
+use std::{cell::Cell, slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use glam::{Mat4, Vec2};
+use vk_mem_alloc::{Allocation, Allocator};
+
+use crate::render::{
+    render_ctx::{RenderCtx, SWAPCHAIN_FORMAT},
+    resource_state::TrackedResource,
+    utils,
+    utils::{
+        pipelines::{MultisampleState, RasterState},
+        reflection::ShaderInterface,
+    },
+};
+
+pub const VELOCITY_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+const HISTORY_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+//The jitter cycles through this many points of the Halton sequence
+const NUM_JITTER_SAMPLES: u32 = 8;
+//How much of the history is kept every frame
+const HISTORY_WEIGHT: f32 = 0.9;
+
+pub struct TaaImage {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    allocation: Allocation,
+}
+
+impl TaaImage {
+    unsafe fn new(
+        device: &Device,
+        allocator: Allocator,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Self> {
+        let (image, allocation, image_view) = utils::create_color_image(
+            device,
+            allocator,
+            extent.width,
+            extent.height,
+            format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::SampleCountFlags::TYPE_1,
+        )?;
+
+        Ok(Self {
+            image,
+            image_view,
+            allocation,
+        })
+    }
+
+    #[inline]
+    pub fn tracked(&self) -> TrackedResource {
+        TrackedResource::Image {
+            image: self.image,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+        }
+    }
+
+    #[inline]
+    unsafe fn destroy(&self, device: &Device, allocator: Allocator) {
+        utils::destroy_image(
+            device,
+            allocator,
+            self.image,
+            self.allocation,
+            self.image_view,
+        );
+    }
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+//The geometry pass renders the color and velocity into its images, which are blended with the reprojected history
+//and written to the swapchain image
+pub struct TaaPass {
+    pub color: TaaImage,
+    pub velocity: TaaImage,
+    //One is read while the other one is written, they swap every frame
+    pub history: [TaaImage; 2],
+    pub sampler: vk::Sampler,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    //Index of the history image written this frame
+    history_idx: Cell<usize>,
+    //Reset whenever a frame is rendered without TAA
+    history_valid: Cell<bool>,
+    jitter_idx: Cell<u32>,
+    prev_view_projection_matrix: Cell<Mat4>,
+    allocator: Allocator,
+    device: Arc<Device>,
+}
+
+impl Drop for TaaPass {
+    fn drop(&mut self) {
+        unsafe {
+            utils::pipelines::destroy(&self.device, self.pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.device.destroy_sampler(self.sampler, None);
+
+            self.destroy_images();
+        }
+    }
+}
+
+unsafe fn create_images(
+    device: &Device,
+    allocator: Allocator,
+    extent: vk::Extent2D,
+) -> Result<(TaaImage, TaaImage, [TaaImage; 2])> {
+    Ok((
+        TaaImage::new(device, allocator, extent, SWAPCHAIN_FORMAT)?,
+        TaaImage::new(device, allocator, extent, VELOCITY_FORMAT)?,
+        [
+            TaaImage::new(device, allocator, extent, HISTORY_FORMAT)?,
+            TaaImage::new(device, allocator, extent, HISTORY_FORMAT)?,
+        ],
+    ))
+}
+
+impl TaaPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_cache: vk::PipelineCache,
+        allocator: Allocator,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        //Create images
+        let (color, velocity, history) = unsafe { create_images(device, allocator, extent) }?;
+
+        //Compile shaders
+        let stages = utils::pipelines::compile_mesh_stages(
+            None,
+            "shaders/fullscreen.mesh.glsl",
+            "main",
+            &[],
+            "shaders/taa.frag.glsl",
+            "main",
+            &[],
+        )?;
+
+        //The history is reprojected between pixels, so it's sampled bilinearly
+        let sampler = unsafe {
+            device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                None,
+            )
+        }?;
+
+        //Create descriptor set layout, the images are pushed every frame since the history images swap
+        let descriptor_set_layout_bindings: Vec<_> = (0..3)
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            })
+            .collect();
+
+        let descriptor_set_layout = unsafe {
+            device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default()
+                    .flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR)
+                    .bindings(&descriptor_set_layout_bindings),
+                None,
+            )
+        }?;
+
+        //Create pipeline layout
+        let pipeline_layout = unsafe {
+            ShaderInterface::reflect(&stages)?
+                .create_pipeline_layout(device, slice::from_ref(&descriptor_set_layout))
+        }?;
+
+        //Create pipeline
+        let pipeline = unsafe {
+            utils::pipelines::create_mesh(
+                device,
+                pipeline_cache,
+                &stages,
+                &[],
+                &[SWAPCHAIN_FORMAT, HISTORY_FORMAT],
+                vk::Format::UNDEFINED,
+                &MultisampleState::default(),
+                &RasterState {
+                    depth_test: false,
+                    depth_write: false,
+                    ..Default::default()
+                },
+                pipeline_layout,
+            )
+        }?;
+
+        Ok(Self {
+            color,
+            velocity,
+            history,
+            sampler,
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+            history_idx: Cell::new(0),
+            history_valid: Cell::new(false),
+            jitter_idx: Cell::new(0),
+            prev_view_projection_matrix: Cell::new(Mat4::IDENTITY),
+            allocator,
+            device: device.clone(),
+        })
+    }
+
+    //None of the frames may be in flight, the history is thrown away
+    pub unsafe fn resize(&mut self, extent: vk::Extent2D) -> Result<()> {
+        let (color, velocity, history) = create_images(&self.device, self.allocator, extent)?;
+
+        self.destroy_images();
+        self.color = color;
+        self.velocity = velocity;
+        self.history = history;
+        self.history_valid.set(false);
+        Ok(())
+    }
+
+    unsafe fn destroy_images(&self) {
+        for image in [&self.color, &self.velocity]
+            .into_iter()
+            .chain(&self.history)
+        {
+            image.destroy(&self.device, self.allocator);
+        }
+    }
+
+    //Returns the jitter of this frame in normalized device coordinates, without TAA nothing is jittered and the
+    //history is thrown away
+    pub fn next_jitter(&self, enabled: bool, extent: vk::Extent2D) -> Vec2 {
+        if !enabled {
+            self.history_valid.set(false);
+            return Vec2::ZERO
+        }
+
+        let jitter_idx = (self.jitter_idx.get() + 1) % NUM_JITTER_SAMPLES;
+        self.jitter_idx.set(jitter_idx);
+
+        //The Halton sequence starts at 0, which would be the same point for both bases
+        let offset = Vec2::new(halton(jitter_idx + 1, 2), halton(jitter_idx + 1, 3)) - 0.5;
+        offset * 2.0 / Vec2::new(extent.width as _, extent.height as _)
+    }
+
+    //Returns the view projection matrix of the last frame and remembers the one of this frame
+    #[inline]
+    pub fn swap_view_projection_matrix(&self, view_projection_matrix: Mat4) -> Mat4 {
+        let prev_view_projection_matrix = self
+            .prev_view_projection_matrix
+            .replace(view_projection_matrix);
+        //The first frame has nothing to reproject from
+        if self.history_valid.get() {
+            prev_view_projection_matrix
+        } else {
+            view_projection_matrix
+        }
+    }
+
+    //The history image read this frame and the one written
+    #[inline]
+    pub fn history_images(&self) -> (&TaaImage, &TaaImage) {
+        let history_idx = self.history_idx.get();
+        (&self.history[1 - history_idx], &self.history[history_idx])
+    }
+
+    #[inline]
+    pub fn history_valid(&self) -> bool {
+        self.history_valid.get()
+    }
+
+    //The color, velocity and previous history images have to be in SHADER_READ_ONLY_OPTIMAL, the swapchain image and
+    //the written history image in COLOR_ATTACHMENT_OPTIMAL
+    pub unsafe fn execute(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+    ) {
+        let device_loader = &ctx.device.device_loader;
+        let (prev_history_image, history_image) = self.history_images();
+
+        //Begin rendering, every pixel is written
+        let color_attachments = [
+            ctx.swapchain.image_views[image_index],
+            history_image.image_view,
+        ]
+        .map(|image_view| {
+            vk::RenderingAttachmentInfo::default()
+                .image_view(image_view)
+                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .store_op(vk::AttachmentStoreOp::STORE)
+        });
+
+        let extent = ctx.swapchain.extent;
+
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D::default().extent(extent))
+            .layer_count(1)
+            .color_attachments(&color_attachments);
+
+        device_loader.cmd_begin_rendering(command_buffer, &rendering_info);
+
+        //Resolve the history
+        device_loader.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline,
+        );
+
+        let viewport = vk::Viewport::default()
+            .width(extent.width as _)
+            .height(extent.height as _)
+            .max_depth(1.0);
+        let scissor = vk::Rect2D::default().extent(extent);
+
+        device_loader.cmd_set_viewport(command_buffer, 0, slice::from_ref(&viewport));
+        device_loader.cmd_set_scissor(command_buffer, 0, slice::from_ref(&scissor));
+
+        let descriptor_image_infos = [
+            self.color.image_view,
+            self.velocity.image_view,
+            prev_history_image.image_view,
+        ]
+        .map(|image_view| {
+            vk::DescriptorImageInfo::default()
+                .sampler(self.sampler)
+                .image_view(image_view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        });
+        let write_descriptor_sets: Vec<_> = descriptor_image_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, descriptor_image_info)| {
+                vk::WriteDescriptorSet::default()
+                    .dst_binding(binding as _)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(slice::from_ref(descriptor_image_info))
+            })
+            .collect();
+
+        ctx.device.push_descriptor_loader.cmd_push_descriptor_set(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &write_descriptor_sets,
+        );
+
+        let push_constants = [
+            1.0 / extent.width as f32,
+            1.0 / extent.height as f32,
+            if self.history_valid.get() {
+                HISTORY_WEIGHT
+            } else {
+                0.0
+            },
+        ];
+
+        device_loader.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            bytemuck::bytes_of(&push_constants),
+        );
+
+        ctx.device
+            .mesh_shader_loader
+            .cmd_draw_mesh_tasks(command_buffer, 1, 1, 1);
+
+        device_loader.cmd_end_rendering(command_buffer);
+
+        //The next frame reads the history written by this one
+        self.history_idx.set(1 - self.history_idx.get());
+        self.history_valid.set(true);
+    }
+}
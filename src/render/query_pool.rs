@@ -1,10 +1,12 @@
 use std::{collections::HashMap, ops::Deref, sync::Arc, time::Duration};
 
 use ash::{prelude::VkResult, vk, Device};
+use bytemuck::{Pod, Zeroable};
 
 pub struct QueryPool {
     query_pool: vk::QueryPool,
     query_count: u32,
+    timestamp_period: f32,
 
     current_idx: u32,
     entries: HashMap<String, u32>,
@@ -17,6 +19,7 @@ impl QueryPool {
         device: &Arc<Device>,
         query_count: u32,
         query_type: vk::QueryType,
+        timestamp_period: f32,
     ) -> VkResult<Self> {
         let query_pool = device.create_query_pool(
             &vk::QueryPoolCreateInfo::default()
@@ -28,6 +31,7 @@ impl QueryPool {
         Ok(Self {
             query_pool,
             query_count,
+            timestamp_period,
 
             current_idx: 0,
             entries: HashMap::new(),
@@ -58,9 +62,14 @@ impl QueryPool {
             .cmd_reset_query_pool(command_buffer, self.query_pool, 0, self.query_count);
     }
 
+    //Only call this after the command buffer which wrote the timestamps has finished executing
     #[inline]
     pub unsafe fn get_results(&self) -> VkResult<HashMap<String, Duration>> {
-        let mut results = vec![0_u64; self.query_count as usize];
+        if self.current_idx == 0 {
+            return Ok(HashMap::new())
+        }
+
+        let mut results = vec![0_u64; self.current_idx as usize];
 
         self.device.get_query_pool_results(
             self.query_pool,
@@ -72,7 +81,10 @@ impl QueryPool {
         Ok(self
             .entries
             .iter()
-            .map(|(name, idx)| (name.clone(), Duration::from_nanos(results[*idx as usize])))
+            .map(|(name, idx)| {
+                let nanos = results[*idx as usize] as f64 * self.timestamp_period as f64;
+                (name.clone(), Duration::from_nanos(nanos as u64))
+            })
             .collect())
     }
 }
@@ -93,3 +105,156 @@ impl Drop for QueryPool {
         }
     }
 }
+
+//The results are written in the bit order of the flags returned by PipelineStatistics::flags, the task and mesh shader
+//invocations stay zero without mesh shader queries
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Zeroable, Pod)]
+pub struct PipelineStatistics {
+    pub fragment_shader_invocations: u64,
+    pub compute_shader_invocations: u64,
+    pub task_shader_invocations: u64,
+    pub mesh_shader_invocations: u64,
+}
+
+impl PipelineStatistics {
+    #[inline]
+    pub fn flags(mesh_shader_queries_supported: bool) -> vk::QueryPipelineStatisticFlags {
+        let flags = vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS
+            | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS;
+        if mesh_shader_queries_supported {
+            flags
+                | vk::QueryPipelineStatisticFlags::TASK_SHADER_INVOCATIONS_EXT
+                | vk::QueryPipelineStatisticFlags::MESH_SHADER_INVOCATIONS_EXT
+        } else {
+            flags
+        }
+    }
+}
+
+pub struct PipelineStatisticsQueryPool {
+    query_pool: vk::QueryPool,
+    query_count: u32,
+    pub flags: vk::QueryPipelineStatisticFlags,
+
+    current_idx: u32,
+    entries: HashMap<String, u32>,
+
+    device: Arc<Device>,
+}
+
+impl PipelineStatisticsQueryPool {
+    pub unsafe fn new(
+        device: &Arc<Device>,
+        query_count: u32,
+        mesh_shader_queries_supported: bool,
+    ) -> VkResult<Self> {
+        let flags = PipelineStatistics::flags(mesh_shader_queries_supported);
+        let query_pool = device.create_query_pool(
+            &vk::QueryPoolCreateInfo::default()
+                .query_count(query_count)
+                .query_type(vk::QueryType::PIPELINE_STATISTICS)
+                .pipeline_statistics(flags),
+            None,
+        )?;
+
+        Ok(Self {
+            query_pool,
+            query_count,
+            flags,
+
+            current_idx: 0,
+            entries: HashMap::new(),
+
+            device: device.clone(),
+        })
+    }
+
+    #[inline]
+    pub unsafe fn begin(&mut self, command_buffer: vk::CommandBuffer, name: impl Into<String>) {
+        self.device.cmd_begin_query(
+            command_buffer,
+            self.query_pool,
+            self.current_idx,
+            vk::QueryControlFlags::empty(),
+        );
+
+        self.entries.insert(name.into(), self.current_idx);
+    }
+
+    #[inline]
+    pub unsafe fn end(&mut self, command_buffer: vk::CommandBuffer) {
+        self.device
+            .cmd_end_query(command_buffer, self.query_pool, self.current_idx);
+
+        self.current_idx += 1;
+    }
+
+    #[inline]
+    pub unsafe fn reset(&mut self, command_buffer: vk::CommandBuffer) {
+        self.current_idx = 0;
+        self.entries.clear();
+        self.device
+            .cmd_reset_query_pool(command_buffer, self.query_pool, 0, self.query_count);
+    }
+
+    //Only call this after the command buffer which wrote the queries has finished executing
+    #[inline]
+    pub unsafe fn get_results(&self) -> VkResult<HashMap<String, PipelineStatistics>> {
+        if self.current_idx == 0 {
+            return Ok(HashMap::new())
+        }
+
+        //Every query writes one value per flag, so the element type sets the stride
+        let results = if self.flags.as_raw().count_ones() == 4 {
+            self.read_results::<PipelineStatistics>()?
+        } else {
+            self.read_results::<[u64; 2]>()?
+        };
+
+        Ok(self
+            .entries
+            .iter()
+            .map(|(name, idx)| (name.clone(), results[*idx as usize]))
+            .collect())
+    }
+
+    unsafe fn read_results<T: Pod>(&self) -> VkResult<Vec<PipelineStatistics>> {
+        let mut results = vec![T::zeroed(); self.current_idx as usize];
+
+        self.device.get_query_pool_results(
+            self.query_pool,
+            0,
+            &mut results,
+            vk::QueryResultFlags::TYPE_64,
+        )?;
+
+        Ok(results
+            .iter()
+            .map(|result| {
+                let mut pipeline_statistics = PipelineStatistics::default();
+                let values = bytemuck::bytes_of(result);
+                bytemuck::bytes_of_mut(&mut pipeline_statistics)[..values.len()]
+                    .copy_from_slice(values);
+                pipeline_statistics
+            })
+            .collect())
+    }
+}
+
+impl Deref for PipelineStatisticsQueryPool {
+    type Target = vk::QueryPool;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.query_pool
+    }
+}
+
+impl Drop for PipelineStatisticsQueryPool {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_query_pool(self.query_pool, None);
+        }
+    }
+}
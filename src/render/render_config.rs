@@ -0,0 +1,150 @@
+use anyhow::{bail, Result};
+use ash::vk;
+
+use crate::render::mesh::MeshletConfig;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GpuSelector {
+    Index(usize),
+    //Matches the first device whose name contains this, ignoring case
+    Name(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderConfig {
+    //Picks the first enumerated device if not set
+    pub gpu: Option<GpuSelector>,
+    //Falls back to FIFO if the surface doesn't support it
+    pub present_mode: vk::PresentModeKHR,
+    //Acquires exclusive fullscreen with VK_EXT_full_screen_exclusive, only on Windows and if the device supports it
+    pub exclusive_fullscreen: bool,
+    pub validation: bool,
+    //Falls back to the highest sample count below it the device supports
+    pub msaa_samples: vk::SampleCountFlags,
+    //Renders both eyes with multiview and shows them side by side, falls back to a single view if the device can't
+    pub stereo: bool,
+    //Extent every eye is rendered at, half of the swapchain image if not set
+    pub eye_extent: Option<vk::Extent2D>,
+    //The meshes are baked with it until it's changed with RenderCtx::set_meshlet_config
+    pub meshlet_config: MeshletConfig,
+}
+
+impl Default for RenderConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            gpu: None,
+            present_mode: vk::PresentModeKHR::FIFO,
+            exclusive_fullscreen: false,
+            validation: cfg!(debug_assertions),
+            msaa_samples: vk::SampleCountFlags::TYPE_1,
+            stereo: false,
+            eye_extent: None,
+            meshlet_config: MeshletConfig::default(),
+        }
+    }
+}
+
+//The render options of the command line, options which aren't passed keep the value of the config
+#[derive(clap::Args, Debug)]
+pub struct RenderArgs {
+    #[arg(
+        long,
+        value_name = "INDEX|NAME",
+        help = "Physical device used for rendering"
+    )]
+    gpu: Option<String>,
+    #[arg(
+        long,
+        value_name = "MODE",
+        value_parser = parse_present_mode,
+        help = "fifo, fifo-relaxed, mailbox or immediate"
+    )]
+    present_mode: Option<vk::PresentModeKHR>,
+    #[arg(
+        long,
+        help = "Acquire exclusive fullscreen on Windows for the lowest latency"
+    )]
+    exclusive_fullscreen: bool,
+    #[arg(long, help = "Enable the validation layers, always on in debug builds")]
+    validation: bool,
+    #[arg(
+        long = "msaa",
+        value_name = "SAMPLES",
+        value_parser = parse_sample_count,
+        help = "1, 2, 4 or 8 samples per pixel"
+    )]
+    msaa_samples: Option<vk::SampleCountFlags>,
+    #[arg(long, help = "Render two eye views side by side with multiview")]
+    stereo: bool,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Maximum vertices of a meshlet, 64 by default"
+    )]
+    meshlet_vertices: Option<usize>,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Maximum triangles of a meshlet, a multiple of 4 and 124 by default"
+    )]
+    meshlet_triangles: Option<usize>,
+    #[arg(
+        long,
+        value_name = "WEIGHT",
+        help = "Weight of the normal cones when building meshlets, from 0 to 1"
+    )]
+    cone_weight: Option<f32>,
+}
+
+fn parse_present_mode(present_mode: &str) -> Result<vk::PresentModeKHR> {
+    Ok(match present_mode {
+        "fifo" => vk::PresentModeKHR::FIFO,
+        "fifo-relaxed" => vk::PresentModeKHR::FIFO_RELAXED,
+        "mailbox" => vk::PresentModeKHR::MAILBOX,
+        "immediate" => vk::PresentModeKHR::IMMEDIATE,
+        _ => bail!("Unknown present mode {present_mode}"),
+    })
+}
+
+fn parse_sample_count(samples: &str) -> Result<vk::SampleCountFlags> {
+    Ok(match samples {
+        "1" => vk::SampleCountFlags::TYPE_1,
+        "2" => vk::SampleCountFlags::TYPE_2,
+        "4" => vk::SampleCountFlags::TYPE_4,
+        "8" => vk::SampleCountFlags::TYPE_8,
+        _ => bail!("Unsupported MSAA sample count {samples}"),
+    })
+}
+
+impl RenderArgs {
+    pub fn apply(self, config: &mut RenderConfig) -> Result<()> {
+        if let Some(gpu) = self.gpu {
+            config.gpu = Some(match gpu.parse() {
+                Ok(index) => GpuSelector::Index(index),
+                Err(_) => GpuSelector::Name(gpu),
+            });
+        }
+        if let Some(present_mode) = self.present_mode {
+            config.present_mode = present_mode;
+        }
+        config.exclusive_fullscreen |= self.exclusive_fullscreen;
+        config.validation |= self.validation;
+        if let Some(msaa_samples) = self.msaa_samples {
+            config.msaa_samples = msaa_samples;
+        }
+        config.stereo |= self.stereo;
+
+        let meshlet_config = &mut config.meshlet_config;
+        if let Some(max_vertices) = self.meshlet_vertices {
+            meshlet_config.max_vertices = max_vertices;
+        }
+        if let Some(max_triangles) = self.meshlet_triangles {
+            meshlet_config.max_triangles = max_triangles;
+        }
+        if let Some(cone_weight) = self.cone_weight {
+            meshlet_config.cone_weight = cone_weight;
+        }
+        meshlet_config.validate()
+    }
+}
@@ -1,353 +1,658 @@
-use std::{mem::ManuallyDrop, slice, sync::Arc};
-
-use ash::{
-    extensions::{
-        ext::MeshShader,
-        khr::{Surface, Swapchain},
-    },
-    vk, Device, Entry, Instance,
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    mem::{self, ManuallyDrop},
+    time::Instant,
 };
+
+use ash::vk;
 use dolly::{
     drivers::Position,
     prelude::{CameraRig, Smooth, YawPitch},
 };
-use glam::{Vec2, Vec3};
-use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
-use vk_mem_alloc::{Allocation, AllocatorCreateFlags, AllocatorCreateInfo};
+use glam::{Quat, Vec3};
 use winit::window::Window;
 
 use crate::render::{
-    frame,
-    frame::Frame,
-    mesh::{MeshCollection, MeshSource, Vertex},
-    passes::{geometry::GeometryPass, instance_cull::InstanceCullPass},
-    query_pool::QueryPool,
+    capture::RgbaImage,
+    deletion_queue::DeletionQueue,
+    error::{ErrorContext, RenderError},
+    frame::NUM_FRAMES,
+    frame_resources::FrameResources,
+    mask_textures::MAX_MASK_TEXTURES,
+    mesh::MeshletConfig,
+    pass_timings::PassTimings,
+    passes::{
+        frustum_debug::FrustumDebugPass,
+        geometry::{CullingStats, GeometryPass},
+        grass::GrassPass,
+        instance_animate::InstanceAnimatePass,
+        instance_cull::InstanceCullPass,
+        light_cull::LightCullPass,
+        outline::OutlinePass,
+        overdraw::OverdrawPass,
+        particles::ParticlePass,
+        picking::PickingPass,
+        skinning::SkinningPass,
+        stereo::{self, Eye, StereoPass},
+        taa::TaaPass,
+    },
+    query_pool::PipelineStatistics,
+    render_config::RenderConfig,
+    render_device::{DeviceConfig, PhysicalDeviceSelector, RenderDevice},
+    render_settings::RenderSettings,
+    scene::{Camera, Scene},
+    scene_resources::SceneResources,
+    secondary_window::SecondaryView,
+    shader_watcher::ShaderWatcher,
+    swapchain::SwapchainBundle,
     utils,
-    utils::globals::GlobalsBuffers,
+    utils::{globals::GlobalsBuffers, pipelines::MultisampleState},
+    workers::{WorkerConfig, WorkerPool},
 };
 pub const SWAPCHAIN_FORMAT: vk::Format = vk::Format::B8G8R8A8_UNORM;
 pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
 pub const FIELD_OF_VIEW: f32 = 90.0;
+pub const NEAR_PLANE: f32 = 0.1;
+pub const FAR_PLANE: f32 = 1000.0;
+//The sets of the mesh collection and its mask textures are replaced when meshes finish loading, the old ones are kept
+//by the deletion queue until the frames in flight finished. Waiting for a collection to load replaces them once more
+const MAX_REPLACED_DESCRIPTOR_SETS: u32 = NUM_FRAMES as u32 + 1;
+//The sets of the mesh collection, its mask textures, the instances and the overdraw image
+pub const MAX_DESCRIPTOR_SETS: u32 = 4 + 2 * MAX_REPLACED_DESCRIPTOR_SETS;
+
+#[derive(Copy, Clone)]
+pub enum RenderTarget<'a> {
+    Window(&'a Window),
+    //Renders into offscreen images, neither a surface nor a swapchain is created
+    Headless(vk::Extent2D),
+}
 
 pub struct RenderCtx {
-    pub entry_loader: Entry,
-
-    pub instance_loader: Instance,
-    pub surface_loader: Surface,
-
-    pub surface: vk::SurfaceKHR,
-
-    pub device_loader: Arc<Device>,
-    pub swapchain_loader: Swapchain,
-    pub mesh_shader_loader: MeshShader,
-
-    pub allocator: vk_mem_alloc::Allocator,
-
-    pub direct_queue: vk::Queue,
-
-    pub swapchain: vk::SwapchainKHR,
-    pub swapchain_images: Vec<vk::Image>,
-    pub swapchain_image_views: Vec<vk::ImageView>,
-    pub depth_image: vk::Image,
-    pub depth_image_view: vk::ImageView,
-    pub depth_image_allocation: Allocation,
+    pub device: ManuallyDrop<RenderDevice>,
+    pub swapchain: ManuallyDrop<SwapchainBundle>,
+    pub frame_resources: ManuallyDrop<FrameResources>,
+    pub scene_resources: ManuallyDrop<SceneResources>,
 
     pub descriptor_pool: vk::DescriptorPool,
 
-    pub globals_buffers: ManuallyDrop<GlobalsBuffers>,
-
+    pub instance_animate_pass: ManuallyDrop<InstanceAnimatePass>,
     pub instance_cull_pass: ManuallyDrop<InstanceCullPass>,
+    pub skinning_pass: ManuallyDrop<SkinningPass>,
+    pub overdraw_pass: ManuallyDrop<OverdrawPass>,
     pub geometry_pass: ManuallyDrop<GeometryPass>,
+    pub frustum_debug_pass: ManuallyDrop<FrustumDebugPass>,
+    //Only created if the device supports task shaders, the grass blades are emitted by one
+    pub grass_pass: Option<GrassPass>,
+    pub particle_pass: ManuallyDrop<ParticlePass>,
+    pub light_cull_pass: ManuallyDrop<LightCullPass>,
+    pub taa_pass: ManuallyDrop<TaaPass>,
+    pub picking_pass: ManuallyDrop<PickingPass>,
+    pub outline_pass: ManuallyDrop<OutlinePass>,
+    //Only created if both eyes are rendered
+    pub stereo_pass: Option<StereoPass>,
+    pub deletion_queue: ManuallyDrop<DeletionQueue>,
+    //None if shaders aren't reloaded when they change
+    pub shader_watcher: Option<ShaderWatcher>,
+    pub recording_workers: WorkerPool,
 
-    pub frames: Vec<ManuallyDrop<Frame>>,
     pub camera_rig: CameraRig,
-    pub mesh_collection: ManuallyDrop<MeshCollection>,
-
-    pub query_pool_timestamp: ManuallyDrop<QueryPool>,
-    pub query_pool_pipeline_statistics: ManuallyDrop<QueryPool>,
+    //Applied on top of the rig, which only tracks position, yaw and pitch
+    pub camera_roll: f32,
+    pub field_of_view: f32,
+    pub render_settings: RenderSettings,
+    //Kept to create everything again after the device was lost
+    pub worker_config: WorkerConfig,
+    pub render_config: RenderConfig,
+    pub device_config: DeviceConfig,
+
+    pub pass_timings: PassTimings,
+    pub pipeline_statistics: HashMap<String, PipelineStatistics>,
+    pub culling_stats: CullingStats,
+    //Set to capture the next frame, the result is stored in captured_frame
+    pub capture_requested: bool,
+    pub captured_frame: Option<RgbaImage>,
+    //Overrides the animation time, so captured frame sequences are deterministic
+    pub fixed_time: Option<f32>,
+    //Replaces the interactive camera rig, used when rendering programmatically
+    pub camera_override: Option<Camera>,
+    //Replaces the eyes derived from the camera with stereo rendering, e.g. with the ones tracked by an XR runtime
+    pub eye_override: Option<[Eye; 2]>,
+    //Layered image the eyes are copied into with stereo rendering, it has to be in COLOR_ATTACHMENT_OPTIMAL and is left
+    //in it
+    pub stereo_target: Option<vk::Image>,
+    //Only set while a secondary window is rendered
+    pub secondary_view: Option<SecondaryView>,
+    //Set to pick the instance under the cursor position in the next frame, the result is stored in selected_instance
+    pub pick_requested: Option<(u32, u32)>,
+    //Outlined while it's set
+    pub selected_instance: Option<u32>,
 
     pub workgroup_size: u32,
+    pub start_time: Instant,
 }
 
-impl RenderCtx {
-    pub fn new(window: &Window) -> Self {
-        let entry_loader = unsafe { Entry::load() }.unwrap();
-
-        let application_info = vk::ApplicationInfo::default().api_version(vk::API_VERSION_1_3);
-
-        let instance_layers = [b"VK_LAYER_KHRONOS_validation\0".as_ptr().cast()];
-
-        let mut instance_extensions = vec![];
-        ash_window::enumerate_required_extensions(window.raw_display_handle())
-            .unwrap()
-            .iter()
-            .for_each(|e| instance_extensions.push(*e));
-
-        let instance_create_info = vk::InstanceCreateInfo::default()
-            .enabled_layer_names(&instance_layers)
-            .enabled_extension_names(&instance_extensions)
-            .application_info(&application_info);
-
-        let instance_loader =
-            unsafe { entry_loader.create_instance(&instance_create_info, None) }.unwrap();
-        let surface_loader = Surface::new(&entry_loader, &instance_loader);
-
-        let surface = unsafe {
-            ash_window::create_surface(
-                &entry_loader,
-                &instance_loader,
-                window.raw_display_handle(),
-                window.raw_window_handle(),
-                None,
-            )
-        }
-        .unwrap();
+//A single worker records on the render thread, without secondary command buffers
+#[inline]
+pub(crate) fn num_recording_threads(recording_workers: &WorkerPool) -> usize {
+    match recording_workers.num_workers() {
+        1 => 0,
+        num_workers => num_workers,
+    }
+}
 
-        let physical_devices = unsafe { instance_loader.enumerate_physical_devices() }.unwrap();
-        let physical_device = physical_devices[0];
+#[inline]
+fn default_camera_rig() -> CameraRig {
+    CameraRig::builder()
+        .with(Position::new(Vec3::Y))
+        .with(YawPitch::new())
+        .with(Smooth::new_position_rotation(1.0, 1.0))
+        .build()
+}
+
+//Configures a RenderCtx before it's created, extensions and features can be added without changing RenderDevice
+pub struct RenderCtxBuilder<'a> {
+    target: RenderTarget<'a>,
+    worker_config: WorkerConfig,
+    render_config: RenderConfig,
+    device_config: DeviceConfig,
+}
 
-        let mut physical_device_vulkan_12_properties =
-            vk::PhysicalDeviceVulkan12Properties::default();
-        let mut physical_device_vulkan_13_properties =
-            vk::PhysicalDeviceVulkan13Properties::default();
-        let mut physical_device_mesh_shader_properties =
-            vk::PhysicalDeviceMeshShaderPropertiesEXT::default();
+impl<'a> RenderCtxBuilder<'a> {
+    #[inline]
+    pub fn worker_config(mut self, worker_config: WorkerConfig) -> Self {
+        self.worker_config = worker_config;
+        self
+    }
 
-        let mut physical_device_properties = vk::PhysicalDeviceProperties2::default()
-            .push_next(&mut physical_device_vulkan_12_properties)
-            .push_next(&mut physical_device_vulkan_13_properties)
-            .push_next(&mut physical_device_mesh_shader_properties);
+    #[inline]
+    pub fn render_config(mut self, render_config: RenderConfig) -> Self {
+        self.render_config = render_config;
+        self
+    }
 
-        unsafe {
-            instance_loader
-                .get_physical_device_properties2(physical_device, &mut physical_device_properties)
+    #[inline]
+    pub fn instance_layer(mut self, layer: &'static CStr) -> Self {
+        self.device_config.instance_layers.push(layer);
+        self
+    }
+
+    #[inline]
+    pub fn instance_extension(mut self, extension: &'static CStr) -> Self {
+        self.device_config.instance_extensions.push(extension);
+        self
+    }
+
+    //Devices which don't support it are rejected
+    #[inline]
+    pub fn device_extension(mut self, extension: &'static CStr) -> Self {
+        self.device_config.device_extensions.push(extension);
+        self
+    }
+
+    //Enables additional core features on top of the ones the example needs
+    #[inline]
+    pub fn features(
+        mut self,
+        features: fn(vk::PhysicalDeviceFeatures) -> vk::PhysicalDeviceFeatures,
+    ) -> Self {
+        self.device_config.features = Some(features);
+        self
+    }
+
+    #[inline]
+    pub fn physical_device(mut self, physical_device: PhysicalDeviceSelector) -> Self {
+        self.device_config.physical_device = Some(physical_device);
+        self
+    }
+
+    pub fn build(self, scene: &Scene) -> Result<RenderCtx, RenderError> {
+        let window = match self.target {
+            RenderTarget::Window(window) => Some(window),
+            RenderTarget::Headless(_) => None,
+        };
+        let extent = match self.target {
+            RenderTarget::Window(window) => {
+                vk::Extent2D {
+                    width: window.inner_size().width,
+                    height: window.inner_size().height,
+                }
+            }
+            RenderTarget::Headless(extent) => extent,
         };
 
-        dbg!(&physical_device_mesh_shader_properties);
-
-        let queue_priority = 1.0;
-        let device_queue_create_info =
-            vk::DeviceQueueCreateInfo::default().queue_priorities(slice::from_ref(&queue_priority));
-
-        let device_extensions = [Swapchain::NAME.as_ptr(), MeshShader::NAME.as_ptr()];
-
-        let physical_device_features = vk::PhysicalDeviceFeatures::default()
-            .pipeline_statistics_query(true)
-            .shader_int64(true);
-
-        let mut physical_device_vulkan_12_features =
-            vk::PhysicalDeviceVulkan12Features::default().buffer_device_address(true);
-        let mut physical_device_vulkan_13_features = vk::PhysicalDeviceVulkan13Features::default()
-            .dynamic_rendering(true)
-            .synchronization2(true)
-            .maintenance4(true);
-        let mut physical_device_mesh_shader_features =
-            vk::PhysicalDeviceMeshShaderFeaturesEXT::default().mesh_shader(true);
-
-        let mut physical_device_features = vk::PhysicalDeviceFeatures2::default()
-            .features(physical_device_features)
-            .push_next(&mut physical_device_vulkan_12_features)
-            .push_next(&mut physical_device_vulkan_13_features)
-            .push_next(&mut physical_device_mesh_shader_features);
-
-        let device_create_info = vk::DeviceCreateInfo::default()
-            .push_next(&mut physical_device_features)
-            .queue_create_infos(slice::from_ref(&device_queue_create_info))
-            .enabled_extension_names(&device_extensions);
-        let device_loader = Arc::new(
-            unsafe { instance_loader.create_device(physical_device, &device_create_info, None) }
-                .unwrap(),
-        );
-        let swapchain_loader = Swapchain::new(&instance_loader, &device_loader);
-        let mesh_shader_loader = MeshShader::new(&instance_loader, &device_loader);
-
-        let allocator = unsafe {
-            vk_mem_alloc::create_allocator(
-                &instance_loader,
-                physical_device,
-                &device_loader,
-                Some(&AllocatorCreateInfo {
-                    flags: AllocatorCreateFlags::BUFFER_DEVICE_ADDRESS,
-                    ..Default::default()
-                }),
-            )
-        }
-        .unwrap();
-
-        let direct_queue = unsafe { device_loader.get_device_queue(0, 0) };
-
-        let swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
-            .surface(surface)
-            .min_image_count(2)
-            .image_format(SWAPCHAIN_FORMAT)
-            .image_color_space(vk::ColorSpaceKHR::SRGB_NONLINEAR)
-            .image_extent(vk::Extent2D {
-                width: window.inner_size().width as _,
-                height: window.inner_size().height as _,
-            })
-            .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(vk::PresentModeKHR::FIFO);
-
-        let swapchain =
-            unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None) }.unwrap();
-        let swapchain_images = unsafe { swapchain_loader.get_swapchain_images(swapchain) }.unwrap();
-
-        let swapchain_image_views = swapchain_images
-            .iter()
-            .map(|image| {
-                let image_view_create_info = vk::ImageViewCreateInfo::default()
-                    .image(*image)
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .format(SWAPCHAIN_FORMAT)
-                    .components(Default::default())
-                    .subresource_range(
-                        vk::ImageSubresourceRange::default()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .layer_count(1)
-                            .level_count(1),
-                    );
-
-                unsafe { device_loader.create_image_view(&image_view_create_info, None) }
-            })
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
-
-        let (depth_image, depth_image_allocation, depth_image_view) = unsafe {
-            utils::create_depth_stencil_image(
-                &device_loader,
-                direct_queue,
-                allocator,
-                window.inner_size().width as _,
-                window.inner_size().height as _,
-                DEPTH_FORMAT,
-            )
+        let asset_workers = WorkerPool::new("asset", self.worker_config.asset_loading.clone())
+            .during("Starting the asset workers")?;
+        let shader_workers =
+            WorkerPool::new("shader", self.worker_config.shader_compilation.clone())
+                .during("Starting the shader workers")?;
+        let recording_workers =
+            WorkerPool::new("recording", self.worker_config.command_recording.clone())
+                .during("Starting the recording workers")?;
+
+        let device = RenderDevice::new(window, &self.render_config, &self.device_config)?;
+        let device_loader = &device.device_loader;
+
+        let swapchain = SwapchainBundle::new(
+            &device,
+            device.surface,
+            extent,
+            self.render_config.present_mode,
+            device.full_screen_exclusive.as_ref(),
+            None,
+        )?;
+        let stereo = self.render_config.stereo && device.multiview_mesh_shader_supported;
+        if self.render_config.stereo && !stereo {
+            println!("Mesh shaders can't render to multiple views, falling back to a single view");
         }
-        .unwrap();
+        //The eyes are rendered into single sampled layers
+        let samples = if stereo && self.render_config.msaa_samples != vk::SampleCountFlags::TYPE_1 {
+            println!("MSAA is not supported with stereo rendering");
+            vk::SampleCountFlags::TYPE_1
+        } else {
+            device.supported_sample_count(self.render_config.msaa_samples)
+        };
 
+        let descriptor_pool_sizes = [
+            vk::DescriptorPoolSize::default()
+                .ty(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(5 + MAX_REPLACED_DESCRIPTOR_SETS),
+            vk::DescriptorPoolSize::default()
+                .ty(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1),
+            vk::DescriptorPoolSize::default()
+                .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count((1 + MAX_REPLACED_DESCRIPTOR_SETS) * MAX_MASK_TEXTURES as u32),
+        ];
         let descriptor_pool = unsafe {
             utils::create_descriptor_pool(
-                &device_loader,
-                &[vk::DescriptorPoolSize::default()
-                    .ty(vk::DescriptorType::STORAGE_BUFFER)
-                    .descriptor_count(3)],
+                device_loader,
+                MAX_DESCRIPTOR_SETS,
+                &descriptor_pool_sizes,
             )
         }
-        .unwrap();
-
-        let globals_buffers = GlobalsBuffers::new(&device_loader, allocator, descriptor_pool);
+        .during("Creating the descriptor pool")?;
 
+        let globals_buffers = GlobalsBuffers::new(
+            device_loader,
+            device.allocator,
+            device
+                .physical_device_properties
+                .limits
+                .min_uniform_buffer_offset_alignment,
+            &device.sharing_family_indices(),
+        )
+        .during("Creating the globals buffers")?;
+
+        let instance_animate_pass =
+            InstanceAnimatePass::new(device_loader, device.pipeline_cache, &globals_buffers)
+                .during("Creating the instance animate pass")?;
+        let overdraw_pass = OverdrawPass::new(
+            device_loader,
+            device.pipeline_cache,
+            device.direct_transfer_queue(),
+            device.allocator,
+            descriptor_pool,
+            extent.width,
+            extent.height,
+        )
+        .during("Creating the overdraw pass")?;
         let geometry_pass = GeometryPass::new(
-            &device_loader,
+            device_loader,
+            device.pipeline_cache,
+            &overdraw_pass,
+            &device.mesh_shader_properties,
+            MultisampleState {
+                rasterization_samples: samples,
+                ..Default::default()
+            },
+            if stereo { stereo::VIEW_MASK } else { 0 },
+            &self.render_config.meshlet_config,
+            device.sample_rate_shading_supported,
+            device.fill_mode_non_solid_supported,
+            device.graphics_pipeline_library_supported,
+            device.primitive_shading_rate_supported,
+            device.non_uniform_indexing_supported,
+            device.task_shader_supported,
+            shader_workers,
+        )
+        .during("Creating the geometry pass")?;
+        let frustum_debug_pass =
+            FrustumDebugPass::new(device_loader, device.pipeline_cache, &globals_buffers)
+                .during("Creating the frustum debug pass")?;
+        let grass_pass = device
+            .task_shader_supported
+            .then(|| {
+                GrassPass::new(
+                    device_loader,
+                    device.pipeline_cache,
+                    device.direct_transfer_queue(),
+                    device.allocator,
+                    &globals_buffers,
+                )
+            })
+            .transpose()
+            .during("Creating the grass pass")?;
+        let particle_pass = ParticlePass::new(
+            device_loader,
+            device.pipeline_cache,
+            device.allocator,
             &globals_buffers,
-            &physical_device_mesh_shader_properties,
-        );
-        let instance_cull_pass =
-            InstanceCullPass::new(&device_loader, &globals_buffers, &geometry_pass);
-
-        let frames: Vec<_> = (0..frame::NUM_FRAMES)
-            .map(|_| ManuallyDrop::new(Frame::new(device_loader.clone())))
-            .collect();
-
-        let camera_rig = CameraRig::builder()
-            .with(Position::new(Vec3::Y))
-            .with(YawPitch::new())
-            .with(Smooth::new_position_rotation(1.0, 1.0))
-            .build();
-
-        let mesh_collection = ManuallyDrop::new(
-            unsafe {
-                MeshCollection::new(
-                    &device_loader,
-                    direct_queue,
-                    allocator,
-                    descriptor_pool,
-                    geometry_pass.descriptor_set_layout,
-                    [
-                        MeshSource::Builtin(
-                            vec![
-                                Vertex::new(
-                                    Vec3::new(0.0, 0.0, 0.0),
-                                    Vec2::new(0.0, 0.0),
-                                    Vec3::new(0.0, 1.0, 0.0),
-                                ),
-                                Vertex::new(
-                                    Vec3::new(1.0, 0.0, 0.0),
-                                    Vec2::new(1.0, 0.0),
-                                    Vec3::new(0.0, 1.0, 0.0),
-                                ),
-                                Vertex::new(
-                                    Vec3::new(1.0, 0.0, 1.0),
-                                    Vec2::new(1.0, 1.0),
-                                    Vec3::new(0.0, 1.0, 0.0),
-                                ),
-                                Vertex::new(
-                                    Vec3::new(0.0, 0.0, 1.0),
-                                    Vec2::new(0.0, 1.0),
-                                    Vec3::new(0.0, 1.0, 0.0),
-                                ),
-                            ],
-                            vec![0, 1, 3, 3, 1, 2],
-                        ),
-                        MeshSource::Path("dragon.obj"),
-                        MeshSource::Path("armadillo.obj"),
-                        MeshSource::Path("bunny.obj"),
-                    ],
+        )
+        .during("Creating the particle pass")?;
+        let light_cull_pass = LightCullPass::new(
+            device_loader,
+            device.pipeline_cache,
+            device.allocator,
+            &globals_buffers,
+        )
+        .during("Creating the light cull pass")?;
+        let taa_pass = TaaPass::new(
+            device_loader,
+            device.pipeline_cache,
+            device.allocator,
+            extent,
+        )
+        .during("Creating the TAA pass")?;
+        let picking_pass = PickingPass::new(device_loader, device.allocator, extent)
+            .during("Creating the picking pass")?;
+        let outline_pass = OutlinePass::new(device_loader, device.pipeline_cache)
+            .during("Creating the outline pass")?;
+        let eye_extent = self.render_config.eye_extent.unwrap_or(vk::Extent2D {
+            width: (extent.width / 2).max(1),
+            height: extent.height,
+        });
+        let stereo_pass = stereo
+            .then(|| {
+                StereoPass::new(
+                    device_loader,
+                    device.pipeline_cache,
+                    device.allocator,
+                    eye_extent,
                 )
-            }
-            .unwrap(),
-        );
+            })
+            .transpose()
+            .during("Creating the stereo pass")?;
+        let instance_cull_pass =
+            InstanceCullPass::new(device_loader, device.pipeline_cache, &globals_buffers)
+                .during("Creating the instance cull pass")?;
+        let skinning_pass =
+            SkinningPass::new(device_loader, device.pipeline_cache, device.allocator)
+                .during("Creating the skinning pass")?;
+
+        let deletion_queue = DeletionQueue::new(device_loader.clone());
+        //Headless runs always render with the shaders they started with, just like builds with embedded shaders
+        let shader_watcher = if swapchain.swapchain.is_some() && !cfg!(feature = "embedded-shaders")
+        {
+            ShaderWatcher::new("shaders")
+                .map_err(|error| eprintln!("Warning: Shader hot reloading is disabled: {error}"))
+                .ok()
+        } else {
+            None
+        };
 
-        let query_pool_timestamp = ManuallyDrop::new(
-            unsafe { QueryPool::new(&device_loader, 8, vk::QueryType::TIMESTAMP) }.unwrap(),
-        );
-        let query_pool_pipeline_statistics = ManuallyDrop::new(
-            unsafe { QueryPool::new(&device_loader, 8, vk::QueryType::PIPELINE_STATISTICS) }
-                .unwrap(),
-        );
+        let scene_resources = unsafe {
+            SceneResources::new(
+                &device,
+                descriptor_pool,
+                instance_cull_pass.mesh_descriptor_set_layout,
+                geometry_pass.mask_descriptor_set_layout,
+                instance_animate_pass.descriptor_set_layout,
+                scene,
+                self.render_config.meshlet_config,
+                asset_workers,
+            )
+        }
+        .during("Loading the scene")?;
+
+        let frame_resources = FrameResources::new(
+            &device,
+            globals_buffers,
+            extent,
+            scene_resources.num_instances(),
+            num_recording_threads(&recording_workers),
+            samples,
+        )?;
+
+        let workgroup_size = device
+            .mesh_shader_properties
+            .max_preferred_mesh_work_group_invocations;
+
+        Ok(RenderCtx {
+            device: ManuallyDrop::new(device),
+            swapchain: ManuallyDrop::new(swapchain),
+            frame_resources: ManuallyDrop::new(frame_resources),
+            scene_resources: ManuallyDrop::new(scene_resources),
 
-        Self {
-            entry_loader,
+            descriptor_pool,
 
-            instance_loader,
-            surface_loader,
+            instance_animate_pass: ManuallyDrop::new(instance_animate_pass),
+            instance_cull_pass: ManuallyDrop::new(instance_cull_pass),
+            skinning_pass: ManuallyDrop::new(skinning_pass),
+            overdraw_pass: ManuallyDrop::new(overdraw_pass),
+            geometry_pass: ManuallyDrop::new(geometry_pass),
+            frustum_debug_pass: ManuallyDrop::new(frustum_debug_pass),
+            grass_pass,
+            particle_pass: ManuallyDrop::new(particle_pass),
+            light_cull_pass: ManuallyDrop::new(light_cull_pass),
+            taa_pass: ManuallyDrop::new(taa_pass),
+            picking_pass: ManuallyDrop::new(picking_pass),
+            outline_pass: ManuallyDrop::new(outline_pass),
+            stereo_pass,
+            deletion_queue: ManuallyDrop::new(deletion_queue),
+            shader_watcher,
+            recording_workers,
+
+            camera_rig: default_camera_rig(),
+            camera_roll: 0.0,
+            field_of_view: FIELD_OF_VIEW,
+            render_settings: RenderSettings::default(),
+            worker_config: self.worker_config,
+            render_config: self.render_config,
+            device_config: self.device_config,
+
+            pass_timings: PassTimings::default(),
+            pipeline_statistics: HashMap::new(),
+            culling_stats: CullingStats::default(),
+            capture_requested: false,
+            captured_frame: None,
+            fixed_time: None,
+            camera_override: None,
+            eye_override: None,
+            stereo_target: None,
+            secondary_view: None,
+            pick_requested: None,
+            selected_instance: None,
+
+            workgroup_size,
+            start_time: Instant::now(),
+        })
+    }
+}
 
-            surface,
+impl RenderCtx {
+    #[inline]
+    pub fn builder(target: RenderTarget) -> RenderCtxBuilder {
+        RenderCtxBuilder {
+            target,
+            worker_config: WorkerConfig::default(),
+            render_config: RenderConfig::default(),
+            device_config: DeviceConfig::default(),
+        }
+    }
 
-            device_loader,
-            swapchain_loader,
-            mesh_shader_loader,
+    #[inline]
+    pub fn new(
+        target: RenderTarget,
+        scene: &Scene,
+        worker_config: &WorkerConfig,
+        render_config: &RenderConfig,
+    ) -> Result<Self, RenderError> {
+        Self::builder(target)
+            .worker_config(worker_config.clone())
+            .render_config(render_config.clone())
+            .build(scene)
+    }
 
-            allocator,
+    #[inline]
+    pub fn camera(&self) -> Camera {
+        self.camera_override.unwrap_or_else(|| {
+            let final_transform = &self.camera_rig.final_transform;
+            let forward = final_transform.forward();
+            Camera {
+                position: final_transform.position,
+                forward,
+                up: Quat::from_axis_angle(forward, self.camera_roll) * final_transform.up(),
+                field_of_view: self.field_of_view,
+            }
+        })
+    }
 
-            direct_queue,
+    //Seconds the instances have been animated for
+    #[inline]
+    pub fn time(&self) -> f32 {
+        self.fixed_time
+            .unwrap_or_else(|| self.start_time.elapsed().as_secs_f32())
+    }
 
-            swapchain,
-            swapchain_images,
-            swapchain_image_views,
-            depth_image,
-            depth_image_view,
-            depth_image_allocation,
+    //Secondary windows don't show the overdraw, its images have the size of the main window. The fragment shader
+    //counts with image atomics
+    #[inline]
+    pub fn overdraw_enabled(&self) -> bool {
+        self.overdraw_pass.enabled
+            && self.secondary_view.is_none()
+            && self.device.fragment_stores_and_atomics_supported
+    }
 
-            descriptor_pool,
+    //Creates everything again on a new device after the device was lost, the scene is uploaded again while the camera
+    //and the settings are kept
+    pub fn recreate(mut self, target: RenderTarget) -> Result<Self, RenderError> {
+        let scene = Scene {
+            meshes: self.scene_resources.mesh_sources.clone(),
+            instances: self
+                .scene_resources
+                .instance_buffers
+                .instance_animations
+                .clone(),
+            time: 0.0,
+        };
+        let builder = RenderCtxBuilder {
+            target,
+            worker_config: self.worker_config.clone(),
+            render_config: self.render_config.clone(),
+            device_config: self.device_config.clone(),
+        };
 
-            globals_buffers: ManuallyDrop::new(globals_buffers),
+        let camera_rig = mem::replace(&mut self.camera_rig, default_camera_rig());
+        let camera_roll = self.camera_roll;
+        let field_of_view = self.field_of_view;
+        let meshlet_config = self.scene_resources.meshlet_config;
+        let render_settings = self.render_settings;
+        let multisample_state = self.geometry_pass.multisample_state;
+        let overdraw_enabled = self.overdraw_pass.enabled;
+        let fixed_time = self.fixed_time;
+        let camera_override = self.camera_override;
+        let selected_instance = self.selected_instance;
+
+        //The window can only have one swapchain, so the old one has to be destroyed first
+        drop(self);
+
+        let mut ctx = builder.build(&scene)?;
+        ctx.camera_rig = camera_rig;
+        ctx.camera_roll = camera_roll;
+        ctx.field_of_view = field_of_view;
+        ctx.set_meshlet_config(meshlet_config)?;
+        ctx.render_settings = render_settings;
+        ctx.geometry_pass.set_multisample_state(multisample_state);
+        ctx.overdraw_pass.enabled = overdraw_enabled;
+        ctx.fixed_time = fixed_time;
+        ctx.camera_override = camera_override;
+        ctx.selected_instance = selected_instance;
+
+        Ok(ctx)
+    }
 
-            instance_cull_pass: ManuallyDrop::new(instance_cull_pass),
-            geometry_pass: ManuallyDrop::new(geometry_pass),
+    //Creates the swapchain again with the new size of the window, together with all images sized like it. The eyes of
+    //stereo rendering keep their size, they are filtered into the swapchain image anyway
+    pub fn resize(&mut self, extent: vk::Extent2D) -> Result<(), RenderError> {
+        unsafe {
+            self.device
+                .device_loader
+                .device_wait_idle()
+                .during("Waiting for the device")?;
+
+            //The new swapchain acquires it again
+            self.swapchain.release_full_screen_exclusive();
+            *self.swapchain = SwapchainBundle::new(
+                &self.device,
+                self.device.surface,
+                extent,
+                self.render_config.present_mode,
+                self.device.full_screen_exclusive.as_ref(),
+                self.swapchain.swapchain,
+            )?;
+            self.frame_resources.resize(&self.device, extent)?;
+            self.overdraw_pass
+                .resize(
+                    self.device.direct_transfer_queue(),
+                    extent.width,
+                    extent.height,
+                )
+                .during("Resizing the overdraw image")?;
+            self.taa_pass
+                .resize(extent)
+                .during("Resizing the TAA images")?;
+            self.picking_pass
+                .resize(extent)
+                .during("Resizing the ID image")?;
+        }
 
-            frames,
-            camera_rig,
-            mesh_collection,
+        Ok(())
+    }
 
-            query_pool_timestamp,
-            query_pool_pipeline_statistics,
+    //Replaces the meshes and instances, all GPU buffers depending on them are recreated
+    pub fn set_scene(&mut self, scene: &Scene) -> Result<(), RenderError> {
+        unsafe {
+            self.device
+                .device_loader
+                .device_wait_idle()
+                .during("Waiting for the device")?;
+            //Frees the replaced descriptor sets of the old collection, so the new one fits into the pool
+            self.deletion_queue.clear();
+
+            self.scene_resources
+                .set_scene(&self.device, scene)
+                .during("Loading the scene")?;
+            //The index might belong to another instance or none at all now
+            self.selected_instance = None;
+
+            //The levels of the instances are written to the frames
+            self.frame_resources
+                .recreate_frames(&self.device, scene.instances.len())
+        }
+    }
 
-            workgroup_size: physical_device_mesh_shader_properties
-                .max_preferred_mesh_work_group_invocations,
+    //The meshlet size is kept, the geometry shaders are compiled for it
+    pub fn set_meshlet_config(&mut self, meshlet_config: MeshletConfig) -> Result<(), RenderError> {
+        let meshlet_config = MeshletConfig {
+            max_vertices: self.geometry_pass.max_meshlet_vertices,
+            max_triangles: self.geometry_pass.max_meshlet_triangles,
+            ..meshlet_config
+        };
+        if self.scene_resources.meshlet_config == meshlet_config {
+            return Ok(())
+        }
+
+        unsafe {
+            self.device
+                .device_loader
+                .device_wait_idle()
+                .during("Waiting for the device")?;
+            self.deletion_queue.clear();
+
+            self.scene_resources
+                .set_meshlet_config(&self.device, meshlet_config)
+                .during("Rebuilding the meshes")
         }
     }
 }
@@ -355,40 +660,35 @@ impl RenderCtx {
 impl Drop for RenderCtx {
     fn drop(&mut self) {
         unsafe {
-            self.device_loader.device_wait_idle().unwrap();
-
-            ManuallyDrop::drop(&mut self.query_pool_pipeline_statistics);
-            ManuallyDrop::drop(&mut self.query_pool_timestamp);
+            //Nothing runs on a lost device anymore, so everything can be destroyed right away
+            match self.device.device_loader.device_wait_idle() {
+                Ok(()) | Err(vk::Result::ERROR_DEVICE_LOST) => {}
+                Err(error) => panic!("Failed to wait for the device: {error}"),
+            }
 
-            ManuallyDrop::drop(&mut self.mesh_collection);
-            self.frames
-                .iter_mut()
-                .for_each(|frame| ManuallyDrop::drop(frame));
+            ManuallyDrop::drop(&mut self.scene_resources);
+            ManuallyDrop::drop(&mut self.frame_resources);
+            self.stereo_pass = None;
+            ManuallyDrop::drop(&mut self.outline_pass);
+            ManuallyDrop::drop(&mut self.picking_pass);
+            ManuallyDrop::drop(&mut self.taa_pass);
+            ManuallyDrop::drop(&mut self.frustum_debug_pass);
+            self.grass_pass = None;
+            ManuallyDrop::drop(&mut self.particle_pass);
+            ManuallyDrop::drop(&mut self.light_cull_pass);
+            ManuallyDrop::drop(&mut self.skinning_pass);
+            ManuallyDrop::drop(&mut self.instance_cull_pass);
             ManuallyDrop::drop(&mut self.geometry_pass);
+            ManuallyDrop::drop(&mut self.deletion_queue);
+            ManuallyDrop::drop(&mut self.overdraw_pass);
+            ManuallyDrop::drop(&mut self.instance_animate_pass);
 
-            ManuallyDrop::drop(&mut self.globals_buffers);
-
-            self.device_loader
+            self.device
+                .device_loader
                 .destroy_descriptor_pool(self.descriptor_pool, None);
 
-            utils::destroy_depth_stencil_image(
-                &self.device_loader,
-                self.allocator,
-                self.depth_image,
-                self.depth_image_allocation,
-                self.depth_image_view,
-            );
-            self.swapchain_image_views
-                .iter()
-                .for_each(|image_view| self.device_loader.destroy_image_view(*image_view, None));
-            self.swapchain_loader
-                .destroy_swapchain(self.swapchain, None);
-
-            vk_mem_alloc::destroy_allocator(self.allocator);
-
-            self.device_loader.destroy_device(None);
-            self.surface_loader.destroy_surface(self.surface, None);
-            self.instance_loader.destroy_instance(None);
+            ManuallyDrop::drop(&mut self.swapchain);
+            ManuallyDrop::drop(&mut self.device);
         }
     }
 }
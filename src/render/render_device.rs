@@ -0,0 +1,810 @@
+use std::{ffi::CStr, mem, slice, sync::Arc};
+
+use ash::{
+    extensions::{
+        ext::MeshShader,
+        khr::{PushDescriptor, Surface, Swapchain},
+    },
+    vk, Device, Entry, Instance,
+};
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use vk_mem_alloc::{AllocatorCreateFlags, AllocatorCreateInfo};
+use winit::window::Window;
+
+use crate::render::{
+    device_fault::DeviceFault,
+    device_info,
+    device_info::DeviceInfo,
+    error::{ErrorContext, RenderError},
+    full_screen_exclusive::{self, FullScreenExclusive},
+    memory_budget::{self, AllocatorStatistics, HeapBudget},
+    render_config::{GpuSelector, RenderConfig},
+    staging_belt::TransferQueue,
+    utils::pipeline_cache,
+};
+
+//What is enabled in addition to what the example needs, see RenderCtxBuilder
+#[derive(Clone, Default)]
+pub struct DeviceConfig {
+    pub instance_layers: Vec<&'static CStr>,
+    pub instance_extensions: Vec<&'static CStr>,
+    //Devices which don't support all of them are rejected
+    pub device_extensions: Vec<&'static CStr>,
+    //Applied to the core features the example enables, they aren't checked for support
+    pub features: Option<fn(vk::PhysicalDeviceFeatures) -> vk::PhysicalDeviceFeatures>,
+    //Replaces the selection by RenderConfig::gpu, e.g. when an XR runtime dictates the device
+    pub physical_device: Option<PhysicalDeviceSelector>,
+}
+
+pub type PhysicalDeviceSelector =
+    Arc<dyn Fn(&Instance) -> anyhow::Result<vk::PhysicalDevice> + Send + Sync>;
+
+//The instance, the device and everything which lives as long as them
+pub struct RenderDevice {
+    pub entry_loader: Entry,
+
+    pub instance_loader: Instance,
+    pub surface_loader: Surface,
+
+    pub surface: Option<vk::SurfaceKHR>,
+
+    pub physical_device: vk::PhysicalDevice,
+    pub physical_device_properties: vk::PhysicalDeviceProperties,
+    pub mesh_shader_properties: vk::PhysicalDeviceMeshShaderPropertiesEXT,
+    pub device_info: DeviceInfo,
+    pub timestamp_period: f32,
+
+    pub sample_rate_shading_supported: bool,
+    pub fill_mode_non_solid_supported: bool,
+    //The overdraw heatmap counts the fragments with image atomics, it can't be shown without them
+    pub fragment_stores_and_atomics_supported: bool,
+    //The alpha tested materials index the mask textures per primitive, they are drawn opaque without it
+    pub non_uniform_indexing_supported: bool,
+    //Without task shaders the meshlet groups aren't culled, a mesh shader workgroup is launched for every meshlet
+    //and there is no grass
+    pub task_shader_supported: bool,
+    //The pipeline statistics only count the task and mesh shader invocations with it
+    pub mesh_shader_queries_supported: bool,
+    //Without graphics pipeline libraries every geometry permutation is created as a whole pipeline
+    pub graphics_pipeline_library_supported: bool,
+    //Without VK_EXT_memory_budget only the heap sizes are known
+    pub memory_budget_supported: bool,
+    //The mesh shader can only pick the shading rate of its primitives with VK_KHR_fragment_shading_rate
+    pub primitive_shading_rate_supported: bool,
+    //Multiview itself is always supported, mesh shaders only optionally render to several views
+    pub multiview_mesh_shader_supported: bool,
+
+    pub device_loader: Arc<Device>,
+    pub swapchain_loader: Swapchain,
+    pub mesh_shader_loader: MeshShader,
+    pub push_descriptor_loader: PushDescriptor,
+    //None without VK_EXT_conditional_rendering, the draws of culled instances are recorded anyway then
+    pub conditional_rendering_loader: Option<vk::ExtConditionalRenderingFn>,
+    //None if the device can't tell why it was lost
+    pub device_fault: Option<DeviceFault>,
+    //None unless exclusive fullscreen was requested on Windows and the device supports it
+    pub full_screen_exclusive: Option<FullScreenExclusive>,
+
+    pub allocator: vk_mem_alloc::Allocator,
+    //Every pipeline is created with it, it's saved to the cache directory when the device is dropped
+    pub pipeline_cache: vk::PipelineCache,
+
+    pub direct_queue: vk::Queue,
+    pub direct_queue_family_index: u32,
+    //The swapchain images are presented on it, it's the direct queue unless that one's family can't present
+    pub present_queue: vk::Queue,
+    pub present_queue_family_index: u32,
+    //Meshes are streamed in on it, it's the direct queue if the device has no dedicated transfer queue
+    pub transfer_queue: TransferQueue,
+    //Culling runs on it alongside the rendering of the previous frame, it's the direct queue if there is no async compute queue
+    pub compute_queue: vk::Queue,
+    pub compute_queue_family_index: u32,
+}
+
+fn select_physical_device(
+    instance_loader: &Instance,
+    gpu: Option<&GpuSelector>,
+) -> Result<vk::PhysicalDevice, RenderError> {
+    let physical_devices = unsafe { instance_loader.enumerate_physical_devices() }
+        .during("Enumerating the physical devices")?;
+    let names: Vec<_> = physical_devices
+        .iter()
+        .map(|physical_device| unsafe {
+            let properties = instance_loader.get_physical_device_properties(*physical_device);
+            device_info::string_from_c_chars(&properties.device_name)
+        })
+        .collect();
+
+    let idx = match gpu {
+        None => Some(0).filter(|_| !physical_devices.is_empty()),
+        Some(GpuSelector::Index(idx)) => Some(*idx).filter(|idx| *idx < physical_devices.len()),
+        Some(GpuSelector::Name(name)) => {
+            let name = name.to_lowercase();
+            names
+                .iter()
+                .position(|device_name| device_name.to_lowercase().contains(&name))
+        }
+    };
+
+    match idx {
+        Some(idx) => Ok(physical_devices[idx]),
+        None => {
+            Err(RenderError::NoMatchingGpu {
+                selector: match gpu {
+                    None => "any GPU".to_owned(),
+                    Some(GpuSelector::Index(idx)) => format!("index {idx}"),
+                    Some(GpuSelector::Name(name)) => format!("\"{name}\""),
+                },
+                available: names,
+            })
+        }
+    }
+}
+
+impl RenderDevice {
+    //A surface is only created if there is a window
+    pub fn new(
+        window: Option<&Window>,
+        render_config: &RenderConfig,
+        device_config: &DeviceConfig,
+    ) -> Result<Self, RenderError> {
+        let entry_loader = unsafe { Entry::load() }?;
+
+        let application_info = vk::ApplicationInfo::default().api_version(vk::API_VERSION_1_3);
+
+        let mut instance_layers: Vec<_> = device_config
+            .instance_layers
+            .iter()
+            .map(|layer| layer.as_ptr())
+            .collect();
+        if render_config.validation {
+            instance_layers.push(b"VK_LAYER_KHRONOS_validation\0".as_ptr().cast());
+        }
+
+        let mut instance_extensions: Vec<_> = device_config
+            .instance_extensions
+            .iter()
+            .map(|extension| extension.as_ptr())
+            .collect();
+        if let Some(window) = window {
+            ash_window::enumerate_required_extensions(window.raw_display_handle())
+                .during("Querying the surface extensions")?
+                .iter()
+                .for_each(|e| instance_extensions.push(*e));
+        }
+
+        //Exclusive fullscreen is acquired for the monitor the window is on, the surface support is queried with
+        //VK_KHR_get_surface_capabilities2
+        let full_screen_exclusive_monitor = window
+            .filter(|_| render_config.exclusive_fullscreen)
+            .and_then(full_screen_exclusive::window_monitor);
+        let surface_capabilities_2_supported = full_screen_exclusive_monitor.is_some()
+            && unsafe { entry_loader.enumerate_instance_extension_properties(None) }
+                .during("Enumerating the instance extensions")?
+                .iter()
+                .any(|properties| {
+                    let name = unsafe { CStr::from_ptr(properties.extension_name.as_ptr()) };
+                    name == vk::KhrGetSurfaceCapabilities2Fn::NAME
+                });
+        if surface_capabilities_2_supported {
+            instance_extensions.push(vk::KhrGetSurfaceCapabilities2Fn::NAME.as_ptr());
+        }
+
+        let instance_create_info = vk::InstanceCreateInfo::default()
+            .enabled_layer_names(&instance_layers)
+            .enabled_extension_names(&instance_extensions)
+            .application_info(&application_info);
+
+        let instance_loader = unsafe { entry_loader.create_instance(&instance_create_info, None) }
+            .during("Creating the instance")?;
+        let surface_loader = Surface::new(&entry_loader, &instance_loader);
+
+        let surface = window
+            .map(|window| {
+                unsafe {
+                    ash_window::create_surface(
+                        &entry_loader,
+                        &instance_loader,
+                        window.raw_display_handle(),
+                        window.raw_window_handle(),
+                        None,
+                    )
+                }
+                .during("Creating the surface")
+            })
+            .transpose()?;
+
+        let physical_device = match &device_config.physical_device {
+            Some(select) => select(&instance_loader).during("Selecting the physical device")?,
+            None => select_physical_device(&instance_loader, render_config.gpu.as_ref())?,
+        };
+
+        //The properties of an extension can only be queried if the device supports it
+        let device_name = unsafe {
+            device_info::string_from_c_chars(
+                &instance_loader
+                    .get_physical_device_properties(physical_device)
+                    .device_name,
+            )
+        };
+        let supported_device_extensions =
+            unsafe { instance_loader.enumerate_device_extension_properties(physical_device) }
+                .during("Enumerating the device extensions")?;
+        let device_extension_supported = |name: &CStr| {
+            supported_device_extensions.iter().any(
+                |properties| unsafe { CStr::from_ptr(properties.extension_name.as_ptr()) } == name,
+            )
+        };
+
+        let mut required_device_extensions = vec![MeshShader::NAME, PushDescriptor::NAME];
+        if surface.is_some() {
+            required_device_extensions.push(Swapchain::NAME);
+        }
+        required_device_extensions.extend_from_slice(&device_config.device_extensions);
+        if let Some(extension) = required_device_extensions
+            .iter()
+            .find(|extension| !device_extension_supported(extension))
+        {
+            return Err(RenderError::MissingExtension {
+                device: device_name,
+                extension: extension.to_string_lossy().into_owned(),
+            })
+        }
+
+        let mut physical_device_vulkan_12_properties =
+            vk::PhysicalDeviceVulkan12Properties::default();
+        let mut physical_device_vulkan_13_properties =
+            vk::PhysicalDeviceVulkan13Properties::default();
+        let mut physical_device_mesh_shader_properties =
+            vk::PhysicalDeviceMeshShaderPropertiesEXT::default();
+
+        let mut physical_device_properties = vk::PhysicalDeviceProperties2::default()
+            .push_next(&mut physical_device_vulkan_12_properties)
+            .push_next(&mut physical_device_vulkan_13_properties)
+            .push_next(&mut physical_device_mesh_shader_properties);
+
+        unsafe {
+            instance_loader
+                .get_physical_device_properties2(physical_device, &mut physical_device_properties)
+        };
+        let physical_device_properties = physical_device_properties.properties;
+        let timestamp_period = physical_device_properties.limits.timestamp_period;
+
+        let device_info = DeviceInfo::new(
+            &physical_device_properties,
+            &physical_device_vulkan_12_properties,
+            &physical_device_mesh_shader_properties,
+            &render_config.meshlet_config,
+        );
+        println!("{device_info}");
+
+        let mut supported_vulkan_11_features = vk::PhysicalDeviceVulkan11Features::default();
+        let mut supported_vulkan_12_features = vk::PhysicalDeviceVulkan12Features::default();
+        let mut supported_vulkan_13_features = vk::PhysicalDeviceVulkan13Features::default();
+        let mut supported_mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+        let mut supported_graphics_pipeline_library_features =
+            vk::PhysicalDeviceGraphicsPipelineLibraryFeaturesEXT::default();
+        let mut supported_fault_features = vk::PhysicalDeviceFaultFeaturesEXT::default();
+        let mut supported_conditional_rendering_features =
+            vk::PhysicalDeviceConditionalRenderingFeaturesEXT::default();
+        let mut supported_fragment_shading_rate_features =
+            vk::PhysicalDeviceFragmentShadingRateFeaturesKHR::default();
+        let mut supported_physical_device_features = vk::PhysicalDeviceFeatures2::default()
+            .push_next(&mut supported_vulkan_11_features)
+            .push_next(&mut supported_vulkan_12_features)
+            .push_next(&mut supported_vulkan_13_features)
+            .push_next(&mut supported_mesh_shader_features)
+            .push_next(&mut supported_graphics_pipeline_library_features)
+            .push_next(&mut supported_fault_features)
+            .push_next(&mut supported_conditional_rendering_features)
+            .push_next(&mut supported_fragment_shading_rate_features);
+        unsafe {
+            instance_loader.get_physical_device_features2(
+                physical_device,
+                &mut supported_physical_device_features,
+            )
+        };
+        let supported_physical_device_features = supported_physical_device_features.features;
+
+        let required_features = [
+            (
+                "pipelineStatisticsQuery",
+                supported_physical_device_features.pipeline_statistics_query,
+            ),
+            (
+                "shaderInt64",
+                supported_physical_device_features.shader_int64,
+            ),
+            ("multiview", supported_vulkan_11_features.multiview),
+            (
+                "bufferDeviceAddress",
+                supported_vulkan_12_features.buffer_device_address,
+            ),
+            (
+                "timelineSemaphore",
+                supported_vulkan_12_features.timeline_semaphore,
+            ),
+            (
+                "dynamicRendering",
+                supported_vulkan_13_features.dynamic_rendering,
+            ),
+            (
+                "synchronization2",
+                supported_vulkan_13_features.synchronization2,
+            ),
+            ("maintenance4", supported_vulkan_13_features.maintenance4),
+            ("meshShader", supported_mesh_shader_features.mesh_shader),
+        ];
+        if let Some((feature, _)) = required_features
+            .iter()
+            .find(|(_, supported)| *supported != vk::TRUE)
+        {
+            return Err(RenderError::MissingFeature {
+                device: device_name,
+                feature: *feature,
+            })
+        }
+
+        let sample_rate_shading_supported =
+            supported_physical_device_features.sample_rate_shading == vk::TRUE;
+        let fill_mode_non_solid_supported =
+            supported_physical_device_features.fill_mode_non_solid == vk::TRUE;
+        let fragment_stores_and_atomics_supported =
+            supported_physical_device_features.fragment_stores_and_atomics == vk::TRUE;
+        let non_uniform_indexing_supported = supported_vulkan_12_features
+            .shader_sampled_image_array_non_uniform_indexing
+            == vk::TRUE;
+        let task_shader_supported = supported_mesh_shader_features.task_shader == vk::TRUE;
+        let mesh_shader_queries_supported =
+            supported_mesh_shader_features.mesh_shader_queries == vk::TRUE;
+
+        let graphics_pipeline_library_supported =
+            device_extension_supported(vk::KhrPipelineLibraryFn::NAME)
+                && device_extension_supported(vk::ExtGraphicsPipelineLibraryFn::NAME)
+                && supported_graphics_pipeline_library_features.graphics_pipeline_library
+                    == vk::TRUE;
+        let memory_budget_supported = device_extension_supported(vk::ExtMemoryBudgetFn::NAME);
+        let device_fault_supported = device_extension_supported(vk::ExtDeviceFaultFn::NAME)
+            && supported_fault_features.device_fault == vk::TRUE;
+        let device_fault_vendor_binary_supported = device_fault_supported
+            && supported_fault_features.device_fault_vendor_binary == vk::TRUE;
+        let conditional_rendering_supported =
+            device_extension_supported(vk::ExtConditionalRenderingFn::NAME)
+                && supported_conditional_rendering_features.conditional_rendering == vk::TRUE;
+        let multiview_mesh_shader_supported =
+            supported_mesh_shader_features.multiview_mesh_shader == vk::TRUE;
+        let primitive_shading_rate_supported =
+            device_extension_supported(vk::KhrFragmentShadingRateFn::NAME)
+                && supported_fragment_shading_rate_features.pipeline_fragment_shading_rate
+                    == vk::TRUE
+                && supported_fragment_shading_rate_features.primitive_fragment_shading_rate
+                    == vk::TRUE
+                && supported_mesh_shader_features.primitive_fragment_shading_rate_mesh_shader
+                    == vk::TRUE;
+        let full_screen_exclusive_supported = surface_capabilities_2_supported
+            && device_extension_supported(vk::ExtFullScreenExclusiveFn::NAME);
+
+        let queue_family_properties =
+            unsafe { instance_loader.get_physical_device_queue_family_properties(physical_device) };
+        let present_supported = (0..queue_family_properties.len() as u32)
+            .map(|queue_family_index| {
+                match surface {
+                    Some(surface) => {
+                        unsafe {
+                            surface_loader.get_physical_device_surface_support(
+                                physical_device,
+                                queue_family_index,
+                                surface,
+                            )
+                        }
+                        .during("Querying the surface support")
+                    }
+                    None => Ok(true),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        //The direct queue renders everything, a family which can also present to the surface is preferred
+        let is_direct_family = |properties: &vk::QueueFamilyProperties| {
+            properties.queue_count > 0
+                && properties
+                    .queue_flags
+                    .contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+        };
+        let direct_queue_family_index = queue_family_properties
+            .iter()
+            .enumerate()
+            .position(|(idx, properties)| is_direct_family(properties) && present_supported[idx])
+            .or_else(|| queue_family_properties.iter().position(is_direct_family))
+            .ok_or_else(|| {
+                RenderError::MissingFeature {
+                    device: device_name.clone(),
+                    feature: "a graphics and compute queue",
+                }
+            })? as u32;
+
+        //Only differs from the direct family if that one can't present
+        let present_queue_family_index = if present_supported[direct_queue_family_index as usize] {
+            direct_queue_family_index
+        } else {
+            queue_family_properties
+                .iter()
+                .enumerate()
+                .position(|(idx, properties)| properties.queue_count > 0 && present_supported[idx])
+                .ok_or_else(|| {
+                    RenderError::MissingFeature {
+                        device: device_name.clone(),
+                        feature: "presenting to the surface",
+                    }
+                })? as u32
+        };
+
+        //Families which can only transfer are usually backed by the copy engines, which run alongside the graphics work
+        let transfer_queue_family_index = queue_family_properties
+            .iter()
+            .position(|properties| {
+                properties.queue_count > 0
+                    && properties.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !properties
+                        .queue_flags
+                        .intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            })
+            .map(|idx| idx as u32);
+
+        let async_compute_queue_family_index = queue_family_properties
+            .iter()
+            .position(|properties| {
+                properties.queue_count > 0
+                    && properties.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                    && !properties.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .map(|idx| idx as u32);
+
+        //The present family can be one of the others, every family gets a single queue
+        let mut queue_family_indices = vec![direct_queue_family_index];
+        for queue_family_index in [
+            Some(present_queue_family_index),
+            transfer_queue_family_index,
+            async_compute_queue_family_index,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !queue_family_indices.contains(&queue_family_index) {
+                queue_family_indices.push(queue_family_index);
+            }
+        }
+
+        let queue_priority = 1.0;
+        let device_queue_create_infos: Vec<_> = queue_family_indices
+            .iter()
+            .map(|queue_family_index| {
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(*queue_family_index)
+                    .queue_priorities(slice::from_ref(&queue_priority))
+            })
+            .collect();
+
+        let mut device_extensions: Vec<_> = required_device_extensions
+            .iter()
+            .map(|extension| extension.as_ptr())
+            .collect();
+        if graphics_pipeline_library_supported {
+            device_extensions.push(vk::KhrPipelineLibraryFn::NAME.as_ptr());
+            device_extensions.push(vk::ExtGraphicsPipelineLibraryFn::NAME.as_ptr());
+        }
+        if device_fault_supported {
+            device_extensions.push(vk::ExtDeviceFaultFn::NAME.as_ptr());
+        }
+        if memory_budget_supported {
+            device_extensions.push(vk::ExtMemoryBudgetFn::NAME.as_ptr());
+        }
+        if conditional_rendering_supported {
+            device_extensions.push(vk::ExtConditionalRenderingFn::NAME.as_ptr());
+        }
+        if primitive_shading_rate_supported {
+            device_extensions.push(vk::KhrFragmentShadingRateFn::NAME.as_ptr());
+        }
+        if full_screen_exclusive_supported {
+            device_extensions.push(vk::ExtFullScreenExclusiveFn::NAME.as_ptr());
+        }
+
+        let mut physical_device_features = vk::PhysicalDeviceFeatures::default()
+            .pipeline_statistics_query(true)
+            .fragment_stores_and_atomics(fragment_stores_and_atomics_supported)
+            .sample_rate_shading(sample_rate_shading_supported)
+            .fill_mode_non_solid(fill_mode_non_solid_supported)
+            .shader_int64(true);
+        if let Some(features) = device_config.features {
+            physical_device_features = features(physical_device_features);
+        }
+
+        //The geometry shaders read the view index even when they render a single view
+        let mut physical_device_vulkan_11_features =
+            vk::PhysicalDeviceVulkan11Features::default().multiview(true);
+        //The mask textures of the alpha tested materials are indexed per primitive if it is supported
+        let mut physical_device_vulkan_12_features = vk::PhysicalDeviceVulkan12Features::default()
+            .buffer_device_address(true)
+            .timeline_semaphore(true)
+            .shader_sampled_image_array_non_uniform_indexing(non_uniform_indexing_supported);
+        let mut physical_device_vulkan_13_features = vk::PhysicalDeviceVulkan13Features::default()
+            .dynamic_rendering(true)
+            .synchronization2(true)
+            .maintenance4(true);
+        let mut physical_device_mesh_shader_features =
+            vk::PhysicalDeviceMeshShaderFeaturesEXT::default()
+                .task_shader(task_shader_supported)
+                .mesh_shader(true)
+                .mesh_shader_queries(mesh_shader_queries_supported)
+                .multiview_mesh_shader(multiview_mesh_shader_supported)
+                .primitive_fragment_shading_rate_mesh_shader(primitive_shading_rate_supported);
+
+        let mut physical_device_graphics_pipeline_library_features =
+            vk::PhysicalDeviceGraphicsPipelineLibraryFeaturesEXT::default()
+                .graphics_pipeline_library(true);
+        let mut physical_device_fault_features = vk::PhysicalDeviceFaultFeaturesEXT::default()
+            .device_fault(true)
+            .device_fault_vendor_binary(device_fault_vendor_binary_supported);
+        let mut physical_device_conditional_rendering_features =
+            vk::PhysicalDeviceConditionalRenderingFeaturesEXT::default()
+                .conditional_rendering(true);
+        //Primitive shading rates are combined with the rate of the pipeline, which needs its own feature
+        let mut physical_device_fragment_shading_rate_features =
+            vk::PhysicalDeviceFragmentShadingRateFeaturesKHR::default()
+                .pipeline_fragment_shading_rate(true)
+                .primitive_fragment_shading_rate(true);
+
+        let mut physical_device_features = vk::PhysicalDeviceFeatures2::default()
+            .features(physical_device_features)
+            .push_next(&mut physical_device_vulkan_11_features)
+            .push_next(&mut physical_device_vulkan_12_features)
+            .push_next(&mut physical_device_vulkan_13_features)
+            .push_next(&mut physical_device_mesh_shader_features);
+        if graphics_pipeline_library_supported {
+            physical_device_features = physical_device_features
+                .push_next(&mut physical_device_graphics_pipeline_library_features);
+        }
+        if device_fault_supported {
+            physical_device_features =
+                physical_device_features.push_next(&mut physical_device_fault_features);
+        }
+        if conditional_rendering_supported {
+            physical_device_features = physical_device_features
+                .push_next(&mut physical_device_conditional_rendering_features);
+        }
+        if primitive_shading_rate_supported {
+            physical_device_features = physical_device_features
+                .push_next(&mut physical_device_fragment_shading_rate_features);
+        }
+
+        let device_create_info = vk::DeviceCreateInfo::default()
+            .push_next(&mut physical_device_features)
+            .queue_create_infos(&device_queue_create_infos)
+            .enabled_extension_names(&device_extensions);
+        let device_loader = Arc::new(
+            unsafe { instance_loader.create_device(physical_device, &device_create_info, None) }
+                .during("Creating the device")?,
+        );
+        let swapchain_loader = Swapchain::new(&instance_loader, &device_loader);
+        let mesh_shader_loader = MeshShader::new(&instance_loader, &device_loader);
+        let push_descriptor_loader = PushDescriptor::new(&instance_loader, &device_loader);
+        let conditional_rendering_loader = conditional_rendering_supported.then(|| {
+            vk::ExtConditionalRenderingFn::load(|name| unsafe {
+                mem::transmute(
+                    instance_loader.get_device_proc_addr(device_loader.handle(), name.as_ptr()),
+                )
+            })
+        });
+        let device_fault = device_fault_supported.then(|| {
+            DeviceFault::new(
+                &instance_loader,
+                &device_loader,
+                device_fault_vendor_binary_supported,
+            )
+        });
+
+        let full_screen_exclusive = full_screen_exclusive_monitor
+            .filter(|_| full_screen_exclusive_supported)
+            .map(|monitor| {
+                FullScreenExclusive::new(&entry_loader, &instance_loader, &device_loader, monitor)
+            });
+        if render_config.exclusive_fullscreen && window.is_some() && full_screen_exclusive.is_none()
+        {
+            println!("Exclusive fullscreen is not supported, presenting through the compositor");
+        }
+
+        let pipeline_cache =
+            unsafe { pipeline_cache::load(&device_loader, &physical_device_properties) }
+                .during("Creating the pipeline cache")?;
+
+        let allocator = unsafe {
+            vk_mem_alloc::create_allocator(
+                &instance_loader,
+                physical_device,
+                &device_loader,
+                Some(&AllocatorCreateInfo {
+                    flags: if memory_budget_supported {
+                        AllocatorCreateFlags::BUFFER_DEVICE_ADDRESS
+                            | AllocatorCreateFlags::EXT_MEMORY_BUDGET
+                    } else {
+                        AllocatorCreateFlags::BUFFER_DEVICE_ADDRESS
+                    },
+                    ..Default::default()
+                }),
+            )
+        }
+        .during("Creating the allocator")?;
+
+        let direct_queue = unsafe { device_loader.get_device_queue(direct_queue_family_index, 0) };
+        let present_queue =
+            unsafe { device_loader.get_device_queue(present_queue_family_index, 0) };
+        let transfer_queue = match transfer_queue_family_index {
+            Some(transfer_queue_family_index) => {
+                TransferQueue::new(
+                    unsafe { device_loader.get_device_queue(transfer_queue_family_index, 0) },
+                    transfer_queue_family_index,
+                    direct_queue_family_index,
+                )
+            }
+            None => TransferQueue::graphics(direct_queue, direct_queue_family_index),
+        };
+        let (compute_queue, compute_queue_family_index) = match async_compute_queue_family_index {
+            Some(async_compute_queue_family_index) => {
+                (
+                    unsafe { device_loader.get_device_queue(async_compute_queue_family_index, 0) },
+                    async_compute_queue_family_index,
+                )
+            }
+            None => (direct_queue, direct_queue_family_index),
+        };
+
+        Ok(Self {
+            entry_loader,
+
+            instance_loader,
+            surface_loader,
+
+            surface,
+
+            physical_device,
+            physical_device_properties,
+            mesh_shader_properties: physical_device_mesh_shader_properties,
+            device_info,
+            timestamp_period,
+
+            sample_rate_shading_supported,
+            fill_mode_non_solid_supported,
+            fragment_stores_and_atomics_supported,
+            non_uniform_indexing_supported,
+            task_shader_supported,
+            mesh_shader_queries_supported,
+            graphics_pipeline_library_supported,
+            memory_budget_supported,
+            primitive_shading_rate_supported,
+            multiview_mesh_shader_supported,
+
+            device_loader,
+            swapchain_loader,
+            mesh_shader_loader,
+            push_descriptor_loader,
+            conditional_rendering_loader,
+            device_fault,
+            full_screen_exclusive,
+
+            allocator,
+            pipeline_cache,
+
+            direct_queue,
+            direct_queue_family_index,
+            present_queue,
+            present_queue_family_index,
+            transfer_queue,
+            compute_queue,
+            compute_queue_family_index,
+        })
+    }
+
+    //Blocking uploads and layout transitions are submitted to the direct queue
+    #[inline]
+    pub fn direct_transfer_queue(&self) -> TransferQueue {
+        TransferQueue::graphics(self.direct_queue, self.direct_queue_family_index)
+    }
+
+    //The families of the direct, transfer and compute queues, empty if they are all the same. Buffers written on the
+    //transfer queue or read by the culling are shared concurrently between them, so no ownership transfers are needed
+    pub fn sharing_family_indices(&self) -> Vec<u32> {
+        let mut family_indices = vec![
+            self.direct_queue_family_index,
+            self.transfer_queue.family_index(),
+            self.compute_queue_family_index,
+        ];
+        family_indices.sort_unstable();
+        family_indices.dedup();
+
+        if family_indices.len() > 1 {
+            family_indices
+        } else {
+            Vec::new()
+        }
+    }
+
+    //The highest sample count up to the requested one which both color and depth attachments support
+    pub fn supported_sample_count(&self, requested: vk::SampleCountFlags) -> vk::SampleCountFlags {
+        let limits = &self.physical_device_properties.limits;
+        let supported =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+
+        let sample_count = [
+            vk::SampleCountFlags::TYPE_8,
+            vk::SampleCountFlags::TYPE_4,
+            vk::SampleCountFlags::TYPE_2,
+        ]
+        .into_iter()
+        .find(|sample_count| {
+            sample_count.as_raw() <= requested.as_raw() && supported.contains(*sample_count)
+        })
+        .unwrap_or(vk::SampleCountFlags::TYPE_1);
+
+        if sample_count != requested {
+            println!("MSAA with {requested:?} samples is not supported, falling back to {sample_count:?}");
+        }
+        sample_count
+    }
+
+    #[inline]
+    pub fn heap_budgets(&self) -> Vec<HeapBudget> {
+        unsafe {
+            memory_budget::query_heap_budgets(
+                &self.instance_loader,
+                self.physical_device,
+                self.memory_budget_supported,
+            )
+        }
+    }
+
+    #[inline]
+    pub fn allocator_statistics(&self) -> AllocatorStatistics {
+        unsafe {
+            memory_budget::calculate_allocator_statistics(
+                &self.instance_loader,
+                self.physical_device,
+                self.allocator,
+            )
+        }
+    }
+
+    //Writes what the driver knows about why the device was lost to a crash report
+    pub fn report_device_fault(&self) {
+        let Some(device_fault) = &self.device_fault else {
+            return
+        };
+
+        match unsafe { device_fault.query() }
+            .and_then(|report| report.write(&self.device_info.device_name))
+        {
+            Ok(path) => eprintln!("Wrote the device fault report to {path}"),
+            Err(error) => eprintln!("Failed to write the device fault report: {error}"),
+        }
+    }
+}
+
+impl Drop for RenderDevice {
+    fn drop(&mut self) {
+        unsafe {
+            vk_mem_alloc::destroy_allocator(self.allocator);
+
+            //Only costs compile time on the next start, so failing to save it isn't an error
+            if let Err(error) = pipeline_cache::save(&self.device_loader, self.pipeline_cache) {
+                eprintln!("Warning: Failed to save the pipeline cache: {error}");
+            }
+            self.device_loader
+                .destroy_pipeline_cache(self.pipeline_cache, None);
+
+            self.device_loader.destroy_device(None);
+            if let Some(surface) = self.surface {
+                self.surface_loader.destroy_surface(surface, None);
+            }
+            self.instance_loader.destroy_instance(None);
+        }
+    }
+}
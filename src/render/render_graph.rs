@@ -0,0 +1,196 @@
+use std::mem;
+
+use ash::vk;
+
+use crate::render::{
+    render_ctx::RenderCtx,
+    resource_state::{ResourceAccess, ResourceStateTracker, TrackedResource},
+};
+
+struct ResourceUsage {
+    resource: TrackedResource,
+    access: ResourceAccess,
+    write: bool,
+}
+
+type RecordFn<'a> = Box<dyn FnOnce(&RenderCtx, vk::CommandBuffer) + 'a>;
+
+pub struct GraphPass<'a> {
+    name: &'static str,
+    usages: Vec<ResourceUsage>,
+    record: RecordFn<'a>,
+}
+
+impl<'a> GraphPass<'a> {
+    #[inline]
+    pub fn read(&mut self, resource: TrackedResource, access: ResourceAccess) -> &mut Self {
+        self.usage(resource, access, false)
+    }
+
+    #[inline]
+    pub fn write(&mut self, resource: TrackedResource, access: ResourceAccess) -> &mut Self {
+        self.usage(resource, access, true)
+    }
+
+    //A resource which is both read and written is transitioned once for both accesses
+    fn usage(
+        &mut self,
+        resource: TrackedResource,
+        access: ResourceAccess,
+        write: bool,
+    ) -> &mut Self {
+        match self
+            .usages
+            .iter_mut()
+            .find(|usage| usage.resource == resource)
+        {
+            Some(usage) => {
+                assert_eq!(
+                    usage.access.layout, access.layout,
+                    "{} uses {resource:?} in two layouts",
+                    self.name
+                );
+                usage.access.stage_mask |= access.stage_mask;
+                usage.access.access_mask |= access.access_mask;
+                usage.write |= write;
+            }
+            None => {
+                self.usages.push(ResourceUsage {
+                    resource,
+                    access,
+                    write,
+                })
+            }
+        }
+        self
+    }
+
+    #[inline]
+    fn writes(&self, resource: TrackedResource) -> bool {
+        self.usages
+            .iter()
+            .any(|usage| usage.write && usage.resource == resource)
+    }
+}
+
+//Records the passes of a frame into one command buffer. Passes declare which resources they read and write, the graph
+//orders them and inserts the barriers between them
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    passes: Vec<GraphPass<'a>>,
+    tracker: ResourceStateTracker,
+    exports: Vec<(TrackedResource, ResourceAccess)>,
+}
+
+impl<'a> RenderGraph<'a> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //The access a resource was left in by earlier command buffers
+    #[inline]
+    pub fn import(&mut self, resource: TrackedResource, access: ResourceAccess) {
+        self.tracker.import(resource, access);
+    }
+
+    //The access a resource is transitioned to after the last pass, like presenting or reading it on the host
+    #[inline]
+    pub fn export(&mut self, resource: TrackedResource, access: ResourceAccess) {
+        self.exports.push((resource, access));
+    }
+
+    pub fn add_pass(
+        &mut self,
+        name: &'static str,
+        record: impl FnOnce(&RenderCtx, vk::CommandBuffer) + 'a,
+    ) -> &mut GraphPass<'a> {
+        self.passes.push(GraphPass {
+            name,
+            usages: Vec::new(),
+            record: Box::new(record),
+        });
+        self.passes.last_mut().unwrap()
+    }
+
+    //Passes writing the same resource run in the order they were added, passes only reading a resource run after all
+    //passes writing it
+    fn execution_order(&self) -> Vec<usize> {
+        let dependencies: Vec<Vec<usize>> = self
+            .passes
+            .iter()
+            .enumerate()
+            .map(|(pass_idx, pass)| {
+                (0..self.passes.len())
+                    .filter(|&other_idx| {
+                        other_idx != pass_idx
+                            && pass.usages.iter().any(|usage| {
+                                self.passes[other_idx].writes(usage.resource)
+                                    && (!pass.writes(usage.resource) || other_idx < pass_idx)
+                            })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut scheduled = vec![false; self.passes.len()];
+        let mut order = Vec::with_capacity(self.passes.len());
+        while order.len() < self.passes.len() {
+            //Ties are broken by the order the passes were added in
+            let pass_idx = (0..self.passes.len())
+                .find(|&pass_idx| {
+                    !scheduled[pass_idx]
+                        && dependencies[pass_idx]
+                            .iter()
+                            .all(|&dependency| scheduled[dependency])
+                })
+                .unwrap_or_else(|| {
+                    let names: Vec<_> = (0..self.passes.len())
+                        .filter(|&pass_idx| !scheduled[pass_idx])
+                        .map(|pass_idx| self.passes[pass_idx].name)
+                        .collect();
+                    panic!("The passes {names:?} depend on each other")
+                });
+
+            scheduled[pass_idx] = true;
+            order.push(pass_idx);
+        }
+
+        order
+    }
+
+    //Every pass is timed under its name
+    pub unsafe fn execute(
+        mut self,
+        ctx: &mut RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+    ) {
+        let order = self.execution_order();
+        let mut passes: Vec<_> = self.passes.drain(..).map(Some).collect();
+
+        for pass_idx in order {
+            let pass = passes[pass_idx].take().unwrap();
+
+            for usage in &pass.usages {
+                if usage.write {
+                    self.tracker.write(usage.resource, usage.access);
+                } else {
+                    self.tracker.read(usage.resource, usage.access);
+                }
+            }
+            self.tracker
+                .flush(&ctx.device.device_loader, command_buffer);
+
+            ctx.frame_resources.frames[frame_index].begin_pass(command_buffer, pass.name);
+            (pass.record)(ctx, command_buffer);
+            ctx.frame_resources.frames[frame_index].end_pass(command_buffer);
+        }
+
+        for (resource, access) in mem::take(&mut self.exports) {
+            self.tracker.read(resource, access);
+        }
+        self.tracker
+            .flush(&ctx.device.device_loader, command_buffer);
+    }
+}
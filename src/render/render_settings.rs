@@ -0,0 +1,78 @@
+use glam::{Mat4, Vec3};
+
+use crate::render::passes::geometry::DebugView;
+
+//Toggled from the key events in main.rs and read by the passes while recording
+#[derive(Copy, Clone, Debug)]
+pub struct RenderSettings {
+    pub triangle_view: bool,
+    pub culling: bool,
+    pub wireframe: bool,
+    pub debug_view: DebugView,
+    //Scales the distance the levels of detail are selected from, larger values pick coarser levels earlier
+    pub lod_bias: f32,
+    //The levels of detail are selected from this position instead of the camera while frozen
+    pub lod_freeze_position: Option<Vec3>,
+    //The frustum planes used for culling are taken from this matrix instead of the camera while frozen
+    pub culling_freeze_view_projection: Option<Mat4>,
+    //Projects without a far plane, nothing is clipped no matter how far away it is
+    pub infinite_far_plane: bool,
+    //Fills the depth before the geometry pass, so only the visible surfaces are shaded
+    pub depth_prepass: bool,
+    //Jitters the projection and blends every frame with the previous ones, which smooths the edges
+    pub taa: bool,
+    //Shades the coarser levels of detail with fewer fragment shader invocations than pixels
+    pub variable_shading_rate: bool,
+    //Draws a procedural field of grass around the instances
+    pub grass: bool,
+    //Draws a fountain of blended particles
+    pub particles: bool,
+    //Shades the meshes with many point and spot lights, which are binned into clusters of the view frustum
+    pub clustered_lighting: bool,
+    //Culls back faces and triangles too small to be rasterized in the mesh shader
+    pub triangle_culling: bool,
+    //Skips the draws of the instances outside the frustum on the device, see InstanceCullPass
+    pub conditional_rendering: bool,
+}
+
+impl Default for RenderSettings {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            triangle_view: false,
+            culling: true,
+            wireframe: false,
+            debug_view: DebugView::default(),
+            lod_bias: 1.0,
+            lod_freeze_position: None,
+            culling_freeze_view_projection: None,
+            infinite_far_plane: false,
+            depth_prepass: false,
+            taa: false,
+            variable_shading_rate: false,
+            grass: false,
+            particles: false,
+            clustered_lighting: false,
+            triangle_culling: false,
+            conditional_rendering: true,
+        }
+    }
+}
+
+impl RenderSettings {
+    #[inline]
+    pub fn toggle_lod_freeze(&mut self, camera_position: Vec3) {
+        self.lod_freeze_position = match self.lod_freeze_position {
+            Some(_) => None,
+            None => Some(camera_position),
+        };
+    }
+
+    #[inline]
+    pub fn toggle_culling_freeze(&mut self, view_projection_matrix: Mat4) {
+        self.culling_freeze_view_projection = match self.culling_freeze_view_projection {
+            Some(_) => None,
+            None => Some(view_projection_matrix),
+        };
+    }
+}
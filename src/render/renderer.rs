@@ -1,52 +1,295 @@
 use std::slice;
 
 use ash::vk;
-use glam::{Mat4, Quat, Vec3};
-use winit::window::Window;
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
 
 use crate::render::{
-    render_ctx::{RenderCtx, FIELD_OF_VIEW},
+    buffer::Buffer,
+    capture,
+    error::{ErrorContext, RenderError},
+    frame,
+    passes::{
+        geometry,
+        grass::GrassPass,
+        instance_cull::InstanceCullPass,
+        light_cull::NUM_LIGHTS,
+        particles::ParticlePass,
+        stereo::{Eye, EYE_SEPARATION},
+    },
+    render_ctx::{RenderCtx, FAR_PLANE, NEAR_PLANE},
+    render_graph::RenderGraph,
+    resource_state::{ResourceAccess, ResourceStateTracker, TrackedResource},
+    scene::Camera,
+    secondary_window::{SecondaryView, SecondaryWindow},
+    shader_watcher::ShaderWatcher,
     utils::globals::Globals,
 };
 
-unsafe fn update_globals(ctx: &RenderCtx, window: &Window) {
-    //Compute view projection matrix
-    let final_transform = &ctx.camera_rig.final_transform;
+//The instances and the skinned meshes are read by the task and mesh shaders
+const INSTANCE_READ: ResourceAccess = ResourceAccess::new(
+    vk::PipelineStageFlags2::from_raw(
+        vk::PipelineStageFlags2::TASK_SHADER_EXT.as_raw()
+            | vk::PipelineStageFlags2::MESH_SHADER_EXT.as_raw(),
+    ),
+    vk::AccessFlags2::SHADER_STORAGE_READ,
+);
+//The previous world matrices are read before they are overwritten
+const INSTANCE_ANIMATE: ResourceAccess = ResourceAccess::new(
+    vk::PipelineStageFlags2::COMPUTE_SHADER,
+    vk::AccessFlags2::from_raw(
+        vk::AccessFlags2::SHADER_STORAGE_READ.as_raw()
+            | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw(),
+    ),
+);
+const DEPTH_WRITE: ResourceAccess = ResourceAccess::new(
+    vk::PipelineStageFlags2::from_raw(
+        vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS.as_raw()
+            | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS.as_raw(),
+    ),
+    vk::AccessFlags2::from_raw(
+        vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ.as_raw()
+            | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw(),
+    ),
+)
+.with_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+const DEPTH_READ: ResourceAccess = ResourceAccess::new(
+    vk::PipelineStageFlags2::from_raw(
+        vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS.as_raw()
+            | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS.as_raw(),
+    ),
+    vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ,
+)
+.with_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
 
-    let mut projection_matrix = Mat4::perspective_lh(
-        FIELD_OF_VIEW.to_radians(),
-        window.inner_size().width as f32 / window.inner_size().height as f32,
-        0.1,
-        1000.0,
-    );
+//The TAA and stereo composite passes sample the images written by the geometry pass
+const SAMPLED_READ: ResourceAccess = ResourceAccess::new(
+    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+    vk::AccessFlags2::SHADER_SAMPLED_READ,
+)
+.with_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+const COLOR_WRITE: ResourceAccess = ResourceAccess::new(
+    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+    vk::AccessFlags2::from_raw(
+        vk::AccessFlags2::COLOR_ATTACHMENT_READ.as_raw()
+            | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw(),
+    ),
+)
+.with_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+//Planes point inwards, a point is inside if dot(plane.xyz, point) + plane.w >= 0. With reversed-Z the fifth plane is the
+//far plane and the sixth the near plane
+fn compute_frustum_planes(view_projection_matrix: &Mat4) -> [Vec4; 6] {
+    let row = |i| view_projection_matrix.row(i);
+
+    [
+        row(3) + row(0),
+        row(3) - row(0),
+        row(3) + row(1),
+        row(3) - row(1),
+        row(2),
+        row(3) - row(2),
+    ]
+    .map(|plane| {
+        //An infinite far plane has no normal, everything is in front of it
+        let length = plane.truncate().length();
+        if length == 0.0 {
+            Vec4::W
+        } else {
+            plane / length
+        }
+    })
+}
+
+//The camera of the secondary window which is rendered, the main camera otherwise
+#[inline]
+fn render_camera(ctx: &RenderCtx) -> Camera {
+    ctx.secondary_view
+        .map_or_else(|| ctx.camera(), |secondary_view| secondary_view.camera)
+}
+
+fn compute_projection_matrix(ctx: &RenderCtx, aspect_ratio: f32) -> Mat4 {
+    let field_of_view = render_camera(ctx).field_of_view.to_radians();
+
+    //Reversed-Z, the near plane is mapped to 1 and the far plane to 0. Together with the float depth buffer this keeps
+    //the precision in the distance, where the ground mesh would z-fight otherwise
+    let mut projection_matrix = if ctx.render_settings.infinite_far_plane {
+        Mat4::perspective_infinite_reverse_lh(field_of_view, aspect_ratio, NEAR_PLANE)
+    } else {
+        Mat4::perspective_lh(field_of_view, aspect_ratio, FAR_PLANE, NEAR_PLANE)
+    };
     projection_matrix.y_axis.y *= -1.0;
 
-    let view_projection_matrix = projection_matrix
-        * Mat4::look_at_lh(
-            final_transform.position,
-            final_transform.position + final_transform.forward(),
-            final_transform.up(),
+    projection_matrix
+}
+
+//Same conventions as compute_projection_matrix, the frustum is given by the tangents of the eye
+fn compute_eye_projection_matrix(ctx: &RenderCtx, eye: &Eye) -> Mat4 {
+    let width = eye.tan_right - eye.tan_left;
+    let height = eye.tan_up - eye.tan_down;
+
+    let (depth_scale, depth_offset) = if ctx.render_settings.infinite_far_plane {
+        (0.0, NEAR_PLANE)
+    } else {
+        (
+            NEAR_PLANE / (NEAR_PLANE - FAR_PLANE),
+            -FAR_PLANE * NEAR_PLANE / (NEAR_PLANE - FAR_PLANE),
         )
-        * Mat4::from_rotation_translation(Quat::IDENTITY, Vec3::new(0.0, 0.0, 1.0));
+    };
+
+    //Y points down in clip space
+    Mat4::from_cols(
+        Vec4::new(2.0 / width, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, -2.0 / height, 0.0, 0.0),
+        Vec4::new(
+            -(eye.tan_right + eye.tan_left) / width,
+            (eye.tan_up + eye.tan_down) / height,
+            depth_scale,
+            1.0,
+        ),
+        Vec4::new(0.0, 0.0, depth_offset, 0.0),
+    )
+}
+
+#[inline]
+fn compute_camera_view_matrix(camera: &Camera) -> Mat4 {
+    camera.view_matrix() * Mat4::from_rotation_translation(Quat::IDENTITY, Vec3::new(0.0, 0.0, 1.0))
+}
+
+#[inline]
+fn compute_view_matrix(ctx: &RenderCtx) -> Mat4 {
+    compute_camera_view_matrix(&render_camera(ctx))
+}
+
+#[inline]
+fn aspect_ratio(extent: vk::Extent2D) -> f32 {
+    extent.width as f32 / extent.height as f32
+}
 
-    ctx.globals_buffers.update(&Globals {
-        view_projection_matrix,
-        frustum_planes: Default::default(), //TODO:
-        camera_pos: final_transform.position,
-        time: 0.0, //TODO:
+pub fn compute_view_projection_matrix(ctx: &RenderCtx) -> Mat4 {
+    compute_projection_matrix(ctx, aspect_ratio(ctx.swapchain.extent)) * compute_view_matrix(ctx)
+}
+
+//Both eyes look in the direction of the camera, half the eye separation to the left and right of it
+fn compute_eye_view_projection_matrices(ctx: &RenderCtx, eye_extent: vk::Extent2D) -> [Mat4; 2] {
+    if let Some(eyes) = &ctx.eye_override {
+        return eyes.map(|eye| {
+            compute_eye_projection_matrix(ctx, &eye) * compute_camera_view_matrix(&eye.camera)
+        })
+    }
+
+    let projection_matrix = compute_projection_matrix(ctx, aspect_ratio(eye_extent));
+    let view_matrix = compute_view_matrix(ctx);
+
+    [-0.5, 0.5].map(|side| {
+        projection_matrix
+            * Mat4::from_translation(Vec3::new(-side * EYE_SEPARATION, 0.0, 0.0))
+            * view_matrix
     })
 }
 
-pub fn render_frame(ctx: &RenderCtx, window: &Window, frame_index: &mut usize) {
+//Moved back until its frustum contains the frusta of both eyes, so nothing one of the eyes sees is culled
+fn compute_stereo_culling_view_projection_matrix(
+    ctx: &RenderCtx,
+    eye_extent: vk::Extent2D,
+) -> Mat4 {
+    let aspect_ratio = aspect_ratio(eye_extent);
+    let half_width = (ctx.camera().field_of_view.to_radians() * 0.5).tan() * aspect_ratio;
+    let eye_separation = ctx.eye_override.map_or(EYE_SEPARATION, |[left, right]| {
+        left.camera.position.distance(right.camera.position)
+    });
+    let distance = 0.5 * eye_separation / half_width;
+
+    compute_projection_matrix(ctx, aspect_ratio)
+        * Mat4::from_translation(Vec3::new(0.0, 0.0, distance))
+        * compute_view_matrix(ctx)
+}
+
+//Returns the frustum planes everything is culled against, the draws are culled with them on the host as well
+unsafe fn update_globals(ctx: &RenderCtx, frame_index: usize) -> [Vec4; 6] {
+    //Compute view projection matrix, with TAA it's offset by a different fraction of a pixel every frame. Secondary
+    //windows leave the TAA state of the main window alone
+    let view_projection_matrix = compute_view_projection_matrix(ctx);
+    let (jitter, prev_view_projection_matrix) = if ctx.secondary_view.is_some() {
+        (Vec2::ZERO, view_projection_matrix)
+    } else {
+        (
+            ctx.taa_pass
+                .next_jitter(ctx.geometry_pass.taa_enabled(ctx), ctx.swapchain.extent),
+            ctx.taa_pass
+                .swap_view_projection_matrix(view_projection_matrix),
+        )
+    };
+
+    let jittered_view_projection_matrix =
+        Mat4::from_translation(jitter.extend(0.0)) * view_projection_matrix;
+    let projection_matrix = compute_projection_matrix(ctx, aspect_ratio(ctx.swapchain.extent));
+    let render_extent = geometry::render_extent(ctx);
+
+    //With stereo rendering the eyes are culled together. Secondary windows are culled like the main window. While the
+    //culling camera is frozen, everything is culled against the frozen frustum
+    let (view_projection_matrices, culling_view_projection_matrix) =
+        match (&ctx.stereo_pass, &ctx.secondary_view) {
+            (Some(stereo_pass), _) => {
+                (
+                    compute_eye_view_projection_matrices(ctx, stereo_pass.eye_extent),
+                    compute_stereo_culling_view_projection_matrix(ctx, stereo_pass.eye_extent),
+                )
+            }
+            (None, Some(secondary_view)) => {
+                (
+                    [jittered_view_projection_matrix; 2],
+                    secondary_view.culling_view_projection_matrix,
+                )
+            }
+            (None, None) => ([jittered_view_projection_matrix; 2], view_projection_matrix),
+        };
+    let culling_view_projection_matrix = ctx
+        .render_settings
+        .culling_freeze_view_projection
+        .unwrap_or(culling_view_projection_matrix);
+
+    let frustum_planes = compute_frustum_planes(&culling_view_projection_matrix);
+    ctx.frame_resources.globals_buffers.update(
+        frame_index,
+        &Globals {
+            view_projection_matrix: jittered_view_projection_matrix,
+            frustum_planes,
+            camera_pos: ctx.camera().position,
+            time: ctx.time(),
+            prev_view_projection_matrix,
+            jitter,
+            viewport_size: Vec2::new(render_extent.width as _, render_extent.height as _),
+            view_projection_matrices,
+            view_matrix: compute_view_matrix(ctx),
+            projection_scale: Vec2::new(projection_matrix.x_axis.x, projection_matrix.y_axis.y),
+            num_lights: NUM_LIGHTS as _,
+            padding_lights: 0,
+            lights_address: ctx.light_cull_pass.lights_address(frame_index),
+            light_clusters_address: ctx.light_cull_pass.cluster_buffer.device_address,
+        },
+    );
+
+    frustum_planes
+}
+
+pub fn render_frame(ctx: &mut RenderCtx, frame_index: &mut usize) -> Result<(), RenderError> {
     unsafe {
+        ctx.scene_resources
+            .mesh_collection
+            .poll_loaded(&mut ctx.deletion_queue)
+            .during("Uploading the loaded meshes")?;
+        let time = ctx.time();
+        ctx.scene_resources.refit_instance_bvh(time);
+
         //Begin frame
-        let device_loader = &ctx.device_loader;
-        let swapchain_loader = &ctx.swapchain_loader;
+        let device_loader = &ctx.device.device_loader;
+        let swapchain_loader = &ctx.device.swapchain_loader;
 
-        let direct_queue = ctx.direct_queue;
-        let swapchain = ctx.swapchain;
+        let direct_queue = ctx.device.direct_queue;
+        let present_queue = ctx.device.present_queue;
+        let swapchain = ctx.swapchain.swapchain;
 
-        let current_frame = &ctx.frames[*frame_index];
+        let current_frame = &ctx.frame_resources.frames[*frame_index];
 
         let present_semaphore = current_frame.present_semaphore;
         let render_semaphore = current_frame.render_semaphore;
@@ -54,58 +297,747 @@ pub fn render_frame(ctx: &RenderCtx, window: &Window, frame_index: &mut usize) {
         let fence = current_frame.fence;
         device_loader
             .wait_for_fences(slice::from_ref(&fence), true, u64::MAX)
-            .unwrap();
-        device_loader.reset_fences(slice::from_ref(&fence)).unwrap();
+            .during("Waiting for the frame")?;
+
+        //Without a swapchain every frame in flight renders into its own offscreen image. The fence is only reset once
+        //the frame is certain to be submitted, an out of date swapchain skips it until it was created again
+        let image_index = match swapchain {
+            Some(swapchain) => {
+                match swapchain_loader.acquire_next_image(
+                    swapchain,
+                    u64::MAX,
+                    present_semaphore,
+                    vk::Fence::null(),
+                ) {
+                    Ok((image_index, suboptimal)) => {
+                        ctx.swapchain.out_of_date |= suboptimal;
+                        image_index
+                    }
+                    Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                        ctx.swapchain.out_of_date = true;
+                        return Ok(())
+                    }
+                    result => result.during("Acquiring the swapchain image")?.0,
+                }
+            }
+            None => *frame_index as u32,
+        };
+
+        device_loader
+            .reset_fences(slice::from_ref(&fence))
+            .during("Resetting the frame fence")?;
+
+        //Pipelines replaced by a shader reload are deleted once no frame in flight uses them anymore. Secondary windows
+        //submit after the main window, so only the frames of the main window are counted. Their timings aren't
+        //reported either
+        if ctx.secondary_view.is_none() {
+            ctx.deletion_queue.advance();
+            if ctx
+                .shader_watcher
+                .as_ref()
+                .map_or(false, ShaderWatcher::poll_changed)
+            {
+                ctx.geometry_pass.reload_pipelines();
+            }
+            ctx.geometry_pass.poll_reload(&mut ctx.deletion_queue);
+
+            //Results of the last submission of this frame are available now that the fence was signaled
+            for pass_result in current_frame
+                .get_pass_results()
+                .during("Reading the pass queries")?
+            {
+                ctx.pass_timings
+                    .push(&pass_result.name, pass_result.duration);
+                ctx.pipeline_statistics
+                    .insert(pass_result.name, pass_result.pipeline_statistics);
+            }
+            ctx.culling_stats = current_frame.take_culling_stats();
+        }
 
         let command_pool = current_frame.command_pool;
         let command_buffer = current_frame.command_buffer;
+        let animate_command_buffer = current_frame.animate_command_buffer;
+        let compute_command_pool = current_frame.compute_command_pool;
+        let compute_command_buffer = current_frame.compute_command_buffer;
+        let cull_semaphore = current_frame.cull_semaphore;
 
         device_loader
             .reset_command_pool(command_pool, vk::CommandPoolResetFlags::RELEASE_RESOURCES)
-            .unwrap();
-
-        let image_index = swapchain_loader
-            .acquire_next_image(swapchain, u64::MAX, present_semaphore, vk::Fence::null())
-            .unwrap()
-            .0;
+            .during("Resetting the command pool")?;
+        device_loader
+            .reset_command_pool(
+                compute_command_pool,
+                vk::CommandPoolResetFlags::RELEASE_RESOURCES,
+            )
+            .during("Resetting the compute command pool")?;
+        for secondary_command_pool in &current_frame.secondary_command_pools {
+            device_loader
+                .reset_command_pool(
+                    *secondary_command_pool,
+                    vk::CommandPoolResetFlags::RELEASE_RESOURCES,
+                )
+                .during("Resetting the secondary command pools")?;
+        }
 
         let command_buffer_begin_info = vk::CommandBufferBeginInfo::default()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
 
         device_loader
             .begin_command_buffer(command_buffer, &command_buffer_begin_info)
-            .unwrap();
+            .during("Beginning the command buffer")?;
 
         //Render frame
-        update_globals(ctx, window);
+        let frustum_planes = update_globals(ctx, *frame_index);
+
+        //Culling is submitted on its own, so it overlaps with the rendering of the previous frame which is still in flight.
+        //It only waits for the instances animated by the previous frame, the animation itself stays on the direct queue and
+        //waits for the culling in turn
+        device_loader
+            .begin_command_buffer(compute_command_buffer, &command_buffer_begin_info)
+            .during("Beginning the compute command buffer")?;
+        ctx.instance_cull_pass
+            .execute(ctx, compute_command_buffer, *frame_index);
+        device_loader
+            .end_command_buffer(compute_command_buffer)
+            .during("Ending the compute command buffer")?;
+
+        //The values of binary semaphores are ignored
+        let culled_semaphore = ctx.instance_cull_pass.culled_semaphore;
+        let num_culled = ctx.instance_cull_pass.num_culled + 1;
+        let mut cull_timeline_submit_info = vk::TimelineSemaphoreSubmitInfo::default()
+            .wait_semaphore_values(slice::from_ref(&ctx.instance_animate_pass.num_animated))
+            .signal_semaphore_values(&[0, num_culled]);
+        device_loader
+            .queue_submit(
+                ctx.device.compute_queue,
+                slice::from_ref(
+                    &vk::SubmitInfo::default()
+                        .wait_semaphores(slice::from_ref(
+                            &ctx.instance_animate_pass.animated_semaphore,
+                        ))
+                        .wait_dst_stage_mask(slice::from_ref(
+                            &vk::PipelineStageFlags::COMPUTE_SHADER,
+                        ))
+                        .command_buffers(slice::from_ref(&compute_command_buffer))
+                        .signal_semaphores(&[cull_semaphore, culled_semaphore])
+                        .push_next(&mut cull_timeline_submit_info),
+                ),
+                vk::Fence::null(),
+            )
+            .during("Submitting the culling")?;
+        ctx.instance_cull_pass.num_culled = num_culled;
+
+        ctx.frame_resources.frames[*frame_index].reset_queries(command_buffer);
+
+        let capture_extent = ctx.swapchain.extent;
+        let capture_buffer = if ctx.capture_requested && ctx.secondary_view.is_none() {
+            Some(
+                Buffer::new_readback(
+                    ctx.device.device_loader.clone(),
+                    ctx.device.allocator,
+                    (capture_extent.width * capture_extent.height * 4) as usize,
+                )
+                .during("Creating the capture buffer")?,
+            )
+        } else {
+            None
+        };
+
+        let instance_buffer =
+            TrackedResource::Buffer(ctx.scene_resources.instance_buffers.instance_buffer.buffer);
+        let color_image = TrackedResource::Image {
+            image: ctx.swapchain.images[image_index as usize],
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+        };
+        let depth_image = TrackedResource::Image {
+            image: ctx.frame_resources.depth_image,
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+        };
+        let msaa_color_image =
+            ctx.frame_resources
+                .msaa_color_image
+                .as_ref()
+                .map(|msaa_color_image| {
+                    TrackedResource::Image {
+                        image: msaa_color_image.image,
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                    }
+                });
+
+        let taa = ctx.geometry_pass.taa_enabled(ctx);
+        let grass = GrassPass::enabled(ctx);
+        let particles = ParticlePass::enabled(ctx);
+        let lighting = ctx.geometry_pass.lighting_enabled(ctx);
+        let light_cluster_buffer =
+            TrackedResource::Buffer(ctx.light_cull_pass.cluster_buffer.buffer);
+        let light_clusters_read = ResourceAccess::new(
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_READ,
+        );
+        let (prev_history_image, history_image) = ctx.taa_pass.history_images();
+        let taa_images @ [taa_color_image, velocity_image, prev_history_image, history_image] = [
+            &ctx.taa_pass.color,
+            &ctx.taa_pass.velocity,
+            prev_history_image,
+            history_image,
+        ]
+        .map(|image| image.tracked());
+        let stereo_images = ctx
+            .stereo_pass
+            .as_ref()
+            .map(|stereo_pass| (stereo_pass.color.tracked(), stereo_pass.depth.tracked()));
+        let stereo_target_image =
+            ctx.stereo_target
+                .filter(|_| stereo_images.is_some())
+                .map(|image| {
+                    TrackedResource::Image {
+                        image,
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                    }
+                });
+
+        //Picking is rare, so the frame is simply waited for like with captures
+        let ids = ctx.geometry_pass.picking_enabled(ctx);
+        let pick_position = ctx.pick_requested.filter(|_| ids);
+        let outlined_instance = ctx.selected_instance.filter(|_| ids);
+        let id_image = ctx.picking_pass.tracked();
+        let picking_buffer = TrackedResource::Buffer(ctx.picking_pass.readback_buffer.buffer);
+
+        //Only the main window poses the skinned meshes, like it animates the instances
+        let skinned_buffers: Vec<_> = if ctx.secondary_view.is_none() {
+            ctx.skinning_pass.written_buffers(ctx)
+        } else {
+            Vec::new()
+        }
+        .into_iter()
+        .map(TrackedResource::Buffer)
+        .collect();
+
+        let draws = geometry::mesh_draws(
+            ctx,
+            &ctx.frame_resources.frames[*frame_index],
+            &frustum_planes,
+        );
+        let draws = &draws;
+        let prepared_draws = ctx
+            .geometry_pass
+            .prepare(ctx, *frame_index, draws)
+            .during("Preparing the geometry draws")?;
+        let prepared_draws = &prepared_draws;
+
+        //The instances are animated once per frame by the main window, in a submission of its own which the culling of the
+        //next frame waits for. The previous frame might still read them
+        let animate = ctx.secondary_view.is_none();
+        if animate {
+            device_loader
+                .begin_command_buffer(animate_command_buffer, &command_buffer_begin_info)
+                .during("Beginning the animate command buffer")?;
+            let mut tracker = ResourceStateTracker::new();
+            tracker.import(instance_buffer, INSTANCE_READ);
+            tracker.write(instance_buffer, INSTANCE_ANIMATE);
+            tracker.flush(device_loader, animate_command_buffer);
+            ctx.instance_animate_pass
+                .execute(ctx, animate_command_buffer);
+            device_loader
+                .end_command_buffer(animate_command_buffer)
+                .during("Ending the animate command buffer")?;
+        }
+
+        let mut graph = RenderGraph::new();
+        //The instances were last written by the animation, which is submitted before the frame. The previous frame might
+        //still write the depth image
+        graph.import(instance_buffer, INSTANCE_ANIMATE);
+        for skinned_buffer in &skinned_buffers {
+            graph.import(*skinned_buffer, INSTANCE_READ);
+        }
+        graph.import(depth_image, DEPTH_WRITE);
+        //The previous frame might still shade with the clusters
+        if lighting {
+            graph.import(light_cluster_buffer, light_clusters_read);
+        }
+        //Chained to the acquire semaphore, which is waited on at COLOR_ATTACHMENT_OUTPUT
+        graph.import(
+            color_image,
+            ResourceAccess::new(
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::NONE,
+            ),
+        );
+        //Its contents are cleared every frame, only the writes of the previous frame have to finish
+        if let Some(msaa_color_image) = msaa_color_image {
+            graph.import(
+                msaa_color_image,
+                ResourceAccess::new(
+                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                ),
+            );
+        }
+        //The previous frame might still sample the images, only its history is kept
+        if taa {
+            for image in taa_images {
+                graph.import(
+                    image,
+                    ResourceAccess::new(
+                        vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                        vk::AccessFlags2::NONE,
+                    ),
+                );
+            }
+            if ctx.taa_pass.history_valid() {
+                graph.import(
+                    prev_history_image,
+                    ResourceAccess::new(
+                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                        vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                    )
+                    .with_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+                );
+            }
+        }
+        //Cleared every frame, the previous frame might still outline or copy the IDs
+        if ids {
+            graph.import(
+                id_image,
+                ResourceAccess::new(
+                    vk::PipelineStageFlags2::from_raw(
+                        vk::PipelineStageFlags2::FRAGMENT_SHADER.as_raw()
+                            | vk::PipelineStageFlags2::COPY.as_raw(),
+                    ),
+                    vk::AccessFlags2::NONE,
+                ),
+            );
+        }
+        //Both are cleared every frame, the previous frame might still composite or copy the eyes
+        if let Some((stereo_color_image, stereo_depth_image)) = stereo_images {
+            graph.import(
+                stereo_color_image,
+                ResourceAccess::new(
+                    vk::PipelineStageFlags2::from_raw(
+                        vk::PipelineStageFlags2::FRAGMENT_SHADER.as_raw()
+                            | vk::PipelineStageFlags2::COPY.as_raw(),
+                    ),
+                    vk::AccessFlags2::NONE,
+                ),
+            );
+            graph.import(
+                stereo_depth_image,
+                ResourceAccess::new(
+                    vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+                    vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ),
+            );
+        }
+
+        let frame_index = *frame_index;
+        if !skinned_buffers.is_empty() {
+            let skinning_pass = graph.add_pass("SkinningPass", move |ctx, command_buffer| {
+                ctx.skinning_pass.execute(ctx, command_buffer, frame_index)
+            });
+            for skinned_buffer in &skinned_buffers {
+                skinning_pass.write(
+                    *skinned_buffer,
+                    ResourceAccess::new(
+                        vk::PipelineStageFlags2::COMPUTE_SHADER,
+                        vk::AccessFlags2::from_raw(
+                            vk::AccessFlags2::SHADER_STORAGE_READ.as_raw()
+                                | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw(),
+                        ),
+                    ),
+                );
+            }
+        }
+        if ctx.geometry_pass.depth_prepass_enabled(ctx) {
+            let depth_prepass = graph
+                .add_pass("DepthPrepass", move |ctx, command_buffer| {
+                    ctx.geometry_pass.execute_depth_prepass(
+                        ctx,
+                        command_buffer,
+                        prepared_draws,
+                        draws,
+                    )
+                })
+                .read(instance_buffer, INSTANCE_READ)
+                .write(depth_image, DEPTH_WRITE);
+            for skinned_buffer in &skinned_buffers {
+                depth_prepass.read(*skinned_buffer, INSTANCE_READ);
+            }
+        }
+        if lighting {
+            graph
+                .add_pass("LightCullPass", move |ctx, command_buffer| {
+                    ctx.light_cull_pass
+                        .execute(ctx, command_buffer, frame_index)
+                })
+                .write(
+                    light_cluster_buffer,
+                    ResourceAccess::new(
+                        vk::PipelineStageFlags2::COMPUTE_SHADER,
+                        vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    ),
+                );
+        }
+        //With MSAA the swapchain image is written by the resolve, which happens in the same stage. With TAA or stereo
+        //rendering the geometry pass renders into their images instead
+        let geometry_pass = graph
+            .add_pass("GeometryPass", move |ctx, command_buffer| {
+                ctx.geometry_pass.execute(
+                    ctx,
+                    command_buffer,
+                    frame_index,
+                    image_index as usize,
+                    prepared_draws,
+                    draws,
+                )
+            })
+            .read(instance_buffer, INSTANCE_READ)
+            .write(
+                stereo_images.map_or(depth_image, |(_, stereo_depth_image)| stereo_depth_image),
+                DEPTH_WRITE,
+            );
+        for skinned_buffer in &skinned_buffers {
+            geometry_pass.read(*skinned_buffer, INSTANCE_READ);
+        }
+        if lighting {
+            geometry_pass.read(light_cluster_buffer, light_clusters_read);
+        }
+        if let Some(msaa_color_image) = msaa_color_image {
+            geometry_pass.write(msaa_color_image, COLOR_WRITE);
+        }
+        if ids {
+            geometry_pass.write(id_image, COLOR_WRITE);
+        }
+        if let Some((stereo_color_image, _)) = stereo_images {
+            geometry_pass.write(stereo_color_image, COLOR_WRITE);
+
+            graph
+                .add_pass("StereoCompositePass", move |ctx, command_buffer| {
+                    ctx.stereo_pass.as_ref().unwrap().execute(
+                        ctx,
+                        command_buffer,
+                        image_index as usize,
+                    )
+                })
+                .read(stereo_color_image, SAMPLED_READ)
+                .write(color_image, COLOR_WRITE);
 
-        ctx.instance_cull_pass.execute(ctx, command_buffer);
-        ctx.geometry_pass
-            .execute(ctx, command_buffer, image_index as usize, window);
+            //The owner of the target waited for its previous use already
+            if let Some(stereo_target_image) = stereo_target_image {
+                graph.import(
+                    stereo_target_image,
+                    ResourceAccess::new(vk::PipelineStageFlags2::COPY, vk::AccessFlags2::NONE)
+                        .with_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+                );
+                graph
+                    .add_pass("StereoCopyPass", move |ctx, command_buffer| {
+                        ctx.stereo_pass.as_ref().unwrap().copy_eyes(
+                            ctx,
+                            command_buffer,
+                            ctx.stereo_target.unwrap(),
+                        )
+                    })
+                    .read(
+                        stereo_color_image,
+                        ResourceAccess::new(
+                            vk::PipelineStageFlags2::COPY,
+                            vk::AccessFlags2::TRANSFER_READ,
+                        )
+                        .with_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+                    )
+                    .write(
+                        stereo_target_image,
+                        ResourceAccess::new(
+                            vk::PipelineStageFlags2::COPY,
+                            vk::AccessFlags2::TRANSFER_WRITE,
+                        )
+                        .with_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+                    );
+                graph.export(stereo_target_image, COLOR_WRITE);
+            }
+        } else if taa {
+            geometry_pass
+                .write(taa_color_image, COLOR_WRITE)
+                .write(velocity_image, COLOR_WRITE);
+        } else {
+            geometry_pass.write(color_image, COLOR_WRITE);
+        }
+
+        //Continues on the images of the geometry pass before they are resolved, there are no stereo images with grass
+        if grass {
+            let grass_pass = graph
+                .add_pass("GrassPass", move |ctx, command_buffer| {
+                    ctx.grass_pass.as_ref().unwrap().execute(
+                        ctx,
+                        command_buffer,
+                        image_index as usize,
+                        taa,
+                    )
+                })
+                .write(depth_image, DEPTH_WRITE);
+            if taa {
+                grass_pass
+                    .write(taa_color_image, COLOR_WRITE)
+                    .write(velocity_image, COLOR_WRITE);
+            } else {
+                grass_pass.write(color_image, COLOR_WRITE);
+            }
+        }
+
+        if taa && stereo_images.is_none() {
+            graph
+                .add_pass("TaaPass", move |ctx, command_buffer| {
+                    ctx.taa_pass
+                        .execute(ctx, command_buffer, image_index as usize)
+                })
+                .read(taa_color_image, SAMPLED_READ)
+                .read(velocity_image, SAMPLED_READ)
+                .read(prev_history_image, SAMPLED_READ)
+                .write(history_image, COLOR_WRITE)
+                .write(color_image, COLOR_WRITE);
+        }
+
+        //Blended over the final image, after TAA so they don't leave trails in the history
+        if particles {
+            graph
+                .add_pass("ParticlePass", move |ctx, command_buffer| {
+                    ctx.particle_pass.execute(
+                        ctx,
+                        command_buffer,
+                        frame_index,
+                        image_index as usize,
+                    )
+                })
+                .read(depth_image, DEPTH_READ)
+                .write(color_image, COLOR_WRITE);
+        }
+
+        if let Some(pick_position) = pick_position {
+            graph
+                .add_pass("PickingPass", move |ctx, command_buffer| {
+                    ctx.picking_pass
+                        .record_copy(ctx, command_buffer, pick_position)
+                })
+                .read(
+                    id_image,
+                    ResourceAccess::new(
+                        vk::PipelineStageFlags2::COPY,
+                        vk::AccessFlags2::TRANSFER_READ,
+                    )
+                    .with_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+                )
+                .write(
+                    picking_buffer,
+                    ResourceAccess::new(
+                        vk::PipelineStageFlags2::COPY,
+                        vk::AccessFlags2::TRANSFER_WRITE,
+                    ),
+                );
+            graph.export(
+                picking_buffer,
+                ResourceAccess::new(vk::PipelineStageFlags2::HOST, vk::AccessFlags2::HOST_READ),
+            );
+        }
+
+        if let Some(outlined_instance) = outlined_instance {
+            graph
+                .add_pass("OutlinePass", move |ctx, command_buffer| {
+                    ctx.outline_pass.execute(
+                        ctx,
+                        command_buffer,
+                        image_index as usize,
+                        outlined_instance,
+                    )
+                })
+                .read(id_image, SAMPLED_READ)
+                .write(color_image, COLOR_WRITE);
+        }
+
+        //Drawn on top of the resolved image, so it's neither multisampled nor blended with the history. The eyes of stereo
+        //rendering don't match the projection of the frustum. Secondary windows always show the frustum of the main window
+        if let Some(view_projection_matrix) = ctx
+            .secondary_view
+            .map(|secondary_view| secondary_view.culling_view_projection_matrix)
+            .or(ctx.render_settings.culling_freeze_view_projection)
+            .filter(|_| ctx.stereo_pass.is_none())
+        {
+            graph
+                .add_pass("FrustumDebugPass", move |ctx, command_buffer| {
+                    ctx.frustum_debug_pass.draw_frustum(
+                        ctx,
+                        command_buffer,
+                        image_index as usize,
+                        &view_projection_matrix,
+                    )
+                })
+                .write(color_image, COLOR_WRITE);
+        }
+
+        if let Some(capture_buffer) = &capture_buffer {
+            let capture_buffer_resource = TrackedResource::Buffer(capture_buffer.buffer);
+            graph
+                .add_pass("CapturePass", move |ctx, command_buffer| {
+                    capture::record_copy(
+                        ctx,
+                        command_buffer,
+                        image_index as usize,
+                        capture_extent,
+                        capture_buffer,
+                    )
+                })
+                .read(
+                    color_image,
+                    ResourceAccess::new(
+                        vk::PipelineStageFlags2::COPY,
+                        vk::AccessFlags2::TRANSFER_READ,
+                    )
+                    .with_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+                )
+                .write(
+                    capture_buffer_resource,
+                    ResourceAccess::new(
+                        vk::PipelineStageFlags2::COPY,
+                        vk::AccessFlags2::TRANSFER_WRITE,
+                    ),
+                );
+            graph.export(
+                capture_buffer_resource,
+                ResourceAccess::new(vk::PipelineStageFlags2::HOST, vk::AccessFlags2::HOST_READ),
+            );
+        }
+
+        graph.export(
+            color_image,
+            ResourceAccess::new(
+                vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+                vk::AccessFlags2::NONE,
+            )
+            .with_layout(ctx.swapchain.final_image_layout()),
+        );
+
+        graph.execute(ctx, command_buffer, frame_index);
 
         //End frame
-        device_loader.end_command_buffer(command_buffer).unwrap();
+        let device_loader = &ctx.device.device_loader;
+        let swapchain_loader = &ctx.device.swapchain_loader;
+
+        device_loader
+            .end_command_buffer(command_buffer)
+            .during("Ending the command buffer")?;
 
-        let wait_semaphores = [present_semaphore];
-        let wait_dst_stage_mask = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        //The culling results are first read by the task shaders, or as the predicates of the draws
+        let cull_wait_dst_stage_mask = if InstanceCullPass::enabled(ctx) {
+            vk::PipelineStageFlags::TASK_SHADER_EXT
+                | vk::PipelineStageFlags::CONDITIONAL_RENDERING_EXT
+        } else {
+            vk::PipelineStageFlags::TASK_SHADER_EXT
+        };
+        let (wait_semaphores, wait_dst_stage_mask) = if swapchain.is_some() {
+            (
+                vec![cull_semaphore, present_semaphore],
+                vec![
+                    cull_wait_dst_stage_mask,
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                ],
+            )
+        } else {
+            (vec![cull_semaphore], vec![cull_wait_dst_stage_mask])
+        };
 
-        let submit_info = vk::SubmitInfo::default()
-            .wait_semaphores(&wait_semaphores)
-            .wait_dst_stage_mask(&wait_dst_stage_mask)
+        let mut submit_info = vk::SubmitInfo::default()
             .command_buffers(slice::from_ref(&command_buffer))
-            .signal_semaphores(slice::from_ref(&render_semaphore));
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_dst_stage_mask);
+        if swapchain.is_some() {
+            submit_info = submit_info.signal_semaphores(slice::from_ref(&render_semaphore));
+        }
+
+        //The animation overwrites the world matrices the culling of this frame reads
+        let num_animated = ctx.instance_animate_pass.num_animated + 1;
+        let mut animate_timeline_submit_info = vk::TimelineSemaphoreSubmitInfo::default()
+            .wait_semaphore_values(slice::from_ref(&ctx.instance_cull_pass.num_culled))
+            .signal_semaphore_values(slice::from_ref(&num_animated));
+        let animate_submit_info = vk::SubmitInfo::default()
+            .wait_semaphores(slice::from_ref(&ctx.instance_cull_pass.culled_semaphore))
+            .wait_dst_stage_mask(slice::from_ref(&vk::PipelineStageFlags::COMPUTE_SHADER))
+            .command_buffers(slice::from_ref(&animate_command_buffer))
+            .signal_semaphores(slice::from_ref(
+                &ctx.instance_animate_pass.animated_semaphore,
+            ))
+            .push_next(&mut animate_timeline_submit_info);
+        let submit_infos = if animate {
+            vec![animate_submit_info, submit_info]
+        } else {
+            vec![submit_info]
+        };
 
         device_loader
-            .queue_submit(direct_queue, slice::from_ref(&submit_info), fence)
-            .unwrap();
+            .queue_submit(direct_queue, &submit_infos, fence)
+            .during("Submitting the frame")?;
+        if animate {
+            ctx.instance_animate_pass.num_animated = num_animated;
+        }
+
+        //Capturing is rare, so simply wait for the frame instead of deferring the readback
+        if let Some(capture_buffer) = capture_buffer {
+            device_loader
+                .wait_for_fences(slice::from_ref(&fence), true, u64::MAX)
+                .during("Waiting for the captured frame")?;
+            ctx.captured_frame = Some(capture::read_captured_frame(
+                &capture_buffer,
+                capture_extent,
+            ));
+            ctx.capture_requested = false;
+        }
+        if pick_position.is_some() {
+            device_loader
+                .wait_for_fences(slice::from_ref(&fence), true, u64::MAX)
+                .during("Waiting for the picked instance")?;
+            ctx.selected_instance = ctx.picking_pass.read_picked_instance();
+            ctx.pick_requested = None;
+        }
 
-        let present_info = vk::PresentInfoKHR::default()
-            .wait_semaphores(slice::from_ref(&render_semaphore))
-            .swapchains(slice::from_ref(&swapchain))
-            .image_indices(slice::from_ref(&image_index));
+        if let Some(swapchain) = swapchain {
+            let present_info = vk::PresentInfoKHR::default()
+                .wait_semaphores(slice::from_ref(&render_semaphore))
+                .swapchains(slice::from_ref(&swapchain))
+                .image_indices(slice::from_ref(&image_index));
 
-        swapchain_loader
-            .queue_present(direct_queue, &present_info)
-            .unwrap();
+            match swapchain_loader.queue_present(present_queue, &present_info) {
+                Ok(suboptimal) => ctx.swapchain.out_of_date |= suboptimal,
+                //E.g. after the window was resized, the swapchain is created again before the next frame
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => ctx.swapchain.out_of_date = true,
+                //E.g. after switching to another window, the frames are presented through the compositor then
+                Err(vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT) => {
+                    println!("Lost exclusive fullscreen, presenting through the compositor");
+                    ctx.swapchain.release_full_screen_exclusive();
+                }
+                Err(result) => Err(result).during("Presenting the frame")?,
+            }
+        }
     }
+
+    Ok(())
+}
+
+//Renders the window with the resources of the main window swapped for its own
+pub fn render_secondary_window(
+    ctx: &mut RenderCtx,
+    secondary_window: &mut SecondaryWindow,
+) -> Result<(), RenderError> {
+    let culling_view_projection_matrix = ctx
+        .render_settings
+        .culling_freeze_view_projection
+        .unwrap_or_else(|| compute_view_projection_matrix(ctx));
+    ctx.secondary_view = Some(SecondaryView {
+        camera: secondary_window.camera,
+        culling_view_projection_matrix,
+    });
+
+    secondary_window.swap_resources(ctx);
+    let result = render_frame(ctx, &mut secondary_window.frame_index);
+    secondary_window.swap_resources(ctx);
+    ctx.secondary_view = None;
+
+    secondary_window.frame_index = (secondary_window.frame_index + 1) % frame::NUM_FRAMES;
+    result
 }
@@ -0,0 +1,76 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicI64, Ordering},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    Pipeline,
+    DescriptorSet,
+    Buffer,
+    Image,
+    CommandBuffer,
+}
+
+const NUM_RESOURCE_KINDS: usize = 5;
+
+static LIVE_COUNTS: [AtomicI64; NUM_RESOURCE_KINDS] = [
+    AtomicI64::new(0),
+    AtomicI64::new(0),
+    AtomicI64::new(0),
+    AtomicI64::new(0),
+    AtomicI64::new(0),
+];
+
+#[inline]
+pub fn track_created(kind: ResourceKind) {
+    LIVE_COUNTS[kind as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn track_destroyed(kind: ResourceKind) {
+    LIVE_COUNTS[kind as usize].fetch_sub(1, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn live_count(kind: ResourceKind) -> i64 {
+    LIVE_COUNTS[kind as usize].load(Ordering::Relaxed)
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ResourceCounts {
+    pub pipelines: i64,
+    pub descriptor_sets: i64,
+    pub max_descriptor_sets: u32,
+    pub buffers: i64,
+    pub images: i64,
+    pub command_buffers: i64,
+}
+
+impl ResourceCounts {
+    pub fn snapshot(max_descriptor_sets: u32) -> Self {
+        Self {
+            pipelines: live_count(ResourceKind::Pipeline),
+            descriptor_sets: live_count(ResourceKind::DescriptorSet),
+            max_descriptor_sets,
+            buffers: live_count(ResourceKind::Buffer),
+            images: live_count(ResourceKind::Image),
+            command_buffers: live_count(ResourceKind::CommandBuffer),
+        }
+    }
+}
+
+impl fmt::Display for ResourceCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pipelines: {}, descriptor sets: {}/{}, buffers: {}, images: {}, command buffers: {}",
+            self.pipelines,
+            self.descriptor_sets,
+            self.max_descriptor_sets,
+            self.buffers,
+            self.images,
+            self.command_buffers
+        )
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use ash::{vk, Device};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TrackedResource {
+    Buffer(vk::Buffer),
+    //Only the first mip level is tracked, the example doesn't use any others. All layers are transitioned together
+    Image {
+        image: vk::Image,
+        aspect_mask: vk::ImageAspectFlags,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ResourceAccess {
+    pub stage_mask: vk::PipelineStageFlags2,
+    pub access_mask: vk::AccessFlags2,
+    //Ignored for buffers
+    pub layout: vk::ImageLayout,
+}
+
+impl ResourceAccess {
+    #[inline]
+    pub const fn new(stage_mask: vk::PipelineStageFlags2, access_mask: vk::AccessFlags2) -> Self {
+        Self {
+            stage_mask,
+            access_mask,
+            layout: vk::ImageLayout::UNDEFINED,
+        }
+    }
+
+    #[inline]
+    pub const fn with_layout(self, layout: vk::ImageLayout) -> Self {
+        Self { layout, ..self }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct ResourceState {
+    layout: vk::ImageLayout,
+    //Layout transitions count as writes
+    last_write_stage_mask: vk::PipelineStageFlags2,
+    last_write_access_mask: vk::AccessFlags2,
+    //Reads which are already synchronized with the last write
+    read_stage_mask: vk::PipelineStageFlags2,
+    read_access_mask: vk::AccessFlags2,
+}
+
+impl ResourceState {
+    //Returns the source stage and access masks of the barrier, None if no barrier is needed
+    fn transition(
+        &mut self,
+        access: ResourceAccess,
+        write: bool,
+    ) -> Option<(vk::PipelineStageFlags2, vk::AccessFlags2)> {
+        if write || access.layout != self.layout {
+            //Waiting for the reads orders the write after them, the last write was already made available to them
+            let src_stage_mask = self.last_write_stage_mask | self.read_stage_mask;
+            let src_access_mask = if self.read_stage_mask.is_empty() {
+                self.last_write_access_mask
+            } else {
+                vk::AccessFlags2::NONE
+            };
+            let layout_changed = access.layout != self.layout;
+
+            *self = Self {
+                layout: access.layout,
+                last_write_stage_mask: access.stage_mask,
+                last_write_access_mask: if write {
+                    access.access_mask
+                } else {
+                    vk::AccessFlags2::NONE
+                },
+                read_stage_mask: if write {
+                    vk::PipelineStageFlags2::NONE
+                } else {
+                    access.stage_mask
+                },
+                read_access_mask: if write {
+                    vk::AccessFlags2::NONE
+                } else {
+                    access.access_mask
+                },
+            };
+
+            return (layout_changed || !src_stage_mask.is_empty())
+                .then_some((src_stage_mask, src_access_mask))
+        }
+
+        //Reads can overlap with each other, they only have to wait for the last write
+        let synchronized = self.read_stage_mask.contains(access.stage_mask)
+            && self.read_access_mask.contains(access.access_mask);
+        self.read_stage_mask |= access.stage_mask;
+        self.read_access_mask |= access.access_mask;
+
+        (!synchronized && !self.last_write_stage_mask.is_empty())
+            .then_some((self.last_write_stage_mask, self.last_write_access_mask))
+    }
+}
+
+//Remembers the layout and the last accesses of buffers and images, so only the barriers which are actually needed are
+//recorded. Barriers are collected until they are flushed, so they can be batched into one vkCmdPipelineBarrier2
+#[derive(Default)]
+pub struct ResourceStateTracker {
+    states: HashMap<TrackedResource, ResourceState>,
+    image_memory_barriers: Vec<vk::ImageMemoryBarrier2<'static>>,
+    buffer_memory_barriers: Vec<vk::BufferMemoryBarrier2<'static>>,
+}
+
+impl ResourceStateTracker {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //The access a resource was left in by earlier command buffers, it's treated as a write to be safe. Resources which
+    //aren't imported start out undefined
+    pub fn import(&mut self, resource: TrackedResource, access: ResourceAccess) {
+        self.states.insert(
+            resource,
+            ResourceState {
+                layout: access.layout,
+                last_write_stage_mask: access.stage_mask,
+                last_write_access_mask: access.access_mask,
+                read_stage_mask: vk::PipelineStageFlags2::NONE,
+                read_access_mask: vk::AccessFlags2::NONE,
+            },
+        );
+    }
+
+    #[inline]
+    pub fn read(&mut self, resource: TrackedResource, access: ResourceAccess) {
+        self.transition(resource, access, false);
+    }
+
+    #[inline]
+    pub fn write(&mut self, resource: TrackedResource, access: ResourceAccess) {
+        self.transition(resource, access, true);
+    }
+
+    fn transition(&mut self, resource: TrackedResource, access: ResourceAccess, write: bool) {
+        let state = self.states.entry(resource).or_insert(ResourceState {
+            layout: vk::ImageLayout::UNDEFINED,
+            last_write_stage_mask: vk::PipelineStageFlags2::NONE,
+            last_write_access_mask: vk::AccessFlags2::NONE,
+            read_stage_mask: vk::PipelineStageFlags2::NONE,
+            read_access_mask: vk::AccessFlags2::NONE,
+        });
+        let old_layout = state.layout;
+        let Some((src_stage_mask, src_access_mask)) = state.transition(access, write) else {
+            return
+        };
+
+        match resource {
+            TrackedResource::Buffer(buffer) => {
+                self.buffer_memory_barriers.push(
+                    vk::BufferMemoryBarrier2::default()
+                        .src_stage_mask(src_stage_mask)
+                        .src_access_mask(src_access_mask)
+                        .dst_stage_mask(access.stage_mask)
+                        .dst_access_mask(access.access_mask)
+                        .buffer(buffer)
+                        .size(vk::WHOLE_SIZE),
+                )
+            }
+            TrackedResource::Image { image, aspect_mask } => {
+                self.image_memory_barriers.push(
+                    vk::ImageMemoryBarrier2::default()
+                        .src_stage_mask(src_stage_mask)
+                        .src_access_mask(src_access_mask)
+                        .dst_stage_mask(access.stage_mask)
+                        .dst_access_mask(access.access_mask)
+                        .old_layout(old_layout)
+                        .new_layout(access.layout)
+                        .image(image)
+                        .subresource_range(
+                            vk::ImageSubresourceRange::default()
+                                .aspect_mask(aspect_mask)
+                                .level_count(1)
+                                .layer_count(vk::REMAINING_ARRAY_LAYERS),
+                        ),
+                )
+            }
+        }
+    }
+
+    //Records the barriers of all transitions since the last flush
+    pub unsafe fn flush(&mut self, device: &Device, command_buffer: vk::CommandBuffer) {
+        if self.image_memory_barriers.is_empty() && self.buffer_memory_barriers.is_empty() {
+            return
+        }
+
+        device.cmd_pipeline_barrier2(
+            command_buffer,
+            &vk::DependencyInfo::default()
+                .image_memory_barriers(&self.image_memory_barriers)
+                .buffer_memory_barriers(&self.buffer_memory_barriers),
+        );
+
+        self.image_memory_barriers.clear();
+        self.buffer_memory_barriers.clear();
+    }
+}
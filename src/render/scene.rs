@@ -0,0 +1,305 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Result};
+use glam::{Mat4, Vec2, Vec3};
+use serde::Deserialize;
+
+use crate::render::{
+    instances,
+    instances::{InstanceAnimation, GROUND_ORIGIN},
+    mesh::{MeshSource, Vertex},
+    mesh_builder::MeshBuilder,
+    render_ctx::FIELD_OF_VIEW,
+    terrain,
+    terrain::TerrainConfig,
+};
+
+//The primitives of the demo scene are about as large as its models
+const PRIMITIVE_SCALE: f32 = 2.0;
+
+fn ground_plane() -> MeshSource {
+    MeshSource::Builtin(
+        vec![
+            Vertex::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec2::new(0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ),
+            Vertex::new(
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec2::new(1.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ),
+            Vertex::new(
+                Vec3::new(1.0, 0.0, 1.0),
+                Vec2::new(1.0, 1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ),
+            Vertex::new(
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec2::new(0.0, 1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ),
+        ],
+        vec![0, 3, 1, 1, 3, 2],
+    )
+}
+
+//Fit into a box of size one around the origin
+#[derive(Copy, Clone, Debug)]
+enum Primitive {
+    Cube,
+    Sphere,
+    Icosphere,
+    Torus,
+    Cylinder,
+}
+
+impl Primitive {
+    const ALL: [Self; 5] = [
+        Self::Cube,
+        Self::Sphere,
+        Self::Icosphere,
+        Self::Torus,
+        Self::Cylinder,
+    ];
+
+    fn mesh_source(self) -> MeshSource {
+        let builder = MeshBuilder::new();
+        match self {
+            Self::Cube => builder.cube(1.0),
+            Self::Sphere => builder.uv_sphere(0.5, 32, 16),
+            Self::Icosphere => builder.icosphere(0.5, 3),
+            Self::Torus => builder.torus(0.35, 0.15, 48, 16),
+            Self::Cylinder => builder.cylinder(0.5, 1.0, 32),
+        }
+        .into()
+    }
+
+    #[inline]
+    fn height(self) -> f32 {
+        match self {
+            Self::Torus => 0.3,
+            _ => 1.0,
+        }
+    }
+}
+
+fn demo_mesh_sources() -> Vec<MeshSource> {
+    vec![
+        ground_plane(),
+        MeshSource::Path("dragon.obj".into()),
+        MeshSource::Path("armadillo.obj".into()),
+        MeshSource::Path("bunny.obj".into()),
+    ]
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MeshDesc {
+    Plane,
+    Path(String),
+    Terrain(TerrainConfig),
+    Cube,
+    Sphere,
+    Icosphere,
+    Torus,
+    Cylinder,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+//Rotations are around the y axis and in degrees, the GPU animates nothing else. Orbits are around the world origin
+#[derive(Deserialize)]
+struct InstanceDesc {
+    mesh: u32,
+    position: [f32; 3],
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default)]
+    angular_velocity: f32,
+    #[serde(default)]
+    orbit_velocity: f32,
+}
+
+//Example: {"meshes": ["plane", {"path": "bunny.obj"}, "torus"], "instances": [{"mesh": 1, "position": [0, 0, 0]}]}
+#[derive(Deserialize)]
+struct SceneDesc {
+    meshes: Vec<MeshDesc>,
+    instances: Vec<InstanceDesc>,
+}
+
+//Everything the renderer draws, the mesh_idx of every instance indexes meshes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scene {
+    pub meshes: Vec<MeshSource>,
+    pub instances: Vec<InstanceAnimation>,
+    //Seconds the instance animations have advanced
+    pub time: f32,
+}
+
+impl Default for Scene {
+    //The plane with the grid of animated models shown by the example
+    #[inline]
+    fn default() -> Self {
+        Self {
+            meshes: demo_mesh_sources(),
+            instances: instances::create_instance_grid(&instances::DEMO_MODEL_PLACEMENTS),
+            time: 0.0,
+        }
+    }
+}
+
+impl Scene {
+    //The demo scene with its models replaced, they are placed unscaled on the ground plane
+    pub fn with_models(paths: &[String]) -> Self {
+        if paths.is_empty() {
+            return Self::default()
+        }
+
+        let mut meshes = vec![ground_plane()];
+        meshes.extend(paths.iter().cloned().map(MeshSource::Path));
+
+        Self {
+            meshes,
+            instances: instances::create_instance_grid(&vec![(1.0, 0.0); paths.len()]),
+            time: 0.0,
+        }
+    }
+
+    //The demo scene with procedural primitives instead of the models, so it runs without any model files
+    pub fn with_primitives() -> Self {
+        let mut meshes = vec![ground_plane()];
+        meshes.extend(Primitive::ALL.map(Primitive::mesh_source));
+
+        let placements = Primitive::ALL.map(|primitive| {
+            (
+                PRIMITIVE_SCALE,
+                GROUND_ORIGIN.y + 0.5 * PRIMITIVE_SCALE * primitive.height(),
+            )
+        });
+
+        Self {
+            meshes,
+            instances: instances::create_instance_grid(&placements),
+            time: 0.0,
+        }
+    }
+
+    //Replaces the ground plane of the demo scene, the instance grid stands on its highest points
+    pub fn with_terrain(mut self, terrain_config: &TerrainConfig) -> Self {
+        self.meshes[0] = terrain::generate(terrain_config);
+        self.instances[0] = InstanceAnimation::new(GROUND_ORIGIN, 1.0, 0.0, 0.0, 0);
+        self
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let scene_desc: SceneDesc = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        let scene = Self {
+            meshes: scene_desc
+                .meshes
+                .into_iter()
+                .map(|mesh_desc| {
+                    match mesh_desc {
+                        MeshDesc::Plane => ground_plane(),
+                        MeshDesc::Path(path) => MeshSource::Path(path),
+                        MeshDesc::Terrain(terrain_config) => terrain::generate(&terrain_config),
+                        MeshDesc::Cube => Primitive::Cube.mesh_source(),
+                        MeshDesc::Sphere => Primitive::Sphere.mesh_source(),
+                        MeshDesc::Icosphere => Primitive::Icosphere.mesh_source(),
+                        MeshDesc::Torus => Primitive::Torus.mesh_source(),
+                        MeshDesc::Cylinder => Primitive::Cylinder.mesh_source(),
+                    }
+                })
+                .collect(),
+            instances: scene_desc
+                .instances
+                .iter()
+                .map(|instance_desc| {
+                    InstanceAnimation::new(
+                        instance_desc.position.into(),
+                        instance_desc.scale,
+                        instance_desc.rotation.to_radians(),
+                        instance_desc.angular_velocity.to_radians(),
+                        instance_desc.mesh,
+                    )
+                    .with_orbit_velocity(instance_desc.orbit_velocity.to_radians())
+                })
+                .collect(),
+            time: 0.0,
+        };
+        scene.validate()?;
+
+        Ok(scene)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.meshes.is_empty() || self.instances.is_empty() {
+            bail!("A scene needs at least one mesh and one instance")
+        }
+
+        if let Some(instance) = self
+            .instances
+            .iter()
+            .find(|instance| instance.mesh_idx as usize >= self.meshes.len())
+        {
+            bail!(
+                "Instance references mesh {}, but the scene only has {} meshes",
+                instance.mesh_idx,
+                self.meshes.len()
+            )
+        }
+
+        Ok(())
+    }
+
+    //Only the meshes and instances live in GPU buffers, so changing the time is free
+    #[inline]
+    pub fn same_geometry(&self, other: &Self) -> bool {
+        self.meshes == other.meshes && self.instances == other.instances
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    pub forward: Vec3,
+    pub up: Vec3,
+    //Vertical field of view in degrees
+    pub field_of_view: f32,
+}
+
+impl Default for Camera {
+    //Matches the initial transform of the interactive camera
+    #[inline]
+    fn default() -> Self {
+        Self {
+            position: Vec3::Y,
+            forward: Vec3::NEG_Z,
+            up: Vec3::Y,
+            field_of_view: FIELD_OF_VIEW,
+        }
+    }
+}
+
+impl Camera {
+    #[inline]
+    pub fn look_at(position: Vec3, target: Vec3) -> Self {
+        Self {
+            position,
+            forward: (target - position).normalize(),
+            up: Vec3::Y,
+            field_of_view: FIELD_OF_VIEW,
+        }
+    }
+
+    #[inline]
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_at_lh(self.position, self.position + self.forward, self.up)
+    }
+}
@@ -0,0 +1,168 @@
+use std::mem::ManuallyDrop;
+
+use anyhow::Result;
+use ash::vk;
+
+use crate::render::{
+    instance_bvh::InstanceBvh,
+    instances::InstanceBuffers,
+    mesh::{MeshCollection, MeshSource, MeshletConfig},
+    render_device::RenderDevice,
+    scene::Scene,
+    workers::WorkerPool,
+};
+
+//The GPU buffers of the meshes and instances which are drawn
+pub struct SceneResources {
+    pub mesh_collection: ManuallyDrop<MeshCollection>,
+    pub instance_buffers: ManuallyDrop<InstanceBuffers>,
+    //Over the bounds of the instances at the time of the frame, see refit_instance_bvh
+    pub instance_bvh: InstanceBvh,
+    pub mesh_sources: Vec<MeshSource>,
+    pub meshlet_config: MeshletConfig,
+    pub asset_workers: WorkerPool,
+    descriptor_pool: vk::DescriptorPool,
+    mesh_descriptor_set_layout: vk::DescriptorSetLayout,
+    mask_descriptor_set_layout: vk::DescriptorSetLayout,
+    instance_descriptor_set_layout: vk::DescriptorSetLayout,
+}
+
+impl SceneResources {
+    pub unsafe fn new(
+        device: &RenderDevice,
+        descriptor_pool: vk::DescriptorPool,
+        mesh_descriptor_set_layout: vk::DescriptorSetLayout,
+        mask_descriptor_set_layout: vk::DescriptorSetLayout,
+        instance_descriptor_set_layout: vk::DescriptorSetLayout,
+        scene: &Scene,
+        meshlet_config: MeshletConfig,
+        asset_workers: WorkerPool,
+    ) -> Result<Self> {
+        let mesh_collection = MeshCollection::new(
+            &device.device_loader,
+            device.direct_transfer_queue(),
+            device.transfer_queue,
+            device.allocator,
+            descriptor_pool,
+            mesh_descriptor_set_layout,
+            mask_descriptor_set_layout,
+            scene.meshes.clone(),
+            &meshlet_config,
+            &asset_workers,
+            device.sharing_family_indices(),
+        )?;
+        let instance_buffers = InstanceBuffers::new(
+            &device.device_loader,
+            device.direct_transfer_queue(),
+            device.allocator,
+            descriptor_pool,
+            instance_descriptor_set_layout,
+            scene.instances.clone(),
+            &device.sharing_family_indices(),
+        )?;
+
+        Ok(Self {
+            mesh_collection: ManuallyDrop::new(mesh_collection),
+            instance_buffers: ManuallyDrop::new(instance_buffers),
+            instance_bvh: InstanceBvh::default(),
+            mesh_sources: scene.meshes.clone(),
+            meshlet_config,
+            asset_workers,
+            descriptor_pool,
+            mesh_descriptor_set_layout,
+            mask_descriptor_set_layout,
+            instance_descriptor_set_layout,
+        })
+    }
+
+    //The device has to be idle
+    pub unsafe fn set_scene(&mut self, device: &RenderDevice, scene: &Scene) -> Result<()> {
+        //Free the old meshes first, the descriptor pool only has room for one collection
+        ManuallyDrop::drop(&mut self.instance_buffers);
+        ManuallyDrop::drop(&mut self.mesh_collection);
+
+        self.mesh_collection = ManuallyDrop::new(MeshCollection::new(
+            &device.device_loader,
+            device.direct_transfer_queue(),
+            device.transfer_queue,
+            device.allocator,
+            self.descriptor_pool,
+            self.mesh_descriptor_set_layout,
+            self.mask_descriptor_set_layout,
+            scene.meshes.clone(),
+            &self.meshlet_config,
+            &self.asset_workers,
+            device.sharing_family_indices(),
+        )?);
+        self.instance_buffers = ManuallyDrop::new(InstanceBuffers::new(
+            &device.device_loader,
+            device.direct_transfer_queue(),
+            device.allocator,
+            self.descriptor_pool,
+            self.instance_descriptor_set_layout,
+            scene.instances.clone(),
+            &device.sharing_family_indices(),
+        )?);
+        self.instance_bvh = InstanceBvh::default();
+
+        self.mesh_sources = scene.meshes.clone();
+        Ok(())
+    }
+
+    //The device has to be idle
+    pub unsafe fn set_meshlet_config(
+        &mut self,
+        device: &RenderDevice,
+        meshlet_config: MeshletConfig,
+    ) -> Result<()> {
+        //Free the old meshes first, the descriptor pool only has room for one collection
+        ManuallyDrop::drop(&mut self.mesh_collection);
+        self.mesh_collection = ManuallyDrop::new(MeshCollection::new(
+            &device.device_loader,
+            device.direct_transfer_queue(),
+            device.transfer_queue,
+            device.allocator,
+            self.descriptor_pool,
+            self.mesh_descriptor_set_layout,
+            self.mask_descriptor_set_layout,
+            self.mesh_sources.clone(),
+            &meshlet_config,
+            &self.asset_workers,
+            device.sharing_family_indices(),
+        )?);
+
+        self.meshlet_config = meshlet_config;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn num_instances(&self) -> usize {
+        self.instance_buffers.num_instances()
+    }
+
+    //The instances are animated on the device, their bounds are computed from the same animation on the host. Meshes
+    //which are still loading are bounded by their placeholder
+    pub fn refit_instance_bvh(&mut self, time: f32) {
+        let aabbs: Vec<_> = self
+            .instance_buffers
+            .instance_animations
+            .iter()
+            .map(|instance_animation| {
+                self.mesh_collection
+                    .mesh_buffers_at(instance_animation.mesh_idx as _)
+                    .aabb()
+                    .transformed(&instance_animation.world_matrix_at(time))
+            })
+            .collect();
+        self.instance_bvh.refit(&aabbs);
+    }
+}
+
+impl Drop for SceneResources {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.instance_buffers);
+            ManuallyDrop::drop(&mut self.mesh_collection);
+        }
+    }
+}
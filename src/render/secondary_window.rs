@@ -0,0 +1,191 @@
+use std::{mem, mem::ManuallyDrop, sync::Arc};
+
+use anyhow::anyhow;
+use ash::{extensions::khr::Surface, vk, Device};
+use glam::Mat4;
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use winit::window::Window;
+
+use crate::render::{
+    error::{ErrorContext, RenderError},
+    frame_resources::FrameResources,
+    render_ctx,
+    render_ctx::RenderCtx,
+    scene::Camera,
+    swapchain::SwapchainBundle,
+    utils::globals::GlobalsBuffers,
+};
+
+//What a secondary window is rendered with, everything else is shared with the main window
+#[derive(Copy, Clone, Debug)]
+pub struct SecondaryView {
+    pub camera: Camera,
+    //The frustum of the main window, so the window shows what the main window culls
+    pub culling_view_projection_matrix: Mat4,
+}
+
+//A window rendered in addition to the main window, e.g. to watch the culling of the main camera from outside. It has its
+//own surface, swapchain and frames in flight, the scene and the passes belong to the RenderCtx
+pub struct SecondaryWindow {
+    pub camera: Camera,
+    pub frame_index: usize,
+    surface: vk::SurfaceKHR,
+    swapchain: ManuallyDrop<SwapchainBundle>,
+    frame_resources: ManuallyDrop<FrameResources>,
+    surface_loader: Surface,
+    device: Arc<Device>,
+}
+
+impl SecondaryWindow {
+    //Stereo rendering has a single set of eye images sized for the main window, so it's not supported
+    pub fn new(ctx: &RenderCtx, window: &Window, camera: Camera) -> Result<Self, RenderError> {
+        if ctx.stereo_pass.is_some() {
+            return Err(RenderError::Other {
+                context: "Creating the secondary window",
+                source: anyhow!("Secondary windows are not supported with stereo rendering"),
+            })
+        }
+
+        let device = &ctx.device;
+        let surface = unsafe {
+            ash_window::create_surface(
+                &device.entry_loader,
+                &device.instance_loader,
+                window.raw_display_handle(),
+                window.raw_window_handle(),
+                None,
+            )
+        }
+        .during("Creating the surface")?;
+
+        let resources = unsafe { Self::create_resources(ctx, window, surface) };
+        let (swapchain, frame_resources) = match resources {
+            Ok(resources) => resources,
+            Err(error) => {
+                unsafe { device.surface_loader.destroy_surface(surface, None) };
+                return Err(error)
+            }
+        };
+
+        Ok(Self {
+            camera,
+            frame_index: 0,
+            surface,
+            swapchain: ManuallyDrop::new(swapchain),
+            frame_resources: ManuallyDrop::new(frame_resources),
+            surface_loader: device.surface_loader.clone(),
+            device: device.device_loader.clone(),
+        })
+    }
+
+    unsafe fn create_resources(
+        ctx: &RenderCtx,
+        window: &Window,
+        surface: vk::SurfaceKHR,
+    ) -> Result<(SwapchainBundle, FrameResources), RenderError> {
+        let device = &ctx.device;
+
+        //The frames are presented on the present queue
+        if !device
+            .surface_loader
+            .get_physical_device_surface_support(
+                device.physical_device,
+                device.present_queue_family_index,
+                surface,
+            )
+            .during("Querying the surface support")?
+        {
+            return Err(RenderError::Other {
+                context: "Creating the secondary window",
+                source: anyhow!("The present queue can't present to the window"),
+            })
+        }
+
+        let extent = vk::Extent2D {
+            width: window.inner_size().width,
+            height: window.inner_size().height,
+        };
+        let swapchain = SwapchainBundle::new(
+            device,
+            Some(surface),
+            extent,
+            ctx.render_config.present_mode,
+            //Only the main window can hold exclusive fullscreen
+            None,
+            None,
+        )?;
+
+        let globals_buffers = GlobalsBuffers::new(
+            &device.device_loader,
+            device.allocator,
+            device
+                .physical_device_properties
+                .limits
+                .min_uniform_buffer_offset_alignment,
+            &device.sharing_family_indices(),
+        )
+        .during("Creating the globals buffers")?;
+        let frame_resources = FrameResources::new(
+            device,
+            globals_buffers,
+            extent,
+            ctx.scene_resources.num_instances(),
+            render_ctx::num_recording_threads(&ctx.recording_workers),
+            ctx.frame_resources.samples,
+        )?;
+
+        Ok((swapchain, frame_resources))
+    }
+
+    #[inline]
+    pub fn out_of_date(&self) -> bool {
+        self.swapchain.out_of_date
+    }
+
+    //Creates the swapchain again with the new size of the window, together with the depth and multisampled images
+    pub fn resize(&mut self, ctx: &RenderCtx, window: &Window) -> Result<(), RenderError> {
+        let extent = vk::Extent2D {
+            width: window.inner_size().width,
+            height: window.inner_size().height,
+        };
+
+        unsafe {
+            self.device
+                .device_wait_idle()
+                .during("Waiting for the device")?;
+
+            *self.swapchain = SwapchainBundle::new(
+                &ctx.device,
+                Some(self.surface),
+                extent,
+                ctx.render_config.present_mode,
+                None,
+                self.swapchain.swapchain,
+            )?;
+            self.frame_resources.resize(&ctx.device, extent)
+        }
+    }
+
+    //Exchanges the swapchain and the frames with the ones of the main window, called again to swap them back
+    #[inline]
+    pub(crate) fn swap_resources(&mut self, ctx: &mut RenderCtx) {
+        mem::swap(&mut self.swapchain, &mut ctx.swapchain);
+        mem::swap(&mut self.frame_resources, &mut ctx.frame_resources);
+    }
+}
+
+impl Drop for SecondaryWindow {
+    fn drop(&mut self) {
+        unsafe {
+            //Nothing runs on a lost device anymore, so everything can be destroyed right away
+            match self.device.device_wait_idle() {
+                Ok(()) | Err(vk::Result::ERROR_DEVICE_LOST) => {}
+                Err(error) => panic!("Failed to wait for the device: {error}"),
+            }
+
+            ManuallyDrop::drop(&mut self.frame_resources);
+            ManuallyDrop::drop(&mut self.swapchain);
+            self.surface_loader.destroy_surface(self.surface, None);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::{
+    path::Path,
+    sync::mpsc::{self, Receiver},
+};
+
+use anyhow::Result;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+//Watches the shader directory on the notify thread, the render thread polls for changes once per frame
+pub struct ShaderWatcher {
+    receiver: Receiver<notify::Result<Event>>,
+    _watcher: RecommendedWatcher,
+}
+
+impl ShaderWatcher {
+    pub fn new(shader_dir: impl AsRef<Path>) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(shader_dir.as_ref(), RecursiveMode::Recursive)?;
+
+        Ok(Self {
+            receiver,
+            _watcher: watcher,
+        })
+    }
+
+    //Editors often save in several steps, so every change since the last poll is coalesced into one
+    pub fn poll_changed(&self) -> bool {
+        let mut changed = false;
+
+        for event in self.receiver.try_iter() {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    changed |= event.paths.iter().any(|path| {
+                        path.extension().map_or(false, |extension| {
+                            extension == "glsl" || extension == "slang" || extension == "wgsl"
+                        })
+                    })
+                }
+                Ok(_) => {}
+                Err(error) => eprintln!("Warning: Failed to watch the shaders: {error}"),
+            }
+        }
+
+        changed
+    }
+}
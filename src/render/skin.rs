@@ -0,0 +1,384 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{bail, Result};
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Quat, Vec3};
+use gltf::animation::{util::ReadOutputs, Interpolation, Property};
+
+use crate::render::{mesh::Mesh, mesh_import};
+
+//Has to match VertexSkin in shaders/skinning.comp.glsl, the weights are unorm16. Vertices without any weight aren't
+//moved by the skeleton
+#[derive(Copy, Clone, Debug, Default, PartialEq, Zeroable, Pod)]
+#[repr(C)]
+pub struct VertexSkin {
+    pub joints: [u16; 4],
+    pub weights: [u16; 4],
+}
+
+//Has to match MorphDelta in shaders/skinning.comp.glsl, it is added to the rest vertex times the weight of its target
+#[derive(Copy, Clone, Debug, Default, PartialEq, Zeroable, Pod)]
+#[repr(C)]
+pub struct MorphDelta {
+    pub position: Vec3,
+    pub normal: Vec3,
+}
+
+//The morph targets of all nodes of a file are numbered one after another
+#[derive(Clone, Debug, Default)]
+pub struct MorphTargets {
+    //The first morph target of every node with morph targets, by the index of the node in the file
+    pub first_targets: HashMap<usize, usize>,
+    //The weights of all targets while they aren't animated
+    pub default_weights: Vec<f32>,
+}
+
+//A node of the scene the skeleton is part of, parents always come before their children
+#[derive(Copy, Clone, Debug)]
+struct SkeletonNode {
+    parent: Option<usize>,
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+}
+
+#[derive(Clone, Debug)]
+enum Keyframes {
+    Translations(Vec<Vec3>),
+    Rotations(Vec<Quat>),
+    Scales(Vec<Vec3>),
+    //The weights of all morph targets of a node
+    Weights(Vec<Vec<f32>>),
+}
+
+//Keyframes of one property of one node, they are interpolated linearly
+#[derive(Clone, Debug)]
+struct Channel {
+    //The node for transforms, the first morph target of the node for weights
+    target: usize,
+    times: Vec<f32>,
+    keyframes: Keyframes,
+}
+
+impl Channel {
+    //Returns the two keyframes around the time and how far it is between them
+    fn sample_position(&self, time: f32) -> (usize, usize, f32) {
+        let next = self
+            .times
+            .partition_point(|keyframe_time| *keyframe_time <= time);
+        if next == 0 {
+            return (0, 0, 0.0)
+        }
+        if next == self.times.len() {
+            return (next - 1, next - 1, 0.0)
+        }
+
+        let (start, end) = (self.times[next - 1], self.times[next]);
+        (
+            next - 1,
+            next,
+            (time - start) / (end - start).max(f32::EPSILON),
+        )
+    }
+}
+
+//Cubic splines store an in and an out tangent around every value, only the values are interpolated
+fn keyframe_values<T>(values: impl Iterator<Item = T>, cubic_spline: bool) -> Vec<T> {
+    if cubic_spline {
+        values.skip(1).step_by(3).collect()
+    } else {
+        values.collect()
+    }
+}
+
+//The joints of a skin and the morph targets with the first animation of the file, which loops forever
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    nodes: Vec<SkeletonNode>,
+    //The node of every joint and the matrix which transforms the vertices from the mesh into the space of the joint
+    joints: Vec<(usize, Mat4)>,
+    default_morph_weights: Vec<f32>,
+    channels: Vec<Channel>,
+    duration: f32,
+}
+
+impl Skeleton {
+    pub fn from_gltf(
+        document: &gltf::Document,
+        scene: &gltf::Scene,
+        skin: Option<&gltf::Skin>,
+        morph_targets: &MorphTargets,
+        buffers: &[gltf::buffer::Data],
+    ) -> Result<Self> {
+        //Only the nodes of the scene are kept, in an order where parents come first
+        let mut nodes = Vec::new();
+        let mut node_indices = HashMap::new();
+        let mut pending: Vec<_> = scene.nodes().map(|node| (node, None)).collect();
+        while let Some((node, parent)) = pending.pop() {
+            let (translation, rotation, scale) = node.transform().decomposed();
+
+            node_indices.insert(node.index(), nodes.len());
+            pending.extend(node.children().map(|child| (child, Some(nodes.len()))));
+            nodes.push(SkeletonNode {
+                parent,
+                translation: translation.into(),
+                rotation: Quat::from_array(rotation),
+                scale: scale.into(),
+            });
+        }
+
+        let mut joints = Vec::new();
+        if let Some(skin) = skin {
+            let inverse_bind_matrices: Vec<_> = match skin
+                .reader(|buffer| Some(&buffers[buffer.index()]))
+                .read_inverse_bind_matrices()
+            {
+                Some(matrices) => {
+                    matrices
+                        .map(|matrix| Mat4::from_cols_array_2d(&matrix))
+                        .collect()
+                }
+                None => vec![Mat4::IDENTITY; skin.joints().count()],
+            };
+
+            for (joint, inverse_bind_matrix) in skin.joints().zip(inverse_bind_matrices) {
+                match node_indices.get(&joint.index()) {
+                    Some(node_idx) => joints.push((*node_idx, inverse_bind_matrix)),
+                    None => bail!("Joint {} is not part of the scene", joint.index()),
+                }
+            }
+        }
+
+        let mut channels = Vec::new();
+        let mut duration = 0.0f32;
+        for channel in document
+            .animations()
+            .next()
+            .into_iter()
+            .flat_map(|animation| animation.channels())
+        {
+            let node_idx = channel.target().node().index();
+            let target = match channel.target().property() {
+                Property::MorphTargetWeights => morph_targets.first_targets.get(&node_idx),
+                _ => node_indices.get(&node_idx),
+            };
+            let Some(target) = target.copied() else {
+                continue
+            };
+
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let (Some(inputs), Some(outputs)) = (reader.read_inputs(), reader.read_outputs())
+            else {
+                continue
+            };
+            let times: Vec<_> = inputs.collect();
+            if times.is_empty() {
+                continue
+            }
+
+            let cubic_spline = channel.sampler().interpolation() == Interpolation::CubicSpline;
+            let keyframes = match outputs {
+                ReadOutputs::Translations(translations) => {
+                    Keyframes::Translations(keyframe_values(
+                        translations.map(Vec3::from),
+                        cubic_spline,
+                    ))
+                }
+                ReadOutputs::Rotations(rotations) => {
+                    Keyframes::Rotations(keyframe_values(
+                        rotations.into_f32().map(Quat::from_array),
+                        cubic_spline,
+                    ))
+                }
+                ReadOutputs::Scales(scales) => {
+                    Keyframes::Scales(keyframe_values(scales.map(Vec3::from), cubic_spline))
+                }
+                ReadOutputs::MorphTargetWeights(weights) => {
+                    let weights: Vec<_> = weights.into_f32().collect();
+                    let num_values = if cubic_spline { 3 } else { 1 } * times.len();
+                    if weights.len() < num_values {
+                        continue
+                    }
+                    Keyframes::Weights(keyframe_values(
+                        weights
+                            .chunks_exact(weights.len() / num_values)
+                            .map(<[f32]>::to_vec),
+                        cubic_spline,
+                    ))
+                }
+            };
+
+            duration = duration.max(times.last().copied().unwrap_or_default());
+            channels.push(Channel {
+                target,
+                times,
+                keyframes,
+            });
+        }
+
+        Ok(Self {
+            nodes,
+            joints,
+            default_morph_weights: morph_targets.default_weights.clone(),
+            channels,
+            duration,
+        })
+    }
+
+    #[inline]
+    pub fn num_joints(&self) -> usize {
+        self.joints.len()
+    }
+
+    #[inline]
+    pub fn num_morph_targets(&self) -> usize {
+        self.default_morph_weights.len()
+    }
+
+    #[inline]
+    fn animation_time(&self, time: f32) -> f32 {
+        if self.duration > 0.0 {
+            time.rem_euclid(self.duration)
+        } else {
+            0.0
+        }
+    }
+
+    //Transforms the vertices from the mesh into the animated pose at the time
+    pub fn joint_matrices(&self, time: f32) -> Vec<Mat4> {
+        let time = self.animation_time(time);
+
+        let mut nodes = self.nodes.clone();
+        for channel in &self.channels {
+            let (start, end, t) = channel.sample_position(time);
+            let node = &mut nodes[channel.target];
+            match &channel.keyframes {
+                Keyframes::Translations(translations) if end < translations.len() => {
+                    node.translation = translations[start].lerp(translations[end], t)
+                }
+                Keyframes::Rotations(rotations) if end < rotations.len() => {
+                    node.rotation = rotations[start].slerp(rotations[end], t)
+                }
+                Keyframes::Scales(scales) if end < scales.len() => {
+                    node.scale = scales[start].lerp(scales[end], t)
+                }
+                _ => {}
+            }
+        }
+
+        let mut global_matrices: Vec<Mat4> = Vec::with_capacity(nodes.len());
+        for node in &nodes {
+            let local_matrix =
+                Mat4::from_scale_rotation_translation(node.scale, node.rotation, node.translation);
+            global_matrices.push(match node.parent {
+                Some(parent) => global_matrices[parent] * local_matrix,
+                None => local_matrix,
+            });
+        }
+
+        self.joints
+            .iter()
+            .map(|(node_idx, inverse_bind_matrix)| {
+                global_matrices[*node_idx] * *inverse_bind_matrix
+            })
+            .collect()
+    }
+
+    //The weight of every morph target at the time
+    pub fn morph_weights(&self, time: f32) -> Vec<f32> {
+        let time = self.animation_time(time);
+
+        let mut morph_weights = self.default_morph_weights.clone();
+        for channel in &self.channels {
+            let Keyframes::Weights(weights) = &channel.keyframes else {
+                continue
+            };
+            let (start, end, t) = channel.sample_position(time);
+            let (Some(start_weights), Some(end_weights)) = (weights.get(start), weights.get(end))
+            else {
+                continue
+            };
+
+            for (i, morph_weight) in morph_weights[channel.target..].iter_mut().enumerate() {
+                if let (Some(start_weight), Some(end_weight)) =
+                    (start_weights.get(i), end_weights.get(i))
+                {
+                    *morph_weight = start_weight + (end_weight - start_weight) * t;
+                }
+            }
+        }
+        morph_weights
+    }
+}
+
+//The skeleton with the influences of every vertex of every level which has its own vertices
+#[derive(Clone, Debug)]
+pub struct MeshSkin {
+    pub skeleton: Skeleton,
+    //Empty for levels which share the vertices of level 0
+    pub level_vertex_skins: Vec<Vec<VertexSkin>>,
+    //The deltas of all vertices of the first morph target, then the ones of the second and so on. Empty like the
+    //vertex skins or without morph targets
+    pub level_morph_deltas: Vec<Vec<MorphDelta>>,
+}
+
+//Baking reorders, welds and simplifies the vertices, but it never changes them. So the influences and morph deltas of the
+//baked vertices are found again by their attributes, which also works for meshes loaded from the meshlet cache. Welded
+//vertices get the morph deltas of one of them
+pub fn load(path: &Path, mesh: &Mesh) -> Result<Option<MeshSkin>> {
+    if !mesh_import::is_gltf(path) {
+        return Ok(None)
+    }
+
+    let gltf_mesh = mesh_import::import_gltf(path)?;
+    let Some(skeleton) = gltf_mesh.skeleton else {
+        return Ok(None)
+    };
+
+    let vertex_indices: HashMap<_, _> = gltf_mesh
+        .vertices
+        .iter()
+        .enumerate()
+        .map(|(vertex_idx, vertex)| (bytemuck::bytes_of(vertex).to_vec(), vertex_idx))
+        .collect();
+
+    let mut level_vertex_skins = Vec::with_capacity(mesh.levels.len());
+    let mut level_morph_deltas = Vec::with_capacity(mesh.levels.len());
+    for level in &mesh.levels {
+        let level_vertex_indices: Vec<_> = level
+            .vertices
+            .iter()
+            .map(|vertex| vertex_indices.get(bytemuck::bytes_of(vertex)).copied())
+            .collect();
+
+        level_vertex_skins.push(
+            level_vertex_indices
+                .iter()
+                .map(|vertex_idx| {
+                    vertex_idx
+                        .map(|vertex_idx| gltf_mesh.vertex_skins[vertex_idx])
+                        .unwrap_or_default()
+                })
+                .collect(),
+        );
+        level_morph_deltas.push(
+            gltf_mesh
+                .morph_deltas
+                .iter()
+                .flat_map(|morph_deltas| {
+                    level_vertex_indices.iter().map(|vertex_idx| {
+                        vertex_idx
+                            .map(|vertex_idx| morph_deltas[vertex_idx])
+                            .unwrap_or_default()
+                    })
+                })
+                .collect(),
+        );
+    }
+
+    Ok(Some(MeshSkin {
+        skeleton,
+        level_vertex_skins,
+        level_morph_deltas,
+    }))
+}
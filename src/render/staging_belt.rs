@@ -0,0 +1,202 @@
+use std::{mem, slice, sync::Arc};
+
+use anyhow::Result;
+use ash::{vk, Device};
+use bytemuck::Pod;
+use vk_mem_alloc::Allocator;
+
+use crate::render::{
+    buffer::Buffer,
+    hitch_detector,
+    resource_registry::{self, ResourceKind},
+};
+
+pub const STAGING_BELT_SIZE: usize = 16 << 20;
+const STAGING_ALIGNMENT: usize = 16;
+
+//The queue uploads are submitted to, a dedicated transfer queue copies without stalling the graphics queue
+#[derive(Copy, Clone, Debug)]
+pub struct TransferQueue {
+    pub queue: vk::Queue,
+    //The graphics family followed by the family of the queue
+    family_indices: [u32; 2],
+}
+
+impl TransferQueue {
+    #[inline]
+    pub fn new(queue: vk::Queue, family_index: u32, graphics_family_index: u32) -> Self {
+        Self {
+            queue,
+            family_indices: [graphics_family_index, family_index],
+        }
+    }
+
+    //Uploads on the graphics queue itself
+    #[inline]
+    pub fn graphics(queue: vk::Queue, family_index: u32) -> Self {
+        Self::new(queue, family_index, family_index)
+    }
+
+    #[inline]
+    pub fn family_index(&self) -> u32 {
+        self.family_indices[1]
+    }
+
+    #[inline]
+    pub fn is_dedicated(&self) -> bool {
+        self.family_indices[0] != self.family_indices[1]
+    }
+}
+
+//Collects copies in one staging buffer and submits them together, the belt is flushed early once it's full
+pub struct StagingBelt {
+    staging_buffer: Buffer,
+    used: usize,
+    copies: Vec<(vk::Buffer, vk::BufferCopy)>,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    transfer_queue: TransferQueue,
+    allocator: Allocator,
+    device: Arc<Device>,
+}
+
+impl Drop for StagingBelt {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_fence(self.fence, None);
+            self.device
+                .free_command_buffers(self.command_pool, slice::from_ref(&self.command_buffer));
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+        resource_registry::track_destroyed(ResourceKind::CommandBuffer);
+    }
+}
+
+impl StagingBelt {
+    pub unsafe fn new(
+        device: Arc<Device>,
+        transfer_queue: TransferQueue,
+        allocator: Allocator,
+        size: usize,
+    ) -> Result<Self> {
+        let staging_buffer = Buffer::new_staging(device.clone(), allocator, size)?;
+
+        let command_pool = device.create_command_pool(
+            &vk::CommandPoolCreateInfo::default().queue_family_index(transfer_queue.family_index()),
+            None,
+        )?;
+        let command_buffer = device.allocate_command_buffers(
+            &vk::CommandBufferAllocateInfo::default()
+                .command_pool(command_pool)
+                .command_buffer_count(1),
+        )?[0];
+        resource_registry::track_created(ResourceKind::CommandBuffer);
+        let fence = device.create_fence(&vk::FenceCreateInfo::default(), None)?;
+
+        Ok(Self {
+            staging_buffer,
+            used: 0,
+            copies: Vec::new(),
+            command_pool,
+            command_buffer,
+            fence,
+            transfer_queue,
+            allocator,
+            device,
+        })
+    }
+
+    //The data is copied into the staging buffer right away, dst is only written once the belt is flushed
+    pub unsafe fn upload<T: Pod>(
+        &mut self,
+        dst: &Buffer,
+        dst_offset: vk::DeviceSize,
+        data: &[T],
+    ) -> Result<()> {
+        let size = mem::size_of_val(data);
+        if size == 0 {
+            return Ok(())
+        }
+        debug_assert!(dst_offset + size as vk::DeviceSize <= dst.size);
+
+        let mut offset = self.used.next_multiple_of(STAGING_ALIGNMENT);
+        if offset + size > self.staging_buffer.size as usize {
+            self.flush()?;
+            offset = 0;
+
+            //Uploads larger than the whole belt grow it, the old staging buffer is idle after the flush
+            if size > self.staging_buffer.size as usize {
+                self.staging_buffer =
+                    Buffer::new_staging(self.device.clone(), self.allocator, size)?;
+            }
+        }
+
+        libc::memcpy(
+            self.staging_buffer
+                .allocation_info
+                .mapped_data
+                .cast::<u8>()
+                .add(offset)
+                .cast(),
+            data.as_ptr().cast(),
+            size,
+        );
+        self.used = offset + size;
+
+        self.copies.push((
+            dst.buffer,
+            vk::BufferCopy::default()
+                .src_offset(offset as _)
+                .dst_offset(dst_offset)
+                .size(size as _),
+        ));
+
+        Ok(())
+    }
+
+    //Submits every pending copy at once and blocks until they finished, afterwards the belt starts from the beginning
+    pub unsafe fn flush(&mut self) -> Result<()> {
+        if self.copies.is_empty() {
+            return Ok(())
+        }
+
+        let device = &self.device;
+
+        device.reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())?;
+        device.begin_command_buffer(
+            self.command_buffer,
+            &vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+        )?;
+        for (dst, copy) in &self.copies {
+            device.cmd_copy_buffer(
+                self.command_buffer,
+                self.staging_buffer.buffer,
+                *dst,
+                slice::from_ref(copy),
+            );
+        }
+        device.end_command_buffer(self.command_buffer)?;
+
+        device.queue_submit(
+            self.transfer_queue.queue,
+            slice::from_ref(
+                &vk::SubmitInfo::default().command_buffers(slice::from_ref(&self.command_buffer)),
+            ),
+            self.fence,
+        )?;
+        device.wait_for_fences(slice::from_ref(&self.fence), true, u64::MAX)?;
+        device.reset_fences(slice::from_ref(&self.fence))?;
+
+        hitch_detector::record_event(format!(
+            "Uploaded {} bytes in {} copies",
+            self.used,
+            self.copies.len()
+        ));
+
+        self.copies.clear();
+        self.used = 0;
+        Ok(())
+    }
+}
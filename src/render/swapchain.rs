@@ -0,0 +1,246 @@
+use std::{ptr, sync::Arc};
+
+use ash::{extensions::khr::Swapchain, vk, Device};
+use vk_mem_alloc::{Allocation, Allocator};
+
+use crate::render::{
+    error::{ErrorContext, RenderError},
+    frame,
+    full_screen_exclusive::FullScreenExclusive,
+    render_ctx::SWAPCHAIN_FORMAT,
+    render_device::RenderDevice,
+    utils,
+};
+
+//The images which are rendered to, offscreen images take the place of the swapchain images when rendering headless
+pub struct SwapchainBundle {
+    pub swapchain: Option<vk::SwapchainKHR>,
+    pub images: Vec<vk::Image>,
+    pub image_views: Vec<vk::ImageView>,
+    pub offscreen_image_allocations: Vec<Allocation>,
+    pub extent: vk::Extent2D,
+    //Set when acquiring or presenting reported that the swapchain doesn't match the surface anymore, or the window was
+    //resized. It has to be created again before the next frame
+    pub out_of_date: bool,
+    //Set while the swapchain holds exclusive fullscreen, it's released before the swapchain is destroyed
+    full_screen_exclusive: Option<FullScreenExclusive>,
+    swapchain_loader: Swapchain,
+    device: Arc<Device>,
+    allocator: Allocator,
+}
+
+impl SwapchainBundle {
+    //Creates a swapchain if there is a surface, the old swapchain of the surface is retired and can be destroyed afterwards
+    pub fn new(
+        device: &RenderDevice,
+        surface: Option<vk::SurfaceKHR>,
+        extent: vk::Extent2D,
+        present_mode: vk::PresentModeKHR,
+        full_screen_exclusive: Option<&FullScreenExclusive>,
+        old_swapchain: Option<vk::SwapchainKHR>,
+    ) -> Result<Self, RenderError> {
+        let device_loader = &device.device_loader;
+        let swapchain_loader = &device.swapchain_loader;
+        let allocator = device.allocator;
+
+        let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC;
+
+        //Presents through the compositor if the surface can't be presented to exclusively
+        let full_screen_exclusive = match (surface, full_screen_exclusive) {
+            (Some(surface), Some(full_screen_exclusive)) => {
+                let supported =
+                    unsafe { full_screen_exclusive.supported(device.physical_device, surface) }
+                        .during("Querying the exclusive fullscreen support")?;
+                if !supported {
+                    println!("Exclusive fullscreen is not supported by the surface");
+                }
+                supported.then_some(full_screen_exclusive)
+            }
+            _ => None,
+        };
+
+        let (swapchain, images, image_views, offscreen_image_allocations) = if let Some(surface) =
+            surface
+        {
+            let supported_present_modes = unsafe {
+                device
+                    .surface_loader
+                    .get_physical_device_surface_present_modes(device.physical_device, surface)
+            }
+            .during("Querying the present modes")?;
+            let present_mode = if supported_present_modes.contains(&present_mode) {
+                present_mode
+            } else {
+                println!("Present mode {present_mode:?} is not supported, falling back to FIFO");
+                vk::PresentModeKHR::FIFO
+            };
+
+            let mut full_screen_exclusive_info = vk::SurfaceFullScreenExclusiveInfoEXT::default()
+                .full_screen_exclusive(vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED);
+            let mut full_screen_exclusive_win32_info =
+                vk::SurfaceFullScreenExclusiveWin32InfoEXT::default().hmonitor(
+                    full_screen_exclusive.map_or(ptr::null_mut(), FullScreenExclusive::monitor),
+                );
+
+            //Rendered on the direct queue and presented on the present queue, which can be of another family
+            let queue_family_indices = [
+                device.direct_queue_family_index,
+                device.present_queue_family_index,
+            ];
+            let mut swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
+                .surface(surface)
+                .min_image_count(2)
+                .image_format(SWAPCHAIN_FORMAT)
+                .image_color_space(vk::ColorSpaceKHR::SRGB_NONLINEAR)
+                .image_extent(extent)
+                .image_array_layers(1)
+                .image_usage(image_usage)
+                .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
+                .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+                .present_mode(present_mode)
+                .old_swapchain(old_swapchain.unwrap_or_default());
+            if device.present_queue_family_index != device.direct_queue_family_index {
+                swapchain_create_info = swapchain_create_info
+                    .image_sharing_mode(vk::SharingMode::CONCURRENT)
+                    .queue_family_indices(&queue_family_indices);
+            }
+            if full_screen_exclusive.is_some() {
+                swapchain_create_info = swapchain_create_info
+                    .push_next(&mut full_screen_exclusive_info)
+                    .push_next(&mut full_screen_exclusive_win32_info);
+            }
+
+            let swapchain =
+                unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None) }
+                    .during("Creating the swapchain")?;
+            let images = unsafe { swapchain_loader.get_swapchain_images(swapchain) }
+                .during("Querying the swapchain images")?;
+
+            let image_views = images
+                .iter()
+                .map(|image| {
+                    let image_view_create_info = vk::ImageViewCreateInfo::default()
+                        .image(*image)
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .format(SWAPCHAIN_FORMAT)
+                        .components(Default::default())
+                        .subresource_range(
+                            vk::ImageSubresourceRange::default()
+                                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                .layer_count(1)
+                                .level_count(1),
+                        );
+
+                    unsafe { device_loader.create_image_view(&image_view_create_info, None) }
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .during("Creating the swapchain image views")?;
+
+            (Some(swapchain), images, image_views, Vec::new())
+        } else {
+            //One image per frame in flight, so the image index is simply the frame index
+            let mut images = Vec::new();
+            let mut image_views = Vec::new();
+            let mut offscreen_image_allocations = Vec::new();
+
+            for _ in 0..frame::NUM_FRAMES {
+                let (image, allocation, image_view) = unsafe {
+                    utils::create_color_image(
+                        device_loader,
+                        allocator,
+                        extent.width,
+                        extent.height,
+                        SWAPCHAIN_FORMAT,
+                        image_usage,
+                        vk::SampleCountFlags::TYPE_1,
+                    )
+                }
+                .during("Creating the offscreen images")?;
+
+                images.push(image);
+                image_views.push(image_view);
+                offscreen_image_allocations.push(allocation);
+            }
+
+            (None, images, image_views, offscreen_image_allocations)
+        };
+
+        //Without the acquisition the swapchain keeps presenting through the compositor
+        let full_screen_exclusive = swapchain
+            .zip(full_screen_exclusive)
+            .filter(|(swapchain, full_screen_exclusive)| {
+                match unsafe { full_screen_exclusive.acquire(*swapchain) } {
+                    Ok(()) => true,
+                    Err(result) => {
+                        println!("Acquiring exclusive fullscreen failed: {result}");
+                        false
+                    }
+                }
+            })
+            .map(|(_, full_screen_exclusive)| full_screen_exclusive.clone());
+
+        Ok(Self {
+            swapchain,
+            images,
+            image_views,
+            offscreen_image_allocations,
+            extent,
+            out_of_date: false,
+            full_screen_exclusive,
+            swapchain_loader: swapchain_loader.clone(),
+            device: device_loader.clone(),
+            allocator,
+        })
+    }
+
+    //Offscreen images are never presented, so they can't be transitioned to PRESENT_SRC_KHR
+    #[inline]
+    pub fn final_image_layout(&self) -> vk::ImageLayout {
+        if self.swapchain.is_some() {
+            vk::ImageLayout::PRESENT_SRC_KHR
+        } else {
+            vk::ImageLayout::GENERAL
+        }
+    }
+
+    //Presents through the compositor from now on, e.g. after another window took the monitor
+    pub fn release_full_screen_exclusive(&mut self) {
+        if let (Some(swapchain), Some(full_screen_exclusive)) =
+            (self.swapchain, self.full_screen_exclusive.take())
+        {
+            unsafe { full_screen_exclusive.release(swapchain) }.ok();
+        }
+    }
+}
+
+impl Drop for SwapchainBundle {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(swapchain) = self.swapchain {
+                if let Some(full_screen_exclusive) = &self.full_screen_exclusive {
+                    full_screen_exclusive.release(swapchain).ok();
+                }
+                self.image_views
+                    .iter()
+                    .for_each(|image_view| self.device.destroy_image_view(*image_view, None));
+                self.swapchain_loader.destroy_swapchain(swapchain, None);
+            } else {
+                for ((image, allocation), image_view) in self
+                    .images
+                    .iter()
+                    .zip(self.offscreen_image_allocations.drain(..))
+                    .zip(&self.image_views)
+                {
+                    utils::destroy_image(
+                        &self.device,
+                        self.allocator,
+                        *image,
+                        allocation,
+                        *image_view,
+                    );
+                }
+            }
+        }
+    }
+}
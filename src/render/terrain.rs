@@ -0,0 +1,122 @@
+use glam::{Vec2, Vec3};
+use serde::Deserialize;
+
+use crate::render::{
+    instances::GROUND_SIZE,
+    mesh::{MeshSource, Vertex},
+};
+
+fn lattice(x: i32, y: i32) -> f32 {
+    let mut hash = (x as u32).wrapping_mul(0x27d4_eb2d) ^ (y as u32).wrapping_mul(0x1656_67b1);
+    hash = (hash ^ (hash >> 15)).wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    (hash & 0xFFFF) as f32 / 65535.0
+}
+
+fn value_noise(x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let (tx, ty) = (x - x.floor(), y - y.floor());
+    let (tx, ty) = (tx * tx * (3.0 - 2.0 * tx), ty * ty * (3.0 - 2.0 * ty));
+
+    let top = lattice(x0, y0) + (lattice(x0 + 1, y0) - lattice(x0, y0)) * tx;
+    let bottom = lattice(x0, y0 + 1) + (lattice(x0 + 1, y0 + 1) - lattice(x0, y0 + 1)) * tx;
+    top + (bottom - top) * ty
+}
+
+//Value noise with a few octaves between 0 and 1, the hills are about 32 units apart
+pub fn height_at(x: f32, y: f32) -> f32 {
+    let mut height = 0.0;
+    let mut amplitude = 0.5;
+    let mut frequency = 1.0 / 32.0;
+    for _ in 0..4 {
+        height += amplitude * value_noise(x * frequency, y * frequency);
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    //The amplitudes add up to 0.9375
+    height / 0.9375
+}
+
+fn default_resolution() -> u32 {
+    512
+}
+
+fn default_size() -> f32 {
+    GROUND_SIZE
+}
+
+fn default_height_scale() -> f32 {
+    8.0
+}
+
+//Example: {"terrain": {"resolution": 1024, "size": 500, "height_scale": 20}}
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub struct TerrainConfig {
+    //Vertices along every side of the grid
+    #[serde(default = "default_resolution")]
+    pub resolution: u32,
+    //Length of every side in meters
+    #[serde(default = "default_size")]
+    pub size: f32,
+    //Depth of the deepest valley in meters
+    #[serde(default = "default_height_scale")]
+    pub height_scale: f32,
+}
+
+impl Default for TerrainConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            resolution: default_resolution(),
+            size: default_size(),
+            height_scale: default_height_scale(),
+        }
+    }
+}
+
+//A grid over the heightmap which starts at the origin and extends along x and z. The highest points are at a height of
+//0, so it never covers what stands on a ground plane at the same height. Baked like any other mesh, which also
+//simplifies it into its levels of detail
+pub fn generate(config: &TerrainConfig) -> MeshSource {
+    let resolution = config.resolution.max(2);
+    let spacing = config.size / (resolution - 1) as f32;
+
+    let height = |x: u32, z: u32| {
+        let (x, z) = (x.min(resolution - 1), z.min(resolution - 1));
+        (height_at(x as f32 * spacing, z as f32 * spacing) - 1.0) * config.height_scale
+    };
+
+    let vertices = (0..resolution * resolution)
+        .map(|i| {
+            let (x, z) = (i % resolution, i / resolution);
+
+            //Central differences, one sided at the borders
+            let slope_x = (height(x + 1, z) - height(x.saturating_sub(1), z))
+                / ((x + 1).min(resolution - 1) - x.saturating_sub(1)) as f32;
+            let slope_z = (height(x, z + 1) - height(x, z.saturating_sub(1)))
+                / ((z + 1).min(resolution - 1) - z.saturating_sub(1)) as f32;
+
+            Vertex::new(
+                Vec3::new(x as f32 * spacing, height(x, z), z as f32 * spacing),
+                Vec2::new(x as f32, z as f32) / (resolution - 1) as f32,
+                Vec3::new(-slope_x, spacing, -slope_z).normalize(),
+            )
+        })
+        .collect();
+
+    let indices = (0..resolution - 1)
+        .flat_map(|z| (0..resolution - 1).map(move |x| z * resolution + x))
+        .flat_map(|i| {
+            [
+                i,
+                i + resolution,
+                i + 1,
+                i + 1,
+                i + resolution,
+                i + resolution + 1,
+            ]
+        })
+        .collect();
+
+    MeshSource::Builtin(vertices, indices)
+}
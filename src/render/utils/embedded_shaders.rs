@@ -0,0 +1,27 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+//Generated by build.rs, the SPIR-V of every shader keyed by its path and the defines it was compiled with
+include!(concat!(env!("OUT_DIR"), "/embedded_shaders.rs"));
+
+//Only the defines in VARIANT_DEFINES of build.rs are compiled into variants of their own
+pub fn find(path: &Path, defines: &[(&str, Option<&str>)]) -> Result<Vec<u32>> {
+    let key = defines.iter().fold(
+        path.to_str().unwrap().replace('\\', "/"),
+        |key, (name, value)| {
+            match value {
+                Some(value) => format!("{key}#{name}={value}"),
+                None => format!("{key}#{name}"),
+            }
+        },
+    );
+
+    let (_, spirv) = EMBEDDED_SHADERS
+        .iter()
+        .find(|(embedded_key, _)| *embedded_key == key)
+        .ok_or_else(|| anyhow!("Shader {key} wasn't embedded at build time"))?;
+
+    //include_bytes only guarantees byte alignment
+    Ok(bytemuck::pod_collect_to_vec(spirv))
+}
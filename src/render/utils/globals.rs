@@ -1,11 +1,12 @@
-use std::{mem, slice, sync::Arc};
+use std::{cell::Cell, mem, slice, sync::Arc};
 
-use ash::{vk, Device};
+use anyhow::Result;
+use ash::{extensions::khr::PushDescriptor, vk, Device};
 use bytemuck::{Pod, Zeroable};
-use glam::{Mat4, Vec3, Vec4};
+use glam::{Mat4, Vec2, Vec3, Vec4};
 use vk_mem_alloc::Allocator;
 
-use crate::render::buffer::Buffer;
+use crate::render::{buffer::Buffer, frame::NUM_FRAMES};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Default, Zeroable, Pod)]
@@ -14,12 +15,32 @@ pub struct Globals {
     pub frustum_planes: [Vec4; 6],
     pub camera_pos: Vec3,
     pub time: f32,
+    //Not jittered, the velocity is computed from it
+    pub prev_view_projection_matrix: Mat4,
+    //Offset of view_projection_matrix in normalized device coordinates, zero without TAA
+    pub jitter: Vec2,
+    //Size of the rendered image in pixels, the mesh shader culls the triangles which miss every pixel center
+    pub viewport_size: Vec2,
+    //Indexed by gl_ViewIndex, both are view_projection_matrix if there is a single view
+    pub view_projection_matrices: [Mat4; 2],
+    //The lights are shaded in view space, see shaders/lights.glsl
+    pub view_matrix: Mat4,
+    //Diagonal of the unjittered projection in x and y, it maps view space to normalized device coordinates
+    pub projection_scale: Vec2,
+    pub num_lights: u32,
+    pub padding_lights: u32,
+    pub lights_address: vk::DeviceAddress,
+    pub light_clusters_address: vk::DeviceAddress,
 }
 
+//Every frame in flight has its own slot, so updating the globals never races with a frame which still reads them.
+//The descriptor set is pushed in the command buffer, so there is no descriptor set to allocate or keep alive
 pub struct GlobalsBuffers {
     pub uniform_buffer: Buffer,
     pub descriptor_set_layout: vk::DescriptorSetLayout,
-    pub descriptor_set: vk::DescriptorSet,
+    slot_size: usize,
+    //Offset of the slot written by the last update, it's the one push_descriptor_set pushes
+    slot_offset: Cell<vk::DeviceSize>,
     device: Arc<Device>,
 }
 
@@ -33,65 +54,88 @@ impl Drop for GlobalsBuffers {
 }
 
 impl GlobalsBuffers {
+    //The culling reads the globals on the compute queue, so they are shared with its family
     pub fn new(
         device: &Arc<Device>,
         allocator: Allocator,
-        descriptor_pool: vk::DescriptorPool,
-    ) -> Self {
+        min_uniform_buffer_offset_alignment: vk::DeviceSize,
+        sharing_family_indices: &[u32],
+    ) -> Result<Self> {
         //Create uniform buffer
-        let uniform_buffer =
-            unsafe { Buffer::new_uniform(device.clone(), allocator, mem::size_of::<Globals>()) }
-                .unwrap();
+        let slot_size = mem::size_of::<Globals>()
+            .next_multiple_of(min_uniform_buffer_offset_alignment.max(1) as usize);
+        let uniform_buffer = unsafe {
+            Buffer::new_uniform(
+                device.clone(),
+                allocator,
+                slot_size * NUM_FRAMES,
+                sharing_family_indices,
+            )
+        }?;
 
         //Create descriptor set layout
         let descriptor_set_layout_binding = vk::DescriptorSetLayoutBinding::default()
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
             .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::MESH_EXT | vk::ShaderStageFlags::COMPUTE);
+            .stage_flags(
+                vk::ShaderStageFlags::TASK_EXT
+                    | vk::ShaderStageFlags::MESH_EXT
+                    | vk::ShaderStageFlags::COMPUTE,
+            );
 
         let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+            .flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR)
             .bindings(slice::from_ref(&descriptor_set_layout_binding));
 
         let descriptor_set_layout = unsafe {
             device.create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
-        }
-        .unwrap();
+        }?;
+
+        Ok(Self {
+            uniform_buffer,
+            descriptor_set_layout,
+            slot_size,
+            slot_offset: Cell::new(0),
+            device: device.clone(),
+        })
+    }
 
-        //Create descriptor set
-        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
-            .descriptor_pool(descriptor_pool)
-            .set_layouts(slice::from_ref(&descriptor_set_layout));
+    //Only call this after waiting for the fence of the frame, its slot is overwritten
+    pub fn update(&self, frame_index: usize, globals: &Globals) {
+        let offset = frame_index * self.slot_size;
+        unsafe { self.uniform_buffer.write_at(offset, globals) };
+        self.slot_offset.set(offset as _);
+    }
 
-        let descriptor_set =
-            unsafe { device.allocate_descriptor_sets(&descriptor_set_allocate_info) }.unwrap()[0];
+    //Address of the slot written by the last update, the geometry shaders read the globals through it
+    #[inline]
+    pub fn device_address(&self) -> vk::DeviceAddress {
+        self.uniform_buffer.device_address + self.slot_offset.get()
+    }
 
-        //Write uniform buffer to descriptor set
+    //Pushes the slot written by the last update, the globals are set 0 of every pipeline layout using them
+    pub unsafe fn push_descriptor_set(
+        &self,
+        push_descriptor_loader: &PushDescriptor,
+        command_buffer: vk::CommandBuffer,
+        pipeline_bind_point: vk::PipelineBindPoint,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
         let descriptor_buffer_info = vk::DescriptorBufferInfo::default()
-            .buffer(uniform_buffer.buffer)
-            .range(uniform_buffer.size);
+            .buffer(self.uniform_buffer.buffer)
+            .offset(self.slot_offset.get())
+            .range(mem::size_of::<Globals>() as _);
 
         let write_descriptor_set = vk::WriteDescriptorSet::default()
-            .dst_set(descriptor_set)
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
             .buffer_info(slice::from_ref(&descriptor_buffer_info));
 
-        unsafe { device.update_descriptor_sets(slice::from_ref(&write_descriptor_set), &[]) };
-
-        Self {
-            uniform_buffer,
-            descriptor_set_layout,
-            descriptor_set,
-            device: device.clone(),
-        }
-    }
-
-    pub fn update(&self, globals: &Globals) {
-        unsafe {
-            libc::memcpy(
-                self.uniform_buffer.allocation_info.mapped_data,
-                globals as *const _ as *const _,
-                mem::size_of::<Globals>(),
-            );
-        }
+        push_descriptor_loader.cmd_push_descriptor_set(
+            command_buffer,
+            pipeline_bind_point,
+            pipeline_layout,
+            0,
+            slice::from_ref(&write_descriptor_set),
+        );
     }
 }
@@ -1,39 +1,71 @@
+#[cfg(feature = "embedded-shaders")]
+pub mod embedded_shaders;
 pub mod globals;
+pub mod permutations;
+pub mod pipeline_cache;
 pub mod pipelines;
+pub mod reflection;
+#[cfg(not(feature = "embedded-shaders"))]
+pub mod shader_compiler;
+#[cfg(not(feature = "embedded-shaders"))]
+pub mod slang;
+#[cfg(all(feature = "wgsl-shaders", not(feature = "embedded-shaders")))]
+pub mod wgsl;
 
-use std::slice;
+use std::{slice, sync::Arc};
 
+use anyhow::Result;
 use ash::{prelude::VkResult, vk, Device};
 use vk_mem_alloc::{Allocation, AllocationCreateInfo, Allocator, MemoryUsage};
 
+use crate::render::{
+    buffer::Buffer,
+    resource_registry::{self, ResourceKind},
+    resource_state::{ResourceAccess, ResourceStateTracker, TrackedResource},
+    staging_belt::TransferQueue,
+};
+
 #[inline]
 pub unsafe fn create_descriptor_pool(
     device: &Device,
+    max_sets: u32,
     pool_sizes: &[vk::DescriptorPoolSize],
 ) -> VkResult<vk::DescriptorPool> {
     device.create_descriptor_pool(
         &vk::DescriptorPoolCreateInfo::default()
-            .max_sets(
-                pool_sizes
-                    .iter()
-                    .map(|pool_size| pool_size.descriptor_count)
-                    .sum(),
-            )
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .max_sets(max_sets)
             .pool_sizes(pool_sizes),
         None,
     )
 }
 
+//Starts at zero, so waiting for zero never blocks
+#[inline]
+pub unsafe fn create_timeline_semaphore(device: &Device) -> VkResult<vk::Semaphore> {
+    let mut semaphore_type_create_info = vk::SemaphoreTypeCreateInfo::default()
+        .semaphore_type(vk::SemaphoreType::TIMELINE)
+        .initial_value(0);
+
+    device.create_semaphore(
+        &vk::SemaphoreCreateInfo::default().push_next(&mut semaphore_type_create_info),
+        None,
+    )
+}
+
 unsafe fn change_image_layout(
     device: &Device,
-    queue: vk::Queue,
+    queue: TransferQueue,
     image: vk::Image,
     old_layout: vk::ImageLayout,
     new_layout: vk::ImageLayout,
     aspect_mask: vk::ImageAspectFlags,
 ) -> VkResult<()> {
     //Make image layout transition, we create and destroy command pool/buffer here to keep it simple
-    let command_pool = device.create_command_pool(&vk::CommandPoolCreateInfo::default(), None)?;
+    let command_pool = device.create_command_pool(
+        &vk::CommandPoolCreateInfo::default().queue_family_index(queue.family_index()),
+        None,
+    )?;
     let command_buffer = device.allocate_command_buffers(
         &vk::CommandBufferAllocateInfo::default()
             .command_pool(command_pool)
@@ -43,29 +75,24 @@ unsafe fn change_image_layout(
 
     device.begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())?;
 
-    let image_memory_barrier = vk::ImageMemoryBarrier2::default()
-        .src_stage_mask(vk::PipelineStageFlags2::TOP_OF_PIPE)
-        .dst_stage_mask(vk::PipelineStageFlags2::BOTTOM_OF_PIPE)
-        .old_layout(old_layout)
-        .new_layout(new_layout)
-        .image(image)
-        .subresource_range(
-            vk::ImageSubresourceRange::default()
-                .aspect_mask(aspect_mask)
-                .level_count(1)
-                .layer_count(1),
-        );
-
-    device.cmd_pipeline_barrier2(
-        command_buffer,
-        &vk::DependencyInfo::default()
-            .image_memory_barriers(slice::from_ref(&image_memory_barrier)),
+    let image = TrackedResource::Image { image, aspect_mask };
+    let mut image_state = ResourceStateTracker::new();
+    image_state.import(
+        image,
+        ResourceAccess::new(vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE)
+            .with_layout(old_layout),
     );
+    image_state.read(
+        image,
+        ResourceAccess::new(vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE)
+            .with_layout(new_layout),
+    );
+    image_state.flush(device, command_buffer);
 
     device.end_command_buffer(command_buffer)?;
 
     device.queue_submit(
-        queue,
+        queue.queue,
         slice::from_ref(
             &vk::SubmitInfo::default().command_buffers(slice::from_ref(&command_buffer)),
         ),
@@ -82,11 +109,12 @@ unsafe fn change_image_layout(
 
 pub unsafe fn create_depth_stencil_image(
     device: &Device,
-    queue: vk::Queue,
+    queue: TransferQueue,
     allocator: Allocator,
     width: u32,
     height: u32,
     format: vk::Format,
+    samples: vk::SampleCountFlags,
 ) -> VkResult<(vk::Image, Allocation, vk::ImageView)> {
     let (image, allocation, _) = vk_mem_alloc::create_image(
         allocator,
@@ -100,7 +128,7 @@ pub unsafe fn create_depth_stencil_image(
             })
             .mip_levels(1)
             .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(samples)
             .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
             .initial_layout(vk::ImageLayout::UNDEFINED),
         &AllocationCreateInfo {
@@ -108,6 +136,7 @@ pub unsafe fn create_depth_stencil_image(
             ..Default::default()
         },
     )?;
+    resource_registry::track_created(ResourceKind::Image);
 
     let mut aspect_mask = vk::ImageAspectFlags::DEPTH;
     if format == vk::Format::D16_UNORM_S8_UINT
@@ -144,8 +173,285 @@ pub unsafe fn create_depth_stencil_image(
     Ok((image, allocation, image_view))
 }
 
+//The image is left in the UNDEFINED layout, it is transitioned whenever it is rendered to
+pub unsafe fn create_color_image(
+    device: &Device,
+    allocator: Allocator,
+    width: u32,
+    height: u32,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    samples: vk::SampleCountFlags,
+) -> VkResult<(vk::Image, Allocation, vk::ImageView)> {
+    let (image, allocation, _) = vk_mem_alloc::create_image(
+        allocator,
+        &vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .usage(usage)
+            .initial_layout(vk::ImageLayout::UNDEFINED),
+        &AllocationCreateInfo {
+            usage: MemoryUsage::AUTO_PREFER_DEVICE,
+            ..Default::default()
+        },
+    )?;
+    resource_registry::track_created(ResourceKind::Image);
+
+    let image_view = device.create_image_view(
+        &vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .components(Default::default())
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(1)
+                    .layer_count(1),
+            ),
+        None,
+    )?;
+
+    Ok((image, allocation, image_view))
+}
+
+//Every layer is one view of a multiview render pass, the view covers all of them. The image is left in the UNDEFINED
+//layout
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn create_layered_image(
+    device: &Device,
+    allocator: Allocator,
+    width: u32,
+    height: u32,
+    layers: u32,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    aspect_mask: vk::ImageAspectFlags,
+) -> VkResult<(vk::Image, Allocation, vk::ImageView)> {
+    let (image, allocation, _) = vk_mem_alloc::create_image(
+        allocator,
+        &vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(layers)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .usage(usage)
+            .initial_layout(vk::ImageLayout::UNDEFINED),
+        &AllocationCreateInfo {
+            usage: MemoryUsage::AUTO_PREFER_DEVICE,
+            ..Default::default()
+        },
+    )?;
+    resource_registry::track_created(ResourceKind::Image);
+
+    let image_view = device.create_image_view(
+        &vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+            .format(format)
+            .components(Default::default())
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(aspect_mask)
+                    .level_count(1)
+                    .layer_count(layers),
+            ),
+        None,
+    )?;
+
+    Ok((image, allocation, image_view))
+}
+
+pub unsafe fn create_storage_image(
+    device: &Device,
+    queue: TransferQueue,
+    allocator: Allocator,
+    width: u32,
+    height: u32,
+    format: vk::Format,
+) -> VkResult<(vk::Image, Allocation, vk::ImageView)> {
+    let (image, allocation, _) = vk_mem_alloc::create_image(
+        allocator,
+        &vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_DST)
+            .initial_layout(vk::ImageLayout::UNDEFINED),
+        &AllocationCreateInfo {
+            usage: MemoryUsage::AUTO_PREFER_DEVICE,
+            ..Default::default()
+        },
+    )?;
+    resource_registry::track_created(ResourceKind::Image);
+
+    let image_view = device.create_image_view(
+        &vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .components(Default::default())
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(1)
+                    .layer_count(1),
+            ),
+        None,
+    )?;
+
+    //Storage images stay in the GENERAL layout for their whole lifetime
+    change_image_layout(
+        device,
+        queue,
+        image,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::GENERAL,
+        vk::ImageAspectFlags::COLOR,
+    )?;
+
+    Ok((image, allocation, image_view))
+}
+
+//Copies the tightly packed pixels into a new image through a staging buffer and blocks until it's in
+//SHADER_READ_ONLY_OPTIMAL, where it stays for its whole lifetime
+pub unsafe fn create_texture_image(
+    device: &Arc<Device>,
+    queue: TransferQueue,
+    allocator: Allocator,
+    width: u32,
+    height: u32,
+    format: vk::Format,
+    pixels: &[u8],
+) -> Result<(vk::Image, Allocation, vk::ImageView)> {
+    let (image, allocation, image_view) = create_color_image(
+        device,
+        allocator,
+        width,
+        height,
+        format,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::SampleCountFlags::TYPE_1,
+    )?;
+
+    let staging_buffer = Buffer::new_staging(device.clone(), allocator, pixels.len().max(1))?;
+    libc::memcpy(
+        staging_buffer.allocation_info.mapped_data,
+        pixels.as_ptr().cast(),
+        pixels.len(),
+    );
+
+    let command_pool = device.create_command_pool(
+        &vk::CommandPoolCreateInfo::default().queue_family_index(queue.family_index()),
+        None,
+    )?;
+    let command_buffer = device.allocate_command_buffers(
+        &vk::CommandBufferAllocateInfo::default()
+            .command_pool(command_pool)
+            .command_buffer_count(1),
+    )?[0];
+    let fence = device.create_fence(&vk::FenceCreateInfo::default(), None)?;
+
+    device.begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())?;
+
+    let tracked_image = TrackedResource::Image {
+        image,
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+    };
+    let mut image_state = ResourceStateTracker::new();
+    image_state.import(
+        tracked_image,
+        ResourceAccess::new(vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+    );
+    image_state.write(
+        tracked_image,
+        ResourceAccess::new(
+            vk::PipelineStageFlags2::COPY,
+            vk::AccessFlags2::TRANSFER_WRITE,
+        )
+        .with_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+    );
+    image_state.flush(device, command_buffer);
+
+    let buffer_image_copy = vk::BufferImageCopy::default()
+        .image_subresource(
+            vk::ImageSubresourceLayers::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .layer_count(1),
+        )
+        .image_extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        });
+    device.cmd_copy_buffer_to_image(
+        command_buffer,
+        staging_buffer.buffer,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        slice::from_ref(&buffer_image_copy),
+    );
+
+    image_state.read(
+        tracked_image,
+        ResourceAccess::new(
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+        )
+        .with_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+    );
+    image_state.flush(device, command_buffer);
+
+    device.end_command_buffer(command_buffer)?;
+
+    device.queue_submit(
+        queue.queue,
+        slice::from_ref(
+            &vk::SubmitInfo::default().command_buffers(slice::from_ref(&command_buffer)),
+        ),
+        fence,
+    )?;
+
+    device.wait_for_fences(slice::from_ref(&fence), true, u64::MAX)?;
+    device.destroy_fence(fence, None);
+    device.destroy_command_pool(command_pool, None);
+
+    Ok((image, allocation, image_view))
+}
+
+//Wang hash of the state, which is advanced to the next one. Returns a value between 0 and 1
+pub fn random(hash: &mut u32) -> f32 {
+    *hash = (*hash ^ 61) ^ (*hash >> 16);
+    *hash = hash.wrapping_mul(9);
+    *hash ^= *hash >> 4;
+    *hash = hash.wrapping_mul(0x27d4_eb2d);
+    *hash ^= *hash >> 15;
+    (*hash & 0xFFFF) as f32 / 65535.0
+}
+
 #[inline]
-pub unsafe fn destroy_depth_stencil_image(
+pub unsafe fn destroy_image(
     device: &Device,
     allocator: Allocator,
     image: vk::Image,
@@ -153,5 +459,17 @@ pub unsafe fn destroy_depth_stencil_image(
     image_view: vk::ImageView,
 ) {
     vk_mem_alloc::destroy_image(allocator, image, allocation);
+    resource_registry::track_destroyed(ResourceKind::Image);
     device.destroy_image_view(image_view, None);
 }
+
+#[inline]
+pub unsafe fn destroy_depth_stencil_image(
+    device: &Device,
+    allocator: Allocator,
+    image: vk::Image,
+    allocation: Allocation,
+    image_view: vk::ImageView,
+) {
+    destroy_image(device, allocator, image, allocation, image_view);
+}
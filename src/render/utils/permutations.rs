@@ -0,0 +1,72 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use ash::{vk, Device};
+
+use crate::render::utils;
+
+//Pipelines which are created the first time their permutation is used, the keys are expected to be normalized so
+//permutations which only differ in features the shaders ignore share one pipeline
+pub struct PipelinePermutations<K> {
+    pipelines: Mutex<HashMap<K, vk::Pipeline>>,
+    device: Arc<Device>,
+}
+
+impl<K> Drop for PipelinePermutations<K> {
+    fn drop(&mut self) {
+        for (_, pipeline) in self.pipelines.get_mut().unwrap().drain() {
+            unsafe {
+                utils::pipelines::destroy(&self.device, pipeline);
+            }
+        }
+    }
+}
+
+impl<K: Copy + Eq + Hash> PipelinePermutations<K> {
+    #[inline]
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
+            pipelines: Mutex::new(HashMap::new()),
+            device,
+        }
+    }
+
+    //The lock is held while creating, so a permutation is never created twice
+    pub fn get_or_create(
+        &self,
+        key: K,
+        create: impl FnOnce(K) -> Result<vk::Pipeline>,
+    ) -> Result<vk::Pipeline> {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if let Some(pipeline) = pipelines.get(&key) {
+            return Ok(*pipeline)
+        }
+
+        let pipeline = create(key)?;
+        pipelines.insert(key, pipeline);
+        Ok(pipeline)
+    }
+
+    //Every permutation which was created so far
+    #[inline]
+    pub fn keys(&self) -> Vec<K> {
+        self.pipelines.lock().unwrap().keys().copied().collect()
+    }
+
+    //Returns the previous pipelines, they have to be destroyed by the caller once they aren't in use anymore
+    pub fn replace_all(
+        &self,
+        pipelines: impl IntoIterator<Item = (K, vk::Pipeline)>,
+    ) -> Vec<vk::Pipeline> {
+        let previous = mem::replace(
+            &mut *self.pipelines.lock().unwrap(),
+            pipelines.into_iter().collect(),
+        );
+        previous.into_values().collect()
+    }
+}
@@ -0,0 +1,71 @@
+use std::{env, fs, path::PathBuf};
+
+use anyhow::Result;
+use ash::{vk, Device};
+
+const APPLICATION_NAME: &str = "vk-ext-mesh-shader-example";
+const CACHE_FILE_NAME: &str = "pipeline_cache.bin";
+//VkPipelineCacheHeaderVersionOne, four u32 followed by the pipeline cache UUID
+const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+//XDG_CACHE_HOME or ~/.cache on Linux, the local app data on Windows and ~/Library/Caches on macOS
+fn cache_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+
+    base.map(|base| base.join(APPLICATION_NAME))
+}
+
+#[inline]
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+//Drivers reject foreign data themselves, but some of them crash on it instead, so it's checked up front
+fn is_compatible(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+    data.len() >= HEADER_SIZE
+        && read_u32(data, 0) as usize >= HEADER_SIZE
+        && read_u32(data, 4) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && read_u32(data, 8) == properties.vendor_id
+        && read_u32(data, 12) == properties.device_id
+        && data[16..HEADER_SIZE] == properties.pipeline_cache_uuid
+}
+
+//Starts with an empty cache if there is no cache file or it belongs to another device or driver
+pub unsafe fn load(
+    device: &Device,
+    properties: &vk::PhysicalDeviceProperties,
+) -> Result<vk::PipelineCache> {
+    let data = cache_dir()
+        .and_then(|cache_dir| fs::read(cache_dir.join(CACHE_FILE_NAME)).ok())
+        .filter(|data| is_compatible(data, properties))
+        .unwrap_or_default();
+
+    Ok(device.create_pipeline_cache(
+        &vk::PipelineCacheCreateInfo::default().initial_data(&data),
+        None,
+    )?)
+}
+
+pub unsafe fn save(device: &Device, pipeline_cache: vk::PipelineCache) -> Result<()> {
+    let Some(cache_dir) = cache_dir() else {
+        return Ok(())
+    };
+
+    let data = device.get_pipeline_cache_data(pipeline_cache)?;
+
+    //Written to a temporary file first, so a crash while saving never leaves a truncated cache behind
+    fs::create_dir_all(&cache_dir)?;
+    let temp_path = cache_dir.join(format!("{CACHE_FILE_NAME}.tmp"));
+    fs::write(&temp_path, data)?;
+    fs::rename(temp_path, cache_dir.join(CACHE_FILE_NAME))?;
+
+    Ok(())
+}
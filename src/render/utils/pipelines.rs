@@ -1,129 +1,300 @@
-use std::{ffi::CString, fs, fs::File, io::Read, path::Path, slice};
+use std::{ffi::CString, path::Path, slice};
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use ash::{vk, Device};
-use shaderc::{CompileOptions, Compiler, ResolvedInclude, ShaderKind, SpirvVersion};
 
-fn create_shader_module(
-    device: &Device,
-    kind: ShaderKind,
-    entry_point_name: &str,
-    path: impl AsRef<Path>,
-    defines: &[(&str, Option<&str>)],
-) -> Result<vk::ShaderModule> {
-    let path = path.as_ref();
-
-    let mut file = File::open(path)?;
+use crate::render::resource_registry::{self, ResourceKind};
+#[cfg(feature = "embedded-shaders")]
+use crate::render::utils::embedded_shaders;
+#[cfg(not(feature = "embedded-shaders"))]
+use crate::render::utils::shader_compiler;
+
+#[derive(Copy, Clone, Debug)]
+pub struct MultisampleState {
+    pub rasterization_samples: vk::SampleCountFlags,
+    pub min_sample_shading: Option<f32>,
+    pub alpha_to_coverage: bool,
+}
 
-    let mut buffer = String::new();
-    file.read_to_string(&mut buffer)?;
+impl Default for MultisampleState {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            min_sample_shading: None,
+            alpha_to_coverage: false,
+        }
+    }
+}
 
-    let compiler = Compiler::new().ok_or_else(|| anyhow!("Failed to create compiler"))?;
-    let mut compile_options =
-        CompileOptions::new().ok_or_else(|| anyhow!("Failed to create compile options"))?;
-    compile_options.set_include_callback(|requested_source, _, _, _| {
-        let path = Path::new("shaders").join(requested_source); //TODO: Only working with simple paths, but it is ok for now
+#[derive(Copy, Clone, Debug)]
+pub struct RasterState {
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub color_write: bool,
+    //Blends the color over the attachment by its alpha, which requires drawing back to front
+    pub alpha_blend: bool,
+    pub polygon_mode: vk::PolygonMode,
+    //Shades with the rate the mesh shader writes for each primitive, requires VK_KHR_fragment_shading_rate
+    pub primitive_shading_rate: bool,
+    //Renders every view in the mask at once with multiview, 0 renders a single view
+    pub view_mask: u32,
+}
 
-        Ok(ResolvedInclude {
-            resolved_name: path.to_str().unwrap().to_owned(),
-            content: fs::read_to_string(path).unwrap(),
-        })
-    });
-    compile_options.set_target_spirv(SpirvVersion::V1_6);
+impl Default for RasterState {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            depth_test: true,
+            depth_write: true,
+            color_write: true,
+            alpha_blend: false,
+            polygon_mode: vk::PolygonMode::FILL,
+            primitive_shading_rate: false,
+            view_mask: 0,
+        }
+    }
+}
 
-    for (name, value) in defines {
-        compile_options.add_macro_definition(name, *value);
+impl MultisampleState {
+    //Sample shading is an optional device feature, so drop it if the device doesn't support it
+    #[inline]
+    pub fn validated(mut self, sample_rate_shading_supported: bool) -> Self {
+        self.min_sample_shading = if sample_rate_shading_supported {
+            self.min_sample_shading.map(|rate| rate.clamp(0.0, 1.0))
+        } else {
+            None
+        };
+        self
     }
+}
 
-    let artifact = compiler.compile_into_spirv(
-        &buffer,
-        kind,
-        path.to_str().unwrap(),
-        entry_point_name,
-        Some(&compile_options),
-    )?;
+//SPIR-V of one shader stage, kept around so the pipeline layout can be reflected before the pipeline is created
+pub struct ShaderStage {
+    pub stage: vk::ShaderStageFlags,
+    pub entry_point: CString,
+    pub spirv: Vec<u32>,
+}
 
-    unsafe {
-        let shader_module_create_info =
-            vk::ShaderModuleCreateInfo::default().code(artifact.as_binary());
+//Looks the shader up in the binary if it was compiled at build time, otherwise it's compiled from the shaders directory
+pub fn compile_shader(
+    stage: vk::ShaderStageFlags,
+    path: impl AsRef<Path>,
+    entry_point_name: &str,
+    defines: &[(&str, Option<&str>)],
+) -> Result<ShaderStage> {
+    #[cfg(feature = "embedded-shaders")]
+    let spirv = embedded_shaders::find(path.as_ref(), defines)?;
+    #[cfg(not(feature = "embedded-shaders"))]
+    let spirv = shader_compiler::compile(stage, path.as_ref(), entry_point_name, defines)?;
+
+    Ok(ShaderStage {
+        stage,
+        entry_point: CString::new(entry_point_name)?,
+        spirv,
+    })
+}
 
-        Ok(device.create_shader_module(&shader_module_create_info, None)?)
+//Compiles the stages of a mesh pipeline, the task shader is optional
+pub fn compile_mesh_stages(
+    task_shader: Option<(&str, &str, &[(&str, Option<&str>)])>,
+    mesh_path: impl AsRef<Path>,
+    mesh_entry_point: &str,
+    mesh_defines: &[(&str, Option<&str>)],
+    fragment_path: impl AsRef<Path>,
+    fragment_entry_point: &str,
+    fragment_defines: &[(&str, Option<&str>)],
+) -> Result<Vec<ShaderStage>> {
+    let mut stages = Vec::with_capacity(3);
+    if let Some((path, entry_point, defines)) = task_shader {
+        stages.push(compile_shader(
+            vk::ShaderStageFlags::TASK_EXT,
+            path,
+            entry_point,
+            defines,
+        )?);
     }
+    stages.push(compile_shader(
+        vk::ShaderStageFlags::MESH_EXT,
+        mesh_path,
+        mesh_entry_point,
+        mesh_defines,
+    )?);
+    stages.push(compile_shader(
+        vk::ShaderStageFlags::FRAGMENT,
+        fragment_path,
+        fragment_entry_point,
+        fragment_defines,
+    )?);
+
+    Ok(stages)
+}
+
+#[inline]
+unsafe fn create_shader_module(device: &Device, stage: &ShaderStage) -> Result<vk::ShaderModule> {
+    let shader_module_create_info = vk::ShaderModuleCreateInfo::default().code(&stage.spirv);
+
+    Ok(device.create_shader_module(&shader_module_create_info, None)?)
 }
 
 pub unsafe fn create_compute(
     device: &Device,
-    path: impl AsRef<Path>,
-    entry_point: &str,
-    defines: &[(&str, Option<&str>)],
+    pipeline_cache: vk::PipelineCache,
+    stage: &ShaderStage,
     layout: vk::PipelineLayout,
 ) -> Result<vk::Pipeline> {
-    let compute_shader =
-        create_shader_module(device, ShaderKind::Compute, entry_point, path, defines)?;
-    let entry_point = CString::new(entry_point)?;
+    let compute_shader = create_shader_module(device, stage)?;
 
     let compute_pipeline_create_info = vk::ComputePipelineCreateInfo::default()
         .stage(
             vk::PipelineShaderStageCreateInfo::default()
                 .stage(vk::ShaderStageFlags::COMPUTE)
                 .module(compute_shader)
-                .name(&entry_point),
+                .name(&stage.entry_point),
         )
         .layout(layout);
 
     let pipeline = device
         .create_compute_pipelines(
-            vk::PipelineCache::null(),
+            pipeline_cache,
             slice::from_ref(&compute_pipeline_create_info),
             None,
         )
-        .unwrap()[0];
+        .map_err(|(_, error)| error);
 
     device.destroy_shader_module(compute_shader, None);
 
+    let pipeline = pipeline?[0];
+    resource_registry::track_created(ResourceKind::Pipeline);
+
     Ok(pipeline)
 }
 
+//Constant i of every stage is specialized to specialization[i], constants a stage doesn't declare are ignored
+#[allow(clippy::too_many_arguments)]
+#[inline]
 pub unsafe fn create_mesh(
     device: &Device,
-    mesh_path: impl AsRef<Path>,
-    mesh_entry_point: &str,
-    mesh_defines: &[(&str, Option<&str>)],
-    fragment_path: impl AsRef<Path>,
-    fragment_entry_point: &str,
-    fragment_defines: &[(&str, Option<&str>)],
-    swapchain_format: vk::Format,
+    pipeline_cache: vk::PipelineCache,
+    stages: &[ShaderStage],
+    specialization: &[u32],
+    color_formats: &[vk::Format],
     depth_format: vk::Format,
+    multisample_state: &MultisampleState,
+    raster_state: &RasterState,
     layout: vk::PipelineLayout,
 ) -> Result<vk::Pipeline> {
-    let mesh_shader = create_shader_module(
+    create_mesh_pipeline(
         device,
-        ShaderKind::Mesh,
-        mesh_entry_point,
-        mesh_path,
-        mesh_defines,
-    )?;
-    let fragment_shader = create_shader_module(
+        pipeline_cache,
+        None,
+        stages,
+        specialization,
+        color_formats,
+        depth_format,
+        multisample_state,
+        raster_state,
+        layout,
+    )
+}
+
+//Creates the parts of a mesh pipeline selected by library_flags, the states of the other parts are ignored and the
+//stages have to belong to the selected parts
+#[allow(clippy::too_many_arguments)]
+#[inline]
+pub unsafe fn create_mesh_library(
+    device: &Device,
+    pipeline_cache: vk::PipelineCache,
+    library_flags: vk::GraphicsPipelineLibraryFlagsEXT,
+    stages: &[ShaderStage],
+    specialization: &[u32],
+    color_formats: &[vk::Format],
+    depth_format: vk::Format,
+    multisample_state: &MultisampleState,
+    raster_state: &RasterState,
+    layout: vk::PipelineLayout,
+) -> Result<vk::Pipeline> {
+    create_mesh_pipeline(
         device,
-        ShaderKind::Fragment,
-        fragment_entry_point,
-        fragment_path,
-        fragment_defines,
-    )?;
-
-    let mesh_entry_point = CString::new(mesh_entry_point)?;
-    let fragment_entry_point = CString::new(fragment_entry_point)?;
-
-    let shader_stage_create_infos = vec![
-        vk::PipelineShaderStageCreateInfo::default()
-            .stage(vk::ShaderStageFlags::MESH_EXT)
-            .module(mesh_shader)
-            .name(&mesh_entry_point),
-        vk::PipelineShaderStageCreateInfo::default()
-            .stage(vk::ShaderStageFlags::FRAGMENT)
-            .module(fragment_shader)
-            .name(&fragment_entry_point),
-    ];
+        pipeline_cache,
+        Some(library_flags),
+        stages,
+        specialization,
+        color_formats,
+        depth_format,
+        multisample_state,
+        raster_state,
+        layout,
+    )
+}
+
+//Links libraries which together contain every part of a mesh pipeline, the libraries can be destroyed afterwards
+pub unsafe fn link_mesh_libraries(
+    device: &Device,
+    pipeline_cache: vk::PipelineCache,
+    libraries: &[vk::Pipeline],
+    layout: vk::PipelineLayout,
+) -> Result<vk::Pipeline> {
+    let mut pipeline_library_create_info =
+        vk::PipelineLibraryCreateInfoKHR::default().libraries(libraries);
+
+    let graphics_pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
+        .layout(layout)
+        .push_next(&mut pipeline_library_create_info);
+
+    let pipeline = device
+        .create_graphics_pipelines(
+            pipeline_cache,
+            slice::from_ref(&graphics_pipeline_create_info),
+            None,
+        )
+        .map_err(|(_, error)| error)?[0];
+    resource_registry::track_created(ResourceKind::Pipeline);
+
+    Ok(pipeline)
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn create_mesh_pipeline(
+    device: &Device,
+    pipeline_cache: vk::PipelineCache,
+    library_flags: Option<vk::GraphicsPipelineLibraryFlagsEXT>,
+    stages: &[ShaderStage],
+    specialization: &[u32],
+    color_formats: &[vk::Format],
+    depth_format: vk::Format,
+    multisample_state: &MultisampleState,
+    raster_state: &RasterState,
+    layout: vk::PipelineLayout,
+) -> Result<vk::Pipeline> {
+    let shader_modules = stages
+        .iter()
+        .map(|stage| create_shader_module(device, stage))
+        .collect::<Result<Vec<_>>>()?;
+
+    let specialization_map_entries: Vec<_> = (0..specialization.len())
+        .map(|i| {
+            vk::SpecializationMapEntry::default()
+                .constant_id(i as _)
+                .offset((i * std::mem::size_of::<u32>()) as _)
+                .size(std::mem::size_of::<u32>())
+        })
+        .collect();
+    let specialization_info = vk::SpecializationInfo::default()
+        .map_entries(&specialization_map_entries)
+        .data(bytemuck::cast_slice(specialization));
+
+    let shader_stage_create_infos: Vec<_> = stages
+        .iter()
+        .zip(&shader_modules)
+        .map(|(stage, shader_module)| {
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(stage.stage)
+                .module(*shader_module)
+                .name(&stage.entry_point)
+                .specialization_info(&specialization_info)
+        })
+        .collect();
 
     let input_assembly_state_create_info = vk::PipelineInputAssemblyStateCreateInfo::default()
         .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
@@ -139,32 +310,49 @@ pub unsafe fn create_mesh(
         .viewports(slice::from_ref(&viewport))
         .scissors(slice::from_ref(&scissor));
 
-    let rasterization_state_create_info =
-        vk::PipelineRasterizationStateCreateInfo::default().line_width(1.0);
+    let rasterization_state_create_info = vk::PipelineRasterizationStateCreateInfo::default()
+        .polygon_mode(raster_state.polygon_mode)
+        .line_width(1.0);
 
     let depth_stencil_state_create_info = vk::PipelineDepthStencilStateCreateInfo::default()
-        .depth_test_enable(true)
-        .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL);
+        .depth_test_enable(raster_state.depth_test)
+        .depth_write_enable(raster_state.depth_write)
+        .depth_compare_op(vk::CompareOp::GREATER_OR_EQUAL);
 
     let multisample_state_create_info = vk::PipelineMultisampleStateCreateInfo::default()
-        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        .rasterization_samples(multisample_state.rasterization_samples)
+        .sample_shading_enable(multisample_state.min_sample_shading.is_some())
+        .min_sample_shading(multisample_state.min_sample_shading.unwrap_or_default())
+        .alpha_to_coverage_enable(multisample_state.alpha_to_coverage);
 
     let blend_attachment_state = vk::PipelineColorBlendAttachmentState::default()
-        .color_write_mask(vk::ColorComponentFlags::RGBA);
-
-    let color_blend_state_create_info = vk::PipelineColorBlendStateCreateInfo::default()
-        .attachments(slice::from_ref(&blend_attachment_state));
+        .blend_enable(raster_state.alpha_blend)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .color_write_mask(if raster_state.color_write {
+            vk::ColorComponentFlags::RGBA
+        } else {
+            vk::ColorComponentFlags::empty()
+        });
+    let blend_attachment_states = vec![blend_attachment_state; color_formats.len()];
+
+    let color_blend_state_create_info =
+        vk::PipelineColorBlendStateCreateInfo::default().attachments(&blend_attachment_states);
 
     let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
     let dynamic_state_create_info =
         vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
 
     let mut pipeline_rendering_create_info = vk::PipelineRenderingCreateInfo::default()
-        .color_attachment_formats(slice::from_ref(&swapchain_format))
+        .view_mask(raster_state.view_mask)
+        .color_attachment_formats(color_formats)
         .depth_attachment_format(depth_format);
 
-    let graphics_pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
+    let mut graphics_pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
         .stages(&shader_stage_create_infos)
         .input_assembly_state(&input_assembly_state_create_info)
         .viewport_state(&viewport_state_create_info)
@@ -176,16 +364,48 @@ pub unsafe fn create_mesh(
         .layout(layout)
         .push_next(&mut pipeline_rendering_create_info);
 
+    //The primitive rate replaces the 1x1 rate of the pipeline, there is no shading rate attachment to combine it with
+    let mut fragment_shading_rate_state_create_info =
+        vk::PipelineFragmentShadingRateStateCreateInfoKHR::default()
+            .fragment_size(vk::Extent2D::default().width(1).height(1))
+            .combiner_ops([
+                vk::FragmentShadingRateCombinerOpKHR::REPLACE,
+                vk::FragmentShadingRateCombinerOpKHR::KEEP,
+            ]);
+    if raster_state.primitive_shading_rate {
+        graphics_pipeline_create_info =
+            graphics_pipeline_create_info.push_next(&mut fragment_shading_rate_state_create_info);
+    }
+
+    let mut graphics_pipeline_library_create_info =
+        vk::GraphicsPipelineLibraryCreateInfoEXT::default()
+            .flags(library_flags.unwrap_or_default());
+    if library_flags.is_some() {
+        graphics_pipeline_create_info = graphics_pipeline_create_info
+            .flags(vk::PipelineCreateFlags::LIBRARY_KHR)
+            .push_next(&mut graphics_pipeline_library_create_info);
+    }
+
     let pipeline = device
         .create_graphics_pipelines(
-            vk::PipelineCache::null(),
+            pipeline_cache,
             slice::from_ref(&graphics_pipeline_create_info),
             None,
         )
-        .unwrap()[0];
+        .map_err(|(_, error)| error);
+
+    shader_modules
+        .into_iter()
+        .for_each(|shader_module| device.destroy_shader_module(shader_module, None));
 
-    device.destroy_shader_module(fragment_shader, None);
-    device.destroy_shader_module(mesh_shader, None);
+    let pipeline = pipeline?[0];
+    resource_registry::track_created(ResourceKind::Pipeline);
 
     Ok(pipeline)
 }
+
+#[inline]
+pub unsafe fn destroy(device: &Device, pipeline: vk::Pipeline) {
+    device.destroy_pipeline(pipeline, None);
+    resource_registry::track_destroyed(ResourceKind::Pipeline);
+}
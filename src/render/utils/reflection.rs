@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use ash::{vk, Device};
+use rspirv_reflect::{BindingCount, Reflection};
+
+use crate::render::utils::pipelines::ShaderStage;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub descriptor_type: vk::DescriptorType,
+    pub descriptor_count: u32,
+    pub stage_flags: vk::ShaderStageFlags,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PushConstants {
+    pub stage_flags: vk::ShaderStageFlags,
+    pub offset: u32,
+    pub size: u32,
+}
+
+//Descriptor sets and push constants used by all stages of a pipeline, or several pipelines sharing one layout
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShaderInterface {
+    pub sets: BTreeMap<u32, BTreeMap<u32, DescriptorBinding>>,
+    pub push_constants: Option<PushConstants>,
+}
+
+impl ShaderInterface {
+    pub fn reflect<'a>(stages: impl IntoIterator<Item = &'a ShaderStage>) -> Result<Self> {
+        let mut shader_interface = Self::default();
+
+        for stage in stages {
+            let reflection = Reflection::new_from_spirv(bytemuck::cast_slice(&stage.spirv))?;
+
+            for (set, bindings) in reflection.get_descriptor_sets()? {
+                let set_bindings = shader_interface.sets.entry(set).or_default();
+
+                for (binding, descriptor_info) in bindings {
+                    let descriptor_count = match descriptor_info.binding_count {
+                        BindingCount::One => 1,
+                        BindingCount::StaticSized(count) => count as _,
+                        BindingCount::Unbounded => {
+                            bail!(
+                                "Unbounded descriptor array {} in set {set} isn't supported",
+                                descriptor_info.name
+                            )
+                        }
+                    };
+                    let descriptor_type = vk::DescriptorType::from_raw(descriptor_info.ty.0 as _);
+
+                    let set_binding = set_bindings.entry(binding).or_insert_with(|| {
+                        DescriptorBinding {
+                            descriptor_type,
+                            descriptor_count,
+                            stage_flags: vk::ShaderStageFlags::empty(),
+                        }
+                    });
+                    if set_binding.descriptor_type != descriptor_type
+                        || set_binding.descriptor_count != descriptor_count
+                    {
+                        bail!(
+                            "Stages disagree on set {set} binding {binding}: {:?}[{}] and {descriptor_type:?}[{descriptor_count}]",
+                            set_binding.descriptor_type,
+                            set_binding.descriptor_count
+                        );
+                    }
+                    set_binding.stage_flags |= stage.stage;
+                }
+            }
+
+            //A single range covers the push constants of every stage, which is what all shaders here expect
+            if let Some(push_constant_info) = reflection.get_push_constant_range()? {
+                let push_constants = shader_interface
+                    .push_constants
+                    .get_or_insert(PushConstants {
+                        stage_flags: vk::ShaderStageFlags::empty(),
+                        offset: push_constant_info.offset,
+                        size: 0,
+                    });
+                let end = (push_constants.offset + push_constants.size)
+                    .max(push_constant_info.offset + push_constant_info.size);
+
+                push_constants.stage_flags |= stage.stage;
+                push_constants.offset = push_constants.offset.min(push_constant_info.offset);
+                push_constants.size = end - push_constants.offset;
+            }
+        }
+
+        Ok(shader_interface)
+    }
+
+    //Stages which have to be passed to cmd_push_constants, they have to match the range exactly
+    #[inline]
+    pub fn push_constant_stages(&self) -> vk::ShaderStageFlags {
+        self.push_constants
+            .map_or(vk::ShaderStageFlags::empty(), |push_constants| {
+                push_constants.stage_flags
+            })
+    }
+
+    //Layouts which are shared with other pipelines have to list their stages too, they are passed as extra_stage_flags
+    pub unsafe fn create_descriptor_set_layout(
+        &self,
+        device: &Device,
+        set: u32,
+        extra_stage_flags: vk::ShaderStageFlags,
+    ) -> Result<vk::DescriptorSetLayout> {
+        let Some(bindings) = self.sets.get(&set) else {
+            bail!("No shader uses descriptor set {set}")
+        };
+
+        let descriptor_set_layout_bindings: Vec<_> = bindings
+            .iter()
+            .map(|(binding, descriptor_binding)| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(*binding)
+                    .descriptor_type(descriptor_binding.descriptor_type)
+                    .descriptor_count(descriptor_binding.descriptor_count)
+                    .stage_flags(descriptor_binding.stage_flags | extra_stage_flags)
+            })
+            .collect();
+
+        Ok(device.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::default().bindings(&descriptor_set_layout_bindings),
+            None,
+        )?)
+    }
+
+    //Every set used by the shaders needs a layout, sets owned by other passes are passed in with the own ones
+    pub unsafe fn create_pipeline_layout(
+        &self,
+        device: &Device,
+        set_layouts: &[vk::DescriptorSetLayout],
+    ) -> Result<vk::PipelineLayout> {
+        if let Some(set) = self
+            .sets
+            .keys()
+            .find(|set| **set as usize >= set_layouts.len())
+        {
+            bail!("No layout for descriptor set {set}")
+        }
+
+        let push_constant_ranges: Vec<_> = self
+            .push_constants
+            .iter()
+            .map(|push_constants| {
+                vk::PushConstantRange::default()
+                    .stage_flags(push_constants.stage_flags)
+                    .offset(push_constants.offset)
+                    .size(push_constants.size)
+            })
+            .collect();
+
+        Ok(device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(set_layouts)
+                .push_constant_ranges(&push_constant_ranges),
+            None,
+        )?)
+    }
+}
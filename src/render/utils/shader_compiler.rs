@@ -0,0 +1,153 @@
+use std::{ffi::OsStr, fmt::Write, fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use ash::vk;
+use shaderc::{CompileOptions, Compiler, ResolvedInclude, ShaderKind, SpirvVersion};
+
+#[cfg(feature = "wgsl-shaders")]
+use crate::render::utils::wgsl;
+use crate::render::{hitch_detector, utils::slang};
+
+//Lines of source printed before and after the line of a compile error
+const ERROR_CONTEXT_LINES: usize = 2;
+
+//Compiles shaders from the shaders directory at runtime, which is what shader hot reloading relies on
+pub fn compile(
+    stage: vk::ShaderStageFlags,
+    path: &Path,
+    entry_point_name: &str,
+    defines: &[(&str, Option<&str>)],
+) -> Result<Vec<u32>> {
+    //The backend is selected by the extension, everything else is GLSL
+    match path.extension().and_then(OsStr::to_str) {
+        Some("slang") => slang::compile(stage, path, entry_point_name, defines),
+        #[cfg(feature = "wgsl-shaders")]
+        Some("wgsl") => wgsl::compile(stage, path, entry_point_name, defines),
+        _ => compile_glsl(stage, path, entry_point_name, defines),
+    }
+}
+
+fn compile_glsl(
+    stage: vk::ShaderStageFlags,
+    path: &Path,
+    entry_point_name: &str,
+    defines: &[(&str, Option<&str>)],
+) -> Result<Vec<u32>> {
+    let kind = match stage {
+        vk::ShaderStageFlags::COMPUTE => ShaderKind::Compute,
+        vk::ShaderStageFlags::TASK_EXT => ShaderKind::Task,
+        vk::ShaderStageFlags::MESH_EXT => ShaderKind::Mesh,
+        vk::ShaderStageFlags::FRAGMENT => ShaderKind::Fragment,
+        stage => bail!("Unsupported shader stage {stage:?}"),
+    };
+
+    let source = fs::read_to_string(path)?;
+
+    let compiler = Compiler::new().ok_or_else(|| anyhow!("Failed to create compiler"))?;
+    let mut compile_options =
+        CompileOptions::new().ok_or_else(|| anyhow!("Failed to create compile options"))?;
+    compile_options.set_include_callback(|requested_source, _, _, _| {
+        let path = Path::new("shaders").join(requested_source); //TODO: Only working with simple paths, but it is ok for now
+
+        Ok(ResolvedInclude {
+            resolved_name: path.to_str().unwrap().to_owned(),
+            content: fs::read_to_string(&path)
+                .map_err(|error| format!("{}: {error}", path.display()))?,
+        })
+    });
+    compile_options.set_target_spirv(SpirvVersion::V1_6);
+
+    for (name, value) in defines {
+        compile_options.add_macro_definition(name, *value);
+    }
+
+    let artifact = compiler
+        .compile_into_spirv(
+            &source,
+            kind,
+            path.to_str().unwrap(),
+            entry_point_name,
+            Some(&compile_options),
+        )
+        .map_err(|error| format_compile_error(path, &source, defines, error))?;
+
+    hitch_detector::record_event(format!("Compiled shader {}", path.display()));
+
+    Ok(artifact.as_binary().to_vec())
+}
+
+//glslang reports every error as file:line: error: message, each one is printed with the source around it
+fn format_compile_error(
+    path: &Path,
+    source: &str,
+    defines: &[(&str, Option<&str>)],
+    error: shaderc::Error,
+) -> anyhow::Error {
+    let shaderc::Error::CompilationError(_, messages) = error else {
+        return anyhow!("Failed to compile {}: {error}", path.display())
+    };
+
+    let mut report = format!("Failed to compile {}", path.display());
+    if !defines.is_empty() {
+        let defines: Vec<_> = defines
+            .iter()
+            .map(|(name, value)| {
+                match value {
+                    Some(value) => format!("{name}={value}"),
+                    None => name.to_string(),
+                }
+            })
+            .collect();
+        write!(report, " with {}", defines.join(" ")).unwrap();
+    }
+
+    for message in messages
+        .lines()
+        .filter(|message| !message.trim().is_empty())
+    {
+        write!(report, "\n{message}").unwrap();
+
+        let mut parts = message.splitn(3, ':');
+        let (Some(file), Some(Ok(line))) = (
+            parts.next(),
+            parts.next().map(|line| line.trim().parse::<usize>()),
+        ) else {
+            continue
+        };
+
+        //Errors in includes refer to the included file
+        let file_source = if Path::new(file) == path {
+            Some(source.to_owned())
+        } else {
+            fs::read_to_string(file).ok()
+        };
+        let Some(file_source) = file_source else {
+            continue
+        };
+
+        for (idx, text) in file_source
+            .lines()
+            .enumerate()
+            .skip(line.saturating_sub(ERROR_CONTEXT_LINES + 1))
+            .take(2 * ERROR_CONTEXT_LINES + 1)
+        {
+            if idx + 1 != line {
+                write!(report, "\n {:>5} | {text}", idx + 1).unwrap();
+                continue
+            }
+
+            //glslang doesn't report columns, so the whole line is underlined
+            let indent = &text[..text.len() - text.trim_start().len()];
+            write!(
+                report,
+                "\n>{:>5} | {text}\n {:>5} | {indent}{}",
+                idx + 1,
+                "",
+                "^".repeat(text.trim().len().max(1))
+            )
+            .unwrap();
+        }
+    }
+
+    anyhow!(report)
+}
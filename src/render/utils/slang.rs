@@ -0,0 +1,71 @@
+use std::{
+    env, fs,
+    path::Path,
+    process::{self, Command},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{anyhow, bail, Result};
+use ash::vk;
+
+use crate::render::hitch_detector;
+
+//Overrides the slangc found in PATH
+const SLANGC_ENV: &str = "SLANGC";
+
+static NEXT_OUTPUT_IDX: AtomicUsize = AtomicUsize::new(0);
+
+//Compiles a Slang shader by running slangc, modules are imported from the shaders directory
+pub fn compile(
+    stage: vk::ShaderStageFlags,
+    path: &Path,
+    entry_point_name: &str,
+    defines: &[(&str, Option<&str>)],
+) -> Result<Vec<u32>> {
+    let stage_name = match stage {
+        vk::ShaderStageFlags::COMPUTE => "compute",
+        vk::ShaderStageFlags::TASK_EXT => "amplification",
+        vk::ShaderStageFlags::MESH_EXT => "mesh",
+        vk::ShaderStageFlags::FRAGMENT => "fragment",
+        stage => bail!("Unsupported shader stage {stage:?}"),
+    };
+
+    //Shaders are compiled on several workers at once, so every compilation writes to its own file
+    let output_path = env::temp_dir().join(format!(
+        "vk-ext-mesh-shader-example-{}-{}.spv",
+        process::id(),
+        NEXT_OUTPUT_IDX.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let mut command = Command::new(env::var_os(SLANGC_ENV).unwrap_or_else(|| "slangc".into()));
+    command
+        .arg(path)
+        .args(["-target", "spirv", "-profile", "spirv_1_6"])
+        .args(["-entry", entry_point_name, "-stage", stage_name])
+        .args(["-I", "shaders", "-o"])
+        .arg(&output_path);
+    for (name, value) in defines {
+        command.arg("-D").arg(match value {
+            Some(value) => format!("{name}={value}"),
+            None => name.to_string(),
+        });
+    }
+
+    let output = command
+        .output()
+        .map_err(|error| anyhow!("Failed to run slangc: {error}"))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to compile {}:\n{}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        )
+    }
+
+    let spirv = fs::read(&output_path)?;
+    fs::remove_file(&output_path)?;
+
+    hitch_detector::record_event(format!("Compiled shader {}", path.display()));
+
+    Ok(bytemuck::pod_collect_to_vec(&spirv))
+}
@@ -0,0 +1,57 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use ash::vk;
+use naga::{
+    back::spv,
+    front::wgsl,
+    valid::{Capabilities, ValidationFlags, Validator},
+    ShaderStage,
+};
+
+use crate::render::hitch_detector;
+
+//Validates a WGSL shader and translates it to SPIR-V with naga, without any native dependency
+pub fn compile(
+    stage: vk::ShaderStageFlags,
+    path: &Path,
+    entry_point_name: &str,
+    defines: &[(&str, Option<&str>)],
+) -> Result<Vec<u32>> {
+    let shader_stage = match stage {
+        vk::ShaderStageFlags::COMPUTE => ShaderStage::Compute,
+        vk::ShaderStageFlags::FRAGMENT => ShaderStage::Fragment,
+        stage => bail!("naga can't compile {stage:?} shaders"),
+    };
+    //WGSL has no preprocessor
+    if !defines.is_empty() {
+        bail!("Defines aren't supported by WGSL shaders")
+    }
+
+    let source = fs::read_to_string(path)?;
+
+    let module = wgsl::parse_str(&source).map_err(|error| {
+        anyhow!(
+            "Failed to parse {}:\n{}",
+            path.display(),
+            error.emit_to_string(&source)
+        )
+    })?;
+    let module_info = Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|error| anyhow!("Failed to validate {}: {error:?}", path.display()))?;
+
+    let spirv = spv::write_vec(
+        &module,
+        &module_info,
+        &spv::Options::default(),
+        Some(&spv::PipelineOptions {
+            shader_stage,
+            entry_point: entry_point_name.to_owned(),
+        }),
+    )?;
+
+    hitch_detector::record_event(format!("Compiled shader {}", path.display()));
+
+    Ok(spirv)
+}
@@ -0,0 +1,119 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec2, Vec3};
+use half::f16;
+
+use crate::render::mesh::Vertex;
+
+//Half the size of Vertex, decoded in the mesh shaders by decode_vertex in shaders/vertex_format.glsl
+#[derive(Copy, Clone, Debug, Default, PartialEq, Zeroable, Pod)]
+#[repr(C)]
+pub struct PackedVertex {
+    //Half floats in -1..1 relative to the bounds of the mesh, the fourth component is unused
+    pub position: [u16; 4],
+    //Octahedral encoded as snorm16
+    pub normal: [i16; 2],
+    //Unorm16 relative to the texture coordinate range of the mesh
+    pub tex_coord: [u16; 2],
+}
+
+//Per mesh transform which turns the packed attributes back into the original ranges
+#[derive(Copy, Clone, Debug, Default, PartialEq, Zeroable, Pod)]
+#[repr(C)]
+pub struct VertexQuantization {
+    pub position_offset: Vec3,
+    pub position_scale: Vec3,
+    pub tex_coord_offset: Vec2,
+    pub tex_coord_scale: Vec2,
+}
+
+//A zero extent would divide by zero, flat meshes like the ground plane keep a scale of one on that axis
+#[inline]
+fn non_zero(scale: f32) -> f32 {
+    if scale > 0.0 {
+        scale
+    } else {
+        1.0
+    }
+}
+
+#[inline]
+fn quantize_snorm16(value: f32) -> i16 {
+    (value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as _
+}
+
+#[inline]
+fn quantize_unorm16(value: f32) -> u16 {
+    (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as _
+}
+
+fn encode_octahedral(normal: Vec3) -> [i16; 2] {
+    let normal = normal / (normal.x.abs() + normal.y.abs() + normal.z.abs()).max(f32::EPSILON);
+
+    let encoded = if normal.z >= 0.0 {
+        Vec2::new(normal.x, normal.y)
+    } else {
+        let sign = |value: f32| if value >= 0.0 { 1.0 } else { -1.0 };
+
+        Vec2::new(
+            (1.0 - normal.y.abs()) * sign(normal.x),
+            (1.0 - normal.x.abs()) * sign(normal.y),
+        )
+    };
+
+    [quantize_snorm16(encoded.x), quantize_snorm16(encoded.y)]
+}
+
+impl VertexQuantization {
+    pub fn from_vertices<'a>(vertices: impl Iterator<Item = &'a Vertex>) -> Self {
+        let (min, max, tex_coord_min, tex_coord_max) = vertices.fold(
+            (
+                Vec3::splat(f32::MAX),
+                Vec3::splat(f32::MIN),
+                Vec2::splat(f32::MAX),
+                Vec2::splat(f32::MIN),
+            ),
+            |(min, max, tex_coord_min, tex_coord_max), vertex| {
+                (
+                    min.min(vertex.position),
+                    max.max(vertex.position),
+                    tex_coord_min.min(vertex.tex_coord),
+                    tex_coord_max.max(vertex.tex_coord),
+                )
+            },
+        );
+
+        if min.x > max.x {
+            return Self::default()
+        }
+
+        let position_scale = (max - min) * 0.5;
+        let tex_coord_scale = tex_coord_max - tex_coord_min;
+
+        Self {
+            position_offset: (min + max) * 0.5,
+            position_scale: Vec3::new(
+                non_zero(position_scale.x),
+                non_zero(position_scale.y),
+                non_zero(position_scale.z),
+            ),
+            tex_coord_offset: tex_coord_min,
+            tex_coord_scale: Vec2::new(non_zero(tex_coord_scale.x), non_zero(tex_coord_scale.y)),
+        }
+    }
+
+    pub fn pack(&self, vertex: &Vertex) -> PackedVertex {
+        let position = (vertex.position - self.position_offset) / self.position_scale;
+        let tex_coord = (vertex.tex_coord - self.tex_coord_offset) / self.tex_coord_scale;
+
+        PackedVertex {
+            position: [
+                f16::from_f32(position.x).to_bits(),
+                f16::from_f32(position.y).to_bits(),
+                f16::from_f32(position.z).to_bits(),
+                0,
+            ],
+            normal: encode_octahedral(vertex.normal),
+            tex_coord: [quantize_unorm16(tex_coord.x), quantize_unorm16(tex_coord.y)],
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::{sync::Arc, thread};
+
+use anyhow::{bail, Result};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum WorkerPriority {
+    #[default]
+    Normal,
+    //Lowers the niceness of the workers, so they never compete with the render thread
+    Low,
+}
+
+#[derive(Clone, Debug)]
+pub struct WorkerPoolConfig {
+    pub num_workers: usize,
+    pub priority: WorkerPriority,
+    //Pins the workers to these cores, which keeps them away from the render thread in benchmarks
+    pub cpu_affinity: Option<Vec<usize>>,
+}
+
+impl Default for WorkerPoolConfig {
+    #[inline]
+    fn default() -> Self {
+        //Leave one core for the render thread
+        let num_workers = thread::available_parallelism()
+            .map(|parallelism| parallelism.get().saturating_sub(1))
+            .unwrap_or(1)
+            .max(1);
+
+        Self {
+            num_workers,
+            priority: WorkerPriority::default(),
+            cpu_affinity: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WorkerConfig {
+    pub asset_loading: WorkerPoolConfig,
+    pub shader_compilation: WorkerPoolConfig,
+    //With a single worker the draws are recorded on the render thread, which is cheaper for small scenes
+    pub command_recording: WorkerPoolConfig,
+}
+
+impl Default for WorkerConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            asset_loading: WorkerPoolConfig::default(),
+            shader_compilation: WorkerPoolConfig::default(),
+            command_recording: WorkerPoolConfig {
+                num_workers: 1,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+//The worker options of the command line, the priority and the cores apply to all pools
+#[derive(clap::Args, Debug)]
+pub struct WorkerArgs {
+    #[arg(
+        long,
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of threads baking meshes"
+    )]
+    asset_workers: Option<u32>,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of threads compiling shaders and creating pipelines"
+    )]
+    shader_workers: Option<u32>,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of threads recording the draws into secondary command buffers"
+    )]
+    recording_workers: Option<u32>,
+    #[arg(
+        long,
+        value_name = "PRIO",
+        value_parser = parse_priority,
+        help = "Priority of all workers, normal or low"
+    )]
+    worker_priority: Option<WorkerPriority>,
+    #[arg(
+        long,
+        value_name = "CPUS",
+        value_delimiter = ',',
+        help = "Comma separated list of cores all workers are pinned to"
+    )]
+    worker_cpus: Option<Vec<usize>>,
+}
+
+fn parse_priority(priority: &str) -> Result<WorkerPriority> {
+    Ok(match priority {
+        "normal" => WorkerPriority::Normal,
+        "low" => WorkerPriority::Low,
+        _ => bail!("Unknown worker priority {priority}"),
+    })
+}
+
+impl WorkerArgs {
+    pub fn apply(self, config: &mut WorkerConfig) {
+        if let Some(num_workers) = self.asset_workers {
+            config.asset_loading.num_workers = num_workers as _;
+        }
+        if let Some(num_workers) = self.shader_workers {
+            config.shader_compilation.num_workers = num_workers as _;
+        }
+        if let Some(num_workers) = self.recording_workers {
+            config.command_recording.num_workers = num_workers as _;
+        }
+
+        for pool_config in [
+            &mut config.asset_loading,
+            &mut config.shader_compilation,
+            &mut config.command_recording,
+        ] {
+            if let Some(priority) = self.worker_priority {
+                pool_config.priority = priority;
+            }
+            if let Some(cpus) = &self.worker_cpus {
+                pool_config.cpu_affinity = Some(cpus.clone());
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn apply_thread_settings(config: &WorkerPoolConfig) {
+    unsafe {
+        //On Linux the priority of a single thread can be changed by passing its thread id
+        if config.priority == WorkerPriority::Low {
+            let thread_id = libc::syscall(libc::SYS_gettid) as libc::id_t;
+            libc::setpriority(libc::PRIO_PROCESS, thread_id, 10);
+        }
+
+        if let Some(cpus) = &config.cpu_affinity {
+            let mut cpu_set: libc::cpu_set_t = std::mem::zeroed();
+            cpus.iter()
+                .for_each(|cpu| libc::CPU_SET(*cpu, &mut cpu_set));
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpu_set);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn apply_thread_settings(_config: &WorkerPoolConfig) {}
+
+//The workers are started once and shared by every clone, rayon's parallel iterators run on them inside install
+#[derive(Clone, Debug)]
+pub struct WorkerPool {
+    thread_pool: Arc<ThreadPool>,
+    num_workers: usize,
+}
+
+impl WorkerPool {
+    pub fn new(name: &'static str, config: WorkerPoolConfig) -> Result<Self> {
+        let num_workers = config.num_workers;
+        let thread_pool = ThreadPoolBuilder::new()
+            .num_threads(num_workers)
+            .thread_name(move |i| format!("{name}-{i}"))
+            .start_handler(move |_| apply_thread_settings(&config))
+            .build()?;
+
+        Ok(Self {
+            thread_pool: Arc::new(thread_pool),
+            num_workers,
+        })
+    }
+
+    #[inline]
+    pub fn num_workers(&self) -> usize {
+        self.num_workers
+    }
+
+    //Runs f on the workers of this pool, including the parallel iterators it starts
+    #[inline]
+    pub fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        self.thread_pool.install(f)
+    }
+
+    //Runs f for every item on the workers of this pool and returns the results in the order of the items
+    pub fn map<T: Send, R: Send>(
+        &self,
+        items: impl IntoIterator<Item = T>,
+        f: impl Fn(T) -> R + Sync + Send,
+    ) -> Vec<R> {
+        let items: Vec<_> = items.into_iter().collect();
+        self.install(|| items.into_par_iter().map(f).collect())
+    }
+}
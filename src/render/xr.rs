@@ -0,0 +1,326 @@
+use std::{
+    ffi::{CStr, CString},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use ash::vk::{self, Handle};
+use glam::{Quat, Vec3};
+use openxr as xr;
+
+use crate::render::{
+    passes::stereo::Eye,
+    render_config::RenderConfig,
+    render_ctx::{RenderCtx, RenderTarget, SWAPCHAIN_FORMAT},
+    renderer,
+    scene::{Camera, Scene},
+    workers::WorkerConfig,
+};
+
+const VIEW_CONFIGURATION_TYPE: xr::ViewConfigurationType =
+    xr::ViewConfigurationType::PRIMARY_STEREO;
+//The runtime only hands out frames while the session is running
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+//The origin of the tracking space is placed where the interactive camera starts
+const ORIGIN: Vec3 = Vec3::Y;
+
+#[derive(Clone, Debug, Default)]
+pub struct XrConfig {
+    pub enabled: bool,
+}
+
+//The XR options of the command line
+#[derive(clap::Args, Debug)]
+pub struct XrArgs {
+    #[arg(
+        long,
+        help = "Render into the headset of the OpenXR runtime, the camera follows the head"
+    )]
+    xr: bool,
+}
+
+impl XrArgs {
+    #[inline]
+    pub fn apply(self, config: &mut XrConfig) {
+        config.enabled |= self.xr;
+    }
+}
+
+//The device config keeps the names for the rest of the program
+fn leak_extension_names(names: &str) -> Result<Vec<&'static CStr>> {
+    names
+        .split_whitespace()
+        .map(|name| Ok(&*Box::leak(CString::new(name)?.into_boxed_c_str())))
+        .collect()
+}
+
+//OpenXR is right-handed with -Z pointing forward, the renderer is left-handed with +Z pointing forward
+#[inline]
+fn to_world(vector: Vec3) -> Vec3 {
+    Vec3::new(vector.x, vector.y, -vector.z)
+}
+
+fn eye_from_view(view: &xr::View) -> Eye {
+    let orientation = view.pose.orientation;
+    let orientation = Quat::from_xyzw(orientation.x, orientation.y, orientation.z, orientation.w);
+    let position = view.pose.position;
+
+    let tan_up = view.fov.angle_up.tan();
+    let tan_down = view.fov.angle_down.tan();
+
+    Eye {
+        camera: Camera {
+            position: ORIGIN + to_world(Vec3::new(position.x, position.y, position.z)),
+            forward: to_world(orientation * Vec3::NEG_Z),
+            up: to_world(orientation * Vec3::Y),
+            field_of_view: (tan_up.atan() - tan_down.atan()).to_degrees(),
+        },
+        tan_left: view.fov.angle_left.tan(),
+        tan_right: view.fov.angle_right.tan(),
+        tan_up,
+        tan_down,
+    }
+}
+
+//Culling uses a symmetric frustum between the eyes, it's widened until it contains the frusta of both
+fn head_camera(eyes: &[Eye; 2], aspect_ratio: f32) -> Camera {
+    let half_height = eyes
+        .iter()
+        .flat_map(|eye| {
+            [
+                eye.tan_up,
+                -eye.tan_down,
+                -eye.tan_left / aspect_ratio,
+                eye.tan_right / aspect_ratio,
+            ]
+        })
+        .fold(0.0, f32::max);
+
+    Camera {
+        position: eyes[0].camera.position.lerp(eyes[1].camera.position, 0.5),
+        forward: eyes[0].camera.forward,
+        up: eyes[0].camera.up,
+        field_of_view: (2.0 * half_height.atan()).to_degrees(),
+    }
+}
+
+//Renders into the swapchain of the OpenXR runtime until it ends the session. Both eyes are rendered with multiview and
+//copied into the layers of the runtime's images
+pub fn run(scene: Scene, worker_config: &WorkerConfig, render_config: &RenderConfig) -> Result<()> {
+    scene.validate()?;
+
+    //Create instance and system
+    let entry = unsafe { xr::Entry::load() }
+        .map_err(|error| anyhow!("Failed to load the OpenXR loader: {error}"))?;
+    if !entry.enumerate_extensions()?.khr_vulkan_enable {
+        bail!("The OpenXR runtime doesn't support Vulkan")
+    }
+
+    let mut enabled_extensions = xr::ExtensionSet::default();
+    enabled_extensions.khr_vulkan_enable = true;
+    let xr_instance = entry.create_instance(
+        &xr::ApplicationInfo {
+            application_name: "vk-ext-mesh-shader-example",
+            application_version: 0,
+            engine_name: "vk-ext-mesh-shader-example",
+            engine_version: 0,
+        },
+        &enabled_extensions,
+        &[],
+    )?;
+    let system = xr_instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+
+    let requirements = xr_instance.graphics_requirements::<xr::Vulkan>(system)?;
+    let min_version = requirements.min_api_version_supported;
+    if (min_version.major(), min_version.minor()) > (1, 3) {
+        bail!(
+            "The OpenXR runtime needs at least Vulkan {}.{}",
+            min_version.major(),
+            min_version.minor()
+        )
+    }
+
+    let view_configuration_views =
+        xr_instance.enumerate_view_configuration_views(system, VIEW_CONFIGURATION_TYPE)?;
+    let eye_extent = vk::Extent2D {
+        width: view_configuration_views[0].recommended_image_rect_width,
+        height: view_configuration_views[0].recommended_image_rect_height,
+    };
+
+    //Create render context, the runtime picks the device which drives the headset
+    let mut builder = RenderCtx::builder(RenderTarget::Headless(vk::Extent2D {
+        width: 2 * eye_extent.width,
+        height: eye_extent.height,
+    }))
+    .worker_config(worker_config.clone())
+    .render_config(RenderConfig {
+        stereo: true,
+        eye_extent: Some(eye_extent),
+        ..render_config.clone()
+    });
+    for extension in leak_extension_names(&xr_instance.vulkan_legacy_instance_extensions(system)?)?
+    {
+        builder = builder.instance_extension(extension);
+    }
+    for extension in leak_extension_names(&xr_instance.vulkan_legacy_device_extensions(system)?)? {
+        builder = builder.device_extension(extension);
+    }
+    let physical_device_instance = xr_instance.clone();
+    let mut ctx = builder
+        .physical_device(Arc::new(move |instance_loader| {
+            let physical_device = unsafe {
+                physical_device_instance
+                    .vulkan_graphics_device(system, instance_loader.handle().as_raw() as _)
+            }?;
+            Ok(vk::PhysicalDevice::from_raw(physical_device as _))
+        }))
+        .build(&scene)?;
+    if ctx.stereo_pass.is_none() {
+        bail!("The device can't render both eyes with multiview")
+    }
+
+    //Create session
+    let (session, mut frame_waiter, mut frame_stream) = unsafe {
+        xr_instance.create_session::<xr::Vulkan>(
+            system,
+            &xr::vulkan::SessionCreateInfo {
+                instance: ctx.device.instance_loader.handle().as_raw() as _,
+                physical_device: ctx.device.physical_device.as_raw() as _,
+                device: ctx.device.device_loader.handle().as_raw() as _,
+                queue_family_index: ctx.device.direct_queue_family_index,
+                queue_index: 0,
+            },
+        )
+    }?;
+    let space =
+        session.create_reference_space(xr::ReferenceSpaceType::LOCAL, xr::Posef::IDENTITY)?;
+
+    if !session
+        .enumerate_swapchain_formats()?
+        .contains(&(SWAPCHAIN_FORMAT.as_raw() as u32))
+    {
+        bail!("The OpenXR runtime doesn't support {SWAPCHAIN_FORMAT:?} swapchains")
+    }
+    let mut swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+        create_flags: xr::SwapchainCreateFlags::EMPTY,
+        usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+            | xr::SwapchainUsageFlags::TRANSFER_DST,
+        format: SWAPCHAIN_FORMAT.as_raw() as _,
+        sample_count: 1,
+        width: eye_extent.width,
+        height: eye_extent.height,
+        face_count: 1,
+        array_size: 2,
+        mip_count: 1,
+    })?;
+    let swapchain_images: Vec<_> = swapchain
+        .enumerate_images()?
+        .into_iter()
+        .map(vk::Image::from_raw)
+        .collect();
+
+    let image_rect = xr::Rect2Di {
+        offset: xr::Offset2Di { x: 0, y: 0 },
+        extent: xr::Extent2Di {
+            width: eye_extent.width as _,
+            height: eye_extent.height as _,
+        },
+    };
+    let aspect_ratio = eye_extent.width as f32 / eye_extent.height as f32;
+
+    let mut event_storage = xr::EventDataBuffer::new();
+    let mut session_running = false;
+    let mut frame_index = 0;
+    'main: loop {
+        while let Some(event) = xr_instance.poll_event(&mut event_storage)? {
+            match event {
+                xr::Event::SessionStateChanged(event) => {
+                    match event.state() {
+                        xr::SessionState::READY => {
+                            session.begin(VIEW_CONFIGURATION_TYPE)?;
+                            session_running = true;
+                        }
+                        xr::SessionState::STOPPING => {
+                            session.end()?;
+                            session_running = false;
+                        }
+                        xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => break 'main,
+                        _ => {}
+                    }
+                }
+                xr::Event::InstanceLossPending(_) => break 'main,
+                _ => {}
+            }
+        }
+
+        if !session_running {
+            thread::sleep(IDLE_POLL_INTERVAL);
+            continue
+        }
+
+        //Begin frame
+        let frame_state = frame_waiter.wait()?;
+        frame_stream.begin()?;
+        if !frame_state.should_render {
+            frame_stream.end(
+                frame_state.predicted_display_time,
+                xr::EnvironmentBlendMode::OPAQUE,
+                &[],
+            )?;
+            continue
+        }
+
+        let (_, views) = session.locate_views(
+            VIEW_CONFIGURATION_TYPE,
+            frame_state.predicted_display_time,
+            &space,
+        )?;
+        let eyes = [eye_from_view(&views[0]), eye_from_view(&views[1])];
+
+        let image_index = swapchain.acquire_image()?;
+        swapchain.wait_image(xr::Duration::INFINITE)?;
+
+        //Render frame
+        ctx.camera_override = Some(head_camera(&eyes, aspect_ratio));
+        ctx.eye_override = Some(eyes);
+        ctx.stereo_target = Some(swapchain_images[image_index as usize]);
+
+        if let Err(error) = renderer::render_frame(&mut ctx, &mut frame_index) {
+            if error.is_device_lost() {
+                ctx.device.report_device_fault();
+            }
+            return Err(error.into())
+        }
+        frame_index = (frame_index + 1) % ctx.frame_resources.frames.len();
+
+        //End frame, the runtime waits for the submitted copy itself
+        swapchain.release_image()?;
+
+        let projection_views = [0, 1].map(|eye| {
+            xr::CompositionLayerProjectionView::new()
+                .pose(views[eye].pose)
+                .fov(views[eye].fov)
+                .sub_image(
+                    xr::SwapchainSubImage::new()
+                        .swapchain(&swapchain)
+                        .image_array_index(eye as _)
+                        .image_rect(image_rect),
+                )
+        });
+        frame_stream.end(
+            frame_state.predicted_display_time,
+            xr::EnvironmentBlendMode::OPAQUE,
+            &[&xr::CompositionLayerProjection::new()
+                .space(&space)
+                .views(&projection_views)],
+        )?;
+    }
+
+    //The runtime's images must not be used anymore once the swapchain is destroyed
+    ctx.stereo_target = None;
+    unsafe { ctx.device.device_loader.device_wait_idle() }?;
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::{fs, io, path::Path};
+
+use anyhow::Result;
+use ash::vk;
+use serde::{Deserialize, Serialize};
+use vk_ext_mesh_shader_example::render::mesh::LodConfig;
+
+pub const SETTINGS_PATH: &str = "settings.toml";
+
+//Persisted between runs, command line options override them without being saved. Only the values changed at runtime
+//are written back
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub width: u32,
+    pub height: u32,
+    pub vsync: bool,
+    pub lod_bias: f32,
+    pub culling: bool,
+    //Degrees the camera turns per pixel of mouse movement
+    pub camera_sensitivity: f32,
+    //Changing it rebuilds the meshlet caches
+    pub lod: LodConfig,
+    //Positions of models closer than this are merged before baking, zero disables it
+    pub weld_tolerance: f32,
+}
+
+impl Default for Settings {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            width: 1600,
+            height: 900,
+            vsync: true,
+            lod_bias: 1.0,
+            culling: true,
+            camera_sensitivity: 0.3,
+            lod: LodConfig::default(),
+            weld_tolerance: 0.0,
+        }
+    }
+}
+
+impl Settings {
+    //A missing file isn't an error, the defaults are used until the settings are saved for the first time
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn present_mode(&self) -> vk::PresentModeKHR {
+        if self.vsync {
+            vk::PresentModeKHR::FIFO
+        } else {
+            vk::PresentModeKHR::IMMEDIATE
+        }
+    }
+}